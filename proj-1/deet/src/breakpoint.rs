@@ -0,0 +1,576 @@
+use crate::dwarf_data::Type;
+use crate::expression::{self, Evaluator, Expression, Program};
+use crate::inferior::WatchKind;
+use crate::modules::Modules;
+use crate::target::Target;
+use crate::unwind;
+use std::fmt;
+use std::ops::Range;
+
+/// A breakpoint condition: an expression that lets the breakpoint stop the inferior unless it
+/// is zero.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    text: String,
+    expression: Expression,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Condition {
+    pub fn parse(text: &str, debug_data: &Modules) -> Result<Condition, String> {
+        Ok(Condition {
+            text: text.trim().to_string(),
+            expression: expression::parse(text, &|name| debug_data.get_type(name).is_some())?,
+        })
+    }
+
+    /// Evaluates the condition in the innermost frame of the stopped inferior.
+    pub fn evaluate(&self, inferior: &dyn Target, debug_data: &Modules) -> Result<bool, String> {
+        let frame = unwind::innermost_frame(inferior, debug_data)
+            .map_err(|e| format!("cannot unwind the stack: {}", e))?;
+        let mut evaluator = Evaluator::new(Program::ReadOnly(inferior), debug_data, frame, true);
+        let value = evaluator.evaluate(&self.expression)?;
+        evaluator.truth(&value)
+    }
+}
+
+/// What a watchpoint watches, and the values it has seen.
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub kind: WatchKind,
+    pub entity_type: Type,
+    /// The value before the last hit
+    pub old_value: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+/// A user breakpoint, or a watchpoint if `watch` is set. A breakpoint may be at several
+/// addresses, e.g. a line in a function that was inlined more than once, and several
+/// breakpoints may share an address; the inferior only ever has one 0xcc inserted there, as long
+/// as at least one of them is enabled.
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub id: usize,
+    /// The location as the user wrote it (a line, a function or `*addr`)
+    pub location: String,
+    /// Every address the location resolved to. A watchpoint has exactly one, what it watches.
+    pub addrs: Vec<usize>,
+    /// Set while the location is in a shared library that isn't loaded, so `addrs` is empty
+    pub pending: bool,
+    pub hit_count: usize,
+    pub enabled: bool,
+    pub condition: Option<Condition>,
+    /// How many more hits to let pass without stopping
+    pub ignore_count: usize,
+    pub watch: Option<Watch>,
+}
+
+impl Breakpoint {
+    fn is_software(&self) -> bool {
+        self.watch.is_none()
+    }
+
+    /// Whether the breakpoint needs a 0xcc at its address.
+    fn is_inserted(&self) -> bool {
+        self.enabled && self.is_software() && !self.pending
+    }
+
+    /// Counts a hit, unless the condition is false, and decides whether it stops the inferior.
+    /// Like gdb, a condition that can't be evaluated counts as true.
+    fn hit(&mut self, evaluate: &mut impl FnMut(&Condition) -> Result<bool, String>) -> bool {
+        if let Some(condition) = &self.condition {
+            match evaluate(condition) {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) => println!(
+                    "Error in testing condition for breakpoint {}: {}",
+                    self.id, e
+                ),
+            }
+        }
+        self.hit_count += 1;
+        if self.ignore_count > 0 {
+            self.ignore_count -= 1;
+            return false;
+        }
+        true
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<7} {:<15} {:<3} {} {:<5} {}",
+            self.id,
+            match &self.watch {
+                None => "breakpoint",
+                Some(watch) if watch.kind == WatchKind::Write => "hw watchpoint",
+                Some(watch) if watch.kind == WatchKind::Read => "read watchpoint",
+                Some(_) => "acc watchpoint",
+            },
+            if self.enabled { "y" } else { "n" },
+            match self.addrs[..] {
+                _ if self.pending => format!("{:<18}", "<PENDING>"),
+                [addr] => format!("{:#018x}", addr),
+                _ => format!("{:<18}", "<MULTIPLE>"),
+            },
+            self.hit_count,
+            self.location
+        )?;
+        if let Some(condition) = &self.condition {
+            write!(f, "\n\tstop only if {}", condition)?;
+        }
+        if self.addrs.len() > 1 {
+            for (i, addr) in self.addrs.iter().enumerate() {
+                write!(
+                    f,
+                    "\n{:<7} {:<15} {:<3} {:#018x}",
+                    format!("{}.{}", self.id, i + 1),
+                    "",
+                    "",
+                    addr
+                )?;
+            }
+        }
+        if self.ignore_count > 0 {
+            write!(
+                f,
+                "\n\tWill ignore next {} crossings of breakpoint.",
+                self.ignore_count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Breakpoints ordered by id. Ids are never reused, so they stay stable across deletions.
+#[derive(Debug, Default)]
+pub struct BreakpointTable {
+    breakpoints: Vec<Breakpoint>,
+    last_id: usize,
+    /// The breakpoints that made the inferior stop the last time it trapped
+    last_hits: Vec<usize>,
+    /// An internal breakpoint in the dynamic linker that it hits whenever it loads or unloads a
+    /// shared library
+    shlib_event: Option<usize>,
+}
+
+impl BreakpointTable {
+    /// Adds an enabled breakpoint at `addrs` and returns its id.
+    pub fn add(
+        &mut self,
+        location: String,
+        addrs: Vec<usize>,
+        condition: Option<Condition>,
+    ) -> usize {
+        self.last_id += 1;
+        let id = self.last_id;
+        self.breakpoints.push(Breakpoint {
+            id,
+            location,
+            addrs,
+            pending: false,
+            hit_count: 0,
+            enabled: true,
+            condition,
+            ignore_count: 0,
+            watch: None,
+        });
+        id
+    }
+
+    /// Adds an enabled watchpoint on `expression`, which lives at `addr` and currently holds
+    /// `value`, and returns its id.
+    pub fn add_watchpoint(
+        &mut self,
+        expression: String,
+        addr: usize,
+        kind: WatchKind,
+        entity_type: Type,
+        value: Vec<u8>,
+    ) -> usize {
+        let id = self.add(expression, vec![addr], None);
+        self.breakpoints.last_mut().unwrap().watch = Some(Watch {
+            kind,
+            entity_type,
+            old_value: value.clone(),
+            value,
+        });
+        id
+    }
+
+    /// Adds a breakpoint whose location can't be resolved until a shared library is loaded, and
+    /// returns its id.
+    pub fn add_pending(&mut self, location: String, condition: Option<Condition>) -> usize {
+        let id = self.add(location, Vec::new(), condition);
+        self.breakpoints.last_mut().unwrap().pending = true;
+        id
+    }
+
+    /// Returns the ids and locations of the pending breakpoints.
+    pub fn pending(&self) -> Vec<(usize, String)> {
+        self.breakpoints
+            .iter()
+            .filter(|bp| bp.pending)
+            .map(|bp| (bp.id, bp.location.clone()))
+            .collect()
+    }
+
+    /// Gives a pending breakpoint its addresses. Returns whether it now needs inserting.
+    pub fn resolve(&mut self, id: usize, addrs: Vec<usize>) -> bool {
+        match self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+            Some(bp) => {
+                bp.addrs = addrs;
+                bp.pending = false;
+                bp.is_inserted()
+            }
+            None => false,
+        }
+    }
+
+    /// Makes the breakpoints inside `image`, code that was unloaded or replaced by an exec,
+    /// pending again. Returns the addresses they were at.
+    pub fn unresolve(&mut self, image: Range<usize>) -> Vec<usize> {
+        let mut addrs = Vec::new();
+        for bp in self.breakpoints.iter_mut() {
+            if bp.is_software() && !bp.pending && bp.addrs.iter().any(|addr| image.contains(addr)) {
+                addrs.append(&mut bp.addrs);
+                bp.pending = true;
+            }
+        }
+        addrs
+    }
+
+    /// Sets the address of the dynamic linker's load notification breakpoint.
+    pub fn set_shlib_event(&mut self, addr: Option<usize>) {
+        self.shlib_event = addr;
+    }
+
+    /// Returns whether `addr` is the dynamic linker's load notification breakpoint.
+    pub fn is_shlib_event(&self, addr: usize) -> bool {
+        self.shlib_event == Some(addr)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|bp| bp.id == id)
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Breakpoint> {
+        let index = self.breakpoints.iter().position(|bp| bp.id == id)?;
+        Some(self.breakpoints.remove(index))
+    }
+
+    /// Enables or disables a breakpoint. Returns None if there is no such id.
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) -> Option<&Breakpoint> {
+        let bp = self.breakpoints.iter_mut().find(|bp| bp.id == id)?;
+        bp.enabled = enabled;
+        Some(bp)
+    }
+
+    /// Returns whether `addr` needs a 0xcc: an enabled breakpoint (not watchpoint) is set there,
+    /// or it is the shared library event breakpoint.
+    pub fn is_enabled_at(&self, addr: usize) -> bool {
+        self.is_shlib_event(addr)
+            || self
+                .breakpoints
+                .iter()
+                .any(|bp| bp.is_inserted() && bp.addrs.contains(&addr))
+    }
+
+    /// Returns the addresses that need a 0xcc in the inferior.
+    pub fn enabled_addresses(&self) -> Vec<usize> {
+        let mut addrs: Vec<usize> = self
+            .breakpoints
+            .iter()
+            .filter(|bp| bp.is_inserted())
+            .flat_map(|bp| bp.addrs.iter().copied())
+            .chain(self.shlib_event)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    /// Returns the `(addr, len, kind)` of the enabled watchpoints. A watchpoint's index in this
+    /// list is its debug register slot.
+    pub fn enabled_watchpoints(&self) -> Vec<(usize, usize, WatchKind)> {
+        self.breakpoints
+            .iter()
+            .filter(|bp| bp.enabled)
+            .filter_map(|bp| {
+                let watch = bp.watch.as_ref()?;
+                Some((bp.addrs[0], watch.entity_type.size, watch.kind))
+            })
+            .collect()
+    }
+
+    /// Moves every breakpoint and watchpoint inside `image` by `delta`, e.g. after a position
+    /// independent executable was loaded at a different address.
+    pub fn rebase(&mut self, image: Range<usize>, delta: usize) {
+        for bp in self.breakpoints.iter_mut() {
+            for addr in bp.addrs.iter_mut().filter(|addr| image.contains(addr)) {
+                *addr = addr.wrapping_add(delta);
+            }
+        }
+    }
+
+    /// Rereads the value of every watchpoint, e.g. after the program was restarted.
+    pub fn refresh_watchpoints(
+        &mut self,
+        mut read: impl FnMut(usize, usize) -> Result<Vec<u8>, nix::Error>,
+    ) {
+        for bp in self.breakpoints.iter_mut() {
+            if let Some(watch) = bp.watch.as_mut() {
+                if let Ok(value) = read(bp.addrs[0], watch.entity_type.size) {
+                    watch.old_value = value.clone();
+                    watch.value = value;
+                }
+            }
+        }
+    }
+
+    /// Sets how many hits of a breakpoint to ignore. Returns false if there is no such id.
+    pub fn set_ignore_count(&mut self, id: usize, count: usize) -> bool {
+        match self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+            Some(bp) => {
+                bp.ignore_count = count;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Decides whether a trap at `addr` should stop the inferior. As in gdb, a breakpoint whose
+    /// condition is false isn't hit at all, while an ignored hit still counts. A condition that
+    /// can't be evaluated counts as true, so the hit is counted and stops the inferior unless it
+    /// is ignored. The shared library event always stops it, without counting as a hit, so the
+    /// debugger can load symbols.
+    pub fn should_stop(
+        &mut self,
+        addr: usize,
+        mut evaluate: impl FnMut(&Condition) -> Result<bool, String>,
+    ) -> bool {
+        self.last_hits.clear();
+        for bp in self
+            .breakpoints
+            .iter_mut()
+            .filter(|bp| bp.is_inserted() && bp.addrs.contains(&addr))
+        {
+            if bp.hit(&mut evaluate) {
+                self.last_hits.push(bp.id);
+            }
+        }
+        !self.last_hits.is_empty() || self.shlib_event == Some(addr)
+    }
+
+    /// Decides whether the watchpoint in debug register `slot` should stop the inferior, reading
+    /// its new value with `read`. Like gdb, a write watchpoint doesn't stop when the value
+    /// written is the same as before. x86 can't trap on reads alone, so a read watchpoint also
+    /// traps on writes; it doesn't stop when the value changed, as that was a write.
+    pub fn should_stop_watch(
+        &mut self,
+        slot: usize,
+        mut read: impl FnMut(usize, usize) -> Result<Vec<u8>, nix::Error>,
+        mut evaluate: impl FnMut(&Condition) -> Result<bool, String>,
+    ) -> bool {
+        self.last_hits.clear();
+        let bp = match self
+            .breakpoints
+            .iter_mut()
+            .filter(|bp| bp.enabled && !bp.is_software())
+            .nth(slot)
+        {
+            Some(bp) => bp,
+            // a debug register we didn't set; let the user look into it
+            None => return true,
+        };
+        let watch = bp.watch.as_mut().unwrap();
+        let value = match read(bp.addrs[0], watch.entity_type.size) {
+            Ok(value) => value,
+            Err(_) => watch.value.clone(),
+        };
+        match watch.kind {
+            WatchKind::Write if value == watch.value => return false,
+            WatchKind::Read if value != watch.value => {
+                watch.value = value;
+                return false;
+            }
+            _ => {}
+        }
+        watch.old_value = std::mem::replace(&mut watch.value, value);
+        if bp.hit(&mut evaluate) {
+            self.last_hits.push(bp.id);
+        }
+        !self.last_hits.is_empty()
+    }
+
+    /// Returns whether the last trap stopped the inferior.
+    pub fn has_last_hits(&self) -> bool {
+        !self.last_hits.is_empty()
+    }
+
+    /// Returns the breakpoints that caused the last stop, and forgets them.
+    pub fn take_last_hits(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.last_hits)
+    }
+
+    pub fn ids(&self) -> Vec<usize> {
+        self.breakpoints.iter().map(|bp| bp.id).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(text: &str) -> Option<Condition> {
+        Some(Condition {
+            text: text.to_string(),
+            expression: expression::parse(text, &|_| false).unwrap(),
+        })
+    }
+
+    #[test]
+    fn ids_are_not_reused() {
+        let mut table = BreakpointTable::default();
+        assert_eq!(table.add("main".to_string(), vec![0x1000], None), 1);
+        assert_eq!(table.add("foo".to_string(), vec![0x1100], None), 2);
+        assert_eq!(table.remove(2).unwrap().location, "foo");
+        assert!(table.remove(2).is_none());
+        assert_eq!(table.add("bar".to_string(), vec![0x1200], None), 3);
+        assert_eq!(table.ids(), vec![1, 3]);
+    }
+
+    #[test]
+    fn disabled_breakpoints_dont_stop() {
+        let mut table = BreakpointTable::default();
+        let first = table.add("main".to_string(), vec![0x1000], None);
+        let second = table.add("*0x1000".to_string(), vec![0x1000], None);
+        table.set_enabled(first, false).unwrap();
+        assert!(table.is_enabled_at(0x1000));
+        assert!(table.should_stop(0x1000, |_| Ok(true)));
+        assert_eq!(table.take_last_hits(), vec![second]);
+        assert_eq!(table.get(first).unwrap().hit_count, 0);
+
+        table.set_enabled(second, false).unwrap();
+        assert!(!table.is_enabled_at(0x1000));
+        assert!(table.enabled_addresses().is_empty());
+        assert!(!table.should_stop(0x1000, |_| Ok(true)));
+        assert!(table.set_enabled(3, true).is_none());
+    }
+
+    #[test]
+    fn info_breakpoints_rows() {
+        let mut table = BreakpointTable::default();
+        let id = table.add("main".to_string(), vec![0x401136], condition("argc > 1"));
+        table.set_ignore_count(id, 2);
+        let id = table.add("foo.c:10".to_string(), vec![0x401150, 0x401190], None);
+        table.set_enabled(id, false);
+        table.add_pending("bar".to_string(), None);
+        let rows: Vec<String> = table.iter().map(|bp| bp.to_string()).collect();
+        assert_eq!(
+            rows,
+            vec![
+                "1       breakpoint      y   0x0000000000401136 0     main\n\
+                 \tstop only if argc > 1\n\
+                 \tWill ignore next 2 crossings of breakpoint.",
+                "2       breakpoint      n   <MULTIPLE>         0     foo.c:10\n\
+                 2.1                         0x0000000000401150\n\
+                 2.2                         0x0000000000401190",
+                "3       breakpoint      y   <PENDING>          0     bar",
+            ]
+        );
+    }
+
+    #[test]
+    fn false_conditions_are_not_hits() {
+        let mut table = BreakpointTable::default();
+        let id = table.add("main".to_string(), vec![0x1000], condition("x"));
+        assert!(!table.should_stop(0x1000, |_| Ok(false)));
+        assert_eq!(table.get(id).unwrap().hit_count, 0);
+        assert!(table.should_stop(0x1000, |_| Ok(true)));
+        assert_eq!(table.take_last_hits(), vec![id]);
+        assert_eq!(table.get(id).unwrap().hit_count, 1);
+    }
+
+    #[test]
+    fn ignored_hits_are_counted() {
+        let mut table = BreakpointTable::default();
+        let id = table.add("main".to_string(), vec![0x1000], None);
+        table.set_ignore_count(id, 2);
+        assert!(!table.should_stop(0x1000, |_| Ok(true)));
+        assert!(!table.should_stop(0x1000, |_| Ok(true)));
+        assert!(!table.has_last_hits());
+        assert!(table.should_stop(0x1000, |_| Ok(true)));
+        let bp = table.get(id).unwrap();
+        assert_eq!((bp.hit_count, bp.ignore_count), (3, 0));
+    }
+
+    #[test]
+    fn conditions_that_fail_to_evaluate_are_hits() {
+        let mut table = BreakpointTable::default();
+        let id = table.add("main".to_string(), vec![0x1000], condition("*p"));
+        table.set_ignore_count(id, 1);
+        let error = |_: &Condition| Err("Cannot access memory at address 0x0".to_string());
+        assert!(!table.should_stop(0x1000, error));
+        assert_eq!(table.get(id).unwrap().hit_count, 1);
+        assert!(table.should_stop(0x1000, error));
+        assert_eq!(table.get(id).unwrap().hit_count, 2);
+    }
+
+    #[test]
+    fn watchpoints_stop_on_their_own_kind_of_access() {
+        let int = Type::from_c_name("int").unwrap();
+        let mut table = BreakpointTable::default();
+        let read = table.add_watchpoint(
+            "r".to_string(),
+            0x2000,
+            WatchKind::Read,
+            int.clone(),
+            vec![1, 0, 0, 0],
+        );
+        let write = table.add_watchpoint(
+            "w".to_string(),
+            0x2004,
+            WatchKind::Write,
+            int,
+            vec![1, 0, 0, 0],
+        );
+        let value = |bytes: [u8; 4]| move |_, _| Ok(bytes.to_vec());
+        let watch = |table: &BreakpointTable, id| table.get(id).unwrap().watch.clone().unwrap();
+
+        // a write to a read watchpoint changes the value but doesn't stop
+        assert!(!table.should_stop_watch(0, value([2, 0, 0, 0]), |_| Ok(true)));
+        assert_eq!(watch(&table, read).value, vec![2, 0, 0, 0]);
+        assert_eq!(table.get(read).unwrap().hit_count, 0);
+        assert!(table.should_stop_watch(0, value([2, 0, 0, 0]), |_| Ok(true)));
+        assert_eq!(table.take_last_hits(), vec![read]);
+        assert_eq!(table.get(read).unwrap().hit_count, 1);
+
+        // writing the same value to a write watchpoint doesn't stop
+        assert!(!table.should_stop_watch(1, value([1, 0, 0, 0]), |_| Ok(true)));
+        assert_eq!(table.get(write).unwrap().hit_count, 0);
+        assert!(table.should_stop_watch(1, value([3, 0, 0, 0]), |_| Ok(true)));
+        assert_eq!(table.take_last_hits(), vec![write]);
+        let watch = watch(&table, write);
+        assert_eq!(
+            (watch.old_value, watch.value),
+            (vec![1, 0, 0, 0], vec![3, 0, 0, 0])
+        );
+        assert_eq!(table.get(write).unwrap().hit_count, 1);
+    }
+}
@@ -0,0 +1,337 @@
+//! Calls functions in the inferior, for expressions like `strlen(buf)`. Arguments are passed and
+//! results taken back the way the System V x86-64 ABI says, and the thread is put back the way it
+//! was afterwards, whether or not the function returned.
+
+use crate::dwarf_data::{Encoding, Kind, Type};
+use crate::inferior::{Inferior, Status};
+use crate::target::Target;
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+
+/// How many arguments go in general purpose registers: rdi, rsi, rdx, rcx, r8 and r9.
+const INTEGER_REGISTERS: usize = 6;
+
+/// How many arguments go in vector registers: xmm0 to xmm7.
+const SSE_REGISTERS: usize = 8;
+
+/// The bytes below the stack pointer a function may keep data in without moving it.
+const RED_ZONE: u64 = 128;
+
+/// How the ABI passes an eightbyte of a value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Class {
+    Integer,
+    Sse,
+}
+
+/// How a call ended.
+pub enum Outcome {
+    /// The function returned these bytes, laid out as its return type; none for void
+    Returned(Vec<u8>),
+    /// The program got this signal before the function returned. The thread has been put back
+    /// the way it was before the call.
+    Interrupted(Signal),
+    /// The program exited, was killed or ran another program during the call
+    Ended(Status),
+}
+
+/// Works out how a value of `entity_type` is passed: in registers, with the class of each of its
+/// eightbytes, or None if in memory.
+fn classify(entity_type: &Type) -> Option<Vec<Class>> {
+    if entity_type.size == 0 || entity_type.size > 16 {
+        return None;
+    }
+    let mut classes = vec![None; entity_type.size.div_ceil(8)];
+    if !classify_part(entity_type, 0, &mut classes) {
+        return None;
+    }
+    // an eightbyte of nothing but padding can go anywhere
+    Some(
+        classes
+            .into_iter()
+            .map(|class| class.unwrap_or(Class::Integer))
+            .collect(),
+    )
+}
+
+/// Merges the classes of the part of a value `offset` bytes in into those of the eightbytes it
+/// is in. Returns false if the part makes the value go in memory, like a misaligned field does.
+// is_multiple_of needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn classify_part(entity_type: &Type, offset: usize, classes: &mut [Option<Class>]) -> bool {
+    match &entity_type.kind {
+        Kind::Struct(members) => members.iter().all(|member| match member.bits {
+            Some((start, _)) => merge(classes, offset + member.offset + start / 8, Class::Integer),
+            None => classify_part(&member.entity_type, offset + member.offset, classes),
+        }),
+        Kind::Array(element, count) => (0..count.unwrap_or(0))
+            .all(|i| classify_part(element, offset + i * element.size, classes)),
+        Kind::Function(_) => false,
+        _ if entity_type.size == 0 || offset % entity_type.size != 0 => false,
+        // long double goes in x87 registers, which never hold arguments
+        _ if entity_type.encoding == Encoding::Float && entity_type.size > 8 => false,
+        _ if entity_type.encoding == Encoding::Float => merge(classes, offset, Class::Sse),
+        _ => merge(classes, offset, Class::Integer),
+    }
+}
+
+/// Adds `class` to that of the eightbyte at `offset`. Integer wins over SSE.
+fn merge(classes: &mut [Option<Class>], offset: usize, class: Class) -> bool {
+    match classes.get_mut(offset / 8) {
+        Some(slot) => {
+            if *slot != Some(Class::Integer) {
+                *slot = Some(class);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Reads up to 8 bytes as a little endian word.
+fn word(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+    u64::from_le_bytes(word)
+}
+
+/// Sign-extends a signed integer to a whole register, which callees that take a wider type
+/// than the prototype-less caller passed, like `labs(-5)`, rely on.
+fn widen(entity_type: &Type, bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    if let (Kind::Base | Kind::Enum(_), Encoding::Signed | Encoding::Char) =
+        (&entity_type.kind, entity_type.encoding)
+    {
+        if (1..8).contains(&bytes.len()) && bytes[bytes.len() - 1] & 0x80 != 0 {
+            bytes.resize(8, 0xff);
+        }
+    }
+    bytes
+}
+
+/// Copies `bytes` onto the stack below `sp`, keeping it 16 byte aligned. Returns the new stack
+/// pointer, which is where they are.
+fn push(inferior: &mut Inferior, sp: u64, bytes: &[u8]) -> Result<u64, nix::Error> {
+    let sp = (sp - bytes.len() as u64) & !15;
+    inferior.write_memory(sp as usize, bytes)?;
+    Ok(sp)
+}
+
+/// Calls the function at `addr` in the current thread with `arguments`, each laid out as its
+/// type, and returns what it returns as `returns`, or nothing for void. Arrays are copied onto
+/// the stack and passed by address. The function returns to `return_addr`, where a breakpoint
+/// catches it, so that has to be somewhere no code runs again, like the entry point.
+/// Breakpoints and watchpoints don't stop the call.
+pub fn call(
+    inferior: &mut Inferior,
+    addr: usize,
+    return_addr: usize,
+    arguments: &[(Type, Vec<u8>)],
+    returns: Option<&Type>,
+) -> Result<Outcome, nix::Error> {
+    let thread = inferior.current_thread();
+    let regs = inferior.get_registers()?;
+    let fpregs = inferior.get_fp_registers()?;
+    // a signal the thread is yet to be delivered waits until after the call
+    let signal = inferior.replace_signal(None);
+    let outcome = run(
+        inferior,
+        addr,
+        return_addr,
+        regs,
+        fpregs,
+        arguments,
+        returns,
+    );
+    if let Ok(Outcome::Ended(_)) = outcome {
+        return outcome;
+    }
+    // put the thread back even if setting up or running the call failed part way, and report
+    // that failure rather than any from restoring
+    inferior.select_thread(thread);
+    let restored = inferior
+        .set_registers(regs)
+        .and_then(|_| inferior.set_fp_registers(&fpregs));
+    // one the call got is dropped with it
+    inferior.replace_signal(signal);
+    outcome.and_then(|outcome| restored.map(|_| outcome))
+}
+
+/// Sets up the registers and stack for the call, runs it and collects the return value.
+fn run(
+    inferior: &mut Inferior,
+    addr: usize,
+    return_addr: usize,
+    mut regs: user_regs_struct,
+    mut fpregs: user_fpregs_struct,
+    arguments: &[(Type, Vec<u8>)],
+    returns: Option<&Type>,
+) -> Result<Outcome, nix::Error> {
+    let mut sp = regs.rsp - RED_ZONE;
+    let mut integers = Vec::new();
+    let mut vectors = Vec::new();
+    let mut stack = Vec::new();
+    // a value returned in memory goes where a hidden first argument points
+    let return_buffer = match returns {
+        Some(returns) if classify(returns).is_none() => {
+            sp = push(inferior, sp, &vec![0; returns.size])?;
+            integers.push(sp);
+            Some(sp as usize)
+        }
+        _ => None,
+    };
+    for (entity_type, bytes) in arguments {
+        let (classes, bytes) = match entity_type.kind {
+            Kind::Array(..) => {
+                sp = push(inferior, sp, bytes)?;
+                (Some(vec![Class::Integer]), sp.to_le_bytes().to_vec())
+            }
+            _ => (classify(entity_type), widen(entity_type, bytes)),
+        };
+        // a value goes in memory if there aren't enough registers left for all of it
+        let fits = |classes: &Vec<Class>| {
+            let needed = |class| classes.iter().filter(|c| **c == class).count();
+            integers.len() + needed(Class::Integer) <= INTEGER_REGISTERS
+                && vectors.len() + needed(Class::Sse) <= SSE_REGISTERS
+        };
+        match classes {
+            Some(classes) if fits(&classes) => {
+                for (class, chunk) in classes.iter().zip(bytes.chunks(8)) {
+                    match class {
+                        Class::Integer => integers.push(word(chunk)),
+                        Class::Sse => vectors.push(word(chunk)),
+                    }
+                }
+            }
+            _ => {
+                stack.extend_from_slice(&bytes);
+                stack.resize((stack.len() + 7) & !7, 0);
+            }
+        }
+    }
+    // the arguments in memory start right above the return address, at a 16 byte boundary
+    sp = push(inferior, sp, &stack)?;
+    sp -= 8;
+    inferior.write_memory(sp as usize, &(return_addr as u64).to_le_bytes())?;
+
+    let registers = vec![
+        &mut regs.rdi,
+        &mut regs.rsi,
+        &mut regs.rdx,
+        &mut regs.rcx,
+        &mut regs.r8,
+        &mut regs.r9,
+    ];
+    for (register, value) in registers.into_iter().zip(integers) {
+        *register = value;
+    }
+    for (i, value) in vectors.iter().enumerate() {
+        let xmm = [*value as u32, (*value >> 32) as u32, 0, 0];
+        fpregs.xmm_space[i * 4..i * 4 + 4].copy_from_slice(&xmm);
+    }
+    // variadic functions are told how many vector registers hold arguments
+    regs.rax = vectors.len() as u64;
+    regs.rsp = sp;
+    regs.rip = addr as u64;
+    // the direction flag is clear on entry to a function
+    regs.eflags &= !0x400;
+    // keep the kernel from restarting a system call the thread was stopped in
+    regs.orig_rax = u64::MAX;
+    inferior.set_registers(regs)?;
+    inferior.set_fp_registers(&fpregs)?;
+
+    match inferior.run_until(return_addr, sp as usize, &mut |_, _| false)? {
+        Status::Stopped(Signal::SIGTRAP, rip) if rip == return_addr => {}
+        Status::Stopped(signal, _) => return Ok(Outcome::Interrupted(signal)),
+        status => return Ok(Outcome::Ended(status)),
+    }
+    let returns = match returns {
+        Some(returns) => returns,
+        None => return Ok(Outcome::Returned(Vec::new())),
+    };
+    if let Some(buffer) = return_buffer {
+        return Ok(Outcome::Returned(
+            inferior.read_memory(buffer, returns.size)?,
+        ));
+    }
+    let regs = inferior.get_registers()?;
+    let fpregs = inferior.get_fp_registers()?;
+    let mut integers = vec![regs.rax, regs.rdx].into_iter();
+    let mut vectors =
+        (0..2).map(|i| fpregs.xmm_space[i * 4] as u64 | (fpregs.xmm_space[i * 4 + 1] as u64) << 32);
+    let bytes = classify(returns)
+        .unwrap_or_default()
+        .iter()
+        .flat_map(|class| {
+            let value = match class {
+                Class::Integer => integers.next(),
+                Class::Sse => vectors.next(),
+            };
+            value.unwrap_or(0).to_le_bytes()
+        })
+        .collect();
+    Ok(Outcome::Returned(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf_data::Member;
+
+    fn structure(size: usize, members: &[(usize, &str)]) -> Type {
+        Type {
+            name: "struct s".to_string(),
+            size,
+            encoding: Encoding::Unsigned,
+            kind: Kind::Struct(
+                members
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (offset, name))| Member {
+                        name: format!("m{}", i),
+                        offset: *offset,
+                        bits: None,
+                        entity_type: Type::from_c_name(name).unwrap(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn floats_share_an_sse_eightbyte() {
+        let pair = structure(8, &[(0, "float"), (4, "float")]);
+        assert_eq!(classify(&pair), Some(vec![Class::Sse]));
+    }
+
+    #[test]
+    fn each_eightbyte_gets_its_own_class() {
+        let mixed = structure(16, &[(0, "int"), (8, "double")]);
+        assert_eq!(classify(&mixed), Some(vec![Class::Integer, Class::Sse]));
+        // integer wins when both are in one eightbyte
+        let shared = structure(16, &[(0, "float"), (4, "int"), (8, "double")]);
+        assert_eq!(classify(&shared), Some(vec![Class::Integer, Class::Sse]));
+    }
+
+    #[test]
+    fn large_or_misaligned_structs_go_in_memory() {
+        let large = structure(24, &[(0, "long"), (8, "long"), (16, "long")]);
+        assert_eq!(classify(&large), None);
+        let packed = structure(5, &[(0, "char"), (1, "int")]);
+        assert_eq!(classify(&packed), None);
+    }
+
+    #[test]
+    fn scalars_take_one_eightbyte() {
+        assert_eq!(
+            classify(&Type::from_c_name("char *").unwrap()),
+            Some(vec![Class::Integer])
+        );
+        assert_eq!(
+            classify(&Type::from_c_name("double").unwrap()),
+            Some(vec![Class::Sse])
+        );
+        let long_double = Type::new("long double".to_string(), 16, Encoding::Float);
+        assert_eq!(classify(&long_double), None);
+    }
+}
@@ -0,0 +1,787 @@
+use crate::inferior::Inferior;
+use crate::target::Target;
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use object::elf::{
+    FileHeader64, Ident, NoteHeader64, ProgramHeader64, ELFCLASS64, ELFDATA2LSB, ELFMAG,
+    ELFOSABI_NONE, EM_X86_64, ET_CORE, EV_CURRENT, NT_AUXV, NT_FILE, NT_FPREGSET, NT_PRPSINFO,
+    NT_PRSTATUS, PF_R, PF_W, PF_X, PT_LOAD, PT_NOTE,
+};
+use object::read::elf::{FileHeader, ProgramHeader};
+use object::{Endianness, LittleEndian as LE, U16, U32, U64};
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::mem::size_of;
+use std::os::unix::fs::FileExt;
+
+/// Sizes of and offsets into the kernel's struct elf_prstatus and struct elf_prpsinfo on
+/// x86-64.
+const PRSTATUS_SIZE: usize = 336;
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REGS: usize = 112;
+const PRPSINFO_SIZE: usize = 136;
+const PRPSINFO_SNAME: usize = 1;
+const PRPSINFO_UID: usize = 16;
+const PRPSINFO_PID: usize = 24;
+const PRPSINFO_FNAME: usize = 40;
+const FNAME_LEN: usize = 16;
+const PRPSINFO_PSARGS: usize = 56;
+const PSARGS_LEN: usize = 80;
+
+const PAGE_SIZE: usize = 4096;
+
+/// A PT_LOAD segment: memory the kernel dumped, of which the first `file_size` bytes are in the
+/// core file at `offset`. The rest wasn't dumped, usually because it maps a file unchanged.
+struct Segment {
+    start: usize,
+    mem_size: usize,
+    file_size: usize,
+    offset: usize,
+}
+
+/// A file mapped into the process, from the NT_FILE note.
+struct MappedFile {
+    start: usize,
+    end: usize,
+    /// The offset in the file the mapping starts at
+    offset: usize,
+    path: String,
+}
+
+/// The state of a crashed process as saved in an ELF core dump. Registers come from the
+/// NT_PRSTATUS note of each thread and memory from the PT_LOAD segments, falling back on the
+/// mapped files for what the kernel left out of the dump, such as code.
+pub struct CoreFile {
+    data: memmap::Mmap,
+    pid: Pid,
+    /// The LWP and registers of each thread. The first one is the thread that got the signal.
+    threads: Vec<(Pid, user_regs_struct, Option<user_fpregs_struct>)>,
+    /// The index of the thread registers are read from
+    current: usize,
+    signal: Option<Signal>,
+    command: String,
+    segments: Vec<Segment>,
+    files: Vec<MappedFile>,
+    entry: Option<usize>,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + size_of::<u64>())?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> Option<i32> {
+    let bytes = bytes.get(offset..offset + size_of::<i32>())?;
+    Some(i32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads the registers out of an NT_PRSTATUS note, whose pr_reg has the layout of
+/// user_regs_struct.
+fn prstatus_registers(desc: &[u8]) -> Option<user_regs_struct> {
+    let regs = desc.get(PRSTATUS_REGS..PRSTATUS_REGS + size_of::<user_regs_struct>())?;
+    Some(unsafe { std::ptr::read_unaligned(regs.as_ptr() as *const user_regs_struct) })
+}
+
+/// Parses an NT_FILE note: a count and a page size, then the start, end and page offset of each
+/// mapping, then their paths.
+fn parse_mapped_files(desc: &[u8]) -> Option<Vec<MappedFile>> {
+    let count = read_u64(desc, 0)? as usize;
+    let page_size = read_u64(desc, 8)? as usize;
+    let names_offset = 16 + count.checked_mul(24)?;
+    let mut names = desc.get(names_offset..)?.split(|byte| *byte == 0);
+    let mut files = Vec::with_capacity(count);
+    for i in 0..count {
+        let entry = 16 + i * 24;
+        files.push(MappedFile {
+            start: read_u64(desc, entry)? as usize,
+            end: read_u64(desc, entry + 8)? as usize,
+            offset: read_u64(desc, entry + 16)? as usize * page_size,
+            path: String::from_utf8_lossy(names.next()?).into_owned(),
+        });
+    }
+    Some(files)
+}
+
+impl CoreFile {
+    /// Reads the core dump at `path`.
+    pub fn open(path: &str) -> Result<CoreFile, Error> {
+        let file = File::open(path)?;
+        let mut core = CoreFile {
+            data: unsafe { memmap::Mmap::map(&file)? },
+            pid: Pid::from_raw(0),
+            threads: Vec::new(),
+            current: 0,
+            signal: None,
+            command: String::new(),
+            segments: Vec::new(),
+            files: Vec::new(),
+            entry: None,
+        };
+        let data: &[u8] = &core.data;
+        let header = FileHeader64::<Endianness>::parse(data)
+            .map_err(|_| invalid("not a 64-bit ELF file"))?;
+        let endian = header.endian().map_err(|_| invalid("unknown byte order"))?;
+        if header.e_type(endian) != ET_CORE {
+            return Err(invalid("not a core dump"));
+        }
+        let program_headers = header
+            .program_headers(endian, data)
+            .map_err(|_| invalid("bad program headers"))?;
+        for program_header in program_headers {
+            if program_header.p_type(endian) == PT_LOAD {
+                core.segments.push(Segment {
+                    start: program_header.p_vaddr(endian) as usize,
+                    mem_size: program_header.p_memsz(endian) as usize,
+                    file_size: program_header.p_filesz(endian) as usize,
+                    offset: program_header.p_offset(endian) as usize,
+                });
+            }
+            let mut notes = match program_header.notes(endian, data) {
+                Ok(Some(notes)) => notes,
+                Ok(None) => continue,
+                Err(_) => return Err(invalid("bad note segment")),
+            };
+            while let Ok(Some(note)) = notes.next() {
+                if note.name() != b"CORE" {
+                    continue;
+                }
+                let desc = note.desc();
+                match note.n_type(endian) {
+                    NT_PRSTATUS => {
+                        let (tid, regs) =
+                            match (read_i32(desc, PRSTATUS_PID), prstatus_registers(desc)) {
+                                (Some(tid), Some(regs)) => (tid, regs),
+                                _ => return Err(invalid("truncated NT_PRSTATUS note")),
+                            };
+                        if core.threads.is_empty() {
+                            let signal = desc.get(PRSTATUS_CURSIG).copied().unwrap_or(0);
+                            core.signal = Signal::try_from(signal as i32).ok();
+                            core.pid = Pid::from_raw(tid);
+                        }
+                        core.threads.push((Pid::from_raw(tid), regs, None));
+                    }
+                    // the floating point registers of the thread whose NT_PRSTATUS came last
+                    NT_FPREGSET if desc.len() >= size_of::<user_fpregs_struct>() => {
+                        if let Some(thread) = core.threads.last_mut() {
+                            thread.2 = Some(unsafe {
+                                std::ptr::read_unaligned(desc.as_ptr() as *const user_fpregs_struct)
+                            });
+                        }
+                    }
+                    NT_PRPSINFO => {
+                        if let Some(pid) = read_i32(desc, PRPSINFO_PID) {
+                            core.pid = Pid::from_raw(pid);
+                        }
+                        let args = desc
+                            .get(PRPSINFO_PSARGS..PRPSINFO_PSARGS + PSARGS_LEN)
+                            .unwrap_or_default();
+                        let len = args.iter().position(|b| *b == 0).unwrap_or(args.len());
+                        core.command = String::from_utf8_lossy(&args[..len]).trim().to_string();
+                    }
+                    NT_AUXV => {
+                        core.entry = desc
+                            .chunks_exact(16)
+                            .find(|pair| read_u64(pair, 0) == Some(libc::AT_ENTRY))
+                            .and_then(|pair| read_u64(pair, 8))
+                            .map(|entry| entry as usize);
+                    }
+                    NT_FILE => {
+                        core.files = parse_mapped_files(desc)
+                            .ok_or_else(|| invalid("truncated NT_FILE note"))?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if core.threads.is_empty() {
+            return Err(invalid("no NT_PRSTATUS note"));
+        }
+        Ok(core)
+    }
+
+    /// Returns the command line the process was started with, as far as the core records it.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Returns the signal that killed the process.
+    pub fn signal(&self) -> Option<Signal> {
+        self.signal
+    }
+
+    /// Copies memory at `addr` into `buf` from a single segment or mapped file. Returns how many
+    /// bytes it could copy from there, or None if `addr` wasn't dumped and isn't in a file.
+    fn read_chunk(&self, addr: usize, buf: &mut [u8]) -> Option<usize> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|segment| addr >= segment.start && addr - segment.start < segment.mem_size);
+        if let Some(segment) = segment {
+            let skip = addr - segment.start;
+            if skip < segment.file_size {
+                let len = buf.len().min(segment.file_size - skip);
+                let start = segment.offset + skip;
+                buf[..len].copy_from_slice(self.data.get(start..start + len)?);
+                return Some(len);
+            }
+        }
+        let file = self
+            .files
+            .iter()
+            .find(|file| addr >= file.start && addr < file.end)?;
+        let len = buf.len().min(file.end - addr);
+        File::open(&file.path)
+            .and_then(|f| {
+                f.read_exact_at(&mut buf[..len], (file.offset + addr - file.start) as u64)
+            })
+            .ok()?;
+        Some(len)
+    }
+}
+
+impl Target for CoreFile {
+    fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    fn threads(&self) -> Vec<(usize, Pid)> {
+        self.threads
+            .iter()
+            .enumerate()
+            .map(|(index, (tid, _, _))| (index + 1, *tid))
+            .collect()
+    }
+
+    fn current_thread(&self) -> usize {
+        self.current + 1
+    }
+
+    fn select_thread(&mut self, id: usize) -> bool {
+        if id == 0 || id > self.threads.len() {
+            return false;
+        }
+        self.current = id - 1;
+        true
+    }
+
+    /// Returns the file mapped where the program's entry point is.
+    fn executable_path(&self) -> Result<String, Error> {
+        let entry = self
+            .entry
+            .ok_or_else(|| invalid("the core has no auxiliary vector"))?;
+        self.files
+            .iter()
+            .find(|file| entry >= file.start && entry < file.end)
+            .map(|file| file.path.clone())
+            .ok_or_else(|| invalid("the core doesn't say which file the executable is"))
+    }
+
+    fn mapped_objects(&self) -> Result<Vec<(String, usize)>, Error> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|file| file.offset == 0)
+            .map(|file| (file.path.clone(), file.start))
+            .collect())
+    }
+
+    fn get_registers(&self) -> Result<user_regs_struct, nix::Error> {
+        Ok(self.threads[self.current].1)
+    }
+
+    fn get_fp_registers(&self) -> Result<user_fpregs_struct, nix::Error> {
+        self.threads[self.current].2.ok_or(Errno::ENODATA)
+    }
+
+    fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+        let bytes = self.read_memory(addr, size_of::<usize>())?;
+        Ok(usize::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = vec![0; len];
+        let mut done = 0;
+        while done < len {
+            done += self
+                .read_chunk(addr + done, &mut bytes[done..])
+                .ok_or(Errno::EIO)?;
+        }
+        Ok(bytes)
+    }
+}
+
+/// A region of /proc/<pid>/maps.
+struct Region {
+    start: usize,
+    end: usize,
+    flags: u32,
+    offset: usize,
+    path: String,
+}
+
+impl Region {
+    /// Whether the contents go into a core dump. The kernel refuses reads of [vvar], which is
+    /// only data the vDSO keeps for itself.
+    fn dumped(&self) -> bool {
+        self.flags & PF_R != 0 && !self.path.starts_with("[vvar")
+    }
+}
+
+fn read_maps(pid: Pid) -> Result<Vec<Region>, Error> {
+    let maps = std::fs::read_to_string(format!("/proc/{}/maps", pid))?;
+    let mut regions = Vec::new();
+    for line in maps.lines() {
+        // start-end perms offset dev inode pathname
+        let fields: Vec<&str> = line.splitn(6, ' ').collect();
+        let range = fields[0].split_once('-').and_then(|(start, end)| {
+            Some((
+                usize::from_str_radix(start, 16).ok()?,
+                usize::from_str_radix(end, 16).ok()?,
+            ))
+        });
+        let (start, end) = match range {
+            Some(range) => range,
+            None => continue,
+        };
+        let perms = fields.get(1).copied().unwrap_or_default().as_bytes();
+        let flag = |index: usize, letter: u8, flag: u32| {
+            if perms.get(index) == Some(&letter) {
+                flag
+            } else {
+                0
+            }
+        };
+        regions.push(Region {
+            start,
+            end,
+            flags: flag(0, b'r', PF_R) | flag(1, b'w', PF_W) | flag(2, b'x', PF_X),
+            offset: fields
+                .get(2)
+                .and_then(|offset| usize::from_str_radix(offset, 16).ok())
+                .unwrap_or(0),
+            path: fields
+                .get(5)
+                .map_or("", |path| path.trim_start())
+                .to_string(),
+        });
+    }
+    Ok(regions)
+}
+
+/// Appends a note named CORE, padding the name and contents to 4 bytes as the ELF spec asks.
+fn push_note(notes: &mut Vec<u8>, n_type: u32, desc: &[u8]) {
+    let header = NoteHeader64 {
+        n_namesz: U32::new(LE, 5),
+        n_descsz: U32::new(LE, desc.len() as u32),
+        n_type: U32::new(LE, n_type),
+    };
+    notes.extend_from_slice(object::bytes_of(&header));
+    notes.extend_from_slice(b"CORE\0\0\0\0");
+    notes.extend_from_slice(desc);
+    notes.resize((notes.len() + 3) & !3, 0);
+}
+
+/// Views a plain C struct from libc as the bytes the kernel would write for it.
+fn raw_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+    bytes[offset..offset + value.len()].copy_from_slice(value);
+}
+
+/// Builds the notes the kernel puts in a core: the process info, the registers of each thread,
+/// the auxiliary vector and the mapped files.
+fn build_notes(inferior: &Inferior, regions: &[Region]) -> Result<Vec<u8>, Error> {
+    let pid = inferior.pid();
+    // pid (comm) state ppid pgrp session ...
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    let (comm, stat) = stat
+        .split_once(" (")
+        .and_then(|(_, rest)| rest.rsplit_once(") "))
+        .ok_or_else(|| invalid("bad /proc/<pid>/stat"))?;
+    let stat: Vec<&str> = stat.split(' ').collect();
+    // the ppid, pgrp and sid, which both prstatus and prpsinfo have right after the pid
+    let mut ids = Vec::new();
+    for index in 1..4 {
+        let id = stat.get(index).and_then(|id| id.parse::<i32>().ok());
+        ids.extend_from_slice(&id.unwrap_or(0).to_le_bytes());
+    }
+
+    let mut notes = Vec::new();
+    let mut prpsinfo = vec![0; PRPSINFO_SIZE];
+    put(&mut prpsinfo, PRPSINFO_SNAME, &stat[0].as_bytes()[..1]);
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid))?;
+    let real_id = |key: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .and_then(|ids| ids.split_whitespace().next())
+            .and_then(|id| id.parse::<u32>().ok())
+            .unwrap_or(0)
+    };
+    put(&mut prpsinfo, PRPSINFO_UID, &real_id("Uid:").to_le_bytes());
+    put(
+        &mut prpsinfo,
+        PRPSINFO_UID + 4,
+        &real_id("Gid:").to_le_bytes(),
+    );
+    put(&mut prpsinfo, PRPSINFO_PID, &pid.as_raw().to_le_bytes());
+    put(&mut prpsinfo, PRPSINFO_PID + 4, &ids);
+    let comm = comm.as_bytes();
+    put(
+        &mut prpsinfo,
+        PRPSINFO_FNAME,
+        &comm[..comm.len().min(FNAME_LEN - 1)],
+    );
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid))?;
+    let args: Vec<u8> = cmdline
+        .iter()
+        .take(PSARGS_LEN - 1)
+        .map(|b| if *b == 0 { b' ' } else { *b })
+        .collect();
+    put(&mut prpsinfo, PRPSINFO_PSARGS, &args);
+    push_note(&mut notes, NT_PRPSINFO, &prpsinfo);
+
+    let threads = inferior
+        .thread_states()
+        .map_err(|e| Error::from_raw_os_error(e as i32))?;
+    for thread in threads {
+        let mut prstatus = vec![0; PRSTATUS_SIZE];
+        let signal = thread.signal.map_or(0, |signal| signal as i16);
+        put(&mut prstatus, PRSTATUS_CURSIG, &signal.to_le_bytes());
+        put(
+            &mut prstatus,
+            PRSTATUS_PID,
+            &thread.tid.as_raw().to_le_bytes(),
+        );
+        put(&mut prstatus, PRSTATUS_PID + 4, &ids);
+        put(&mut prstatus, PRSTATUS_REGS, raw_bytes(&thread.regs));
+        push_note(&mut notes, NT_PRSTATUS, &prstatus);
+        push_note(&mut notes, NT_FPREGSET, raw_bytes(&thread.fpregs));
+    }
+
+    let auxv = std::fs::read(format!("/proc/{}/auxv", pid))?;
+    push_note(&mut notes, NT_AUXV, &auxv);
+
+    let files: Vec<&Region> = regions
+        .iter()
+        .filter(|region| region.path.starts_with('/'))
+        .collect();
+    let mut file_note = Vec::new();
+    for value in [files.len(), PAGE_SIZE] {
+        file_note.extend_from_slice(&(value as u64).to_le_bytes());
+    }
+    for file in &files {
+        for value in [file.start, file.end, file.offset / PAGE_SIZE] {
+            file_note.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+    }
+    for file in &files {
+        file_note.extend_from_slice(file.path.as_bytes());
+        file_note.push(0);
+    }
+    push_note(&mut notes, NT_FILE, &file_note);
+    Ok(notes)
+}
+
+fn program_header(
+    p_type: u32,
+    p_flags: u32,
+    offset: usize,
+    addr: usize,
+    file_size: usize,
+    mem_size: usize,
+    align: usize,
+) -> ProgramHeader64<LE> {
+    ProgramHeader64 {
+        p_type: U32::new(LE, p_type),
+        p_flags: U32::new(LE, p_flags),
+        p_offset: U64::new(LE, offset as u64),
+        p_vaddr: U64::new(LE, addr as u64),
+        p_paddr: U64::new(LE, 0),
+        p_filesz: U64::new(LE, file_size as u64),
+        p_memsz: U64::new(LE, mem_size as u64),
+        p_align: U64::new(LE, align as u64),
+    }
+}
+
+/// Saves the state of the stopped inferior as an ELF core dump at `path`: the notes, then each
+/// region of memory as a PT_LOAD segment, with the breakpoints taken out.
+pub fn write_core(inferior: &Inferior, path: &str) -> Result<(), Error> {
+    let regions = read_maps(inferior.pid())?;
+    let notes = build_notes(inferior, &regions)?;
+
+    let header_size = size_of::<FileHeader64<LE>>();
+    let program_header_size = size_of::<ProgramHeader64<LE>>();
+    let header = FileHeader64 {
+        e_ident: Ident {
+            magic: ELFMAG,
+            class: ELFCLASS64,
+            data: ELFDATA2LSB,
+            version: EV_CURRENT,
+            os_abi: ELFOSABI_NONE,
+            abi_version: 0,
+            padding: [0; 7],
+        },
+        e_type: U16::new(LE, ET_CORE),
+        e_machine: U16::new(LE, EM_X86_64),
+        e_version: U32::new(LE, EV_CURRENT as u32),
+        e_entry: U64::new(LE, 0),
+        e_phoff: U64::new(LE, header_size as u64),
+        e_shoff: U64::new(LE, 0),
+        e_flags: U32::new(LE, 0),
+        e_ehsize: U16::new(LE, header_size as u16),
+        e_phentsize: U16::new(LE, program_header_size as u16),
+        e_phnum: U16::new(LE, (regions.len() + 1) as u16),
+        e_shentsize: U16::new(LE, 0),
+        e_shnum: U16::new(LE, 0),
+        e_shstrndx: U16::new(LE, 0),
+    };
+
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(object::bytes_of(&header))?;
+    let notes_offset = header_size + program_header_size * (regions.len() + 1);
+    let note_header = program_header(PT_NOTE, 0, notes_offset, 0, notes.len(), 0, 4);
+    out.write_all(object::bytes_of(&note_header))?;
+    // the memory starts on the page after the notes
+    let data_offset = (notes_offset + notes.len() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let mut offset = data_offset;
+    for region in &regions {
+        let size = region.end - region.start;
+        let file_size = if region.dumped() { size } else { 0 };
+        let load = program_header(
+            PT_LOAD,
+            region.flags,
+            offset,
+            region.start,
+            file_size,
+            size,
+            PAGE_SIZE,
+        );
+        out.write_all(object::bytes_of(&load))?;
+        offset += file_size;
+    }
+    out.write_all(&notes)?;
+    out.write_all(&vec![0; data_offset - notes_offset - notes.len()])?;
+
+    let mem = File::open(format!("/proc/{}/mem", inferior.pid()))?;
+    let mut buf = vec![0; 1 << 20];
+    for region in regions.iter().filter(|region| region.dumped()) {
+        let mut addr = region.start;
+        while addr < region.end {
+            let len = buf.len().min(region.end - addr);
+            let chunk = &mut buf[..len];
+            // like the kernel, leave what can't be read as zeros
+            if mem.read_exact_at(chunk, addr as u64).is_err() {
+                chunk.fill(0);
+            }
+            inferior.hide_breakpoints(addr, chunk);
+            out.write_all(chunk)?;
+            addr += len;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a path for a scratch core file that no other test uses.
+    fn scratch_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("deet-{}-{}.core", name, std::process::id()));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn prstatus(tid: i32, signal: i16, regs: &user_regs_struct) -> Vec<u8> {
+        let mut prstatus = vec![0; PRSTATUS_SIZE];
+        put(&mut prstatus, PRSTATUS_CURSIG, &signal.to_le_bytes());
+        put(&mut prstatus, PRSTATUS_PID, &tid.to_le_bytes());
+        put(&mut prstatus, PRSTATUS_REGS, raw_bytes(regs));
+        prstatus
+    }
+
+    /// Writes a core of process 42 with two threads, the first of which got SIGSEGV, its command
+    /// line, auxiliary vector and two mapped files, and 16 bytes of memory dumped at 0x600000.
+    fn write_small_core(path: &str) {
+        let mut notes = Vec::new();
+        let mut prpsinfo = vec![0; PRPSINFO_SIZE];
+        put(&mut prpsinfo, PRPSINFO_PID, &42i32.to_le_bytes());
+        put(&mut prpsinfo, PRPSINFO_FNAME, b"crash");
+        put(&mut prpsinfo, PRPSINFO_PSARGS, b"./crash --hard ");
+        push_note(&mut notes, NT_PRPSINFO, &prpsinfo);
+
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401010;
+        regs.rsp = 0x7ffc0000;
+        regs.rax = 0xdead;
+        push_note(&mut notes, NT_PRSTATUS, &prstatus(42, 11, &regs));
+        let mut fpregs: user_fpregs_struct = unsafe { std::mem::zeroed() };
+        fpregs.mxcsr = 0x1f80;
+        push_note(&mut notes, NT_FPREGSET, raw_bytes(&fpregs));
+        regs.rip = 0x401234;
+        regs.rax = 7;
+        push_note(&mut notes, NT_PRSTATUS, &prstatus(43, 0, &regs));
+
+        let mut auxv = Vec::new();
+        for value in [libc::AT_PAGESZ, 4096, libc::AT_ENTRY, 0x401000, 0, 0] {
+            auxv.extend_from_slice(&value.to_le_bytes());
+        }
+        push_note(&mut notes, NT_AUXV, &auxv);
+
+        let mut files = Vec::new();
+        for value in [2, PAGE_SIZE, 0x400000, 0x402000, 0, 0x7f0000, 0x7f1000, 1] {
+            files.extend_from_slice(&(value as u64).to_le_bytes());
+        }
+        files.extend_from_slice(b"/usr/bin/crash\0/usr/lib/libcrash.so\0");
+        push_note(&mut notes, NT_FILE, &files);
+
+        let header_size = size_of::<FileHeader64<LE>>();
+        let program_header_size = size_of::<ProgramHeader64<LE>>();
+        let notes_offset = header_size + 2 * program_header_size;
+        let memory_offset = notes_offset + notes.len();
+        let mut header: FileHeader64<LE> = unsafe { std::mem::zeroed() };
+        header.e_ident.magic = ELFMAG;
+        header.e_ident.class = ELFCLASS64;
+        header.e_ident.data = ELFDATA2LSB;
+        header.e_ident.version = EV_CURRENT;
+        header.e_type = U16::new(LE, ET_CORE);
+        header.e_machine = U16::new(LE, EM_X86_64);
+        header.e_phoff = U64::new(LE, header_size as u64);
+        header.e_ehsize = U16::new(LE, header_size as u16);
+        header.e_phentsize = U16::new(LE, program_header_size as u16);
+        header.e_phnum = U16::new(LE, 2);
+
+        let mut core = Vec::new();
+        core.extend_from_slice(object::bytes_of(&header));
+        let note_header = program_header(PT_NOTE, 0, notes_offset, 0, notes.len(), 0, 4);
+        core.extend_from_slice(object::bytes_of(&note_header));
+        let load = program_header(PT_LOAD, PF_R | PF_W, memory_offset, 0x600000, 16, 0x1000, 1);
+        core.extend_from_slice(object::bytes_of(&load));
+        core.extend_from_slice(&notes);
+        core.extend((0..16).map(|i| i as u8 * 3));
+        std::fs::write(path, core).unwrap();
+    }
+
+    #[test]
+    fn reads_notes_of_hand_built_core() {
+        let path = scratch_path("notes");
+        write_small_core(&path);
+        let mut core = CoreFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(core.pid(), Pid::from_raw(42));
+        assert_eq!(core.command(), "./crash --hard");
+        assert_eq!(core.signal(), Some(Signal::SIGSEGV));
+        assert_eq!(
+            core.threads(),
+            vec![(1, Pid::from_raw(42)), (2, Pid::from_raw(43))]
+        );
+        assert_eq!(core.current_thread(), 1);
+        let regs = core.get_registers().unwrap();
+        assert_eq!(
+            (regs.rip, regs.rsp, regs.rax),
+            (0x401010, 0x7ffc0000, 0xdead)
+        );
+        assert_eq!(core.get_fp_registers().unwrap().mxcsr, 0x1f80);
+
+        assert!(core.select_thread(2));
+        assert!(!core.select_thread(3));
+        let regs = core.get_registers().unwrap();
+        assert_eq!((regs.rip, regs.rax), (0x401234, 7));
+        assert_eq!(core.get_fp_registers().unwrap_err(), Errno::ENODATA);
+    }
+
+    #[test]
+    fn reads_mappings_and_memory_of_hand_built_core() {
+        let path = scratch_path("mappings");
+        write_small_core(&path);
+        let core = CoreFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(core.executable_path().unwrap(), "/usr/bin/crash");
+        // only mappings from the start of a file are objects to load symbols for
+        assert_eq!(
+            core.mapped_objects().unwrap(),
+            vec![("/usr/bin/crash".to_string(), 0x400000)]
+        );
+        assert_eq!(core.files[1].offset, PAGE_SIZE);
+        assert_eq!(core.read_memory(0x600004, 3).unwrap(), vec![12, 15, 18]);
+        assert_eq!(core.read_word(0x600008).unwrap(), 0x2d2a2724211e1b18);
+        // the rest of the segment wasn't dumped and isn't in a mapped file
+        assert_eq!(core.read_memory(0x60000e, 4).unwrap_err(), Errno::EIO);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_cores() {
+        let path = scratch_path("not-core");
+        std::fs::write(&path, b"\x7fELF but not really").unwrap();
+        let error = CoreFile::open(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reads_back_core_written_by_gcore() {
+        let mut inferior = Inferior::new("/bin/sleep", &["10".to_string()]).unwrap();
+        let regs = inferior.get_registers().unwrap();
+        let rip = regs.rip as usize;
+        let code = inferior.read_memory(rip, 16).unwrap();
+        let stack = inferior.read_memory(regs.rsp as usize, 64).unwrap();
+        inferior.insert_breakpoint(rip).unwrap();
+        let mut raw = [0];
+        File::open(format!("/proc/{}/mem", inferior.pid()))
+            .and_then(|mem| mem.read_exact_at(&mut raw, rip as u64))
+            .unwrap();
+        assert_eq!(raw, [0xcc]);
+
+        let path = scratch_path("gcore");
+        let written = write_core(&inferior, &path);
+        inferior.kill().unwrap();
+        written.unwrap();
+        let core = CoreFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(core.pid(), inferior.pid());
+        assert_eq!(core.threads(), vec![(1, inferior.pid())]);
+        assert_eq!(core.signal(), None);
+        assert_eq!(core.command(), "/bin/sleep 10");
+        let saved = core.get_registers().unwrap();
+        assert_eq!(
+            (
+                saved.rip,
+                saved.rsp,
+                saved.rbp,
+                saved.rdx,
+                saved.eflags,
+                saved.fs_base
+            ),
+            (
+                regs.rip,
+                regs.rsp,
+                regs.rbp,
+                regs.rdx,
+                regs.eflags,
+                regs.fs_base
+            )
+        );
+        assert!(core.get_fp_registers().is_ok());
+        let executable = std::fs::canonicalize("/bin/sleep").unwrap();
+        assert_eq!(
+            core.executable_path().unwrap(),
+            executable.to_str().unwrap()
+        );
+        // the breakpoint's 0xcc is left out of the dump
+        assert_eq!(core.read_memory(rip, 16).unwrap(), code);
+        assert_eq!(core.read_memory(regs.rsp as usize, 64).unwrap(), stack);
+    }
+}
@@ -1,9 +1,20 @@
-use std::collections::HashMap;
-
+use crate::breakpoint::{BreakpointTable, Condition};
+use crate::core_file::{self, CoreFile};
 use crate::debugger_command::DebuggerCommand;
+use crate::disassembler::{self, Marks, Modifiers};
 use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use crate::inferior::Inferior;
-use nix::sys::ptrace;
+use crate::examine::{self, Format};
+use crate::expression::{self, Evaluator, Expression, Program};
+use crate::inferior::{ForkPolicy, Inferior, Status, Trap, WatchKind, DEBUG_REGISTER_SLOTS};
+use crate::modules::{Module, Modules};
+use crate::registers::{self, Register};
+use crate::signals::{self, parse_signal, SignalPolicy, SignalTable};
+use crate::source::SourceFiles;
+use crate::target::Target;
+use crate::unwind::{self, Frame};
+use libc::user_regs_struct;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
@@ -12,33 +23,134 @@ pub struct Debugger {
     history_path: String,
     readline: Editor<()>,
     inferior: Option<Inferior>,
-    debug_data: DwarfData,
-    breakpoints: HashMap<usize, u8>,
+    /// The core dump being examined, when there is no live inferior
+    core: Option<CoreFile>,
+    debug_data: Modules,
+    breakpoints: BreakpointTable,
+    /// The frame `print` and `info locals` look at, counted from the innermost one
+    selected_frame: usize,
+    fork_policy: ForkPolicy,
+    signals: SignalTable,
+    /// The format `x` last used, and where it left off
+    examine_format: Format,
+    next_examine: Option<usize>,
+    sources: SourceFiles,
+    /// The expressions `display` shows at every stop, and how many have been set so far
+    displays: Vec<Display>,
+    display_count: usize,
+}
+
+/// An expression shown each time the inferior stops.
+struct Display {
+    id: usize,
+    text: String,
+    expression: Expression,
+    /// The function whose locals the expression uses; it is only shown in there
+    function: Option<String>,
 }
 
 fn parse_address(addr: &str) -> Option<usize> {
     let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
         &addr[2..]
     } else {
-        &addr
+        addr
     };
     usize::from_str_radix(addr_without_0x, 16).ok()
 }
 
+/// Loads the debugging symbols of `target`, explaining what went wrong if that fails.
+fn load_debug_data(target: &str) -> Option<DwarfData> {
+    match DwarfData::from_file(target) {
+        Ok(val) => Some(val),
+        Err(DwarfError::ErrorOpeningFile) => {
+            println!("Could not open file {}", target);
+            None
+        }
+        Err(DwarfError::DwarfFormatError(err)) => {
+            println!("Could not debugging symbols from {}: {:?}", target, err);
+            None
+        }
+    }
+}
+
+/// Builds the callback the inferior uses to decide whether a breakpoint trap should stop it,
+/// which takes care of conditions, ignore counts and hit counts.
+fn breakpoint_filter<'a>(
+    breakpoints: &'a mut BreakpointTable,
+    debug_data: &'a Modules,
+) -> impl FnMut(&Inferior, Trap) -> bool + 'a {
+    move |inferior, trap| {
+        let evaluate = |condition: &Condition| condition.evaluate(inferior, debug_data);
+        match trap {
+            Trap::Breakpoint(addr) => breakpoints.should_stop(addr, evaluate),
+            Trap::Watchpoint(slot) => breakpoints.should_stop_watch(
+                slot,
+                |addr, len| inferior.read_memory(addr, len),
+                evaluate,
+            ),
+        }
+    }
+}
+
+/// Returns the program being examined: the inferior, or failing that the core dump.
+fn current_target_mut<'a>(
+    inferior: &'a mut Option<Inferior>,
+    core: &'a mut Option<CoreFile>,
+) -> Option<&'a mut dyn Target> {
+    match (inferior, core) {
+        (Some(inferior), _) => Some(inferior),
+        (None, Some(core)) => Some(core),
+        (None, None) => None,
+    }
+}
+
+/// Which way `select_frame` moves.
+enum FrameMove {
+    To,
+    Up,
+    Down,
+}
+
+/// How gdb introduces each kind of watchpoint.
+fn watch_label(kind: WatchKind) -> &'static str {
+    match kind {
+        WatchKind::Write => "Hardware watchpoint",
+        WatchKind::Read => "Hardware read watchpoint",
+        WatchKind::Access => "Hardware access (read/write) watchpoint",
+    }
+}
+
+/// Decides from the registers before and after a single step whether the instruction that ran
+/// was a call: the stack grew by one word, and that word is an address just past the old rip.
+fn entered_call(
+    inferior: &Inferior,
+    before: &user_regs_struct,
+    after: &user_regs_struct,
+) -> Result<bool, nix::Error> {
+    if after.rsp != before.rsp.wrapping_sub(8) {
+        return Ok(false);
+    }
+    let return_addr = inferior.read_word(after.rsp as usize)? as u64;
+    Ok(return_addr > before.rip && return_addr <= before.rip + 15 && after.rip != return_addr)
+}
+
+/// Decides from the registers before and after a single step whether the instruction that ran
+/// was a ret: one word was popped off the stack and execution continued at that word.
+fn returned(
+    inferior: &Inferior,
+    before: &user_regs_struct,
+    after: &user_regs_struct,
+) -> Result<bool, nix::Error> {
+    Ok(after.rsp == before.rsp + 8 && inferior.read_word(before.rsp as usize)? as u64 == after.rip)
+}
+
 impl Debugger {
     /// Initializes the debugger.
     pub fn new(target: &str) -> Debugger {
         // TODO (milestone 3): initialize the DwarfData
-        let debug_data = match DwarfData::from_file(target) {
-            Ok(val) => val,
-            Err(DwarfError::ErrorOpeningFile) => {
-                println!("Could not open file {}", target);
-                std::process::exit(1);
-            }
-            Err(DwarfError::DwarfFormatError(err)) => {
-                println!("Could not debugging symbols from {}: {:?}", target, err);
-                std::process::exit(1);
-            }
+        let debug_data = match load_debug_data(target) {
+            Some(val) => Modules::new(target, val),
+            None => std::process::exit(1),
         };
         debug_data.print(); // for debug
 
@@ -52,8 +164,17 @@ impl Debugger {
             history_path,
             readline,
             inferior: None,
+            core: None,
             debug_data,
-            breakpoints: HashMap::new(),
+            breakpoints: BreakpointTable::default(),
+            selected_frame: 0,
+            fork_policy: ForkPolicy::default(),
+            signals: SignalTable::default(),
+            examine_format: Format::default(),
+            next_examine: None,
+            sources: SourceFiles::new(target),
+            displays: Vec::new(),
+            display_count: 0,
         }
     }
 
@@ -61,44 +182,405 @@ impl Debugger {
         loop {
             match self.get_next_command() {
                 DebuggerCommand::Run(args) => {
-                    self.kill_inferior_if_exists().unwrap();
-                    if let Some(inferior) =
-                        Inferior::new(&self.target, &args, &mut self.breakpoints)
-                    {
+                    self.kill_inferior_if_exists();
+                    if let Some(inferior) = Inferior::new(&self.target, &args) {
                         // Create the inferior
-                        self.inferior = Some(inferior);
+                        self.start_session(inferior);
                         self.step_next();
                     } else {
                         println!("Error starting subprocess");
                     }
                 }
                 DebuggerCommand::Continue => {
-                    self.step_next();
-                }
-                DebuggerCommand::BackTrace => {
-                    if let Some(inferior) = self.inferior.as_mut() {
-                        inferior.print_backtrace(&self.debug_data).unwrap();
+                    if self.inferior.is_some() {
+                        self.step_next();
                     } else {
-                        println!("There isn't any child process")
+                        println!("The program is not being run.");
                     }
                 }
+                DebuggerCommand::Step => {
+                    self.step_source_line(false);
+                }
+                DebuggerCommand::Next => {
+                    self.step_source_line(true);
+                }
+                DebuggerCommand::Finish => {
+                    self.finish();
+                }
+                DebuggerCommand::Print(expression) => {
+                    self.print_expression(&expression);
+                }
+                DebuggerCommand::Examine(format, expression) => {
+                    self.examine_memory(&format, &expression);
+                }
+                DebuggerCommand::Disassemble(modifiers, args) => {
+                    self.disassemble(&modifiers, &args);
+                }
+                DebuggerCommand::List(args) => {
+                    self.list_source(&args);
+                }
+                DebuggerCommand::BackTrace => {
+                    self.print_backtrace();
+                }
                 DebuggerCommand::Quit => {
-                    self.kill_inferior_if_exists().unwrap();
+                    // leave processes we attached to running, like gdb does
+                    if self.inferior.as_ref().is_some_and(Inferior::is_attached) {
+                        self.detach();
+                    } else {
+                        self.kill_inferior_if_exists();
+                    }
                     return;
                 }
+                DebuggerCommand::Attach(pid) => {
+                    self.attach(&pid);
+                }
+                DebuggerCommand::Detach => {
+                    self.detach();
+                }
+                DebuggerCommand::GenerateCore(args) => {
+                    self.generate_core(&args);
+                }
                 DebuggerCommand::Breakpoint(addr) => {
                     self.add_breakpoint(addr);
                 }
+                DebuggerCommand::InfoBreakpoints => {
+                    self.print_breakpoints();
+                }
+                DebuggerCommand::InfoSharedLibrary => {
+                    self.print_shared_libraries();
+                }
+                DebuggerCommand::InfoLocals => {
+                    self.print_frame_variables(false);
+                }
+                DebuggerCommand::InfoArgs => {
+                    self.print_frame_variables(true);
+                }
+                DebuggerCommand::InfoThreads => {
+                    self.print_threads();
+                }
+                DebuggerCommand::InfoInferiors => {
+                    self.print_inferiors();
+                }
+                DebuggerCommand::InfoRegisters(all, names) => {
+                    self.print_registers(all, &names);
+                }
+                DebuggerCommand::InfoSignals(args) => {
+                    self.print_signals(&args);
+                }
+                DebuggerCommand::Handle(args) => {
+                    self.handle_signals(&args);
+                }
+                DebuggerCommand::Set(args) => {
+                    self.set_option(&args);
+                }
+                DebuggerCommand::Show(args) => {
+                    self.show_option(&args);
+                }
+                DebuggerCommand::Thread(args) => {
+                    self.select_thread(&args);
+                }
+                DebuggerCommand::Frame(args) => {
+                    self.select_frame(&args, FrameMove::To);
+                }
+                DebuggerCommand::Up(args) => {
+                    self.select_frame(&args, FrameMove::Up);
+                }
+                DebuggerCommand::Down(args) => {
+                    self.select_frame(&args, FrameMove::Down);
+                }
+                DebuggerCommand::Delete(ids) => {
+                    self.delete_breakpoints(&ids);
+                }
+                DebuggerCommand::Disable(ids) => {
+                    self.enable_breakpoints(&ids, false);
+                }
+                DebuggerCommand::Enable(ids) => {
+                    self.enable_breakpoints(&ids, true);
+                }
+                DebuggerCommand::Ignore(args) => {
+                    self.ignore_breakpoint(&args);
+                }
+                DebuggerCommand::Watch(kind, expression) => {
+                    self.add_watchpoint(kind, expression);
+                }
+                DebuggerCommand::Display(expression) => {
+                    self.add_display(&expression);
+                }
+                DebuggerCommand::Undisplay(args) => {
+                    self.undisplay(&args);
+                }
+                DebuggerCommand::InfoDisplay => {
+                    self.print_displays();
+                }
             }
         }
     }
 
-    fn kill_inferior_if_exists(&mut self) -> Result<(), std::io::Error> {
-        if let Some(inferior) = self.inferior.as_mut() {
+    fn kill_inferior_if_exists(&mut self) {
+        if let Some(mut inferior) = self.inferior.take() {
             println!("Killing running inferior (pid {})", inferior.pid());
-            inferior.kill()?;
+            if let Err(e) = inferior.kill() {
+                println!("Error killing inferior: {}", e);
+            }
+        }
+    }
+
+    /// Implements `attach`, which starts debugging the already running process `pid` using the
+    /// symbols of its executable.
+    pub fn attach(&mut self, pid: &str) {
+        let pid = match pid.parse::<i32>() {
+            Ok(pid) if pid > 0 => Pid::from_raw(pid),
+            _ => {
+                println!("Usage: attach <pid>");
+                return;
+            }
+        };
+        if self.inferior.is_some() {
+            println!("The program is already being debugged. Kill or detach it first.");
+            return;
+        }
+        let exe = format!("/proc/{}/exe", pid);
+        let debug_data = match load_debug_data(&exe) {
+            Some(debug_data) => debug_data,
+            None => return,
+        };
+        let inferior = match Inferior::attach(pid) {
+            Ok(inferior) => inferior,
+            Err(e) => {
+                println!("Could not attach to process {}: {}", pid, e);
+                return;
+            }
+        };
+        println!("Attached to process {}", pid);
+        // take the breakpoints back to link-time addresses before switching symbols
+        self.relocate(0);
+        self.unload_libraries(|_| false);
+        // so that `run` starts the same program
+        self.target = inferior.executable_path().unwrap_or(exe);
+        self.debug_data = Modules::new(&self.target, debug_data);
+        let status = inferior
+            .get_registers()
+            .map(|regs| Status::Stopped(Signal::SIGSTOP, regs.rip as usize));
+        self.start_session(inferior);
+        self.report_stop(status, false);
+    }
+
+    /// Implements `--core`: opens a core dump to examine the state the program crashed in.
+    pub fn load_core(&mut self, path: &str) {
+        let core = match CoreFile::open(path) {
+            Ok(core) => core,
+            Err(e) => {
+                println!("Could not read core file {}: {}", path, e);
+                return;
+            }
+        };
+        if !core.command().is_empty() {
+            println!("Core was generated by `{}'.", core.command());
+        }
+        if let Some(signal) = core.signal() {
+            println!(
+                "Program terminated with signal {}, {}.",
+                signal.as_str(),
+                signals::describe(signal)
+            );
+        }
+        self.core = Some(core);
+        self.selected_frame = 0;
+        self.load_program();
+        match unwind::innermost_frame(self.core.as_ref().unwrap(), &self.debug_data) {
+            Ok(frame) => println!("{}", frame.describe(0, &self.debug_data)),
+            Err(e) => println!("Cannot unwind the stack: {}", e),
+        }
+    }
+
+    /// Returns the program being examined: the inferior, or failing that the core dump.
+    fn current_target(&self) -> Option<&dyn Target> {
+        match (&self.inferior, &self.core) {
+            (Some(inferior), _) => Some(inferior),
+            (None, Some(core)) => Some(core),
+            (None, None) => None,
+        }
+    }
+
+    /// Takes control of a freshly started or attached inferior.
+    fn start_session(&mut self, mut inferior: Inferior) {
+        inferior.set_fork_policy(self.fork_policy);
+        inferior.set_signal_table(self.signals.clone());
+        self.inferior = Some(inferior);
+        // a live process replaces the core dump
+        self.core = None;
+        self.load_program();
+    }
+
+    /// Gets the symbols in line with the program the inferior has just started running, or the
+    /// core dump: relocates them to where the executable was loaded, loads the shared libraries
+    /// mapped so far, then puts the breakpoints and watchpoints in a live inferior.
+    fn load_program(&mut self) {
+        let executable = self.debug_data.executable();
+        if executable.is_position_independent() {
+            match self.current_target().unwrap().load_base() {
+                Ok(Some(base)) => self.relocate(base.wrapping_sub(executable.link_base())),
+                _ => println!("Could not find where the executable is loaded in memory."),
+            }
+        }
+        // none of the libraries of a previous run are there yet
+        self.unload_libraries(|_| false);
+        self.update_shared_libraries();
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.insert_breakpoints(&self.breakpoints.enabled_addresses());
+            self.restore_watchpoints();
+        }
+    }
+
+    /// Moves the symbols of the executable, and the breakpoints set on them, to a binary loaded
+    /// `bias` bytes from its link-time addresses.
+    fn relocate(&mut self, bias: usize) {
+        let old_image = self.debug_data.executable().image();
+        self.debug_data.executable_mut().set_load_bias(bias);
+        let delta = self
+            .debug_data
+            .executable()
+            .image()
+            .start
+            .wrapping_sub(old_image.start);
+        self.breakpoints.rebase(old_image, delta);
+    }
+
+    /// Brings the modules in line with the shared libraries mapped into the inferior or core
+    /// dump: forgets unloaded ones, loads the symbols of new ones and resolves pending
+    /// breakpoints in them.
+    fn update_shared_libraries(&mut self) {
+        let target = self.current_target().unwrap();
+        let libraries: Vec<(String, usize)> =
+            match (target.executable_path(), target.mapped_objects()) {
+                (Ok(exe), Ok(mapped)) => mapped
+                    .into_iter()
+                    .filter(|(path, _)| *path != exe)
+                    .collect(),
+                (Err(e), _) | (_, Err(e)) => {
+                    println!("Cannot read the shared libraries of the inferior: {}", e);
+                    return;
+                }
+            };
+        self.unload_libraries(|module| {
+            libraries
+                .iter()
+                .any(|(path, base)| module.path == *path && module.base == *base)
+        });
+        for (path, base) in libraries {
+            let loaded = self.debug_data.libraries().iter();
+            if loaded
+                .clone()
+                .any(|module| module.path == path && module.base == base)
+            {
+                continue;
+            }
+            // not every mapped file is an object file, e.g. the locale archive
+            if let Ok(debug_data) = DwarfData::from_file(&path) {
+                self.debug_data.add_library(path, base, debug_data);
+            }
+        }
+
+        let mut sites = Vec::new();
+        // the dynamic linker calls this empty function after each change to its list of objects
+        let shlib_event = self.debug_data.libraries().iter().find_map(|module| {
+            module
+                .debug_data
+                .get_addr_for_function(None, "_dl_debug_state")
+        });
+        self.breakpoints.set_shlib_event(shlib_event);
+        sites.extend(shlib_event);
+        for (id, location) in self.breakpoints.pending() {
+            if let Ok(Some(addrs)) = self.resolve_location(&location) {
+                if self.breakpoints.resolve(id, addrs.clone()) {
+                    sites.extend(addrs);
+                }
+            }
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.insert_breakpoints(&sites);
+        }
+    }
+
+    /// Switches to the symbols of the program the inferior exec'ed, and looks up the breakpoints
+    /// again in it.
+    fn follow_exec(&mut self) {
+        let inferior = self.inferior.as_ref().unwrap();
+        let path = match inferior.executable_path() {
+            Ok(path) => path,
+            Err(e) => {
+                println!("Cannot find the program the inferior is executing: {}", e);
+                return;
+            }
+        };
+        println!(
+            "process {} is executing new program: {}",
+            inferior.pid(),
+            path
+        );
+        // the breakpoints went away with the old program
+        self.unload_libraries(|_| false);
+        self.breakpoints.unresolve(0..usize::MAX);
+        self.breakpoints.set_shlib_event(None);
+        let debug_data = match load_debug_data(&path) {
+            Some(debug_data) => debug_data,
+            // leave the breakpoints pending rather than resolve them against the old symbols
+            None => return,
+        };
+        self.target = path;
+        self.debug_data = Modules::new(&self.target, debug_data);
+        self.load_program();
+    }
+
+    /// Drops the shared libraries for which `keep` returns false, making the breakpoints in them
+    /// pending.
+    fn unload_libraries(&mut self, keep: impl FnMut(&Module) -> bool) {
+        for module in self.debug_data.remove_libraries(keep) {
+            for addr in self.breakpoints.unresolve(module.debug_data.image()) {
+                if let Some(inferior) = self.inferior.as_mut() {
+                    // the code may be gone already
+                    let _ = inferior.remove_breakpoint(addr);
+                }
+            }
+        }
+    }
+
+    /// Implements `gcore [file]`, which saves the state of the inferior as a core dump, to
+    /// core.<pid> by default.
+    fn generate_core(&self, args: &[String]) {
+        let inferior = match self.inferior.as_ref() {
+            Some(inferior) => inferior,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let path = match args {
+            [] => format!("core.{}", inferior.pid()),
+            [path] => path.clone(),
+            _ => {
+                println!("Usage: gcore [file]");
+                return;
+            }
+        };
+        match core_file::write_core(inferior, &path) {
+            Ok(()) => println!("Saved corefile {}", path),
+            Err(e) => println!("Can't create a corefile {}: {}", path, e),
+        }
+    }
+
+    /// Implements `detach`: removes our breakpoints from the inferior and lets it run on.
+    fn detach(&mut self) {
+        match self.inferior.take() {
+            Some(inferior) => {
+                let pid = inferior.pid();
+                match inferior.detach() {
+                    Ok(()) => println!("Detached from process {}", pid),
+                    Err(e) => println!("Error detaching from process {}: {}", pid, e),
+                }
+            }
+            None => println!("The program is not being run."),
         }
-        Ok(())
     }
 
     /// This function prompts the user to enter a command, and continues re-prompting until the user
@@ -121,7 +603,7 @@ impl Debugger {
                     panic!("Unexpected I/O error: {:?}", err);
                 }
                 Ok(line) => {
-                    if line.trim().len() == 0 {
+                    if line.trim().is_empty() {
                         continue;
                     }
                     self.readline.add_history_entry(line.as_str());
@@ -143,55 +625,1464 @@ impl Debugger {
     }
 
     fn step_next(&mut self) {
-        let inferior = self.inferior.as_mut().unwrap();
-        match inferior.continue_run(None, &mut self.breakpoints) {
-            Ok(status) => match status {
-                crate::inferior::Status::Stopped(signal, rip) => {
-                    println!("Child stopped (signal {:?})", signal);
-                    let _line = self.debug_data.get_line_from_addr(rip);
-                    let _func = self.debug_data.get_function_from_addr(rip);
-                    if _line.is_some() && _func.is_some() {
-                        println!("Stopped at {} {}", _func.unwrap(), _line.unwrap());
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            let result = inferior.continue_run(&mut breakpoint_filter(
+                &mut self.breakpoints,
+                &self.debug_data,
+            ));
+            if let Ok(Status::Exec) = result {
+                self.follow_exec();
+                continue;
+            }
+            if !self.at_shlib_event(&result) {
+                self.report_stop(result, true);
+                return;
+            }
+            self.update_shared_libraries();
+        }
+    }
+
+    /// Runs the inferior until it gets back to `addr` with a stack pointer above `sp`, keeping
+    /// track of the shared libraries loaded meanwhile.
+    fn run_until(&mut self, addr: usize, sp: usize) -> Result<Status, nix::Error> {
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            let result = inferior.run_until(
+                addr,
+                sp,
+                &mut breakpoint_filter(&mut self.breakpoints, &self.debug_data),
+            );
+            if !self.at_shlib_event(&result) {
+                return result;
+            }
+            self.update_shared_libraries();
+        }
+    }
+
+    /// Returns whether the inferior stopped only because the dynamic linker loaded or unloaded
+    /// something.
+    fn at_shlib_event(&self, result: &Result<Status, nix::Error>) -> bool {
+        match result {
+            Ok(Status::Stopped(Signal::SIGTRAP, rip)) => {
+                self.breakpoints.is_shlib_event(*rip) && !self.breakpoints.has_last_hits()
+            }
+            _ => false,
+        }
+    }
+
+    /// Prints where the inferior stopped, or how it ended. SIGTRAP stops are only announced when
+    /// `show_trap` is set, since they are the expected outcome of stepping.
+    fn report_stop(&mut self, result: Result<Status, nix::Error>, show_trap: bool) {
+        self.selected_frame = 0;
+        match result {
+            Ok(Status::Stopped(signal, rip)) => {
+                let hits = self.breakpoints.take_last_hits();
+                let (watch_hits, breakpoint_hits): (Vec<usize>, Vec<usize>) = hits
+                    .iter()
+                    .partition(|id| self.breakpoints.get(**id).unwrap().watch.is_some());
+                if !breakpoint_hits.is_empty() {
+                    let ids: Vec<String> =
+                        breakpoint_hits.iter().map(|id| id.to_string()).collect();
+                    println!("Child stopped (breakpoint {})", ids.join(", "));
+                } else if watch_hits.is_empty() && (show_trap || signal != Signal::SIGTRAP) {
+                    println!(
+                        "Child stopped (signal {}, {})",
+                        signal.as_str(),
+                        signals::describe(signal)
+                    );
+                }
+                for id in watch_hits {
+                    let bp = self.breakpoints.get(id).unwrap();
+                    let watch = bp.watch.as_ref().unwrap();
+                    println!("{} {}: {}", watch_label(watch.kind), id, bp.location);
+                    let format = |bytes: &[u8]| watch.entity_type.format_value(bytes);
+                    if watch.old_value != watch.value {
+                        println!("Old value = {}", format(&watch.old_value));
+                        println!("New value = {}", format(&watch.value));
+                    } else {
+                        println!("Value = {}", format(&watch.value));
                     }
                 }
-                crate::inferior::Status::Exited(code) => {
-                    println!("Child exited (status {})", code)
+                let line = self.debug_data.get_line_from_addr(rip);
+                let func = self.debug_data.get_function_from_addr(rip);
+                match (func, line) {
+                    (Some(func), Some(line)) => {
+                        println!("Stopped at {} {}", func, line);
+                        self.sources.show_stop(&line.file, line.number);
+                    }
+                    (Some(func), None) => println!("Stopped at {:#x} in {}", rip, func),
+                    _ => println!("Stopped at {:#x}", rip),
+                }
+                self.show_displays();
+            }
+            Ok(Status::Exec) => {
+                self.follow_exec();
+                if let Ok(regs) = self.inferior.as_ref().unwrap().get_registers() {
+                    println!("Stopped at {:#x}", regs.rip);
+                }
+            }
+            Ok(Status::Exited(code)) => {
+                println!("Child exited (status {})", code);
+                if let Some(mut inferior) = self.inferior.take() {
+                    inferior.release_held();
+                }
+            }
+            Ok(Status::Signaled(signal)) => {
+                println!(
+                    "Child exited due to signal {}, {}",
+                    signal.as_str(),
+                    signals::describe(signal)
+                );
+                if let Some(mut inferior) = self.inferior.take() {
+                    inferior.release_held();
                 }
-                crate::inferior::Status::Signaled(_) => todo!(),
-            },
-            Err(e) => {
-                let regs = ptrace::getregs(inferior.pid()).unwrap();
-                let _line = self.debug_data.get_line_from_addr(regs.rip as usize);
-                let _func = self.debug_data.get_function_from_addr(regs.rip as usize);
             }
+            Err(e) => println!("Error controlling the child: {}", e),
         }
     }
 
-    fn add_breakpoint(&mut self, location: String) {
+    /// Implements `step` and `next`.
+    fn step_source_line(&mut self, over_calls: bool) {
         if self.inferior.is_none() {
-            println!("Please start inferior");
+            println!("The program is not being run.");
             return;
         }
+        let result = self.step_line(over_calls);
+        self.report_stop(result, false);
+    }
 
-        if let Ok(line) = location.parse::<usize>() {
-            let addr = self.debug_data.get_addr_for_line(None, line).unwrap();
-            self.breakpoints.insert(addr, 0xcc);
-            println!("Set beakpoint {} at {}", self.breakpoints.len(), addr)
-        } else if location.starts_with("*") {
-            match parse_address(&location[1..]) {
-                Some(addr) => {
-                    self.breakpoints.insert(addr, 0xcc);
-                    println!("Set beakpoint {} at {}", self.breakpoints.len(), addr)
+    /// Single-steps the inferior until it reaches a different source line. Calls into functions
+    /// without line information are always run to their return; with `over_calls` set, every
+    /// call is. When the current function returns, stepping carries on to the end of the
+    /// caller's line.
+    fn step_line(&mut self, over_calls: bool) -> Result<Status, nix::Error> {
+        let mut regs = self.inferior.as_ref().unwrap().get_registers()?;
+        let mut start_line = self.debug_data.get_line_from_addr(regs.rip as usize);
+        loop {
+            let inferior = self.inferior.as_mut().unwrap();
+            let status = inferior.step(&mut breakpoint_filter(
+                &mut self.breakpoints,
+                &self.debug_data,
+            ))?;
+            let mut rip = match status {
+                Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                other => return Ok(other),
+            };
+            if self.breakpoints.has_last_hits() {
+                // a watchpoint went off
+                return Ok(status);
+            }
+            let prev = regs;
+            regs = inferior.get_registers()?;
+
+            if entered_call(inferior, &prev, &regs)? {
+                match self.debug_data.get_function_containing(rip) {
+                    Some(func) if !over_calls => {
+                        // Stop after the prologue, where the function's first line begins
+                        let body = self.debug_data.get_prologue_end(func);
+                        let mut status = status;
+                        while rip != body {
+                            status = inferior.step(&mut breakpoint_filter(
+                                &mut self.breakpoints,
+                                &self.debug_data,
+                            ))?;
+                            rip = match status {
+                                Status::Stopped(Signal::SIGTRAP, rip) => rip,
+                                other => return Ok(other),
+                            };
+                            if self.breakpoints.has_last_hits() {
+                                return Ok(status);
+                            }
+                        }
+                        return Ok(status);
+                    }
+                    _ => {
+                        let sp = regs.rsp as usize;
+                        let return_addr = inferior.read_word(sp)?;
+                        match self.run_until(return_addr, sp)? {
+                            Status::Stopped(Signal::SIGTRAP, rip) if rip == return_addr => {}
+                            other => return Ok(other),
+                        }
+                        regs = self.inferior.as_ref().unwrap().get_registers()?;
+                        rip = return_addr;
+                    }
+                }
+            } else if returned(self.inferior.as_ref().unwrap(), &prev, &regs)? {
+                // We are back in the caller right after its call instruction. Finish the line
+                // that call belongs to.
+                start_line = self.debug_data.get_line_from_addr(rip - 1);
+                if start_line.is_none() {
+                    return Ok(status);
                 }
+            }
+
+            match (&start_line, self.debug_data.get_line_from_addr(rip)) {
+                (Some(start), Some(line))
+                    if start.file == line.file && start.number == line.number => {}
+                _ => return Ok(Status::Stopped(Signal::SIGTRAP, rip)),
+            }
+        }
+    }
+
+    /// Implements `finish`: runs until the current function returns to its caller.
+    fn finish(&mut self) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        let result = self.finish_frame();
+        if let Ok(None) = result {
+            return;
+        }
+        self.report_stop(result.map(Option::unwrap), false);
+    }
+
+    fn finish_frame(&mut self) -> Result<Option<Status>, nix::Error> {
+        let regs = self.inferior.as_ref().unwrap().get_registers()?;
+        let rip = regs.rip as usize;
+        match self.debug_data.get_function_from_addr(rip) {
+            Some(func) if func == "main" => {
+                println!("\"finish\" not meaningful in the outermost frame.");
+                return Ok(None);
+            }
+            Some(func) => println!("Run till exit from {}", func),
+            None => println!("Run till exit from {:#x}", rip),
+        }
+        let inferior = self.inferior.as_ref().unwrap();
+        // the return address sits just below the CFA
+        let frame = unwind::innermost_frame(inferior, &self.debug_data)?;
+        let slot = frame.cfa.ok_or(nix::Error::EIO)? - 8;
+        let return_addr = inferior.read_word(slot)?;
+        self.run_until(return_addr, slot).map(Some)
+    }
+
+    /// Parses an expression, telling typedefs apart from variables by the debugging
+    /// information.
+    fn parse_expression(&self, text: &str) -> Result<Expression, String> {
+        expression::parse(text, &|name| self.debug_data.get_type(name).is_some())
+    }
+
+    /// Sets up the evaluation of expressions in the selected frame, which can only look at the
+    /// program.
+    fn evaluator(&self) -> Result<Evaluator<'_>, String> {
+        let target = self
+            .current_target()
+            .ok_or("The program is not being run.")?;
+        let frame = self
+            .selected_frame()
+            .map_err(|e| format!("Cannot unwind the stack: {}", e))?;
+        Ok(Evaluator::new(
+            Program::ReadOnly(target),
+            &self.debug_data,
+            frame,
+            self.selected_frame == 0,
+        ))
+    }
+
+    /// Sets up the evaluation of expressions in the selected frame, which may change a live
+    /// inferior.
+    fn evaluator_mut(&mut self) -> Result<Evaluator<'_>, String> {
+        let frame = match self.current_target() {
+            Some(_) => self
+                .selected_frame()
+                .map_err(|e| format!("Cannot unwind the stack: {}", e))?,
+            None => return Err("The program is not being run.".to_string()),
+        };
+        let program = match (&mut self.inferior, &self.core) {
+            (Some(inferior), _) => Program::Live(inferior),
+            (None, Some(core)) => Program::ReadOnly(core),
+            (None, None) => unreachable!(),
+        };
+        Ok(Evaluator::new(
+            program,
+            &self.debug_data,
+            frame,
+            self.selected_frame == 0,
+        ))
+    }
+
+    /// Works out the address an expression of `x` refers to. Like in gdb, a pointer or a number
+    /// stands for the address it holds, and any other lvalue for its own address.
+    fn evaluate_address(&self, expression: &str) -> Result<usize, String> {
+        let expression = self.parse_expression(expression)?;
+        let mut evaluator = self.evaluator()?;
+        let value = evaluator.evaluate(&expression)?;
+        evaluator.address(&value)
+    }
+
+    /// Implements `x/FMT [address]`. Without an address it carries on where the last one
+    /// stopped.
+    fn examine_memory(&mut self, spec: &str, expression: &str) {
+        let target = match self.current_target() {
+            Some(target) => target,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        let format = match Format::parse(spec, self.examine_format) {
+            Ok(format) => format,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let addr = if expression.trim().is_empty() {
+            match self.next_examine {
+                Some(addr) => addr,
                 None => {
-                    println!("Invalid breakpoints: {}", location);
+                    println!("Argument required (starting display address).");
+                    return;
+                }
+            }
+        } else {
+            match self.evaluate_address(expression) {
+                Ok(addr) => addr,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        };
+        match examine::examine(target, &self.debug_data, addr, format) {
+            Ok(next) => self.next_examine = Some(next),
+            Err(e) => println!("{}", e),
+        }
+        self.examine_format = format;
+    }
+
+    /// Implements `list`: with no argument it carries on after the lines shown last, `list -`
+    /// goes back before them, and a line number, function or `file:line` shows the lines
+    /// around it.
+    fn list_source(&mut self, args: &str) {
+        let result = match args.trim() {
+            "" if self.sources.current_file().is_none() => self
+                .source_location("main")
+                .map(|(file, line)| self.sources.list_around(&file, line)),
+            "" => self.sources.list_more(),
+            "-" => self.sources.list_less(),
+            location => self
+                .source_location(location)
+                .map(|(file, line)| self.sources.list_around(&file, line)),
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+
+    /// Works out the file and line a `list` location refers to: `N`, `FUNCTION`, `FILE:N` or
+    /// `FILE:FUNCTION`. A bare line number is in the file listed or stopped in last.
+    fn source_location(&self, location: &str) -> Result<(String, usize), String> {
+        let (file, rest) = match location.rsplit_once(':') {
+            Some((file, rest)) => (Some(file.trim()), rest.trim()),
+            None => (None, location),
+        };
+        if let Ok(line) = rest.parse::<usize>() {
+            let file = match file {
+                Some(file) => self
+                    .debug_data
+                    .get_source_path(file)
+                    .ok_or_else(|| format!("No source file named {}.", file))?,
+                None => match self.sources.current_file() {
+                    Some(file) => file.to_string(),
+                    None => self.source_location("main")?.0,
+                },
+            };
+            return Ok((file, line));
+        }
+        let addr = self
+            .debug_data
+            .get_addr_for_function(file, rest)
+            .ok_or_else(|| format!("Function \"{}\" not defined.", rest))?;
+        let line = self
+            .debug_data
+            .get_line_from_addr(addr)
+            .ok_or_else(|| format!("No line number known for {}.", rest))?;
+        Ok((line.file, line.number))
+    }
+
+    /// Works out the address of code: an expression as `x` takes it when there is a process or
+    /// core, and otherwise a number or a function.
+    fn code_address(&self, target: Option<&dyn Target>, expression: &str) -> Result<usize, String> {
+        if target.is_some() {
+            return self.evaluate_address(expression);
+        }
+        let expression = expression.trim();
+        let number = match expression.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => expression.parse().ok(),
+        };
+        number
+            .or_else(|| self.debug_data.get_addr_for_function(None, expression))
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", expression))
+    }
+
+    /// Implements `disassemble[/rs] [function|address|start,end|start,+length]`. Without an
+    /// argument it shows the function of the selected frame. The code comes from the process
+    /// or core, or from the executable when there is neither.
+    fn disassemble(&mut self, modifiers: &str, args: &str) {
+        let mut options = Modifiers::default();
+        for modifier in modifiers.chars() {
+            match modifier {
+                'r' => options.raw = true,
+                's' | 'm' => options.source = true,
+                _ => {
+                    println!("Invalid disassembly modifier.");
+                    return;
                 }
             }
-        } else if let Some(addr) = self.debug_data.get_addr_for_function(None, &location) {
-            self.breakpoints.insert(addr, 0xcc);
-            println!("Set beakpoint {} at {}", self.breakpoints.len(), addr)
+        }
+        let target = self.current_target();
+        let pc = match target {
+            Some(_) => self.selected_frame().ok().map(|frame| frame.pc()),
+            None => None,
+        };
+        let function_at = |addr: usize, missing: &str| {
+            self.debug_data
+                .get_function_range(addr)
+                .ok_or_else(|| missing.to_string())
+        };
+        let args = args.trim();
+        let listing = if args.is_empty() {
+            match pc {
+                Some(pc) => function_at(
+                    pc,
+                    "No function contains program counter for selected frame.",
+                ),
+                None => Err("No frame selected.".to_string()),
+            }
+            .map(|(name, range)| (range, Some(name)))
+        } else if let Some((start, end)) = args.split_once(',') {
+            self.code_address(target, start).and_then(|start| {
+                let end = match end.trim().strip_prefix('+') {
+                    Some(len) => start + self.code_address(None, len)?,
+                    // like gdb takes start,+length, take start,length when it can't be an end
+                    None => match self.code_address(target, end)? {
+                        end if end > start => end,
+                        len => start + len,
+                    },
+                };
+                Ok((start..end, None))
+            })
         } else {
-            println!("Usage: b|break|breakpoint *address|line|func");
+            self.code_address(target, args).and_then(|addr| {
+                function_at(addr, "No function contains specified address.")
+                    .map(|(name, range)| (range, Some(name)))
+            })
+        };
+        let (range, function) = match listing {
+            Ok(listing) => listing,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let len = range.end.saturating_sub(range.start);
+        let code = match target {
+            Some(target) => disassembler::read_code(target, range.start, len).ok(),
+            None => {
+                // the symbols may still be relocated for a process that has exited
+                let bias = self.debug_data.executable().load_bias();
+                let addr = range.start.wrapping_sub(bias);
+                disassembler::read_file_code(
+                    &self.target,
+                    addr,
+                    len + disassembler::MAX_INSTRUCTION_LEN,
+                )
+            }
+        };
+        let code = match code {
+            Some(code) if !code.is_empty() => code,
+            _ => {
+                println!("Cannot access memory at address {:#x}", range.start);
+                return;
+            }
+        };
+        let marks = Marks {
+            pc,
+            breakpoints: self
+                .breakpoints
+                .iter()
+                .filter(|bp| bp.enabled && bp.watch.is_none() && !bp.pending)
+                .flat_map(|bp| bp.addrs.iter().copied())
+                .collect(),
+        };
+        disassembler::disassemble(
+            &self.debug_data,
+            &code,
+            range,
+            function.as_deref(),
+            &marks,
+            options,
+            &mut self.sources,
+        );
+    }
+
+    /// Reads `$name` in the selected frame. Outer frames only have the registers the unwinder
+    /// recovered, so the value is None for the others.
+    fn register_value(
+        &self,
+        target: &dyn Target,
+        name: &str,
+    ) -> Result<(&'static Register, Option<u64>), String> {
+        let register = registers::find(name.trim_start_matches('$'))
+            .ok_or_else(|| format!("Invalid register `{}'", name.trim_start_matches('$')))?;
+        let regs = target
+            .get_registers()
+            .map_err(|e| format!("Cannot read registers: {}", e))?;
+        let frame = self
+            .selected_frame()
+            .map_err(|e| format!("Cannot unwind the stack: {}", e))?;
+        Ok((
+            register,
+            register.value(&regs, &frame, self.selected_frame == 0),
+        ))
+    }
+
+    /// Implements `set var lvalue = value`, which writes into a variable, memory or a register.
+    fn set_variable(&mut self, assignment: &str) {
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        let expression = match self.parse_expression(assignment) {
+            Ok(expression @ Expression::Assign(..)) => expression,
+            Ok(_) => {
+                println!("Usage: set var <lvalue> = <expression>");
+                return;
+            }
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.evaluate_expression(&expression) {
+            println!("{}", e);
+        }
+    }
+
+    /// Evaluates an expression and renders its value. The program may end while the expression
+    /// calls a function in it, which is reported like any other exit.
+    fn evaluate_expression(&mut self, expression: &Expression) -> Result<String, String> {
+        let mut evaluator = self.evaluator_mut()?;
+        let result = evaluator
+            .evaluate(expression)
+            .and_then(|value| evaluator.format(&value));
+        if let Some(status) = evaluator.take_ended() {
+            self.report_stop(Ok(status), false);
+        }
+        result
+    }
+
+    /// Implements `print`: evaluates an expression against the selected frame.
+    fn print_expression(&mut self, text: &str) {
+        if text.is_empty() {
+            println!("Usage: p|print <expression>");
+            return;
+        }
+        let target = match self.current_target() {
+            Some(target) => target,
+            None => {
+                println!("The program is not being run.");
+                return;
+            }
+        };
+        // a lone register is shown the way `info registers` knows best
+        if let Some(name) = text.strip_prefix('$').filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }) {
+            match self.register_value(target, name) {
+                Ok((register, Some(value))) => println!(
+                    "{} = {}",
+                    text,
+                    register.print_value(value, &self.debug_data)
+                ),
+                Ok((_, None)) => println!("{} = <not saved>", text),
+                Err(e) => println!("{}", e),
+            }
+            return;
+        }
+        match self
+            .parse_expression(text)
+            .and_then(|expression| self.evaluate_expression(&expression))
+        {
+            Ok(value) => println!("{} = {}", text, value),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Returns the name of the function of the selected frame.
+    fn selected_function(&self) -> Option<String> {
+        let frame = self.selected_frame().ok()?;
+        frame.function_and_line(&self.debug_data).0
+    }
+
+    /// Implements `display expression`, which shows the expression now and at every stop.
+    /// Without an expression it shows them all again.
+    fn add_display(&mut self, text: &str) {
+        if text.is_empty() {
+            return self.show_displays();
+        }
+        let expression = match self.parse_expression(text) {
+            Ok(expression) => expression,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        // an expression that uses locals only makes sense in their function
+        let function = match self
+            .current_target()
+            .and_then(|_| self.selected_frame().ok())
+        {
+            Some(frame) => {
+                let locals = self
+                    .debug_data
+                    .get_scope_variables(frame.lookup_pc(), frame.inlined);
+                let names = expression.identifiers();
+                if locals.iter().any(|var| names.contains(&var.name.as_str())) {
+                    frame.function_and_line(&self.debug_data).0
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+        // like gdb, refuse an expression that can't be evaluated where it is set
+        let value = match self.current_target() {
+            Some(_) => match self.evaluate_expression(&expression) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            },
+            None => None,
+        };
+        self.display_count += 1;
+        if let Some(value) = value {
+            println!("{}: {} = {}", self.display_count, text, value);
+        }
+        self.displays.push(Display {
+            id: self.display_count,
+            text: text.to_string(),
+            expression,
+            function,
+        });
+    }
+
+    fn show_display(&mut self, display: &Display) {
+        match self.evaluate_expression(&display.expression) {
+            Ok(value) => println!("{}: {} = {}", display.id, display.text, value),
+            Err(e) => println!("{}: {} = <error: {}>", display.id, display.text, e),
+        }
+    }
+
+    /// Shows the expressions set with `display` that make sense in the selected frame.
+    fn show_displays(&mut self) {
+        let displays = std::mem::take(&mut self.displays);
+        let function = self.selected_function();
+        for display in displays
+            .iter()
+            .filter(|display| display.function.is_none() || display.function == function)
+        {
+            self.show_display(display);
+        }
+        self.displays = displays;
+    }
+
+    /// Implements `undisplay [ids]`. No ids means every display.
+    fn undisplay(&mut self, args: &[String]) {
+        if args.is_empty() {
+            self.displays.clear();
+            return;
+        }
+        let mut ids = Vec::new();
+        for arg in args {
+            match arg.parse::<usize>() {
+                Ok(id) => ids.push(id),
+                Err(_) => {
+                    println!("Arguments must be display numbers.");
+                    return;
+                }
+            }
+        }
+        for id in ids {
+            match self.displays.iter().position(|display| display.id == id) {
+                Some(index) => {
+                    self.displays.remove(index);
+                }
+                None => println!("No display number {}.", id),
+            }
+        }
+    }
+
+    /// Implements `info display`.
+    fn print_displays(&self) {
+        if self.displays.is_empty() {
+            println!("There are no auto-display expressions now.");
+            return;
+        }
+        println!("Auto-display expressions now in effect:");
+        println!("Num Expression");
+        for display in &self.displays {
+            println!("{}:   {}", display.id, display.text);
+        }
+    }
+
+    fn add_breakpoint(&mut self, spec: String) {
+        let (location, condition) = match spec.split_once(" if ") {
+            Some((location, condition)) => match Condition::parse(condition, &self.debug_data) {
+                Ok(condition) => (location.to_string(), Some(condition)),
+                Err(e) => {
+                    println!("Invalid condition \"{}\": {}", condition, e);
+                    return;
+                }
+            },
+            None => (spec, None),
+        };
+        match self.resolve_location(&location) {
+            Ok(Some(addrs)) => self.set_breakpoint(location, addrs, condition),
+            Ok(None) => {
+                let id = self.breakpoints.add_pending(location.clone(), condition);
+                println!("Breakpoint {} ({}) pending.", id, location);
+            }
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    /// Works out the addresses of a breakpoint location: a line in the current file, `file:line`,
+    /// `*addr`, a function, or a function in a source file or shared library as `file:func` or
+    /// `library:func`. A line without code slides forward to the next one with some, and may
+    /// resolve to several addresses. Returns None if the library isn't loaded yet.
+    fn resolve_location(&self, location: &str) -> Result<Option<Vec<usize>>, String> {
+        let usage = "Usage: b|break|breakpoint *address|[file:]line|[file:]func|library:func";
+        if let Some(addr) = location.strip_prefix('*') {
+            return match parse_address(addr) {
+                Some(addr) => Ok(Some(vec![addr])),
+                None => Err(format!("Invalid breakpoints: {}", location)),
+            };
+        }
+        let (file, rest) = match location.rsplit_once(':') {
+            Some((file, rest)) => (Some(file.trim()), rest.trim()),
+            None => (None, location.trim()),
+        };
+        if let Ok(line) = rest.parse::<usize>() {
+            let file = match file {
+                Some(file) => file.to_string(),
+                None => match self.sources.current_file() {
+                    Some(file) => file.to_string(),
+                    None => self.source_location("main")?.0,
+                },
+            };
+            return match self.debug_data.get_addrs_for_line(&file, line) {
+                Some((_, addrs)) => Ok(Some(addrs)),
+                // a file with code anywhere at all just doesn't have any this far down
+                None if self.debug_data.get_addrs_for_line(&file, 0).is_some() => {
+                    Err(format!("No line {} in file \"{}\".", line, file))
+                }
+                None => Err(format!("No source file named {}.", file)),
+            };
+        }
+        let addr = match file {
+            Some(file) => match self.debug_data.find(file) {
+                Some(module) => match module.debug_data.get_addr_for_function(None, rest) {
+                    Some(addr) => addr,
+                    None => return Err(format!("Function \"{}\" not defined in {}.", rest, file)),
+                },
+                None if self.debug_data.get_source_path(file).is_some() => {
+                    match self.debug_data.get_addr_for_function(Some(file), rest) {
+                        Some(addr) => addr,
+                        None => {
+                            return Err(format!(
+                                "Function \"{}\" not defined in \"{}\".",
+                                rest, file
+                            ))
+                        }
+                    }
+                }
+                None if file.contains(".so") => return Ok(None),
+                None => return Err(format!("No source file named {}.", file)),
+            },
+            None if rest.is_empty() => return Err(usage.to_string()),
+            None => match self.debug_data.get_addr_for_function(None, rest) {
+                Some(addr) => addr,
+                None => return Err(format!("Function \"{}\" not defined.", rest)),
+            },
+        };
+        // Stop after the prologue, once the arguments are in place for conditions to read
+        Ok(Some(vec![self
+            .debug_data
+            .get_function_containing(addr)
+            .map_or(addr, |func| {
+                self.debug_data.get_prologue_end(func)
+            })]))
+    }
+
+    /// Records a breakpoint and, if the inferior is running, inserts it right away.
+    fn set_breakpoint(
+        &mut self,
+        location: String,
+        addrs: Vec<usize>,
+        condition: Option<Condition>,
+    ) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            for (i, addr) in addrs.iter().enumerate() {
+                if let Err(e) = inferior.insert_breakpoint(*addr) {
+                    println!("Cannot insert breakpoint at {:#x}: {}", addr, e);
+                    // take out the ones already in, unless other breakpoints share them
+                    for addr in &addrs[..i] {
+                        self.sync_breakpoint_site(*addr);
+                    }
+                    return;
+                }
+            }
+        }
+        let shown: Vec<String> = addrs.iter().map(|addr| format!("{:#x}", addr)).collect();
+        let id = self.breakpoints.add(location, addrs, condition);
+        match shown.len() {
+            1 => println!("Set breakpoint {} at {}", id, shown[0]),
+            count => println!(
+                "Set breakpoint {} at {} locations: {}",
+                id,
+                count,
+                shown.join(", ")
+            ),
+        }
+    }
+
+    /// Makes the inferior's 0xcc at `addr` agree with the breakpoint table after a change.
+    fn sync_breakpoint_site(&mut self, addr: usize) {
+        if let Some(inferior) = self.inferior.as_mut() {
+            let result = if self.breakpoints.is_enabled_at(addr) {
+                inferior.insert_breakpoint(addr)
+            } else {
+                inferior.remove_breakpoint(addr)
+            };
+            if let Err(e) = result {
+                println!("Cannot update breakpoint at {:#x}: {}", addr, e);
+            }
+        }
+    }
+
+    /// Reprograms the inferior's debug registers to match the enabled watchpoints. Returns false
+    /// if that isn't possible.
+    fn sync_watchpoints(&mut self) -> bool {
+        if let Some(inferior) = self.inferior.as_mut() {
+            match inferior.set_watchpoints(&self.breakpoints.enabled_watchpoints()) {
+                Ok(()) => {}
+                Err(nix::Error::ENOSPC) => {
+                    println!(
+                        "Hardware watchpoints are limited to {} at a time.",
+                        DEBUG_REGISTER_SLOTS
+                    );
+                    return false;
+                }
+                Err(e) => {
+                    println!("Cannot set watchpoints: {}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Puts the watchpoints into a freshly started inferior.
+    fn restore_watchpoints(&mut self) {
+        if self.breakpoints.enabled_watchpoints().is_empty() || !self.sync_watchpoints() {
+            return;
+        }
+        let inferior = self.inferior.as_ref().unwrap();
+        self.breakpoints
+            .refresh_watchpoints(|addr, len| inferior.read_memory(addr, len));
+    }
+
+    /// Implements `watch`, `rwatch` and `awatch` on a variable or `*addr`.
+    fn add_watchpoint(&mut self, kind: WatchKind, expression: String) {
+        if expression.is_empty() {
+            println!("Usage: watch|rwatch|awatch <variable>|*address");
+            return;
+        }
+        if self.inferior.is_none() {
+            println!("The program is not being run.");
+            return;
+        }
+        let location = self
+            .parse_expression(&expression)
+            .and_then(|parsed| {
+                let mut evaluator = self.evaluator()?;
+                evaluator.evaluate(&parsed)
+            })
+            .and_then(|value| match value.bits {
+                Some(_) => Err(format!("Cannot watch {}: it is a bit field", expression)),
+                None => Ok((value.addr()?, value.entity_type)),
+            });
+        let (addr, entity_type) = match location {
+            Ok(location) => location,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        let size = entity_type.size;
+        if ![1, 2, 4, 8].contains(&size) || addr % size != 0 {
+            println!(
+                "Cannot watch {}: the debug registers only cover aligned 1, 2, 4 or 8 byte locations",
+                expression
+            );
+            return;
+        }
+        let inferior = self.inferior.as_mut().unwrap();
+        let mut watches = self.breakpoints.enabled_watchpoints();
+        watches.push((addr, size, kind));
+        match inferior.set_watchpoints(&watches) {
+            Ok(()) => {}
+            Err(nix::Error::ENOSPC) => {
+                println!(
+                    "Hardware watchpoints are limited to {} at a time.",
+                    DEBUG_REGISTER_SLOTS
+                );
+                return;
+            }
+            Err(e) => {
+                println!("Cannot set watchpoint: {}", e);
+                return;
+            }
+        }
+        let value = inferior.read_memory(addr, size).unwrap_or_default();
+        let id =
+            self.breakpoints
+                .add_watchpoint(expression.clone(), addr, kind, entity_type, value);
+        println!("{} {}: {}", watch_label(kind), id, expression);
+    }
+
+    /// Parses the breakpoint ids given to `delete`, `disable` or `enable`. No ids means every
+    /// breakpoint.
+    fn parse_breakpoint_ids(&self, args: &[String]) -> Option<Vec<usize>> {
+        if args.is_empty() {
+            return Some(self.breakpoints.ids());
+        }
+        let mut ids = Vec::new();
+        for arg in args {
+            match arg.parse::<usize>() {
+                Ok(id) => ids.push(id),
+                Err(_) => {
+                    println!("Bad breakpoint number '{}'", arg);
+                    return None;
+                }
+            }
+        }
+        Some(ids)
+    }
+
+    /// Implements `info breakpoints`.
+    fn print_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints.");
+            return;
+        }
+        println!(
+            "{:<7} {:<15} {:<3} {:<18} {:<5} What",
+            "Num", "Type", "Enb", "Address", "Hits"
+        );
+        for bp in self.breakpoints.iter() {
+            println!("{}", bp);
+        }
+    }
+
+    /// Implements `bt`: prints every frame of the stack, unwound with the call frame information.
+    fn print_backtrace(&self) {
+        let target = match self.current_target() {
+            Some(target) => target,
+            None => {
+                println!("No stack.");
+                return;
+            }
+        };
+        match unwind::backtrace(target, &self.debug_data) {
+            Ok(frames) => {
+                for (i, frame) in frames.iter().enumerate() {
+                    println!("{}", frame.describe(i, &self.debug_data));
+                }
+            }
+            Err(e) => println!("Cannot unwind the stack: {}", e),
+        }
+    }
+
+    /// Returns the frame selected with `frame`, `up` or `down`.
+    fn selected_frame(&self) -> Result<Frame, nix::Error> {
+        let target = self.current_target().unwrap();
+        let frames = unwind::backtrace(target, &self.debug_data)?;
+        let index = self.selected_frame.min(frames.len().saturating_sub(1));
+        // there is no frame to select if the unwinder found none at all
+        frames.into_iter().nth(index).ok_or(nix::Error::ESRCH)
+    }
+
+    /// Implements `frame`, `up` and `down`, then prints the newly selected frame.
+    fn select_frame(&mut self, args: &[String], movement: FrameMove) {
+        let target = match self.current_target() {
+            Some(target) => target,
+            None => {
+                println!("No stack.");
+                return;
+            }
+        };
+        let count = match args {
+            [] => None,
+            [count] => match count.parse::<usize>() {
+                Ok(count) => Some(count),
+                Err(_) => {
+                    println!("Invalid number \"{}\".", count);
+                    return;
+                }
+            },
+            _ => {
+                println!("Usage: frame|up|down [N]");
+                return;
+            }
+        };
+        let frames = match unwind::backtrace(target, &self.debug_data) {
+            Ok(frames) => frames,
+            Err(e) => {
+                println!("Cannot unwind the stack: {}", e);
+                return;
+            }
+        };
+        let selected = match movement {
+            FrameMove::To => count.unwrap_or(self.selected_frame),
+            FrameMove::Up => self.selected_frame + count.unwrap_or(1),
+            FrameMove::Down => match self.selected_frame.checked_sub(count.unwrap_or(1)) {
+                Some(selected) => selected,
+                None => {
+                    println!("Bottom (innermost) frame selected; you cannot go down.");
+                    return;
+                }
+            },
+        };
+        if selected >= frames.len() {
+            match movement {
+                FrameMove::Up => println!("Initial frame selected; you cannot go up."),
+                _ => println!("No frame at level {}.", selected),
+            }
+            return;
+        }
+        self.selected_frame = selected;
+        println!("{}", frames[selected].describe(selected, &self.debug_data));
+        if let (_, Some(line)) = frames[selected].function_and_line(&self.debug_data) {
+            self.sources.show_stop(&line.file, line.number);
+        }
+    }
+
+    /// Implements `info locals` and, with `parameters` set, `info args` for the selected frame.
+    fn print_frame_variables(&self, parameters: bool) {
+        let target = match self.current_target() {
+            Some(target) => target,
+            None => {
+                println!("No frame selected.");
+                return;
+            }
+        };
+        let frame = match self.selected_frame() {
+            Ok(frame) => frame,
+            Err(e) => {
+                println!("Cannot unwind the stack: {}", e);
+                return;
+            }
+        };
+        if self
+            .debug_data
+            .get_function_containing(frame.lookup_pc())
+            .is_none()
+        {
+            println!("No symbol table info available.");
+            return;
+        }
+        // innermost block first, like gdb
+        let mut variables = self
+            .debug_data
+            .get_scope_variables(frame.lookup_pc(), frame.inlined)
+            .into_iter()
+            .filter(|var| var.is_parameter == parameters)
+            .peekable();
+        if variables.peek().is_none() {
+            println!(
+                "{}",
+                if parameters {
+                    "No arguments."
+                } else {
+                    "No locals."
+                }
+            );
+        }
+        for var in variables {
+            let value = target
+                .variable_storage(&self.debug_data, var, &frame)
+                .and_then(|storage| storage.read(target, var.entity_type.size));
+            match value {
+                Ok(Some(bytes)) => {
+                    println!("{} = {}", var.name, var.entity_type.format_value(&bytes))
+                }
+                Ok(None) => println!("{} = <optimized out>", var.name),
+                Err(e) => println!("{} = <error: {}>", var.name, e),
+            }
+        }
+    }
+
+    /// Implements `info threads`, showing where each thread is.
+    fn print_threads(&mut self) {
+        let target = match current_target_mut(&mut self.inferior, &mut self.core) {
+            Some(target) => target,
+            None => {
+                println!("No threads.");
+                return;
+            }
+        };
+        let current = target.current_thread();
+        println!("  Id   Target Id         Frame");
+        for (id, tid) in target.threads() {
+            target.select_thread(id);
+            let location = match unwind::innermost_frame(target, &self.debug_data) {
+                Ok(frame) => frame.location(&self.debug_data),
+                Err(e) => format!("<error: {}>", e),
+            };
+            let marker = if id == current { '*' } else { ' ' };
+            println!(
+                "{} {:<4} {:<17} {}",
+                marker,
+                id,
+                format!("LWP {}", tid),
+                location
+            );
+        }
+        target.select_thread(current);
+    }
+
+    /// Implements `thread`, which shows the current thread, and `thread N`, which switches to
+    /// thread N.
+    fn select_thread(&mut self, args: &[String]) {
+        let target = match current_target_mut(&mut self.inferior, &mut self.core) {
+            Some(target) => target,
+            None => {
+                println!("No thread selected.");
+                return;
+            }
+        };
+        let id = match args {
+            [] => {
+                let current = target.current_thread();
+                let (_, tid) = target
+                    .threads()
+                    .into_iter()
+                    .find(|(id, _)| *id == current)
+                    .unwrap();
+                println!("[Current thread is {} (LWP {})]", current, tid);
+                return;
+            }
+            [id] => match id.parse::<usize>() {
+                Ok(id) => id,
+                Err(_) => {
+                    println!("Invalid thread ID: {}", id);
+                    return;
+                }
+            },
+            _ => {
+                println!("Usage: thread [N]");
+                return;
+            }
+        };
+        if !target.select_thread(id) {
+            println!("Unknown thread {}.", id);
+            return;
+        }
+        self.selected_frame = 0;
+        let (_, tid) = target
+            .threads()
+            .into_iter()
+            .find(|(thread, _)| *thread == id)
+            .unwrap();
+        println!("[Switching to thread {} (LWP {})]", id, tid);
+        match unwind::innermost_frame(target, &self.debug_data) {
+            Ok(frame) => println!("{}", frame.describe(0, &self.debug_data)),
+            Err(e) => println!("Cannot read the registers of thread {}: {}", id, e),
+        }
+    }
+
+    /// Implements `info inferiors`: the process we follow and those held after a fork.
+    fn print_inferiors(&self) {
+        match self.inferior.as_ref() {
+            Some(inferior) => {
+                println!("* process {} {}", inferior.pid(), self.target);
+                for pid in inferior.held_processes() {
+                    println!("  process {} (held after fork)", pid);
+                }
+            }
+            None => println!("No inferiors."),
+        }
+    }
+
+    /// Implements `info registers [name...]` and `info all-registers`, which adds the x87 and
+    /// SSE registers to the general purpose ones.
+    fn print_registers(&self, all: bool, names: &[String]) {
+        let target = match self.current_target() {
+            Some(target) => target,
+            None => {
+                println!("The program has no registers now.");
+                return;
+            }
+        };
+        let general: Vec<&str> = match names {
+            [] => registers::REGISTERS
+                .iter()
+                .map(|register| register.name)
+                .collect(),
+            names => names
+                .iter()
+                .map(|name| name.trim_start_matches('$'))
+                .collect(),
+        };
+        let fp = if all || general.iter().any(|name| registers::find(name).is_none()) {
+            match target.get_fp_registers() {
+                Ok(fpregs) => registers::fp_registers(&fpregs),
+                Err(e) => {
+                    println!("Cannot read floating point registers: {}", e);
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        for name in general {
+            let line = match registers::find(name) {
+                Some(_) => match self.register_value(target, name) {
+                    Ok((register, Some(value))) => {
+                        registers::columns(value, register.natural(value, &self.debug_data))
+                    }
+                    Ok((_, None)) => "<not saved>".to_string(),
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                },
+                None => match fp.iter().find(|(fp_name, _)| fp_name == name) {
+                    Some((_, value)) => value.clone(),
+                    None => {
+                        println!("Invalid register `{}'", name);
+                        return;
+                    }
+                },
+            };
+            println!("{:<15}{}", name, line);
+        }
+        if all && names.is_empty() {
+            for (name, value) in &fp {
+                println!("{:<15}{}", name, value);
+            }
+        }
+    }
+
+    /// Implements `info signals`, for every signal or just the ones given.
+    fn print_signals(&self, args: &[String]) {
+        let signals: Vec<Signal> = if args.is_empty() {
+            Signal::iterator().collect()
+        } else {
+            match args
+                .iter()
+                .map(|arg| parse_signal(arg).ok_or(arg))
+                .collect()
+            {
+                Ok(signals) => signals,
+                Err(arg) => {
+                    println!("Unknown signal \"{}\".", arg);
+                    return;
+                }
+            }
+        };
+        self.signals.print(&signals);
+    }
+
+    /// Implements `handle SIGNAL... [KEYWORD...]`, which changes how the signals are dealt with
+    /// (stop, nostop, print, noprint, pass, nopass) and shows the result.
+    fn handle_signals(&mut self, args: &[String]) {
+        // the signals come first; "stop" is a keyword here rather than SIGSTOP
+        let is_keyword = |arg: &String| SignalPolicy::default().apply(arg);
+        let split = args
+            .iter()
+            .position(|arg| is_keyword(arg) || (parse_signal(arg).is_none() && arg != "all"))
+            .unwrap_or(args.len());
+        let (names, keywords) = args.split_at(split);
+        if names.is_empty() {
+            match keywords.first() {
+                Some(arg) => println!("Unknown signal \"{}\".", arg),
+                None => {
+                    println!("Usage: handle SIGNAL... [stop|nostop|print|noprint|pass|nopass]...")
+                }
+            }
+            return;
+        }
+        let mut signals: Vec<Signal> = Vec::new();
+        for name in names {
+            match parse_signal(name) {
+                Some(signal) => signals.push(signal),
+                None => signals.extend(Signal::iterator()),
+            }
+        }
+        for signal in &signals {
+            let mut policy = self.signals.get(*signal);
+            for keyword in keywords {
+                if !policy.apply(keyword) {
+                    println!("Unrecognized flag word: \"{}\".", keyword);
+                    return;
+                }
+            }
+            self.signals.set(*signal, policy);
+        }
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_signal_table(self.signals.clone());
+        }
+        self.signals.print(&signals);
+    }
+
+    /// Implements `set follow-fork-mode parent|child`, `set detach-on-fork on|off` and
+    /// `set substitute-path FROM TO`, and hands assignments like `set var x = 5` or `set x = 5`
+    /// over to `set_variable`.
+    fn set_option(&mut self, args: &[String]) {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let mut policy = self.fork_policy;
+        match args[..] {
+            ["var" | "variable", ..] => return self.set_variable(&args[1..].join(" ")),
+            [option, ..]
+                if !matches!(
+                    option,
+                    "follow-fork-mode" | "detach-on-fork" | "substitute-path"
+                ) =>
+            {
+                return self.set_variable(&args.join(" "))
+            }
+            ["substitute-path", from, to] => return self.sources.substitute(from, to),
+            ["follow-fork-mode", "parent"] => policy.follow_child = false,
+            ["follow-fork-mode", "child"] => policy.follow_child = true,
+            ["detach-on-fork", "on"] => policy.detach = true,
+            ["detach-on-fork", "off"] => policy.detach = false,
+            _ => {
+                println!(
+                    "Usage: set follow-fork-mode parent|child, set detach-on-fork on|off, \
+                     set substitute-path FROM TO"
+                );
+                return;
+            }
+        }
+        self.fork_policy = policy;
+        if let Some(inferior) = self.inferior.as_mut() {
+            inferior.set_fork_policy(policy);
+        }
+    }
+
+    /// Implements `show follow-fork-mode`, `show detach-on-fork` and `show substitute-path`.
+    fn show_option(&self, args: &[String]) {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args[..] {
+            ["follow-fork-mode"] => println!(
+                "Debugger response to a program call of fork or vfork is \"{}\".",
+                if self.fork_policy.follow_child {
+                    "child"
+                } else {
+                    "parent"
+                }
+            ),
+            ["detach-on-fork"] => println!(
+                "Whether to detach the other process after a fork is {}.",
+                if self.fork_policy.detach { "on" } else { "off" }
+            ),
+            ["substitute-path"] => self.sources.print_substitutions(),
+            _ => println!("Usage: show follow-fork-mode|detach-on-fork|substitute-path"),
+        }
+    }
+
+    /// Implements `info sharedlibrary`.
+    fn print_shared_libraries(&self) {
+        let libraries = self.debug_data.libraries();
+        if libraries.is_empty() {
+            println!("No shared libraries loaded at this time.");
+            return;
+        }
+        println!("{:<18} {:<18} Shared Object Library", "From", "To");
+        for module in libraries {
+            let image = module.debug_data.image();
+            println!("{:#018x} {:#018x} {}", image.start, image.end, module.path);
+        }
+    }
+
+    /// Implements `delete`.
+    fn delete_breakpoints(&mut self, args: &[String]) {
+        let ids = match self.parse_breakpoint_ids(args) {
+            Some(ids) => ids,
+            None => return,
+        };
+        for id in ids {
+            match self.breakpoints.remove(id) {
+                Some(bp) if bp.watch.is_some() => {
+                    self.sync_watchpoints();
+                }
+                Some(bp) => {
+                    for addr in bp.addrs {
+                        self.sync_breakpoint_site(addr);
+                    }
+                }
+                None => println!("No breakpoint number {}.", id),
+            }
+        }
+    }
+
+    /// Implements `ignore`.
+    fn ignore_breakpoint(&mut self, args: &[String]) {
+        let (id, count) = match args {
+            [id, count] => match (id.parse::<usize>(), count.parse::<usize>()) {
+                (Ok(id), Ok(count)) => (id, count),
+                _ => {
+                    println!("Usage: ignore <breakpoint number> <count>");
+                    return;
+                }
+            },
+            _ => {
+                println!("Usage: ignore <breakpoint number> <count>");
+                return;
+            }
+        };
+        if !self.breakpoints.set_ignore_count(id, count) {
+            println!("No breakpoint number {}.", id);
+        } else if count == 0 {
+            println!("Will stop next time breakpoint {} is reached.", id);
+        } else {
+            println!("Will ignore next {} crossings of breakpoint {}.", count, id);
+        }
+    }
+
+    /// Implements `enable` and `disable`.
+    fn enable_breakpoints(&mut self, args: &[String], enabled: bool) {
+        let ids = match self.parse_breakpoint_ids(args) {
+            Some(ids) => ids,
+            None => return,
+        };
+        for id in ids {
+            let (addrs, is_watchpoint) = match self.breakpoints.set_enabled(id, enabled) {
+                Some(bp) => (bp.addrs.clone(), bp.watch.is_some()),
+                None => {
+                    println!("No breakpoint number {}.", id);
+                    continue;
+                }
+            };
+            if !is_watchpoint {
+                for addr in addrs {
+                    self.sync_breakpoint_site(addr);
+                }
+            } else if !self.sync_watchpoints() {
+                self.breakpoints.set_enabled(id, false);
+            } else if let Some(inferior) = self.inferior.as_ref() {
+                // don't report changes made while the watchpoint was off
+                self.breakpoints
+                    .refresh_watchpoints(|addr, len| inferior.read_memory(addr, len));
+            }
         }
     }
 }
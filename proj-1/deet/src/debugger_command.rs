@@ -1,13 +1,48 @@
+use crate::inferior::WatchKind;
+
 pub enum DebuggerCommand {
     Quit,
     Run(Vec<String>),
     Continue,
     BackTrace,
     Breakpoint(String),
+    Step,
+    Next,
+    Finish,
+    Print(String),
+    Display(String),
+    Undisplay(Vec<String>),
+    Examine(String, String),
+    Disassemble(String, String),
+    List(String),
+    InfoBreakpoints,
+    InfoDisplay,
+    InfoSharedLibrary,
+    InfoLocals,
+    InfoThreads,
+    InfoInferiors,
+    InfoSignals(Vec<String>),
+    InfoRegisters(bool, Vec<String>),
+    Handle(Vec<String>),
+    Set(Vec<String>),
+    Show(Vec<String>),
+    Thread(Vec<String>),
+    InfoArgs,
+    Frame(Vec<String>),
+    Up(Vec<String>),
+    Down(Vec<String>),
+    Delete(Vec<String>),
+    Disable(Vec<String>),
+    Enable(Vec<String>),
+    Ignore(Vec<String>),
+    Watch(WatchKind, String),
+    Attach(String),
+    Detach,
+    GenerateCore(Vec<String>),
 }
 
 impl DebuggerCommand {
-    pub fn from_tokens(tokens: &Vec<&str>) -> Option<DebuggerCommand> {
+    pub fn from_tokens(tokens: &[&str]) -> Option<DebuggerCommand> {
         match tokens[0] {
             "q" | "quit" => Some(DebuggerCommand::Quit),
             "r" | "run" => {
@@ -18,12 +53,112 @@ impl DebuggerCommand {
             }
             "c" | "cont" => Some(DebuggerCommand::Continue),
             "bt" | "backtrace" => Some(DebuggerCommand::BackTrace),
-            "b" | "break" => {
-                let args = tokens[1..].to_vec();
-                Some(DebuggerCommand::Breakpoint(
-                    args.iter().map(|s| s.to_string()).collect(),
+            "b" | "break" => Some(DebuggerCommand::Breakpoint(tokens[1..].join(" "))),
+            "s" | "step" => Some(DebuggerCommand::Step),
+            "n" | "next" => Some(DebuggerCommand::Next),
+            "fin" | "finish" => Some(DebuggerCommand::Finish),
+            "p" | "print" => Some(DebuggerCommand::Print(tokens[1..].join(" "))),
+            "display" => Some(DebuggerCommand::Display(tokens[1..].join(" "))),
+            "undisplay" => Some(DebuggerCommand::Undisplay(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            examine if examine == "x" || examine.starts_with("x/") => {
+                Some(DebuggerCommand::Examine(
+                    examine[1..].trim_start_matches('/').to_string(),
+                    tokens[1..].join(" "),
                 ))
             }
+            disassemble
+                if disassemble.split('/').next() == Some("disas")
+                    || disassemble.split('/').next() == Some("disassemble") =>
+            {
+                // the modifiers may be attached, as in disas/r, or a separate word
+                let mut modifiers: String = disassemble.split('/').skip(1).collect();
+                let mut args = &tokens[1..];
+                if let Some(word) = args.first().and_then(|word| word.strip_prefix('/')) {
+                    modifiers.push_str(word);
+                    args = &args[1..];
+                }
+                Some(DebuggerCommand::Disassemble(modifiers, args.join(" ")))
+            }
+            "l" | "list" => Some(DebuggerCommand::List(tokens[1..].join(" "))),
+            "i" | "info" => match tokens.get(1) {
+                Some(&"b") | Some(&"break") | Some(&"breakpoints") => {
+                    Some(DebuggerCommand::InfoBreakpoints)
+                }
+                Some(&"shared") | Some(&"sharedlibrary") => {
+                    Some(DebuggerCommand::InfoSharedLibrary)
+                }
+                Some(&"locals") => Some(DebuggerCommand::InfoLocals),
+                Some(&"display") => Some(DebuggerCommand::InfoDisplay),
+                Some(&"threads") => Some(DebuggerCommand::InfoThreads),
+                Some(&"inferiors") => Some(DebuggerCommand::InfoInferiors),
+                Some(&"signals") | Some(&"handle") => Some(DebuggerCommand::InfoSignals(
+                    tokens[2..].iter().map(|s| s.to_string()).collect(),
+                )),
+                Some(&"args") => Some(DebuggerCommand::InfoArgs),
+                Some(&"r") | Some(&"reg") | Some(&"registers") => {
+                    Some(DebuggerCommand::InfoRegisters(
+                        false,
+                        tokens[2..].iter().map(|s| s.to_string()).collect(),
+                    ))
+                }
+                Some(&"all-registers") => Some(DebuggerCommand::InfoRegisters(
+                    true,
+                    tokens[2..].iter().map(|s| s.to_string()).collect(),
+                )),
+                _ => None,
+            },
+            "d" | "delete" => Some(DebuggerCommand::Delete(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "disable" => Some(DebuggerCommand::Disable(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "enable" => Some(DebuggerCommand::Enable(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "ignore" => Some(DebuggerCommand::Ignore(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "watch" => Some(DebuggerCommand::Watch(
+                WatchKind::Write,
+                tokens[1..].join(" "),
+            )),
+            "rwatch" => Some(DebuggerCommand::Watch(
+                WatchKind::Read,
+                tokens[1..].join(" "),
+            )),
+            "awatch" => Some(DebuggerCommand::Watch(
+                WatchKind::Access,
+                tokens[1..].join(" "),
+            )),
+            "f" | "frame" => Some(DebuggerCommand::Frame(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "handle" => Some(DebuggerCommand::Handle(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "set" => Some(DebuggerCommand::Set(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "show" => Some(DebuggerCommand::Show(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "thread" => Some(DebuggerCommand::Thread(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "up" => Some(DebuggerCommand::Up(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "down" => Some(DebuggerCommand::Down(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
+            "attach" => Some(DebuggerCommand::Attach(tokens[1..].join(" "))),
+            "detach" => Some(DebuggerCommand::Detach),
+            "gcore" | "generate-core-file" => Some(DebuggerCommand::GenerateCore(
+                tokens[1..].iter().map(|s| s.to_string()).collect(),
+            )),
             // Default case:
             _ => None,
         }
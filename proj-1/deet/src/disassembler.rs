@@ -0,0 +1,2275 @@
+use crate::examine::symbolize;
+use crate::modules::Modules;
+use crate::source::SourceFiles;
+use crate::target::Target;
+use object::{Object, ObjectSection};
+use std::convert::TryInto;
+use std::ops::Range;
+
+/// The longest an x86 instruction can be.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+const REGS64: [&str; 16] = [
+    "rax", "rcx", "rdx", "rbx", "rsp", "rbp", "rsi", "rdi", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+const REGS32: [&str; 16] = [
+    "eax", "ecx", "edx", "ebx", "esp", "ebp", "esi", "edi", "r8d", "r9d", "r10d", "r11d", "r12d",
+    "r13d", "r14d", "r15d",
+];
+const REGS16: [&str; 16] = [
+    "ax", "cx", "dx", "bx", "sp", "bp", "si", "di", "r8w", "r9w", "r10w", "r11w", "r12w", "r13w",
+    "r14w", "r15w",
+];
+/// The byte registers with a REX prefix, which replaces %ah-%bh with the low bytes of
+/// %rsp-%rdi.
+const REGS8_REX: [&str; 16] = [
+    "al", "cl", "dl", "bl", "spl", "bpl", "sil", "dil", "r8b", "r9b", "r10b", "r11b", "r12b",
+    "r13b", "r14b", "r15b",
+];
+const REGS8: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+const SEGMENTS: [&str; 8] = ["es", "cs", "ss", "ds", "fs", "gs", "?", "?"];
+
+/// Condition codes, in the order of the low nibble of jcc, setcc and cmovcc.
+const CONDITIONS: [&str; 16] = [
+    "o", "no", "b", "ae", "e", "ne", "be", "a", "s", "ns", "p", "np", "l", "ge", "le", "g",
+];
+const ALU: [&str; 8] = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+const SHIFTS: [&str; 8] = ["rol", "ror", "rcl", "rcr", "shl", "shr", "shl", "sar"];
+const UNARY: [&str; 8] = ["test", "test", "not", "neg", "mul", "imul", "div", "idiv"];
+/// The predicates of cmpps and friends, by immediate.
+const PREDICATES: [&str; 8] = ["eq", "lt", "le", "unord", "neq", "nlt", "nle", "ord"];
+
+/// The predicates of the AVX-512 integer compares, by immediate.
+const VECTOR_PREDICATES: [&str; 8] = ["eq", "lt", "le", "false", "neq", "nlt", "nle", "true"];
+
+/// x87 instructions with a memory operand, by opcode (0xd8-0xdf) and the reg field of ModRM.
+const X87_MEMORY: [[&str; 8]; 8] = [
+    [
+        "fadds", "fmuls", "fcoms", "fcomps", "fsubs", "fsubrs", "fdivs", "fdivrs",
+    ],
+    [
+        "flds", "", "fsts", "fstps", "fldenv", "fldcw", "fnstenv", "fnstcw",
+    ],
+    [
+        "fiaddl", "fimull", "ficoml", "ficompl", "fisubl", "fisubrl", "fidivl", "fidivrl",
+    ],
+    [
+        "fildl", "fisttpl", "fistl", "fistpl", "", "fldt", "", "fstpt",
+    ],
+    [
+        "faddl", "fmull", "fcoml", "fcompl", "fsubl", "fsubrl", "fdivl", "fdivrl",
+    ],
+    [
+        "fldl", "fisttpll", "fstl", "fstpl", "frstor", "", "fnsave", "fnstsw",
+    ],
+    [
+        "fiadds", "fimuls", "ficoms", "ficomps", "fisubs", "fisubrs", "fidivs", "fidivrs",
+    ],
+    [
+        "filds", "fisttps", "fists", "fistps", "fbld", "fildll", "fbstp", "fistpll",
+    ],
+];
+
+/// A decoded instruction.
+pub struct Instruction {
+    pub len: usize,
+    /// The mnemonic and operands in AT&T syntax, like gdb shows them
+    pub text: String,
+    /// Where a direct jump or call goes. It is the last operand.
+    pub target: Option<usize>,
+    /// The address a %rip-relative operand refers to
+    pub reference: Option<usize>,
+}
+
+impl Instruction {
+    /// Renders the instruction with the symbols of the addresses it refers to.
+    pub fn render(&self, debug_data: &Modules) -> String {
+        let mut text = self.text.clone();
+        if let Some(symbol) = self.target.and_then(|target| symbolize(debug_data, target)) {
+            text = format!("{} {}", text, symbol);
+        }
+        if let Some(reference) = self.reference {
+            text = match symbolize(debug_data, reference) {
+                Some(symbol) => format!("{}        # {:#x} {}", text, reference, symbol),
+                None => format!("{}        # {:#x}", text, reference),
+            };
+        }
+        text
+    }
+}
+
+/// The ModRM byte of an instruction, with the memory operand it encodes, if any.
+struct ModRm {
+    reg: usize,
+    rm: usize,
+    mem: Option<String>,
+}
+
+/// The VEX or EVEX prefix of an AVX instruction, which stands for the 0x0f escapes, the SSE
+/// prefix and REX, and adds an operand.
+#[derive(Clone, Copy, Default)]
+struct Vex {
+    /// Which opcode map: 1, 2 and 3 are those after 0x0f, 0x0f 0x38 and 0x0f 0x3a
+    map: u8,
+    /// The SSE prefix it stands for: 0, 0x66, 0xf3 or 0xf2
+    prefix: u8,
+    /// The register of the extra source operand
+    vvvv: usize,
+    /// The vector length in bytes
+    len: usize,
+    evex: bool,
+    /// The opmask register of an EVEX instruction, if it isn't %k0
+    mask: usize,
+    /// Whether masked out elements are zeroed rather than left alone
+    zeroing: bool,
+    /// Whether the memory operand is one element broadcast to the whole vector
+    broadcast: bool,
+    /// What EVEX adds to the reg and register r/m operands, to reach %xmm16-%xmm31
+    reg_high: usize,
+    rm_high: usize,
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    addr: usize,
+    pos: usize,
+    rex: u8,
+    operand_prefix: bool,
+    address_prefix: bool,
+    /// The last of the F2 and F3 prefixes
+    rep: Option<u8>,
+    lock: bool,
+    segment: Option<u8>,
+    /// What an 8-bit displacement is multiplied by, which EVEX makes the size of the operand
+    disp8_scale: i64,
+    /// Prefixes that mean nothing here but are shown, like repeated 0x66
+    extra_prefixes: Vec<&'static str>,
+    /// Whether the instruction used the 0x66 and F2/F3 prefixes, which otherwise get shown as
+    /// prefix words the way objdump does
+    used_operand_prefix: bool,
+    used_rep: bool,
+    /// Whether the segment prefix went into a memory operand
+    used_segment: bool,
+    target: Option<usize>,
+    rip_displacement: Option<i64>,
+}
+
+/// The AT&T suffix for an operand of `size` bytes.
+fn suffix(size: usize) -> &'static str {
+    match size {
+        1 => "b",
+        2 => "w",
+        4 => "l",
+        _ => "q",
+    }
+}
+
+fn hex(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#x}", value.unsigned_abs())
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+/// An immediate operand, shown as the unsigned value of an operand of `size` bytes.
+fn immediate(value: i64, size: usize) -> String {
+    let mask = if size >= 8 {
+        u64::MAX
+    } else {
+        (1 << (size * 8)) - 1
+    };
+    format!("${:#x}", value as u64 & mask)
+}
+
+/// The name of the SSE2 integer instruction with `opcode` after 0x66 0x0f, as `pcmpeqb`.
+fn packed_integer(opcode: u8) -> Option<&'static str> {
+    Some(match opcode {
+        0x60 => "punpcklbw",
+        0x61 => "punpcklwd",
+        0x62 => "punpckldq",
+        0x63 => "packsswb",
+        0x64 => "pcmpgtb",
+        0x65 => "pcmpgtw",
+        0x66 => "pcmpgtd",
+        0x67 => "packuswb",
+        0x68 => "punpckhbw",
+        0x69 => "punpckhwd",
+        0x6a => "punpckhdq",
+        0x6b => "packssdw",
+        0x6c => "punpcklqdq",
+        0x6d => "punpckhqdq",
+        0x74 => "pcmpeqb",
+        0x75 => "pcmpeqw",
+        0x76 => "pcmpeqd",
+        0xd4 => "paddq",
+        0xd8 => "psubusb",
+        0xd9 => "psubusw",
+        0xda => "pminub",
+        0xdb => "pand",
+        0xdc => "paddusb",
+        0xdd => "paddusw",
+        0xde => "pmaxub",
+        0xdf => "pandn",
+        0xe0 => "pavgb",
+        0xe3 => "pavgw",
+        0xe4 => "pmulhuw",
+        0xe5 => "pmulhw",
+        0xe8 => "psubsb",
+        0xe9 => "psubsw",
+        0xea => "pminsw",
+        0xeb => "por",
+        0xec => "paddsb",
+        0xed => "paddsw",
+        0xee => "pmaxsw",
+        0xef => "pxor",
+        0xf4 => "pmuludq",
+        0xf5 => "pmaddwd",
+        0xf6 => "psadbw",
+        0xf8 => "psubb",
+        0xf9 => "psubw",
+        0xfa => "psubd",
+        0xfb => "psubq",
+        0xfc => "paddb",
+        0xfd => "paddw",
+        0xfe => "paddd",
+        _ => return None,
+    })
+}
+
+/// The name of the SSSE3 or SSE4 instruction with `opcode` after 0x66 0x0f 0x38, as `pshufb`.
+fn packed_integer_38(opcode: u8) -> Option<&'static str> {
+    Some(match opcode {
+        0x00 => "pshufb",
+        0x17 => "ptest",
+        0x29 => "pcmpeqq",
+        0x37 => "pcmpgtq",
+        0x38 => "pminsb",
+        0x39 => "pminsd",
+        0x3a => "pminuw",
+        0x3b => "pminud",
+        0x3c => "pmaxsb",
+        0x3d => "pmaxsd",
+        0x3e => "pmaxuw",
+        0x3f => "pmaxud",
+        _ => return None,
+    })
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8], addr: usize) -> Decoder<'a> {
+        Decoder {
+            bytes,
+            addr,
+            pos: 0,
+            rex: 0,
+            operand_prefix: false,
+            address_prefix: false,
+            rep: None,
+            lock: false,
+            segment: None,
+            disp8_scale: 1,
+            extra_prefixes: Vec::new(),
+            used_operand_prefix: false,
+            used_rep: false,
+            used_segment: false,
+            target: None,
+            rip_displacement: None,
+        }
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Reads a little-endian signed value of `size` bytes.
+    fn signed(&mut self, size: usize) -> Option<i64> {
+        let bytes = self.bytes.get(self.pos..self.pos + size)?;
+        self.pos += size;
+        Some(match size {
+            1 => bytes[0] as i8 as i64,
+            2 => i16::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            4 => i32::from_le_bytes(bytes.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(bytes.try_into().unwrap()),
+        })
+    }
+
+    fn rex_w(&self) -> bool {
+        self.rex & 8 != 0
+    }
+
+    /// The operand size of instructions that default to 32 bits.
+    fn size_v(&mut self) -> usize {
+        if self.rex_w() {
+            8
+        } else if self.operand_prefix {
+            self.used_operand_prefix = true;
+            2
+        } else {
+            4
+        }
+    }
+
+    /// The operand size of instructions that default to 64 bits, like push and call.
+    fn size_64(&mut self) -> usize {
+        if self.operand_prefix && !self.rex_w() {
+            self.used_operand_prefix = true;
+            2
+        } else {
+            8
+        }
+    }
+
+    /// The size of an immediate for an operand of `size` bytes, which is at most 4 bytes.
+    fn size_z(size: usize) -> usize {
+        size.min(4)
+    }
+
+    /// The mandatory prefix of an SSE instruction: F2 or F3 if present, else 0x66, else 0.
+    fn sse_prefix(&mut self) -> u8 {
+        if let Some(rep) = self.rep {
+            self.used_rep = true;
+            rep
+        } else if self.operand_prefix {
+            self.used_operand_prefix = true;
+            0x66
+        } else {
+            0
+        }
+    }
+
+    fn reg(&self, index: usize, size: usize) -> String {
+        let name = match size {
+            1 if self.rex != 0 => REGS8_REX[index],
+            1 => REGS8.get(index).copied().unwrap_or("?"),
+            2 => REGS16[index],
+            4 => REGS32[index],
+            8 => REGS64[index],
+            32 => return format!("%ymm{}", index),
+            64 => return format!("%zmm{}", index),
+            _ => return format!("%xmm{}", index),
+        };
+        format!("%{}", name)
+    }
+
+    fn modrm(&mut self) -> Option<ModRm> {
+        let modrm = self.byte()?;
+        let md = modrm >> 6;
+        let reg = ((modrm >> 3) & 7) as usize | if self.rex & 4 != 0 { 8 } else { 0 };
+        let rm = (modrm & 7) as usize | if self.rex & 1 != 0 { 8 } else { 0 };
+        if md == 3 {
+            return Some(ModRm { reg, rm, mem: None });
+        }
+        let mut base = Some(rm);
+        let mut index = None;
+        let mut scale = 1;
+        if modrm & 7 == 4 {
+            let sib = self.byte()?;
+            scale = 1 << (sib >> 6);
+            let sib_index = ((sib >> 3) & 7) as usize | if self.rex & 2 != 0 { 8 } else { 0 };
+            if sib_index != 4 {
+                index = Some(sib_index);
+            }
+            base = Some((sib & 7) as usize | if self.rex & 1 != 0 { 8 } else { 0 });
+            if sib & 7 == 5 && md == 0 {
+                base = None;
+            }
+        }
+        let rip_relative = md == 0 && modrm & 7 == 5;
+        let displacement = match md {
+            1 => Some(self.signed(1)? * self.disp8_scale),
+            2 => Some(self.signed(4)?),
+            _ if rip_relative || base.is_none() => Some(self.signed(4)?),
+            _ => None,
+        };
+        let mut mem = String::new();
+        if let Some(segment) = self
+            .segment
+            .filter(|segment| *segment == 0x64 || *segment == 0x65)
+        {
+            self.used_segment = true;
+            mem.push_str(if segment == 0x64 { "%fs:" } else { "%gs:" });
+        }
+        match displacement {
+            // an absolute address
+            Some(displacement) if base.is_none() && index.is_none() && !rip_relative => {
+                mem.push_str(&format!("{:#x}", displacement as u64))
+            }
+            Some(displacement) => mem.push_str(&hex(displacement)),
+            None => {}
+        }
+        if rip_relative {
+            self.rip_displacement = displacement;
+            mem.push_str(if self.address_prefix {
+                "(%eip)"
+            } else {
+                "(%rip)"
+            });
+        } else if base.is_some() || index.is_some() {
+            mem.push('(');
+            if let Some(base) = base {
+                mem.push_str(&self.address_reg(base));
+            }
+            if let Some(index) = index {
+                mem.push_str(&format!(",{},{}", self.address_reg(index), scale));
+            }
+            mem.push(')');
+        }
+        Some(ModRm {
+            reg,
+            rm,
+            mem: Some(mem),
+        })
+    }
+
+    /// A register used in an address, which is 32 bits wide after an 0x67 prefix.
+    fn address_reg(&self, index: usize) -> String {
+        if self.address_prefix {
+            format!("%{}", REGS32[index])
+        } else {
+            format!("%{}", REGS64[index])
+        }
+    }
+
+    /// The r/m operand, as a register of `size` bytes or memory.
+    fn rm(&self, modrm: &ModRm, size: usize) -> String {
+        match &modrm.mem {
+            Some(mem) => mem.clone(),
+            None => self.reg(modrm.rm, size),
+        }
+    }
+
+    /// Adds the size suffix to a mnemonic whose operand size nothing else shows: one with a
+    /// memory operand and no register.
+    fn sized(&self, mnemonic: &str, modrm: &ModRm, size: usize) -> String {
+        match modrm.mem {
+            Some(_) => format!("{}{}", mnemonic, suffix(size)),
+            None => mnemonic.to_string(),
+        }
+    }
+
+    /// Reads a relative branch displacement and returns the target as an operand.
+    fn branch(&mut self, size: usize) -> Option<String> {
+        let displacement = self.signed(size)?;
+        let target = (self.addr + self.pos).wrapping_add(displacement as usize);
+        self.target = Some(target);
+        Some(format!("{:#x}", target))
+    }
+
+    /// Decodes the instruction, returning its mnemonic and its operands in Intel order.
+    fn instruction(&mut self) -> Option<(String, Vec<String>)> {
+        loop {
+            match self.peek()? {
+                0x66 => {
+                    if self.operand_prefix {
+                        self.extra_prefixes.push("data16");
+                    }
+                    self.operand_prefix = true;
+                }
+                0x67 => self.address_prefix = true,
+                0xf0 => self.lock = true,
+                prefix @ (0xf2 | 0xf3) => self.rep = Some(prefix),
+                prefix @ (0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65) => self.segment = Some(prefix),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+        // in 64-bit mode these are always VEX and EVEX rather than les, lds and bound
+        if let 0xc4 | 0xc5 | 0x62 = self.peek()? {
+            return self.vex_instruction();
+        }
+        if let 0x40..=0x4f = self.peek()? {
+            self.rex = self.byte()?;
+        }
+        let opcode = self.byte()?;
+        let op = |mnemonic: &str, operands: Vec<String>| Some((mnemonic.to_string(), operands));
+        match opcode {
+            0x00..=0x3f if opcode & 7 < 6 => {
+                let mnemonic = ALU[(opcode >> 3) as usize];
+                match opcode & 7 {
+                    0 | 2 => {
+                        let m = self.modrm()?;
+                        let (rm, reg) = (self.rm(&m, 1), self.reg(m.reg, 1));
+                        op(
+                            mnemonic,
+                            if opcode & 2 == 0 {
+                                vec![rm, reg]
+                            } else {
+                                vec![reg, rm]
+                            },
+                        )
+                    }
+                    1 | 3 => {
+                        let size = self.size_v();
+                        let m = self.modrm()?;
+                        let (rm, reg) = (self.rm(&m, size), self.reg(m.reg, size));
+                        op(
+                            mnemonic,
+                            if opcode & 2 == 0 {
+                                vec![rm, reg]
+                            } else {
+                                vec![reg, rm]
+                            },
+                        )
+                    }
+                    4 => {
+                        let imm = self.signed(1)?;
+                        op(mnemonic, vec!["%al".to_string(), immediate(imm, 1)])
+                    }
+                    _ => {
+                        let size = self.size_v();
+                        let imm = self.signed(Self::size_z(size))?;
+                        op(mnemonic, vec![self.reg(0, size), immediate(imm, size)])
+                    }
+                }
+            }
+            0x50..=0x5f => {
+                let size = self.size_64();
+                let reg = (opcode & 7) as usize | if self.rex & 1 != 0 { 8 } else { 0 };
+                let mnemonic = if opcode < 0x58 { "push" } else { "pop" };
+                op(mnemonic, vec![self.reg(reg, size)])
+            }
+            0x63 => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                let mnemonic = if size == 8 { "movslq" } else { "movsxd" };
+                op(mnemonic, vec![self.reg(m.reg, size), self.rm(&m, 4)])
+            }
+            0x68 | 0x6a => {
+                let size = self.size_64();
+                let imm = self.signed(if opcode == 0x68 {
+                    Self::size_z(size)
+                } else {
+                    1
+                })?;
+                op("push", vec![immediate(imm, size)])
+            }
+            0x69 | 0x6b => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                let imm = self.signed(if opcode == 0x69 {
+                    Self::size_z(size)
+                } else {
+                    1
+                })?;
+                let operands = vec![
+                    self.reg(m.reg, size),
+                    self.rm(&m, size),
+                    immediate(imm, size),
+                ];
+                op("imul", operands)
+            }
+            0x70..=0x7f => {
+                let target = self.branch(1)?;
+                op(
+                    &format!("j{}", CONDITIONS[(opcode & 15) as usize]),
+                    vec![target],
+                )
+            }
+            0x80 | 0x81 | 0x83 => {
+                let size = if opcode == 0x80 { 1 } else { self.size_v() };
+                let m = self.modrm()?;
+                let imm = self.signed(if opcode == 0x81 {
+                    Self::size_z(size)
+                } else {
+                    1
+                })?;
+                let mnemonic = self.sized(ALU[m.reg & 7], &m, size);
+                Some((mnemonic, vec![self.rm(&m, size), immediate(imm, size)]))
+            }
+            0x84..=0x8b => {
+                let size = if opcode & 1 == 0 { 1 } else { self.size_v() };
+                let m = self.modrm()?;
+                let (rm, reg) = (self.rm(&m, size), self.reg(m.reg, size));
+                let mnemonic = match opcode {
+                    0x84 | 0x85 => "test",
+                    0x86 | 0x87 => "xchg",
+                    _ => "mov",
+                };
+                op(
+                    mnemonic,
+                    if opcode < 0x8a {
+                        vec![rm, reg]
+                    } else {
+                        vec![reg, rm]
+                    },
+                )
+            }
+            0x8c | 0x8e => {
+                let m = self.modrm()?;
+                let segment = format!("%{}", SEGMENTS[m.reg & 7]);
+                let rm = self.rm(&m, if m.mem.is_some() { 2 } else { 4 });
+                op(
+                    "mov",
+                    if opcode == 0x8c {
+                        vec![rm, segment]
+                    } else {
+                        vec![segment, rm]
+                    },
+                )
+            }
+            0x8d => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                m.mem.as_ref()?;
+                op("lea", vec![self.reg(m.reg, size), self.rm(&m, size)])
+            }
+            0x8f => {
+                let size = self.size_64();
+                let m = self.modrm()?;
+                op("pop", vec![self.rm(&m, size)])
+            }
+            0x90 if self.rex & 1 == 0 => match self.rep {
+                Some(0xf3) => {
+                    self.used_rep = true;
+                    op("pause", vec![])
+                }
+                _ if self.operand_prefix => {
+                    self.used_operand_prefix = true;
+                    op("xchg", vec!["%ax".to_string(), "%ax".to_string()])
+                }
+                _ => op("nop", vec![]),
+            },
+            0x90..=0x97 => {
+                let size = self.size_v();
+                let reg = (opcode & 7) as usize | if self.rex & 1 != 0 { 8 } else { 0 };
+                op("xchg", vec![self.reg(reg, size), self.reg(0, size)])
+            }
+            0x98 => {
+                let mnemonic = match self.size_v() {
+                    2 => "cbtw",
+                    4 => "cwtl",
+                    _ => "cltq",
+                };
+                op(mnemonic, vec![])
+            }
+            0x99 => {
+                let mnemonic = match self.size_v() {
+                    2 => "cwtd",
+                    4 => "cltd",
+                    _ => "cqto",
+                };
+                op(mnemonic, vec![])
+            }
+            0x9b => {
+                // fwait followed by an fn* instruction is the waiting form without the n
+                let start = self.pos;
+                if let Some(0xd9 | 0xdb | 0xdd | 0xdf) = self.peek() {
+                    let opcode = self.byte()?;
+                    if let Some((mnemonic, operands)) = self.x87(opcode) {
+                        if let Some(rest) = mnemonic.strip_prefix("fn") {
+                            return Some((format!("f{}", rest), operands));
+                        }
+                    }
+                }
+                self.pos = start;
+                op("fwait", vec![])
+            }
+            0x9c => op("pushf", vec![]),
+            0x9d => op("popf", vec![]),
+            0x9e => op("sahf", vec![]),
+            0x9f => op("lahf", vec![]),
+            0xa0..=0xa3 => {
+                let size = if opcode & 1 == 0 { 1 } else { self.size_v() };
+                let offset = format!("{:#x}", self.signed(8)? as u64);
+                let reg = self.reg(0, size);
+                op(
+                    "movabs",
+                    if opcode < 0xa2 {
+                        vec![reg, offset]
+                    } else {
+                        vec![offset, reg]
+                    },
+                )
+            }
+            0xa4..=0xa7 | 0xaa..=0xaf => self.string_instruction(opcode),
+            0xa8 => {
+                let imm = self.signed(1)?;
+                op("test", vec!["%al".to_string(), immediate(imm, 1)])
+            }
+            0xa9 => {
+                let size = self.size_v();
+                let imm = self.signed(Self::size_z(size))?;
+                op("test", vec![self.reg(0, size), immediate(imm, size)])
+            }
+            0xb0..=0xbf => {
+                let size = if opcode < 0xb8 { 1 } else { self.size_v() };
+                let reg = (opcode & 7) as usize | if self.rex & 1 != 0 { 8 } else { 0 };
+                let imm = self.signed(size)?;
+                let mnemonic = if size == 8 { "movabs" } else { "mov" };
+                op(mnemonic, vec![self.reg(reg, size), immediate(imm, size)])
+            }
+            0xc0 | 0xc1 | 0xd0..=0xd3 => {
+                let size = if opcode & 1 == 0 { 1 } else { self.size_v() };
+                let m = self.modrm()?;
+                let mnemonic = self.sized(SHIFTS[m.reg & 7], &m, size);
+                let mut operands = vec![self.rm(&m, size)];
+                match opcode {
+                    0xc0 | 0xc1 => operands.push(immediate(self.signed(1)?, 1)),
+                    0xd2 | 0xd3 => operands.push("%cl".to_string()),
+                    _ => {}
+                }
+                Some((mnemonic, operands))
+            }
+            0xc2 => {
+                let imm = self.signed(2)?;
+                op("ret", vec![immediate(imm, 2)])
+            }
+            0xc3 => op("ret", vec![]),
+            0xc6 if self.peek()? == 0xf8 => {
+                self.pos += 1;
+                let imm = self.signed(1)?;
+                op("xabort", vec![immediate(imm, 1)])
+            }
+            0xc7 if self.peek()? == 0xf8 => {
+                self.pos += 1;
+                let size = Self::size_z(self.size_v());
+                let target = self.branch(size)?;
+                op("xbegin", vec![target])
+            }
+            0xc6 | 0xc7 => {
+                let size = if opcode == 0xc6 { 1 } else { self.size_v() };
+                let m = self.modrm()?;
+                if m.reg & 7 != 0 {
+                    return None;
+                }
+                let imm = self.signed(Self::size_z(size))?;
+                let mnemonic = self.sized("mov", &m, size);
+                Some((mnemonic, vec![self.rm(&m, size), immediate(imm, size)]))
+            }
+            0xc8 => {
+                let size = self.signed(2)?;
+                let level = self.signed(1)?;
+                op("enter", vec![immediate(level, 1), immediate(size, 2)])
+            }
+            0xc9 => op("leave", vec![]),
+            0xcc => op("int3", vec![]),
+            0xcd => {
+                let imm = self.signed(1)?;
+                op("int", vec![immediate(imm, 1)])
+            }
+            0xd8..=0xdf => self.x87(opcode),
+            0xe0..=0xe3 => {
+                let target = self.branch(1)?;
+                let mnemonic = ["loopne", "loope", "loop", "jrcxz"][(opcode & 3) as usize];
+                op(mnemonic, vec![target])
+            }
+            0xe8 => {
+                let target = self.branch(4)?;
+                op("call", vec![target])
+            }
+            0xe9 | 0xeb => {
+                let target = self.branch(if opcode == 0xe9 { 4 } else { 1 })?;
+                op("jmp", vec![target])
+            }
+            0xf4 => op("hlt", vec![]),
+            0xf5 => op("cmc", vec![]),
+            0xf6 | 0xf7 => {
+                let size = if opcode == 0xf6 { 1 } else { self.size_v() };
+                let m = self.modrm()?;
+                let mnemonic = self.sized(UNARY[m.reg & 7], &m, size);
+                let mut operands = vec![self.rm(&m, size)];
+                if m.reg & 7 < 2 {
+                    operands.push(immediate(self.signed(Self::size_z(size))?, size));
+                }
+                Some((mnemonic, operands))
+            }
+            0xf8 => op("clc", vec![]),
+            0xf9 => op("stc", vec![]),
+            0xfc => op("cld", vec![]),
+            0xfd => op("std", vec![]),
+            0xfe => {
+                let m = self.modrm()?;
+                let mnemonic = ["inc", "dec"].get(m.reg & 7)?;
+                let mnemonic = self.sized(mnemonic, &m, 1);
+                Some((mnemonic, vec![self.rm(&m, 1)]))
+            }
+            0xff => {
+                let peeked = self.bytes.get(self.pos).copied()?;
+                let size = match (peeked >> 3) & 7 {
+                    0 | 1 => self.size_v(),
+                    _ => self.size_64(),
+                };
+                let m = self.modrm()?;
+                match m.reg & 7 {
+                    0 | 1 => {
+                        let mnemonic = self.sized(["inc", "dec"][m.reg & 7], &m, size);
+                        Some((mnemonic, vec![self.rm(&m, size)]))
+                    }
+                    2 | 4 => {
+                        let mnemonic = if m.reg & 7 == 2 { "call" } else { "jmp" };
+                        op(mnemonic, vec![format!("*{}", self.rm(&m, size))])
+                    }
+                    3 | 5 => {
+                        let mnemonic = if m.reg & 7 == 3 { "lcall" } else { "ljmp" };
+                        op(mnemonic, vec![format!("*{}", self.rm(&m, size))])
+                    }
+                    6 => op("push", vec![self.rm(&m, size)]),
+                    _ => None,
+                }
+            }
+            0x0f => self.two_byte(),
+            _ => None,
+        }
+    }
+
+    /// Decodes movs, cmps, stos, lods and scas, whose operands are implicit.
+    fn string_instruction(&mut self, opcode: u8) -> Option<(String, Vec<String>)> {
+        let size = if opcode & 1 == 0 { 1 } else { self.size_v() };
+        let source = "%ds:(%rsi)".to_string();
+        let destination = "%es:(%rdi)".to_string();
+        let (mnemonic, operands, compares) = match opcode & 0xfe {
+            0xa4 => (
+                format!("movs{}", suffix(size)),
+                vec![destination, source],
+                false,
+            ),
+            0xa6 => (
+                format!("cmps{}", suffix(size)),
+                vec![source, destination],
+                true,
+            ),
+            0xaa => (
+                "stos".to_string(),
+                vec![destination, self.reg(0, size)],
+                false,
+            ),
+            0xac => ("lods".to_string(), vec![self.reg(0, size), source], false),
+            _ => (
+                "scas".to_string(),
+                vec![self.reg(0, size), destination],
+                true,
+            ),
+        };
+        let prefix = match (self.rep, compares) {
+            (Some(0xf3), false) => "rep ",
+            (Some(0xf3), true) => "repz ",
+            (Some(_), _) => "repnz ",
+            (None, _) => "",
+        };
+        self.used_rep = true;
+        Some((format!("{}{}", prefix, mnemonic), operands))
+    }
+
+    /// Decodes the x87 floating point instructions, 0xd8-0xdf.
+    fn x87(&mut self, opcode: u8) -> Option<(String, Vec<String>)> {
+        let m = self.modrm()?;
+        let group = (opcode - 0xd8) as usize;
+        let reg = m.reg & 7;
+        if let Some(mem) = &m.mem {
+            let mnemonic = X87_MEMORY[group][reg];
+            return match mnemonic {
+                "" => None,
+                _ => Some((mnemonic.to_string(), vec![mem.clone()])),
+            };
+        }
+        let i = m.rm & 7;
+        let st = |i: usize| format!("%st({})", i);
+        let top = "%st".to_string();
+        let op = |mnemonic: &str, operands: Vec<String>| Some((mnemonic.to_string(), operands));
+        match (opcode, reg) {
+            (0xd8, _) => {
+                let mnemonic = [
+                    "fadd", "fmul", "fcom", "fcomp", "fsub", "fsubr", "fdiv", "fdivr",
+                ][reg];
+                op(mnemonic, vec![top, st(i)])
+            }
+            (0xd9, 0) => op("fld", vec![st(i)]),
+            (0xd9, 1) => op("fxch", vec![st(i)]),
+            (0xd9, _) => {
+                let mnemonic = match 0xc0 | (reg << 3) as u8 | i as u8 {
+                    0xd0 => "fnop",
+                    0xe0 => "fchs",
+                    0xe1 => "fabs",
+                    0xe4 => "ftst",
+                    0xe5 => "fxam",
+                    0xe8 => "fld1",
+                    0xe9 => "fldl2t",
+                    0xea => "fldl2e",
+                    0xeb => "fldpi",
+                    0xec => "fldlg2",
+                    0xed => "fldln2",
+                    0xee => "fldz",
+                    0xf0 => "f2xm1",
+                    0xf1 => "fyl2x",
+                    0xf2 => "fptan",
+                    0xf3 => "fpatan",
+                    0xf4 => "fxtract",
+                    0xf5 => "fprem1",
+                    0xf6 => "fdecstp",
+                    0xf7 => "fincstp",
+                    0xf8 => "fprem",
+                    0xf9 => "fyl2xp1",
+                    0xfa => "fsqrt",
+                    0xfb => "fsincos",
+                    0xfc => "frndint",
+                    0xfd => "fscale",
+                    0xfe => "fsin",
+                    0xff => "fcos",
+                    _ => return None,
+                };
+                op(mnemonic, vec![])
+            }
+            (0xda, 5) if i == 1 => op("fucompp", vec![]),
+            (0xda, 0..=3) => {
+                let mnemonic = ["fcmovb", "fcmove", "fcmovbe", "fcmovu"][reg];
+                op(mnemonic, vec![top, st(i)])
+            }
+            (0xdb, 0..=3) => {
+                let mnemonic = ["fcmovnb", "fcmovne", "fcmovnbe", "fcmovnu"][reg];
+                op(mnemonic, vec![top, st(i)])
+            }
+            (0xdb, 4) if i == 2 => op("fnclex", vec![]),
+            (0xdb, 4) if i == 3 => op("fninit", vec![]),
+            (0xdb, 5) => op("fucomi", vec![top, st(i)]),
+            (0xdb, 6) => op("fcomi", vec![top, st(i)]),
+            (0xdc, 2) | (0xdc, 3) => None,
+            (0xdc, _) => {
+                // objdump's AT&T names for these swap the r in fsub(r) and fdiv(r)
+                let mnemonic = ["fadd", "fmul", "", "", "fsub", "fsubr", "fdiv", "fdivr"][reg];
+                op(mnemonic, vec![st(i), top])
+            }
+            (0xdd, 0) => op("ffree", vec![st(i)]),
+            (0xdd, 2) => op("fst", vec![st(i)]),
+            (0xdd, 3) => op("fstp", vec![st(i)]),
+            (0xdd, 4) => op("fucom", vec![st(i)]),
+            (0xdd, 5) => op("fucomp", vec![st(i)]),
+            (0xde, 3) if i == 1 => op("fcompp", vec![]),
+            (0xde, 2) | (0xde, 3) => None,
+            (0xde, _) => {
+                let mnemonic = [
+                    "faddp", "fmulp", "", "", "fsubp", "fsubrp", "fdivp", "fdivrp",
+                ][reg];
+                op(mnemonic, vec![st(i), top])
+            }
+            (0xdf, 0) => op("ffreep", vec![st(i)]),
+            (0xdf, 4) if i == 0 => op("fnstsw", vec!["%ax".to_string()]),
+            (0xdf, 5) => op("fucomip", vec![top, st(i)]),
+            (0xdf, 6) => op("fcomip", vec![top, st(i)]),
+            _ => None,
+        }
+    }
+
+    /// Decodes the instructions that start with 0x0f.
+    fn two_byte(&mut self) -> Option<(String, Vec<String>)> {
+        let opcode = self.byte()?;
+        let op = |mnemonic: &str, operands: Vec<String>| Some((mnemonic.to_string(), operands));
+        match opcode {
+            0x05 => op("syscall", vec![]),
+            0x0b => op("ud2", vec![]),
+            0x01 if self.peek()? == 0xd0 => {
+                self.pos += 1;
+                op("xgetbv", vec![])
+            }
+            0x01 if self.peek()? == 0xd5 => {
+                self.pos += 1;
+                op("xend", vec![])
+            }
+            0x01 if self.peek()? == 0xd6 => {
+                self.pos += 1;
+                op("xtest", vec![])
+            }
+            0x01 if self.peek()? == 0xee => {
+                self.pos += 1;
+                op("rdpkru", vec![])
+            }
+            0x01 if self.peek()? == 0xef => {
+                self.pos += 1;
+                op("wrpkru", vec![])
+            }
+            0x01 if self.peek()? == 0xf9 => {
+                self.pos += 1;
+                op("rdtscp", vec![])
+            }
+            0x18 => {
+                let m = self.modrm()?;
+                let mnemonic =
+                    ["prefetchnta", "prefetcht0", "prefetcht1", "prefetcht2"].get(m.reg & 7)?;
+                op(mnemonic, vec![m.mem?])
+            }
+            0x1e if self.rep == Some(0xf3) && matches!(self.peek()?, 0xfa | 0xfb) => {
+                self.used_rep = true;
+                let mnemonic = if self.byte()? == 0xfa {
+                    "endbr64"
+                } else {
+                    "endbr32"
+                };
+                op(mnemonic, vec![])
+            }
+            0x1f => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                let mnemonic = self.sized("nop", &m, size);
+                Some((mnemonic, vec![self.rm(&m, size)]))
+            }
+            0x31 => op("rdtsc", vec![]),
+            0x38 => self.three_byte_38(),
+            0x3a => self.three_byte_3a(),
+            0xa2 => op("cpuid", vec![]),
+            0x40..=0x4f => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                let mnemonic = format!("cmov{}", CONDITIONS[(opcode & 15) as usize]);
+                Some((mnemonic, vec![self.reg(m.reg, size), self.rm(&m, size)]))
+            }
+            0x80..=0x8f => {
+                let target = self.branch(4)?;
+                op(
+                    &format!("j{}", CONDITIONS[(opcode & 15) as usize]),
+                    vec![target],
+                )
+            }
+            0x90..=0x9f => {
+                let m = self.modrm()?;
+                let mnemonic = format!("set{}", CONDITIONS[(opcode & 15) as usize]);
+                Some((mnemonic, vec![self.rm(&m, 1)]))
+            }
+            0xa3 | 0xab | 0xb3 | 0xbb => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                let mnemonic = ["bt", "bts", "btr", "btc"][((opcode >> 3) & 3) as usize];
+                op(mnemonic, vec![self.rm(&m, size), self.reg(m.reg, size)])
+            }
+            0xba => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                if m.reg & 7 < 4 {
+                    return None;
+                }
+                let mnemonic = self.sized(["bt", "bts", "btr", "btc"][m.reg & 3], &m, size);
+                let imm = immediate(self.signed(1)?, 1);
+                Some((mnemonic, vec![self.rm(&m, size), imm]))
+            }
+            0xa4 | 0xa5 | 0xac | 0xad => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                let mut operands = vec![self.rm(&m, size), self.reg(m.reg, size)];
+                if opcode & 1 == 0 {
+                    operands.push(immediate(self.signed(1)?, 1));
+                } else {
+                    operands.push("%cl".to_string());
+                }
+                op(if opcode < 0xa8 { "shld" } else { "shrd" }, operands)
+            }
+            0xae => {
+                let m = self.modrm()?;
+                match (&m.mem, m.reg & 7) {
+                    (Some(mem), 2) => op("ldmxcsr", vec![mem.clone()]),
+                    (Some(mem), 3) => op("stmxcsr", vec![mem.clone()]),
+                    (Some(mem), 7) => op("clflush", vec![mem.clone()]),
+                    (None, 5) => op("lfence", vec![]),
+                    (None, 6) => op("mfence", vec![]),
+                    (None, 7) => op("sfence", vec![]),
+                    _ => None,
+                }
+            }
+            0xaf => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                op("imul", vec![self.reg(m.reg, size), self.rm(&m, size)])
+            }
+            0xb0 | 0xb1 | 0xc0 | 0xc1 => {
+                let size = if opcode & 1 == 0 { 1 } else { self.size_v() };
+                let m = self.modrm()?;
+                let mnemonic = if opcode < 0xc0 { "cmpxchg" } else { "xadd" };
+                op(mnemonic, vec![self.rm(&m, size), self.reg(m.reg, size)])
+            }
+            0xb6 | 0xb7 | 0xbe | 0xbf => {
+                let size = self.size_v();
+                let source_size = if opcode & 1 == 0 { 1 } else { 2 };
+                let m = self.modrm()?;
+                let kind = if opcode < 0xb8 { "movz" } else { "movs" };
+                let mnemonic = format!("{}{}{}", kind, suffix(source_size), suffix(size));
+                Some((
+                    mnemonic,
+                    vec![self.reg(m.reg, size), self.rm(&m, source_size)],
+                ))
+            }
+            0xbc | 0xbd => {
+                let size = self.size_v();
+                let m = self.modrm()?;
+                let mnemonic = match (self.rep == Some(0xf3), opcode) {
+                    (true, 0xbc) => "tzcnt",
+                    (true, _) => "lzcnt",
+                    (false, 0xbc) => "bsf",
+                    (false, _) => "bsr",
+                };
+                if self.rep == Some(0xf3) {
+                    self.used_rep = true;
+                }
+                op(mnemonic, vec![self.reg(m.reg, size), self.rm(&m, size)])
+            }
+            0xc8..=0xcf => {
+                let size = if self.rex_w() { 8 } else { 4 };
+                let reg = (opcode & 7) as usize | if self.rex & 1 != 0 { 8 } else { 0 };
+                op("bswap", vec![self.reg(reg, size)])
+            }
+            _ => self.sse(opcode),
+        }
+    }
+
+    /// Decodes the few instructions starting 0x0f 0x38 that string functions use.
+    fn three_byte_38(&mut self) -> Option<(String, Vec<String>)> {
+        let opcode = self.byte()?;
+        if opcode == 0xf0 || opcode == 0xf1 {
+            let size = self.size_v();
+            let m = self.modrm()?;
+            let (rm, reg) = (m.mem.clone()?, self.reg(m.reg, size));
+            let operands = if opcode == 0xf0 {
+                vec![reg, rm]
+            } else {
+                vec![rm, reg]
+            };
+            return Some(("movbe".to_string(), operands));
+        }
+        if self.sse_prefix() != 0x66 {
+            return None;
+        }
+        let mnemonic = packed_integer_38(opcode)?;
+        let m = self.modrm()?;
+        Some((
+            mnemonic.to_string(),
+            vec![self.reg(m.reg, 16), self.rm(&m, 16)],
+        ))
+    }
+
+    /// Decodes the few instructions starting 0x0f 0x3a that string functions use.
+    fn three_byte_3a(&mut self) -> Option<(String, Vec<String>)> {
+        let opcode = self.byte()?;
+        if self.sse_prefix() != 0x66 {
+            return None;
+        }
+        let mnemonic = match opcode {
+            0x08 => "roundps",
+            0x09 => "roundpd",
+            0x0a => "roundss",
+            0x0b => "roundsd",
+            0x0f => "palignr",
+            0x60 => "pcmpestrm",
+            0x61 => "pcmpestri",
+            0x62 => "pcmpistrm",
+            0x63 => "pcmpistri",
+            _ => return None,
+        };
+        let m = self.modrm()?;
+        let imm = immediate(self.signed(1)?, 1);
+        Some((
+            mnemonic.to_string(),
+            vec![self.reg(m.reg, 16), self.rm(&m, 16), imm],
+        ))
+    }
+
+    /// Decodes the SSE instructions gcc emits for floating point and vector code.
+    fn sse(&mut self, opcode: u8) -> Option<(String, Vec<String>)> {
+        let prefix = self.sse_prefix();
+        // the packed single, packed double, scalar single and scalar double forms
+        let form = match prefix {
+            0 => "ps",
+            0x66 => "pd",
+            0xf3 => "ss",
+            _ => "sd",
+        };
+        let xmm = 16;
+        let op = |mnemonic: &str, operands: Vec<String>| Some((mnemonic.to_string(), operands));
+        match opcode {
+            0x10 | 0x11 => {
+                let m = self.modrm()?;
+                let mnemonic = match prefix {
+                    0 => "movups",
+                    0x66 => "movupd",
+                    0xf3 => "movss",
+                    _ => "movsd",
+                };
+                let (rm, reg) = (self.rm(&m, xmm), self.reg(m.reg, xmm));
+                op(
+                    mnemonic,
+                    if opcode == 0x10 {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            0x12 | 0x13 | 0x16 | 0x17 if prefix == 0 || prefix == 0x66 => {
+                let m = self.modrm()?;
+                let high = opcode >= 0x16;
+                let mnemonic = match (high, prefix, &m.mem) {
+                    (false, 0, None) => "movhlps",
+                    (true, 0, None) => "movlhps",
+                    (false, 0, _) => "movlps",
+                    (true, 0, _) => "movhps",
+                    (false, _, _) => "movlpd",
+                    (true, _, _) => "movhpd",
+                };
+                let (rm, reg) = (self.rm(&m, xmm), self.reg(m.reg, xmm));
+                op(
+                    mnemonic,
+                    if opcode & 1 == 0 {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            0x14 | 0x15 if prefix == 0 || prefix == 0x66 => {
+                let m = self.modrm()?;
+                let kind = if opcode == 0x14 { "unpckl" } else { "unpckh" };
+                op(
+                    &format!("{}{}", kind, form),
+                    vec![self.reg(m.reg, xmm), self.rm(&m, xmm)],
+                )
+            }
+            0x28 | 0x29 if prefix == 0 || prefix == 0x66 => {
+                let m = self.modrm()?;
+                let (rm, reg) = (self.rm(&m, xmm), self.reg(m.reg, xmm));
+                let mnemonic = format!("mova{}", form);
+                op(
+                    &mnemonic,
+                    if opcode == 0x28 {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            0x2b if prefix == 0 || prefix == 0x66 => {
+                let m = self.modrm()?;
+                op(
+                    &format!("movnt{}", form),
+                    vec![m.mem?, self.reg(m.reg, xmm)],
+                )
+            }
+            0x2a if prefix == 0xf2 || prefix == 0xf3 => {
+                let size = if self.rex_w() { 8 } else { 4 };
+                let m = self.modrm()?;
+                let mnemonic = self.sized(&format!("cvtsi2{}", form), &m, size);
+                Some((mnemonic, vec![self.reg(m.reg, xmm), self.rm(&m, size)]))
+            }
+            0x2c | 0x2d if prefix == 0xf2 || prefix == 0xf3 => {
+                let size = if self.rex_w() { 8 } else { 4 };
+                let m = self.modrm()?;
+                let truncate = if opcode == 0x2c { "t" } else { "" };
+                let mnemonic = format!("cvt{}{}2si", truncate, form);
+                op(&mnemonic, vec![self.reg(m.reg, size), self.rm(&m, xmm)])
+            }
+            0x2e | 0x2f if prefix == 0 || prefix == 0x66 => {
+                let m = self.modrm()?;
+                let kind = if opcode == 0x2e { "ucomis" } else { "comis" };
+                let mnemonic = format!("{}{}", kind, &form[1..]);
+                op(&mnemonic, vec![self.reg(m.reg, xmm), self.rm(&m, xmm)])
+            }
+            0x50 if prefix == 0 || prefix == 0x66 => {
+                let m = self.modrm()?;
+                if m.mem.is_some() {
+                    return None;
+                }
+                let mnemonic = format!("movmsk{}", form);
+                op(&mnemonic, vec![self.reg(m.reg, 4), self.rm(&m, xmm)])
+            }
+            0x51..=0x5f => {
+                let m = self.modrm()?;
+                let mnemonic = match (opcode, prefix) {
+                    (0x5a, 0) => "cvtps2pd".to_string(),
+                    (0x5a, 0x66) => "cvtpd2ps".to_string(),
+                    (0x5a, 0xf3) => "cvtss2sd".to_string(),
+                    (0x5a, _) => "cvtsd2ss".to_string(),
+                    (0x5b, 0) => "cvtdq2ps".to_string(),
+                    (0x5b, 0x66) => "cvtps2dq".to_string(),
+                    (0x5b, 0xf3) => "cvttps2dq".to_string(),
+                    (0x54..=0x57, 0xf2 | 0xf3) | (0x52 | 0x53, 0x66 | 0xf2) | (0x5b, _) => {
+                        return None
+                    }
+                    _ => {
+                        let operation = [
+                            "sqrt", "rsqrt", "rcp", "and", "andn", "or", "xor", "add", "mul", "",
+                            "", "sub", "min", "div", "max",
+                        ][(opcode - 0x51) as usize];
+                        format!("{}{}", operation, form)
+                    }
+                };
+                Some((mnemonic, vec![self.reg(m.reg, xmm), self.rm(&m, xmm)]))
+            }
+            0x60..=0x6d
+            | 0x74..=0x76
+            | 0xd4
+            | 0xd8..=0xdf
+            | 0xe0..=0xe5
+            | 0xe8..=0xef
+            | 0xf1..=0xfe
+                if prefix == 0x66 =>
+            {
+                let mnemonic = packed_integer(opcode)?;
+                let m = self.modrm()?;
+                op(mnemonic, vec![self.reg(m.reg, xmm), self.rm(&m, xmm)])
+            }
+            0x6e | 0x7e if prefix == 0x66 => {
+                let size = if self.rex_w() { 8 } else { 4 };
+                let m = self.modrm()?;
+                let mnemonic = if size == 8 { "movq" } else { "movd" };
+                let (rm, reg) = (self.rm(&m, size), self.reg(m.reg, xmm));
+                op(
+                    mnemonic,
+                    if opcode == 0x6e {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            0x7e if prefix == 0xf3 => {
+                let m = self.modrm()?;
+                op("movq", vec![self.reg(m.reg, xmm), self.rm(&m, xmm)])
+            }
+            0xd6 if prefix == 0x66 => {
+                let m = self.modrm()?;
+                op("movq", vec![self.rm(&m, xmm), self.reg(m.reg, xmm)])
+            }
+            0x6f | 0x7f if prefix == 0x66 || prefix == 0xf3 => {
+                let m = self.modrm()?;
+                let mnemonic = if prefix == 0x66 { "movdqa" } else { "movdqu" };
+                let (rm, reg) = (self.rm(&m, xmm), self.reg(m.reg, xmm));
+                op(
+                    mnemonic,
+                    if opcode == 0x6f {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            0x70 if prefix != 0 => {
+                let m = self.modrm()?;
+                let mnemonic = match prefix {
+                    0x66 => "pshufd",
+                    0xf3 => "pshufhw",
+                    _ => "pshuflw",
+                };
+                let imm = immediate(self.signed(1)?, 1);
+                op(mnemonic, vec![self.reg(m.reg, xmm), self.rm(&m, xmm), imm])
+            }
+            0x71..=0x73 if prefix == 0x66 => {
+                let m = self.modrm()?;
+                let width = ["w", "d", "q"][(opcode - 0x71) as usize];
+                let mnemonic = match (m.reg & 7, opcode) {
+                    (2, _) => format!("psrl{}", width),
+                    (4, 0x71 | 0x72) => format!("psra{}", width),
+                    (6, _) => format!("psll{}", width),
+                    (3, 0x73) => "psrldq".to_string(),
+                    (7, 0x73) => "pslldq".to_string(),
+                    _ => return None,
+                };
+                let imm = immediate(self.signed(1)?, 1);
+                Some((mnemonic, vec![self.rm(&m, xmm), imm]))
+            }
+            0xc2 => {
+                let m = self.modrm()?;
+                let (reg, rm) = (self.reg(m.reg, xmm), self.rm(&m, xmm));
+                let predicate = self.signed(1)? as u8;
+                match PREDICATES.get(predicate as usize) {
+                    Some(name) => op(&format!("cmp{}{}", name, form), vec![reg, rm]),
+                    None => op(
+                        &format!("cmp{}", form),
+                        vec![reg, rm, immediate(predicate as i64, 1)],
+                    ),
+                }
+            }
+            0xc6 if prefix == 0 || prefix == 0x66 => {
+                let m = self.modrm()?;
+                let imm = immediate(self.signed(1)?, 1);
+                let mnemonic = format!("shuf{}", form);
+                op(&mnemonic, vec![self.reg(m.reg, xmm), self.rm(&m, xmm), imm])
+            }
+            0xc4 if prefix == 0x66 => {
+                let m = self.modrm()?;
+                let rm = self.rm(&m, 4);
+                let imm = immediate(self.signed(1)?, 1);
+                op("pinsrw", vec![self.reg(m.reg, xmm), rm, imm])
+            }
+            0xc5 if prefix == 0x66 => {
+                let m = self.modrm()?;
+                if m.mem.is_some() {
+                    return None;
+                }
+                let imm = immediate(self.signed(1)?, 1);
+                op("pextrw", vec![self.reg(m.reg, 4), self.rm(&m, xmm), imm])
+            }
+            0xd7 if prefix == 0x66 => {
+                let m = self.modrm()?;
+                op("pmovmskb", vec![self.reg(m.reg, 4), self.rm(&m, xmm)])
+            }
+            0xe6 if prefix != 0 => {
+                let m = self.modrm()?;
+                let mnemonic = match prefix {
+                    0x66 => "cvttpd2dq",
+                    0xf3 => "cvtdq2pd",
+                    _ => "cvtpd2dq",
+                };
+                op(mnemonic, vec![self.reg(m.reg, xmm), self.rm(&m, xmm)])
+            }
+            0xe7 if prefix == 0x66 => {
+                let m = self.modrm()?;
+                op("movntdq", vec![m.mem?, self.reg(m.reg, xmm)])
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes an instruction with a VEX or EVEX prefix. One this doesn't know comes out as
+    /// `.byte` and its bytes, so that what follows it still decodes.
+    fn vex_instruction(&mut self) -> Option<(String, Vec<String>)> {
+        // the prefixes VEX stands for can't go with it
+        if self.operand_prefix || self.rep.is_some() || self.lock {
+            return None;
+        }
+        let kind = self.byte()?;
+        let first = self.byte()?;
+        let prefixes = [0, 0x66, 0xf3, 0xf2];
+        // the bits that extend register numbers are inverted, as is vvvv
+        let vex = if kind == 0xc5 {
+            self.rex = if first & 0x80 == 0 { 4 } else { 0 };
+            Vex {
+                map: 1,
+                prefix: prefixes[(first & 3) as usize],
+                vvvv: (!first >> 3 & 15) as usize,
+                len: if first & 4 == 0 { 16 } else { 32 },
+                ..Vex::default()
+            }
+        } else {
+            let second = self.byte()?;
+            self.rex = (!first >> 5 & 7) | (second >> 4 & 8);
+            let prefix = prefixes[(second & 3) as usize];
+            let vvvv = (!second >> 3 & 15) as usize;
+            if kind == 0xc4 {
+                Vex {
+                    map: first & 0x1f,
+                    prefix,
+                    vvvv,
+                    len: if second & 4 == 0 { 16 } else { 32 },
+                    ..Vex::default()
+                }
+            } else {
+                let third = self.byte()?;
+                Vex {
+                    map: first & 7,
+                    prefix,
+                    vvvv: vvvv | if third & 8 == 0 { 16 } else { 0 },
+                    len: 16 << (third >> 5 & 3),
+                    evex: true,
+                    mask: (third & 7) as usize,
+                    zeroing: third & 0x80 != 0,
+                    broadcast: third & 0x10 != 0,
+                    reg_high: if first & 0x10 == 0 { 16 } else { 0 },
+                    rm_high: if first & 0x40 == 0 { 16 } else { 0 },
+                }
+            }
+        };
+        let opcode = self.byte()?;
+        let start = self.pos;
+        let decoded = match vex.map {
+            1 => self.vex_0f(vex, opcode),
+            2 => self.vex_0f38(vex, opcode),
+            3 => self.vex_0f3a(vex, opcode),
+            _ => None,
+        };
+        if let Some((mnemonic, mut operands)) = decoded {
+            // the opmask goes on the destination
+            if let Some(destination) = operands.first_mut() {
+                if vex.mask != 0 {
+                    destination.push_str(&format!("{{%k{}}}", vex.mask));
+                }
+                if vex.zeroing {
+                    destination.push_str("{z}");
+                }
+            }
+            return Some((mnemonic, operands));
+        }
+        // skip the operands to find where the instruction ends
+        self.pos = start;
+        self.disp8_scale = 1;
+        if vex.map != 1 || opcode != 0x77 {
+            self.modrm()?;
+        }
+        if vex.map == 3 || vex.map == 1 && matches!(opcode, 0x70..=0x73 | 0xc2 | 0xc4..=0xc6) {
+            self.byte()?;
+        }
+        self.target = None;
+        self.rip_displacement = None;
+        self.used_segment = true;
+        let bytes: Vec<String> = self.bytes[..self.pos]
+            .iter()
+            .map(|byte| format!("{:#04x}", byte))
+            .collect();
+        Some((format!(".byte {}", bytes.join(",")), vec![]))
+    }
+
+    /// Reads the ModRM byte of a VEX instruction whose memory operand is `size` bytes, which is
+    /// what EVEX scales an 8-bit displacement by unless it broadcasts an element.
+    fn vex_modrm(&mut self, vex: Vex, size: usize) -> Option<ModRm> {
+        let element = if self.rex_w() { 8 } else { 4 };
+        if vex.evex {
+            self.disp8_scale = if vex.broadcast { element } else { size } as i64;
+        }
+        let mut m = self.modrm()?;
+        m.reg |= vex.reg_high;
+        match &mut m.mem {
+            // with a register operand the bit means rounding control, which isn't decoded
+            None if vex.broadcast => return None,
+            None => m.rm |= vex.rm_high,
+            Some(mem) if vex.broadcast => mem.push_str(&format!("{{1to{}}}", vex.len / element)),
+            Some(_) => {}
+        }
+        Some(m)
+    }
+
+    /// Decodes the VEX and EVEX instructions that stand for ones starting 0x0f.
+    fn vex_0f(&mut self, vex: Vex, opcode: u8) -> Option<(String, Vec<String>)> {
+        let len = vex.len;
+        let w = self.rex_w();
+        let op = |mnemonic: &str, operands: Vec<String>| Some((mnemonic.to_string(), operands));
+        match (vex.prefix, opcode) {
+            (_, 0x77) if !vex.evex => op(if len == 16 { "vzeroupper" } else { "vzeroall" }, vec![]),
+            (0 | 0x66, 0x10 | 0x11 | 0x28 | 0x29) => {
+                let m = self.vex_modrm(vex, len)?;
+                let kind = if opcode < 0x28 { "u" } else { "a" };
+                let form = if vex.prefix == 0 { "ps" } else { "pd" };
+                let (rm, reg) = (self.rm(&m, len), self.reg(m.reg, len));
+                op(
+                    &format!("vmov{}{}", kind, form),
+                    if opcode & 1 == 0 {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            (0xf3 | 0xf2, 0x10 | 0x11) => {
+                let size = if vex.prefix == 0xf3 { 4 } else { 8 };
+                let m = self.vex_modrm(vex, size)?;
+                let mnemonic = if size == 4 { "vmovss" } else { "vmovsd" };
+                let (rm, reg) = (self.rm(&m, 16), self.reg(m.reg, 16));
+                // between registers it merges the rest of another one in
+                let mut operands = match m.mem {
+                    Some(_) => vec![reg],
+                    None => vec![reg, self.reg(vex.vvvv, 16)],
+                };
+                if opcode == 0x10 {
+                    operands.push(rm);
+                } else {
+                    operands.insert(0, rm);
+                }
+                op(mnemonic, operands)
+            }
+            (0 | 0x66, 0x2e | 0x2f) => {
+                let double = vex.prefix == 0x66;
+                let m = self.vex_modrm(vex, if double { 8 } else { 4 })?;
+                let kind = if opcode == 0x2e { "vucomis" } else { "vcomis" };
+                let mnemonic = format!("{}{}", kind, if double { "d" } else { "s" });
+                op(&mnemonic, vec![self.reg(m.reg, 16), self.rm(&m, 16)])
+            }
+            (0 | 0x66, 0x54..=0x57) | (_, 0x58 | 0x59 | 0x5c..=0x5f) => {
+                let (form, size) = match vex.prefix {
+                    0 => ("ps", len),
+                    0x66 => ("pd", len),
+                    0xf3 => ("ss", 4),
+                    _ => ("sd", 8),
+                };
+                let operation = [
+                    "and", "andn", "or", "xor", "add", "mul", "", "", "sub", "min", "div", "max",
+                ][(opcode - 0x54) as usize];
+                // AVX-512 only has the bitwise ones for doublewords and quadwords
+                if vex.evex && opcode < 0x58 {
+                    return None;
+                }
+                let m = self.vex_modrm(vex, size)?;
+                let registers = if size == len { len } else { 16 };
+                let operands = vec![
+                    self.reg(m.reg, registers),
+                    self.reg(vex.vvvv, registers),
+                    self.rm(&m, registers),
+                ];
+                op(&format!("v{}{}", operation, form), operands)
+            }
+            (_, 0x41..=0x47 | 0x4a | 0x4b | 0x90..=0x93 | 0x98 | 0x99) if !vex.evex => {
+                self.mask_instruction(vex, opcode)
+            }
+            (0x66 | 0xf3 | 0xf2, 0x6f | 0x7f) => {
+                let mnemonic = match (vex.prefix, vex.evex, w) {
+                    (0x66, false, _) => "vmovdqa",
+                    (0xf3, false, _) => "vmovdqu",
+                    (0x66, true, false) => "vmovdqa32",
+                    (0x66, true, true) => "vmovdqa64",
+                    (0xf3, true, false) => "vmovdqu32",
+                    (0xf3, true, true) => "vmovdqu64",
+                    (_, true, false) => "vmovdqu8",
+                    (_, true, true) => "vmovdqu16",
+                    _ => return None,
+                };
+                let m = self.vex_modrm(vex, len)?;
+                let (rm, reg) = (self.rm(&m, len), self.reg(m.reg, len));
+                op(
+                    mnemonic,
+                    if opcode == 0x6f {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            (0x66, 0x6e | 0x7e) => {
+                let size = if w { 8 } else { 4 };
+                let m = self.vex_modrm(vex, size)?;
+                let mnemonic = if w { "vmovq" } else { "vmovd" };
+                let (rm, reg) = (self.rm(&m, size), self.reg(m.reg, 16));
+                op(
+                    mnemonic,
+                    if opcode == 0x6e {
+                        vec![reg, rm]
+                    } else {
+                        vec![rm, reg]
+                    },
+                )
+            }
+            (0xf3, 0x7e) => {
+                let m = self.vex_modrm(vex, 8)?;
+                op("vmovq", vec![self.reg(m.reg, 16), self.rm(&m, 16)])
+            }
+            (0x66, 0xd6) => {
+                let m = self.vex_modrm(vex, 8)?;
+                op("vmovq", vec![self.rm(&m, 16), self.reg(m.reg, 16)])
+            }
+            (0x66, 0x64..=0x66 | 0x74..=0x76) if vex.evex => {
+                // the AVX-512 compares set a mask register
+                let m = self.vex_modrm(vex, len)?;
+                let operands = vec![
+                    format!("%k{}", m.reg & 7),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, len),
+                ];
+                op(&format!("v{}", packed_integer(opcode)?), operands)
+            }
+            (0x66, 0xd7) if !vex.evex => {
+                let m = self.vex_modrm(vex, len)?;
+                if m.mem.is_some() {
+                    return None;
+                }
+                op("vpmovmskb", vec![self.reg(m.reg, 4), self.rm(&m, len)])
+            }
+            (0x66, 0xe7) => {
+                let m = self.vex_modrm(vex, len)?;
+                op("vmovntdq", vec![m.mem?, self.reg(m.reg, len)])
+            }
+            (0x66, 0x70) => {
+                let m = self.vex_modrm(vex, len)?;
+                let imm = immediate(self.signed(1)?, 1);
+                op("vpshufd", vec![self.reg(m.reg, len), self.rm(&m, len), imm])
+            }
+            (0x66, 0x71..=0x73) => {
+                let m = self.vex_modrm(vex, len)?;
+                let width = ["w", "d", "q"][(opcode - 0x71) as usize];
+                let mnemonic = match (m.reg & 7, opcode) {
+                    (2, _) => format!("vpsrl{}", width),
+                    (4, 0x71 | 0x72) => format!("vpsra{}", width),
+                    (6, _) => format!("vpsll{}", width),
+                    (3, 0x73) => "vpsrldq".to_string(),
+                    (7, 0x73) => "vpslldq".to_string(),
+                    _ => return None,
+                };
+                let imm = immediate(self.signed(1)?, 1);
+                Some((
+                    mnemonic,
+                    vec![self.reg(vex.vvvv, len), self.rm(&m, len), imm],
+                ))
+            }
+            (0x66, _) => {
+                let name = packed_integer(opcode)?;
+                // AVX-512 names the bitwise ones for the element size the mask works on
+                let mnemonic = match (vex.evex, opcode) {
+                    (true, 0xdb | 0xdf | 0xeb | 0xef) => {
+                        format!("v{}{}", name, if w { "q" } else { "d" })
+                    }
+                    _ => format!("v{}", name),
+                };
+                let m = self.vex_modrm(vex, len)?;
+                let operands = vec![
+                    self.reg(m.reg, len),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, len),
+                ];
+                Some((mnemonic, operands))
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes the instructions on the AVX-512 mask registers, %k0-%k7.
+    fn mask_instruction(&mut self, vex: Vex, opcode: u8) -> Option<(String, Vec<String>)> {
+        let w = self.rex_w();
+        let m = self.modrm()?;
+        let k = |index: usize| format!("%k{}", index & 7);
+        // moves to and from general registers use F2 for doublewords and quadwords
+        if let 0x92 | 0x93 = opcode {
+            let (suffix, size) = match (vex.prefix, w) {
+                (0, false) => ("w", 4),
+                (0x66, false) => ("b", 4),
+                (0xf2, false) => ("d", 4),
+                (0xf2, true) => ("q", 8),
+                _ => return None,
+            };
+            if m.mem.is_some() {
+                return None;
+            }
+            let operands = if opcode == 0x92 {
+                vec![k(m.reg), self.reg(m.rm, size)]
+            } else {
+                vec![self.reg(m.reg, size), k(m.rm)]
+            };
+            return Some((format!("kmov{}", suffix), operands));
+        }
+        let suffix = match (vex.prefix, w) {
+            (0, false) => "w",
+            (0, true) => "q",
+            (0x66, false) => "b",
+            (0x66, true) => "d",
+            _ => return None,
+        };
+        let rm = m.mem.clone().unwrap_or_else(|| k(m.rm));
+        let (name, operands) = match (opcode, m.mem) {
+            (0x90, _) => ("kmov", vec![k(m.reg), rm]),
+            (0x91, Some(mem)) => ("kmov", vec![mem, k(m.reg)]),
+            (0x44, None) => ("knot", vec![k(m.reg), k(m.rm)]),
+            (0x98, None) => ("kortest", vec![k(m.reg), k(m.rm)]),
+            (0x99, None) => ("ktest", vec![k(m.reg), k(m.rm)]),
+            (0x4b, None) => {
+                let suffix = match suffix {
+                    "b" => "bw",
+                    "w" => "wd",
+                    "q" => "dq",
+                    _ => return None,
+                };
+                let operands = vec![k(m.reg), k(vex.vvvv), k(m.rm)];
+                return Some((format!("kunpck{}", suffix), operands));
+            }
+            (0x41..=0x47 | 0x4a, None) => {
+                let name = match opcode {
+                    0x41 => "kand",
+                    0x42 => "kandn",
+                    0x45 => "kor",
+                    0x46 => "kxnor",
+                    0x47 => "kxor",
+                    0x4a => "kadd",
+                    _ => return None,
+                };
+                (name, vec![k(m.reg), k(vex.vvvv), k(m.rm)])
+            }
+            _ => return None,
+        };
+        Some((format!("{}{}", name, suffix), operands))
+    }
+
+    /// Decodes the VEX and EVEX instructions that stand for ones starting 0x0f 0x38, and the BMI
+    /// instructions on general registers.
+    fn vex_0f38(&mut self, vex: Vex, opcode: u8) -> Option<(String, Vec<String>)> {
+        let len = vex.len;
+        let w = self.rex_w();
+        let op = |mnemonic: &str, operands: Vec<String>| Some((mnemonic.to_string(), operands));
+        match (vex.prefix, opcode) {
+            (0x66, 0x18 | 0x58 | 0x59 | 0x78 | 0x79) => {
+                let (mnemonic, size) = match opcode {
+                    0x18 => ("vbroadcastss", 4),
+                    0x58 => ("vpbroadcastd", 4),
+                    // EVEX.W0 makes it vbroadcasti32x2
+                    0x59 if vex.evex && !w => return None,
+                    0x59 => ("vpbroadcastq", 8),
+                    0x78 => ("vpbroadcastb", 1),
+                    _ => ("vpbroadcastw", 2),
+                };
+                let m = self.vex_modrm(vex, size)?;
+                op(mnemonic, vec![self.reg(m.reg, len), self.rm(&m, 16)])
+            }
+            (0x66, 0x7a..=0x7c) if vex.evex => {
+                let (mnemonic, size) = match (opcode, w) {
+                    (0x7a, _) => ("vpbroadcastb", 4),
+                    (0x7b, _) => ("vpbroadcastw", 4),
+                    (_, false) => ("vpbroadcastd", 4),
+                    _ => ("vpbroadcastq", 8),
+                };
+                let m = self.vex_modrm(vex, size)?;
+                if m.mem.is_some() {
+                    return None;
+                }
+                op(mnemonic, vec![self.reg(m.reg, len), self.rm(&m, size)])
+            }
+            (0x66 | 0xf3, 0x26 | 0x27) if vex.evex => {
+                let test = if vex.prefix == 0x66 {
+                    "vptestm"
+                } else {
+                    "vptestnm"
+                };
+                let element = match (opcode, w) {
+                    (0x26, false) => "b",
+                    (0x26, true) => "w",
+                    (_, false) => "d",
+                    _ => "q",
+                };
+                let m = self.vex_modrm(vex, len)?;
+                let operands = vec![
+                    format!("%k{}", m.reg & 7),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, len),
+                ];
+                op(&format!("{}{}", test, element), operands)
+            }
+            (0x66, 0x17) if !vex.evex => {
+                let m = self.vex_modrm(vex, len)?;
+                op("vptest", vec![self.reg(m.reg, len), self.rm(&m, len)])
+            }
+            (0x66, 0x00..=0x3f) => {
+                let mut mnemonic = format!("v{}", packed_integer_38(opcode)?);
+                let m = self.vex_modrm(vex, len)?;
+                let destination = match opcode {
+                    0x29 | 0x37 if vex.evex => format!("%k{}", m.reg & 7),
+                    _ => self.reg(m.reg, len),
+                };
+                // EVEX.W1 makes the doubleword ones quadword
+                if vex.evex && w && mnemonic.ends_with('d') {
+                    mnemonic.pop();
+                    mnemonic.push('q');
+                }
+                let operands = vec![destination, self.reg(vex.vvvv, len), self.rm(&m, len)];
+                Some((mnemonic, operands))
+            }
+            _ if vex.evex => None,
+            (0, 0xf2) => {
+                let size = if w { 8 } else { 4 };
+                let m = self.modrm()?;
+                let operands = vec![
+                    self.reg(m.reg, size),
+                    self.reg(vex.vvvv, size),
+                    self.rm(&m, size),
+                ];
+                op("andn", operands)
+            }
+            (0, 0xf3) => {
+                let size = if w { 8 } else { 4 };
+                let m = self.modrm()?;
+                let mnemonic = ["blsr", "blsmsk", "blsi"].get((m.reg & 7).checked_sub(1)?)?;
+                op(mnemonic, vec![self.reg(vex.vvvv, size), self.rm(&m, size)])
+            }
+            (_, 0xf5..=0xf7) => {
+                let size = if w { 8 } else { 4 };
+                let m = self.modrm()?;
+                let (reg, rm, vvvv) = (
+                    self.reg(m.reg, size),
+                    self.rm(&m, size),
+                    self.reg(vex.vvvv, size),
+                );
+                match (vex.prefix, opcode) {
+                    (0, 0xf5) => op("bzhi", vec![reg, rm, vvvv]),
+                    (0xf3, 0xf5) => op("pext", vec![reg, vvvv, rm]),
+                    (0xf2, 0xf5) => op("pdep", vec![reg, vvvv, rm]),
+                    (0xf2, 0xf6) => op("mulx", vec![reg, vvvv, rm]),
+                    (0, 0xf7) => op("bextr", vec![reg, rm, vvvv]),
+                    (0x66, 0xf7) => op("shlx", vec![reg, rm, vvvv]),
+                    (0xf3, 0xf7) => op("sarx", vec![reg, rm, vvvv]),
+                    (0xf2, 0xf7) => op("shrx", vec![reg, rm, vvvv]),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes the VEX and EVEX instructions that stand for ones starting 0x0f 0x3a, which all
+    /// take an immediate.
+    fn vex_0f3a(&mut self, vex: Vex, opcode: u8) -> Option<(String, Vec<String>)> {
+        if vex.prefix != 0x66 {
+            return None;
+        }
+        let len = vex.len;
+        let w = self.rex_w();
+        let op = |mnemonic: &str, operands: Vec<String>| Some((mnemonic.to_string(), operands));
+        match opcode {
+            0x1e | 0x1f | 0x3e | 0x3f if vex.evex => {
+                let m = self.vex_modrm(vex, len)?;
+                let mut operands = vec![
+                    format!("%k{}", m.reg & 7),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, len),
+                ];
+                let predicate = self.signed(1)? as u8;
+                let unsigned = if opcode & 1 == 0 { "u" } else { "" };
+                let element = match (opcode, w) {
+                    (0x3e | 0x3f, false) => "b",
+                    (0x3e | 0x3f, true) => "w",
+                    (_, false) => "d",
+                    _ => "q",
+                };
+                match VECTOR_PREDICATES.get(predicate as usize) {
+                    Some(name) => op(&format!("vpcmp{}{}{}", name, unsigned, element), operands),
+                    None => {
+                        operands.push(immediate(predicate as i64, 1));
+                        op(&format!("vpcmp{}{}", unsigned, element), operands)
+                    }
+                }
+            }
+            0x25 if vex.evex => {
+                let m = self.vex_modrm(vex, len)?;
+                let operands = vec![
+                    self.reg(m.reg, len),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, len),
+                    immediate(self.signed(1)?, 1),
+                ];
+                op(if w { "vpternlogq" } else { "vpternlogd" }, operands)
+            }
+            0x0f => {
+                let m = self.vex_modrm(vex, len)?;
+                let operands = vec![
+                    self.reg(m.reg, len),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, len),
+                    immediate(self.signed(1)?, 1),
+                ];
+                op("vpalignr", operands)
+            }
+            _ if vex.evex => None,
+            0x38 if len == 32 => {
+                let m = self.modrm()?;
+                let operands = vec![
+                    self.reg(m.reg, len),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, 16),
+                    immediate(self.signed(1)?, 1),
+                ];
+                op("vinserti128", operands)
+            }
+            0x39 if len == 32 => {
+                let m = self.modrm()?;
+                let operands = vec![
+                    self.rm(&m, 16),
+                    self.reg(m.reg, len),
+                    immediate(self.signed(1)?, 1),
+                ];
+                op("vextracti128", operands)
+            }
+            0x46 if len == 32 => {
+                let m = self.modrm()?;
+                let operands = vec![
+                    self.reg(m.reg, len),
+                    self.reg(vex.vvvv, len),
+                    self.rm(&m, len),
+                    immediate(self.signed(1)?, 1),
+                ];
+                op("vperm2i128", operands)
+            }
+            0x60..=0x63 if len == 16 => {
+                let mnemonic = ["vpcmpestrm", "vpcmpestri", "vpcmpistrm", "vpcmpistri"]
+                    [(opcode - 0x60) as usize];
+                let m = self.modrm()?;
+                let operands = vec![
+                    self.reg(m.reg, 16),
+                    self.rm(&m, 16),
+                    immediate(self.signed(1)?, 1),
+                ];
+                op(mnemonic, operands)
+            }
+            _ => None,
+        }
+    }
+
+    /// The prefixes the instruction didn't use, written as objdump does before the mnemonic.
+    fn prefix_words(&self, mnemonic: &str) -> Vec<&'static str> {
+        let branch = mnemonic.starts_with('j') || mnemonic == "call" || mnemonic == "ret";
+        let mut words = self.extra_prefixes.clone();
+        if self.operand_prefix && !self.used_operand_prefix && words.is_empty() {
+            words.push("data16");
+        }
+        if self.lock {
+            words.push("lock");
+        }
+        match self.rep {
+            Some(0xf2) if !self.used_rep && branch => words.push("bnd"),
+            Some(0xf2) if !self.used_rep => words.push("repnz"),
+            Some(_) if !self.used_rep => words.push("repz"),
+            _ => {}
+        }
+        match self.segment {
+            Some(0x3e) if branch => words.push("notrack"),
+            Some(segment) if !self.used_segment => words.push(match segment {
+                0x26 => "es",
+                0x2e => "cs",
+                0x36 => "ss",
+                0x3e => "ds",
+                0x64 => "fs",
+                _ => "gs",
+            }),
+            _ => {}
+        }
+        words
+    }
+}
+
+/// Decodes the instruction at the start of `bytes`, which were read from `addr`. Bytes that
+/// aren't a known instruction come out as a one-byte `(bad)`, as in objdump, except for VEX and
+/// EVEX instructions, whose length is known.
+pub fn decode(bytes: &[u8], addr: usize) -> Instruction {
+    let mut decoder = Decoder::new(bytes, addr);
+    let (mnemonic, mut operands) = match decoder.instruction() {
+        Some(instruction) if decoder.pos <= MAX_INSTRUCTION_LEN => instruction,
+        _ => {
+            return Instruction {
+                len: 1,
+                text: "(bad)".to_string(),
+                target: None,
+                reference: None,
+            }
+        }
+    };
+    let mut words = decoder.prefix_words(&mnemonic);
+    words.push(&mnemonic);
+    let mnemonic = words.join(" ");
+    // AT&T order puts the destination last
+    operands.reverse();
+    let text = if operands.is_empty() {
+        mnemonic
+    } else {
+        format!("{:<6} {}", mnemonic, operands.join(","))
+    };
+    let len = decoder.pos;
+    Instruction {
+        len,
+        text,
+        target: decoder.target,
+        reference: decoder
+            .rip_displacement
+            .map(|displacement| (addr + len).wrapping_add(displacement as usize)),
+    }
+}
+
+/// Reads the `len` bytes of code at `addr` and as much after them as the last instruction
+/// might need, as long as that is mapped.
+pub fn read_code(target: &dyn Target, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+    target
+        .read_memory(addr, len + MAX_INSTRUCTION_LEN)
+        .or_else(|_| {
+            // stop at the end of the page the code ends on
+            let end = ((addr + len.max(1) - 1) | 0xfff) + 1;
+            target.read_memory(addr, end - addr)
+        })
+}
+
+/// Reads `len` bytes of code at link-time address `addr` out of the sections of the ELF file at
+/// `path`, for disassembling when there is no process.
+pub fn read_file_code(path: &str, addr: usize, len: usize) -> Option<Vec<u8>> {
+    let data = std::fs::read(path).ok()?;
+    let object = object::File::parse(&*data).ok()?;
+    let section = object.sections().find(|section| {
+        let start = section.address() as usize;
+        start <= addr && addr < start + section.size() as usize
+    })?;
+    let bytes = section.data().ok()?;
+    let start = addr - section.address() as usize;
+    Some(bytes.get(start..(start + len).min(bytes.len()))?.to_vec())
+}
+
+/// What `disassemble` shows besides the instructions.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Modifiers {
+    /// The bytes of each instruction (/r)
+    pub raw: bool,
+    /// The source lines the instructions came from (/s)
+    pub source: bool,
+}
+
+/// The addresses `disassemble` points out: the pc gets an arrow, and breakpoint sites a star.
+#[derive(Debug, Default)]
+pub struct Marks {
+    pub pc: Option<usize>,
+    pub breakpoints: Vec<usize>,
+}
+
+/// Implements `disassemble`: decodes `code`, which was read from `range.start` and may run
+/// past the end of the range, and prints the instructions that start in the range. In a
+/// listing of a whole function, `function` names it and addresses are shown as offsets into
+/// it.
+pub fn disassemble(
+    debug_data: &Modules,
+    code: &[u8],
+    range: Range<usize>,
+    function: Option<&str>,
+    marks: &Marks,
+    modifiers: Modifiers,
+    sources: &mut SourceFiles,
+) {
+    match function {
+        Some(name) => println!("Dump of assembler code for function {}:", name),
+        None => println!(
+            "Dump of assembler code from {:#x} to {:#x}:",
+            range.start, range.end
+        ),
+    }
+    let mut last_line = None;
+    let mut offset = 0;
+    while range.start + offset < range.end && offset < code.len() {
+        let addr = range.start + offset;
+        let instruction = decode(&code[offset..], addr);
+        if modifiers.source {
+            if let Some(line) = debug_data.get_line_from_addr(addr) {
+                let key = (line.file.clone(), line.number);
+                if last_line.as_ref() != Some(&key) {
+                    if last_line.as_ref().map(|(file, _)| file) != Some(&line.file) {
+                        println!("{}:", line.file);
+                    } else {
+                        println!();
+                    }
+                    sources.print_line(&line.file, line.number);
+                    last_line = Some(key);
+                }
+            }
+        }
+        let marker = match (marks.pc == Some(addr), marks.breakpoints.contains(&addr)) {
+            (true, true) => "=>*",
+            (true, false) => "=> ",
+            (false, true) => "  *",
+            (false, false) => "   ",
+        };
+        let location = match (function, symbolize(debug_data, addr)) {
+            (Some(_), _) => format!(" <+{}>", addr - range.start),
+            (None, Some(symbol)) => format!(" {}", symbol),
+            (None, None) => String::new(),
+        };
+        let raw = if modifiers.raw {
+            let end = (offset + instruction.len).min(code.len());
+            let bytes: Vec<String> = code[offset..end]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!("{}\t", bytes.join(" "))
+        } else {
+            String::new()
+        };
+        println!(
+            "{}{:#018x}{}:\t{}{}",
+            marker,
+            addr,
+            location,
+            raw,
+            instruction.render(debug_data)
+        );
+        offset += instruction.len;
+    }
+    println!("End of assembler dump.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `bytes` as one instruction, checking that it takes all of them.
+    fn text(bytes: &[u8]) -> String {
+        let instruction = decode(bytes, 0);
+        assert_eq!(instruction.len, bytes.len(), "{}", instruction.text);
+        instruction.text
+    }
+
+    // the expected text is what objdump shows for the same bytes
+
+    #[test]
+    fn vex() {
+        assert_eq!(
+            text(&[0xc5, 0xfe, 0x6f, 0x04, 0x17]),
+            "vmovdqu (%rdi,%rdx,1),%ymm0"
+        );
+        assert_eq!(
+            text(&[0xc5, 0xcd, 0x74, 0x40, 0x20]),
+            "vpcmpeqb 0x20(%rax),%ymm6,%ymm0"
+        );
+        assert_eq!(text(&[0xc5, 0xfd, 0xd7, 0xc1]), "vpmovmskb %ymm1,%eax");
+        assert_eq!(text(&[0xc5, 0xf9, 0x6e, 0xc6]), "vmovd  %esi,%xmm0");
+        assert_eq!(text(&[0xc5, 0xf8, 0x77]), "vzeroupper");
+        assert_eq!(text(&[0xc5, 0xfb, 0x58, 0xc1]), "vaddsd %xmm1,%xmm0,%xmm0");
+        let instruction = decode(&[0xc5, 0xfb, 0x10, 0x0d, 0x9c, 0xfc, 0x01, 0x00], 7);
+        assert_eq!(instruction.text, "vmovsd 0x1fc9c(%rip),%xmm1");
+        assert_eq!(instruction.reference, Some(0x1fcab));
+    }
+
+    #[test]
+    fn mask_registers() {
+        assert_eq!(text(&[0xc5, 0xfb, 0x93, 0xcc]), "kmovd  %k4,%ecx");
+        assert_eq!(text(&[0xc4, 0xe1, 0xf9, 0x98, 0xc8]), "kortestd %k0,%k1");
+    }
+
+    #[test]
+    fn evex() {
+        // the 8-bit displacement is in units of the operand size
+        assert_eq!(
+            text(&[0x62, 0xe1, 0xfe, 0x48, 0x7f, 0x47, 0x01]),
+            "vmovdqu64 %zmm16,0x40(%rdi)"
+        );
+        assert_eq!(
+            text(&[0x62, 0xe2, 0x65, 0x40, 0x3b, 0x60, 0x07]),
+            "vpminud 0x1c0(%rax),%zmm19,%zmm20"
+        );
+        assert_eq!(
+            text(&[0x62, 0xf1, 0x7d, 0x58, 0xef, 0x47, 0x01]),
+            "vpxord 0x4(%rdi){1to16},%zmm0,%zmm0"
+        );
+        assert_eq!(
+            text(&[0x62, 0xf1, 0x7f, 0xc9, 0x6f, 0x0f]),
+            "vmovdqu8 (%rdi),%zmm1{%k1}{z}"
+        );
+        assert_eq!(
+            text(&[0x62, 0xf3, 0x6d, 0x22, 0x3e, 0x0f, 0x04]),
+            "vpcmpnequb (%rdi),%ymm18,%k1{%k2}"
+        );
+        assert_eq!(
+            text(&[0x62, 0xf2, 0x76, 0x49, 0x26, 0xe1]),
+            "vptestnmb %zmm1,%zmm1,%k4{%k1}"
+        );
+        assert_eq!(
+            text(&[0x62, 0xe3, 0x75, 0x20, 0x25, 0x57, 0xfd, 0xde]),
+            "vpternlogd $0xde,-0x60(%rdi),%ymm17,%ymm18"
+        );
+        assert_eq!(
+            text(&[0x62, 0xe2, 0x7d, 0x28, 0x7a, 0xc6]),
+            "vpbroadcastb %esi,%ymm16"
+        );
+    }
+
+    #[test]
+    fn bmi() {
+        assert_eq!(
+            text(&[0xc4, 0xe2, 0xa0, 0xf5, 0xda]),
+            "bzhi   %r11,%rdx,%rbx"
+        );
+        assert_eq!(
+            text(&[0xc4, 0xc2, 0x7a, 0xf7, 0xc0]),
+            "sarx   %eax,%r8d,%eax"
+        );
+    }
+
+    #[test]
+    fn tsx() {
+        let instruction = decode(&[0xc7, 0xf8, 0x00, 0x00, 0x00, 0x00], 0x85bee);
+        assert_eq!(instruction.text, "xbegin 0x85bf4");
+        assert_eq!(instruction.target, Some(0x85bf4));
+        assert_eq!(text(&[0xc6, 0xf8, 0xff]), "xabort $0xff");
+        assert_eq!(text(&[0x0f, 0x01, 0xd5]), "xend");
+    }
+
+    #[test]
+    fn unknown_vex_keeps_in_sync() {
+        // vfmadd213sd %xmm2,%xmm1,%xmm0 isn't decoded, but the ret after it is
+        let code = [0xc4, 0xe2, 0xf1, 0xa9, 0xc2, 0xc3];
+        let instruction = decode(&code, 0);
+        assert_eq!(instruction.text, ".byte 0xc4,0xe2,0xf1,0xa9,0xc2");
+        assert_eq!(decode(&code[instruction.len..], 5).text, "ret");
+    }
+}
@@ -1,7 +1,12 @@
 use crate::gimli_wrapper;
+use crate::unwind::{CallFrameInfo, UnwindRow};
 use addr2line::Context;
-use object::Object;
+use object::{Object, ObjectKind, ObjectSegment, ObjectSymbol, SymbolFlags, SymbolKind};
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::{Rc, Weak};
 use std::{fmt, fs};
 
 #[derive(Debug)]
@@ -13,6 +18,29 @@ pub enum Error {
 pub struct DwarfData {
     files: Vec<File>,
     addr2line: Context<addr2line::gimli::EndianRcSlice<addr2line::gimli::RunTimeEndian>>,
+    /// Whether the binary can be loaded anywhere (a PIE), so its addresses need relocating.
+    position_independent: bool,
+    /// The link-time address range covered by the binary's loadable segments.
+    image: Range<usize>,
+    /// How far the binary was loaded from its link-time addresses. All addresses in `files` and
+    /// `symbols` have already been moved by this much.
+    load_bias: usize,
+    /// Function symbols from the ELF symbol tables, for code without debugging information.
+    symbols: Vec<Symbol>,
+    /// The link-time address execution starts at
+    entry: usize,
+    cfi: CallFrameInfo,
+}
+
+/// A function symbol from an ELF symbol table.
+#[derive(Debug, Clone)]
+struct Symbol {
+    name: String,
+    address: usize,
+    size: usize,
+    /// Whether this is a GNU indirect function, whose code is a resolver that returns the
+    /// address of the implementation to use, like `strlen` in glibc
+    indirect: bool,
 }
 
 impl fmt::Debug for DwarfData {
@@ -32,66 +60,405 @@ impl DwarfData {
         let file = fs::File::open(path).or(Err(Error::ErrorOpeningFile))?;
         let mmap = unsafe { memmap::Mmap::map(&file).or(Err(Error::ErrorOpeningFile))? };
         let object = object::read::File::parse(&*mmap)
-            .or_else(|e| Err(gimli_wrapper::Error::ObjectError(e.to_string())))?;
+            .map_err(|e| gimli_wrapper::Error::ObjectError(e.to_string()))?;
         let endian = if object.is_little_endian() {
             gimli::RunTimeEndian::Little
         } else {
             gimli::RunTimeEndian::Big
         };
+        let image_start = object
+            .segments()
+            .map(|seg| seg.address())
+            .min()
+            .unwrap_or(0);
+        let image_end = object
+            .segments()
+            .map(|seg| seg.address() + seg.size())
+            .max()
+            .unwrap_or(0);
+        // .symtab is usually stripped from libraries, so take .dynsym as well
+        let symbols = object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .filter_map(|sym| {
+                let indirect = matches!(sym.flags(), SymbolFlags::Elf { st_info, .. }
+                    if st_info & 0xf == object::elf::STT_GNU_IFUNC);
+                if (sym.kind() != SymbolKind::Text && !indirect) || sym.address() == 0 {
+                    return None;
+                }
+                Some(Symbol {
+                    name: sym.name().ok()?.to_string(),
+                    address: sym.address() as usize,
+                    size: sym.size() as usize,
+                    indirect,
+                })
+            })
+            .collect();
         Ok(DwarfData {
             files: gimli_wrapper::load_file(&object, endian)?,
-            addr2line: Context::new(&object).or_else(|e| Err(gimli_wrapper::Error::from(e)))?,
+            addr2line: Context::new(&object).map_err(gimli_wrapper::Error::from)?,
+            position_independent: object.kind() == ObjectKind::Dynamic,
+            image: image_start as usize..image_end as usize,
+            load_bias: 0,
+            symbols,
+            entry: object.entry() as usize,
+            cfi: CallFrameInfo::load(&object, endian),
         })
     }
 
+    /// Returns whether the binary is position independent, i.e. its runtime addresses depend on
+    /// where it gets loaded.
+    pub fn is_position_independent(&self) -> bool {
+        self.position_independent
+    }
+
+    /// Returns the lowest link-time address of the binary, which is where the load base of the
+    /// process corresponds to.
+    pub fn link_base(&self) -> usize {
+        self.image.start
+    }
+
+    /// Returns the runtime address execution of the binary starts at.
+    pub fn entry_point(&self) -> usize {
+        self.entry.wrapping_add(self.load_bias)
+    }
+
+    /// Returns the runtime address range the binary occupies.
+    pub fn image(&self) -> Range<usize> {
+        self.image.start.wrapping_add(self.load_bias)..self.image.end.wrapping_add(self.load_bias)
+    }
+
+    /// Returns how far the binary was loaded from its link-time addresses.
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    /// Relocates every address to a binary loaded `bias` bytes away from its link-time addresses.
+    pub fn set_load_bias(&mut self, bias: usize) {
+        let delta = bias.wrapping_sub(self.load_bias);
+        let relocate_variable = |var: &mut Variable| match var.location {
+            Location::Address(ref mut addr) => *addr = addr.wrapping_add(delta),
+            Location::FramePointerOffset(_) => {}
+            Location::Expression(ref mut expression) => expression.load_bias = bias,
+            Location::List(ref mut entries) => {
+                for (range, expression) in entries {
+                    *range = range.start.wrapping_add(delta)..range.end.wrapping_add(delta);
+                    expression.load_bias = bias;
+                }
+            }
+        };
+        for file in &mut self.files {
+            file.global_variables.iter_mut().for_each(relocate_variable);
+            // declarations (address 0, no code) stay where they are
+            for func in file
+                .functions
+                .iter_mut()
+                .filter(|func| func.text_length > 0)
+            {
+                func.address = func.address.wrapping_add(delta);
+                if let Some(frame_base) = func.frame_base.as_mut() {
+                    frame_base.load_bias = bias;
+                }
+                func.variables.iter_mut().for_each(relocate_variable);
+                for scope in &mut func.scopes {
+                    scope.relocate(delta, &relocate_variable);
+                }
+            }
+            for line in &mut file.lines {
+                line.address = line.address.wrapping_add(delta);
+            }
+        }
+        for sym in &mut self.symbols {
+            sym.address = sym.address.wrapping_add(delta);
+        }
+        self.load_bias = bias;
+    }
+
+    /// Translates a runtime address back to the link-time address addr2line knows about.
+    fn link_time_addr(&self, addr: usize) -> Option<u64> {
+        addr.checked_sub(self.load_bias)?.try_into().ok()
+    }
+
     #[allow(dead_code)]
     fn get_target_file(&self, file: &str) -> Option<&File> {
         self.files.iter().find(|f| {
-            f.name == file || (!file.contains("/") && f.name.ends_with(&format!("/{}", file)))
+            f.name == file || (!file.contains('/') && f.name.ends_with(&format!("/{}", file)))
         })
     }
 
-    #[allow(dead_code)]
-    pub fn get_addr_for_line(&self, file: Option<&str>, line_number: usize) -> Option<usize> {
-        let target_file = match file {
-            Some(filename) => self.get_target_file(filename)?,
-            None => self.files.get(0)?,
+    /// Returns the path of the source file of the unit `file` names, which is relative to the
+    /// compilation directory if the unit's name was.
+    pub fn get_source_path(&self, file: &str) -> Option<String> {
+        let target_file = self.get_target_file(file)?;
+        match target_file.comp_dir {
+            Some(ref comp_dir) if !target_file.name.starts_with('/') => Some(
+                Path::new(comp_dir)
+                    .join(&target_file.name)
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            _ => Some(target_file.name.clone()),
+        }
+    }
+
+    /// Returns the first line at or after `line_number` of `file` that has code, and the
+    /// addresses a breakpoint on it goes at: one in each function, or inlined copy of one, that
+    /// the line has code in. `file` may be just a file name.
+    pub fn get_addrs_for_line(
+        &self,
+        file: &str,
+        line_number: usize,
+    ) -> Option<(usize, Vec<usize>)> {
+        let suffix = format!("/{}", file);
+        let lines: Vec<&Line> = self
+            .files
+            .iter()
+            .flat_map(|f| f.lines.iter())
+            .filter(|line| line.file == file || line.file.ends_with(&suffix))
+            .collect();
+        let number = lines
+            .iter()
+            .map(|line| line.number)
+            .filter(|number| *number >= line_number)
+            .min()?;
+        let mut addrs: Vec<usize> = lines
+            .iter()
+            .filter(|line| line.number == number)
+            .map(|line| line.address)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        // a line may be spread over several places in one function, like the parts of a for
+        // loop, and only the first of them is wanted
+        let mut blocks = Vec::new();
+        addrs.retain(|addr| {
+            let block = self.code_block(*addr);
+            let first = !blocks.contains(&block);
+            blocks.push(block);
+            first
+        });
+        let addrs = addrs
+            .into_iter()
+            .map(|addr| match self.get_function_containing(addr) {
+                // a line that a function starts on stops after its prologue, as the function does
+                Some(func) if func.address == addr => self.get_prologue_end(func),
+                _ => addr,
+            })
+            .collect();
+        Some((number, addrs))
+    }
+
+    /// Names the function, or the inlined copy of a function, that `addr` is in: where the
+    /// function starts, and the chain of inlined calls leading to `addr` and where they were made.
+    fn code_block(&self, addr: usize) -> String {
+        let mut block = match self.get_function_containing(addr) {
+            Some(func) => format!("{:#x}", func.address),
+            None => String::new(),
         };
-        Some(
-            target_file
-                .lines
-                .iter()
-                .find(|line| line.number >= line_number)?
-                .address,
-        )
+        let mut frames = match self
+            .link_time_addr(addr)
+            .and_then(|addr| self.addr2line.find_frames(addr).ok())
+        {
+            Some(frames) => frames,
+            None => return block,
+        };
+        let mut innermost = true;
+        while let Ok(Some(frame)) = frames.next() {
+            if let Some(name) = frame
+                .function
+                .as_ref()
+                .and_then(|func| func.raw_name().ok())
+            {
+                block.push_str(&format!(" {}", name));
+            }
+            // the location of an outer frame is where it made the call that was inlined
+            if let Some(location) = frame.location.filter(|_| !innermost) {
+                block.push_str(&format!(
+                    "@{}:{}",
+                    location.line.unwrap_or(0),
+                    location.column.unwrap_or(0)
+                ));
+            }
+            innermost = false;
+        }
+        block
     }
 
-    #[allow(dead_code)]
+    /// Returns the entry point of the function `func_name`, falling back to the symbol table for
+    /// functions without debugging information.
     pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        // declarations of external functions have no code
+        let defines = |func: &&Function| func.name == func_name && func.text_length > 0;
         match file {
             Some(filename) => Some(
                 self.get_target_file(filename)?
                     .functions
                     .iter()
-                    .find(|func| func.name == func_name)?
+                    .find(defines)?
                     .address,
             ),
             None => {
                 for file in &self.files {
-                    if let Some(func) = file.functions.iter().find(|func| func.name == func_name) {
+                    if let Some(func) = file.functions.iter().find(defines) {
                         return Some(func.address);
                     }
                 }
-                None
+                // the code of an indirect function only picks the implementation
+                self.symbols
+                    .iter()
+                    .find(|sym| sym.name == func_name && !sym.indirect)
+                    .map(|sym| sym.address)
             }
         }
     }
 
+    /// Returns the address of the resolver of the indirect function `func_name`, which returns
+    /// the address of the implementation when called.
+    pub fn get_indirect_function(&self, func_name: &str) -> Option<usize> {
+        self.symbols
+            .iter()
+            .find(|sym| sym.name == func_name && sym.indirect)
+            .map(|sym| sym.address)
+    }
+
+    /// Returns the type of the function `func_name`, from its definition or else a declaration
+    /// of it, like those of library functions in the units that call them.
+    pub fn get_function_type(&self, func_name: &str) -> Option<Type> {
+        let functions = || {
+            self.files
+                .iter()
+                .flat_map(|file| file.functions.iter())
+                .filter(|func| func.name == func_name && func.entity_type.is_some())
+        };
+        functions()
+            .find(|func| func.text_length > 0)
+            .or_else(|| functions().next())?
+            .entity_type
+            .clone()
+    }
+
+    /// Looks up a type by the name C code would use for it, like `struct node` or a typedef.
+    /// A complete struct is preferred over declarations of it.
+    pub fn get_type(&self, name: &str) -> Option<Type> {
+        let mut types = self
+            .files
+            .iter()
+            .flat_map(|file| file.types.values())
+            .filter(|entity_type| entity_type.name == name);
+        let first = types.next()?;
+        if first.size > 0 {
+            return Some(first.clone());
+        }
+        Some(
+            types
+                .find(|entity_type| entity_type.size > 0)
+                .unwrap_or(first)
+                .clone(),
+        )
+    }
+
+    /// Looks up an enumerator by name, returning the enum it belongs to and its value.
+    pub fn get_enumerator(&self, name: &str) -> Option<(Type, i64)> {
+        self.files
+            .iter()
+            .flat_map(|file| file.types.values())
+            .find_map(|entity_type| match &entity_type.kind {
+                Kind::Enum(values) => values
+                    .iter()
+                    .find(|(value_name, _)| value_name == name)
+                    .map(|(_, value)| (entity_type.clone(), *value)),
+                _ => None,
+            })
+    }
+
+    /// Returns the function whose code range contains `addr`.
+    pub fn get_function_containing(&self, addr: usize) -> Option<&Function> {
+        self.files
+            .iter()
+            .flat_map(|file| file.functions.iter())
+            .find(|func| func.address <= addr && addr < func.address + func.text_length)
+    }
+
+    /// Returns the name and code range of the function containing `addr`, from the debugging
+    /// information or else the symbol table.
+    pub fn get_function_range(&self, addr: usize) -> Option<(String, Range<usize>)> {
+        if let Some(func) = self.get_function_containing(addr) {
+            return Some((
+                func.name.clone(),
+                func.address..func.address + func.text_length,
+            ));
+        }
+        self.symbols
+            .iter()
+            .find(|sym| sym.address <= addr && addr < sym.address + sym.size)
+            .map(|sym| (sym.name.clone(), sym.address..sym.address + sym.size))
+    }
+
+    /// Returns the variables in scope at `addr`, innermost scope first, in the function that is
+    /// `inlined` inlined calls deep into the one containing `addr`. Variables of blocks that
+    /// `addr` isn't in, and of calls inlined into that function, are out of scope.
+    pub fn get_scope_variables(&self, addr: usize, inlined: usize) -> Vec<&Variable> {
+        let func = match self.get_function_containing(addr) {
+            Some(func) => func,
+            None => return Vec::new(),
+        };
+        // the scopes containing addr, outermost first, split up by function
+        let mut levels = vec![vec![&func.variables]];
+        let mut scopes = &func.scopes;
+        while let Some(scope) = scopes.iter().find(|scope| scope.contains(addr)) {
+            if scope.inlined {
+                levels.push(Vec::new());
+            }
+            levels.last_mut().unwrap().push(&scope.variables);
+            scopes = &scope.scopes;
+        }
+        let level = levels.len().saturating_sub(1).min(inlined);
+        levels
+            .swap_remove(level)
+            .into_iter()
+            .rev()
+            .flatten()
+            .collect()
+    }
+
+    /// Looks up a variable visible at `addr` in the function `inlined` inlined calls deep: a
+    /// local of the innermost scope declaring one by that name, or failing that, a global.
+    pub fn get_variable(&self, addr: usize, inlined: usize, name: &str) -> Option<&Variable> {
+        if let Some(var) = self
+            .get_scope_variables(addr, inlined)
+            .into_iter()
+            .find(|var| var.name == name)
+        {
+            return Some(var);
+        }
+        self.files
+            .iter()
+            .flat_map(|file| file.global_variables.iter())
+            .find(|var| var.name == name)
+    }
+
+    /// Returns the address of the first line-table entry after the entry point of `func`, which
+    /// is where its prologue ends and the code of its first line begins.
+    pub fn get_prologue_end(&self, func: &Function) -> usize {
+        self.files
+            .iter()
+            .flat_map(|file| file.lines.iter())
+            .map(|line| line.address)
+            .filter(|addr| func.address < *addr && *addr < func.address + func.text_length)
+            .min()
+            .unwrap_or(func.address)
+    }
+
+    /// Returns the rules for unwinding a frame executing at `addr`.
+    pub fn unwind_row(&self, addr: usize) -> Option<UnwindRow> {
+        self.cfi.find_row(self.link_time_addr(addr)?)
+    }
+
     #[allow(dead_code)]
     pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
         let location = self
             .addr2line
-            .find_location(curr_addr.try_into().unwrap())
+            .find_location(self.link_time_addr(curr_addr)?)
             .ok()??;
         Some(Line {
             file: location.file?.to_string(),
@@ -100,15 +467,56 @@ impl DwarfData {
         })
     }
 
-    #[allow(dead_code)]
+    /// Returns the functions running at `addr` and their lines, innermost first: the function
+    /// the code belongs to, then any it was inlined into, with the line of the inlined call.
+    pub fn get_inlined_frames(&self, addr: usize) -> Vec<(Option<String>, Option<Line>)> {
+        let mut frames = match self
+            .link_time_addr(addr)
+            .and_then(|addr| self.addr2line.find_frames(addr).ok())
+        {
+            Some(frames) => frames,
+            None => return Vec::new(),
+        };
+        let mut inlined_frames = Vec::new();
+        while let Ok(Some(frame)) = frames.next() {
+            let name = frame
+                .function
+                .as_ref()
+                .and_then(|func| func.raw_name().ok())
+                .map(|name| name.to_string());
+            let line = frame.location.and_then(|location| {
+                Some(Line {
+                    file: location.file?.to_string(),
+                    number: location.line? as usize,
+                    address: addr,
+                })
+            });
+            inlined_frames.push((name, line));
+        }
+        inlined_frames
+    }
+
+    /// Returns the name of the function containing `curr_addr`, from the debugging information
+    /// or else the symbol table.
     pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
         let frame = self
             .addr2line
-            .find_frames(curr_addr.try_into().unwrap())
-            .ok()?
-            .next()
-            .ok()??;
-        Some(frame.function?.raw_name().ok()?.to_string())
+            .find_frames(self.link_time_addr(curr_addr)?)
+            .ok()
+            .and_then(|mut frames| frames.next().ok().flatten());
+        if let Some(name) = frame
+            .and_then(|frame| frame.function)
+            .and_then(|func| func.raw_name().ok().map(|name| name.to_string()))
+        {
+            return Some(name);
+        }
+        self.symbols
+            .iter()
+            .find(|sym| {
+                sym.address == curr_addr
+                    || (sym.address < curr_addr && curr_addr < sym.address + sym.size)
+            })
+            .map(|sym| sym.name.clone())
     }
 
     #[allow(dead_code)]
@@ -148,32 +556,426 @@ impl DwarfData {
     }
 }
 
+/// How the bytes of a value of some type are to be interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Signed,
+    Unsigned,
+    Char,
+    UnsignedChar,
+    Boolean,
+    Float,
+    Pointer,
+}
+
+/// The types a unit declares, by their .debug_info offset.
+pub type TypeTable = HashMap<usize, Type>;
+
+/// How a pointer refers to the type it points to. Types declared in the debugging information
+/// are looked up where they were declared, since a struct may point to itself.
+#[derive(Debug, Clone)]
+pub enum TypeRef {
+    Declared {
+        table: Weak<TypeTable>,
+        offset: usize,
+    },
+    /// A type the debugger came up with, like that of `&x`
+    Made(Rc<Type>),
+}
+
+impl TypeRef {
+    /// Returns the type referred to, or None if the symbols it was declared in were unloaded.
+    pub fn get(&self) -> Option<Type> {
+        match self {
+            TypeRef::Declared { table, offset } => table.upgrade()?.get(offset).cloned(),
+            TypeRef::Made(target) => Some(Type::clone(target)),
+        }
+    }
+}
+
+/// A member of a struct or union.
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// Empty for an anonymous struct or union inside another
+    pub name: String,
+    pub offset: usize,
+    /// For a bit field, how many bits into `offset` it starts and how many it takes up
+    pub bits: Option<(usize, usize)>,
+    pub entity_type: Type,
+}
+
+/// What sort of type a type is. Typedefs and qualifiers like `const` don't have a kind of their
+/// own: they take that of the type they name.
+#[derive(Debug, Clone, Default)]
+pub enum Kind {
+    /// An integer, boolean or floating point type, as the encoding says
+    #[default]
+    Base,
+    /// A pointer, to void if the target isn't known
+    Pointer(Option<TypeRef>),
+    /// A struct or union; the members of a union are all at offset 0
+    Struct(Vec<Member>),
+    /// An array of a number of elements, which isn't known for e.g. flexible array members
+    Array(Box<Type>, Option<usize>),
+    /// An enum and the names of its values
+    Enum(Vec<(String, i64)>),
+    /// A function, and its prototype unless nothing is known about it but its address
+    Function(Option<Box<Signature>>),
+}
+
+/// What a function takes and returns, as its prototype declares.
+#[derive(Debug, Clone, Default)]
+pub struct Signature {
+    /// None for a function that returns void
+    pub returns: Option<Type>,
+    pub parameters: Vec<Type>,
+    /// Whether more arguments may follow the parameters, as for `printf`
+    pub variadic: bool,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Type {
     pub name: String,
     pub size: usize,
+    pub encoding: Encoding,
+    pub kind: Kind,
 }
 
+/// Arrays with more elements than this are cut short when printed, as gdb does.
+const PRINT_ELEMENTS: usize = 200;
+
+/// Runs of identical elements longer than this are printed as `<repeats N times>`.
+const REPEAT_THRESHOLD: usize = 10;
+
 impl Type {
-    pub fn new(name: String, size: usize) -> Self {
+    pub fn new(name: String, size: usize, encoding: Encoding) -> Self {
+        Type {
+            name,
+            size,
+            encoding,
+            kind: match encoding {
+                Encoding::Pointer => Kind::Pointer(None),
+                _ => Kind::Base,
+            },
+        }
+    }
+
+    /// Returns the type of a pointer to a value of this type.
+    pub fn pointer_to(&self) -> Type {
         Type {
-            name: name,
-            size: size,
+            name: pointer_name(&self.name),
+            size: 8,
+            encoding: Encoding::Pointer,
+            kind: Kind::Pointer(Some(TypeRef::Made(Rc::new(self.clone())))),
+        }
+    }
+
+    /// Returns the type a pointer points to, or None if this isn't a pointer or the target is
+    /// unknown, as for `void *`.
+    pub fn pointee(&self) -> Option<Type> {
+        match &self.kind {
+            Kind::Pointer(Some(target)) => target.get(),
+            _ => None,
+        }
+    }
+
+    /// Returns the member `name` of a struct or union.
+    pub fn member(&self, name: &str) -> Option<&Member> {
+        match &self.kind {
+            Kind::Struct(members) => members.iter().find(|member| member.name == name),
+            _ => None,
+        }
+    }
+
+    /// Returns whether values of this type are a single number, as opposed to structs, unions
+    /// and arrays.
+    pub fn is_scalar(&self) -> bool {
+        !matches!(
+            self.kind,
+            Kind::Struct(_) | Kind::Array(..) | Kind::Function(_)
+        )
+    }
+
+    /// Returns the C base type or pointer type written as `name`, for casts.
+    pub fn from_c_name(name: &str) -> Option<Type> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(pointee) = name.strip_suffix('*') {
+            let pointee = Type::from_c_name(pointee)?;
+            return Some(match pointee.name.as_str() {
+                "void" => Type::new("void *".to_string(), 8, Encoding::Pointer),
+                _ => pointee.pointer_to(),
+            });
+        }
+        let (size, encoding) = match name.as_str() {
+            "char" | "signed char" => (1, Encoding::Char),
+            "unsigned char" => (1, Encoding::UnsignedChar),
+            "_Bool" | "bool" => (1, Encoding::Boolean),
+            "short" | "short int" | "signed short" => (2, Encoding::Signed),
+            "unsigned short" | "unsigned short int" => (2, Encoding::Unsigned),
+            "int" | "signed" | "signed int" => (4, Encoding::Signed),
+            "unsigned" | "unsigned int" => (4, Encoding::Unsigned),
+            "long" | "long int" | "long long" | "long long int" => (8, Encoding::Signed),
+            "unsigned long" | "unsigned long int" | "unsigned long long" => (8, Encoding::Unsigned),
+            "float" => (4, Encoding::Float),
+            "double" => (8, Encoding::Float),
+            "void" => (1, Encoding::Signed),
+            _ => return None,
+        };
+        Some(Type::new(name, size, encoding))
+    }
+
+    /// Interprets the bytes of a value of this type as an integer. Returns None for floating
+    /// point types, structs and arrays.
+    pub fn integer_value(&self, bytes: &[u8]) -> Option<i128> {
+        let (unsigned, signed) = raw_value(bytes);
+        match self.encoding {
+            _ if !self.is_scalar() => None,
+            Encoding::Signed | Encoding::Char => Some(signed as i128),
+            Encoding::Float => None,
+            _ => Some(unsigned as i128),
+        }
+    }
+
+    /// Extracts a bit field of this type from the bytes it is stored in, given where it starts
+    /// and how many bits it takes up.
+    pub fn read_bits(&self, bytes: &[u8], (start, width): (usize, usize)) -> Vec<u8> {
+        let (raw, _) = raw_value(bytes);
+        let mut value = (raw >> start) & (u64::MAX >> (64 - width));
+        let signed = matches!(self.encoding, Encoding::Signed | Encoding::Char);
+        if signed && width < 64 && value >> (width - 1) & 1 == 1 {
+            value |= u64::MAX << width;
+        }
+        value.to_le_bytes()[..self.size.min(8)].to_vec()
+    }
+
+    /// Stores `value` into a bit field in the bytes it is stored in, leaving the bits around it
+    /// alone.
+    pub fn write_bits(&self, bytes: &mut [u8], (start, width): (usize, usize), value: &[u8]) {
+        let (storage, _) = raw_value(bytes);
+        let mask = (u64::MAX >> (64 - width)) << start;
+        let merged = (storage & !mask) | ((raw_value(value).0 << start) & mask);
+        let len = bytes.len().min(8);
+        bytes[..len].copy_from_slice(&merged.to_le_bytes()[..len]);
+    }
+
+    /// Renders a value of this type from its bytes in the inferior's memory.
+    pub fn format_value(&self, bytes: &[u8]) -> String {
+        match self.kind {
+            Kind::Pointer(_) => format!("({}) {:#x}", self.name, raw_value(bytes).0),
+            _ => self.format_element(bytes),
+        }
+    }
+
+    /// Renders a value that is part of a struct or array, where gdb leaves out the types of
+    /// pointers.
+    fn format_element(&self, bytes: &[u8]) -> String {
+        let (unsigned, signed) = raw_value(bytes);
+        match &self.kind {
+            Kind::Pointer(_) => format!("{:#x}", unsigned),
+            Kind::Struct(members) => {
+                let fields: Vec<String> = members
+                    .iter()
+                    .map(|member| {
+                        let value = member.format_value(bytes);
+                        match member.name.as_str() {
+                            "" => value,
+                            name => format!("{} = {}", name, value),
+                        }
+                    })
+                    .collect();
+                format!("{{{}}}", fields.join(", "))
+            }
+            Kind::Array(element, count) => {
+                let count = count.unwrap_or(0);
+                if matches!(element.encoding, Encoding::Char | Encoding::UnsignedChar)
+                    && element.size == 1
+                    && element.is_scalar()
+                {
+                    return format_string(&bytes[..count.min(bytes.len())]);
+                }
+                let elements: Vec<&[u8]> = (0..count.min(PRINT_ELEMENTS))
+                    .filter_map(|i| bytes.get(i * element.size..(i + 1) * element.size))
+                    .collect();
+                let mut parts = Vec::new();
+                let mut i = 0;
+                while i < elements.len() {
+                    let run = elements[i..]
+                        .iter()
+                        .take_while(|bytes| **bytes == elements[i])
+                        .count();
+                    let value = element.format_element(elements[i]);
+                    if run > REPEAT_THRESHOLD {
+                        parts.push(format!("{} <repeats {} times>", value, run));
+                        i += run;
+                    } else {
+                        parts.push(value);
+                        i += 1;
+                    }
+                }
+                if count > PRINT_ELEMENTS {
+                    parts.push("...".to_string());
+                }
+                format!("{{{}}}", parts.join(", "))
+            }
+            Kind::Enum(values) => {
+                let value = match self.encoding {
+                    Encoding::Unsigned => unsigned as i64,
+                    _ => signed,
+                };
+                match values.iter().find(|(_, v)| *v == value) {
+                    Some((name, _)) => name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            Kind::Function(_) => format!("{{{}}}", self.name),
+            Kind::Base => match self.encoding {
+                Encoding::Signed => signed.to_string(),
+                Encoding::Unsigned => unsigned.to_string(),
+                Encoding::Char => format!("{} {}", signed, char_literal(unsigned as u8)),
+                Encoding::UnsignedChar => format!("{} {}", unsigned, char_literal(unsigned as u8)),
+                Encoding::Boolean => (unsigned != 0).to_string(),
+                Encoding::Float if self.size == 4 => f32::from_bits(unsigned as u32).to_string(),
+                Encoding::Float if self.size == 8 => f64::from_bits(unsigned).to_string(),
+                Encoding::Float => format!("<{} of {} bytes>", self.name, self.size),
+                Encoding::Pointer => format!("{:#x}", unsigned),
+            },
         }
     }
 }
 
+impl Member {
+    /// Extracts the bytes of this member from those of the struct, shifting a bit field down
+    /// into a value of its own.
+    pub fn value(&self, bytes: &[u8]) -> Vec<u8> {
+        let end = (self.offset + self.entity_type.size).min(bytes.len());
+        let bytes = bytes.get(self.offset..end).unwrap_or_default();
+        match self.bits {
+            Some(bits) => self.entity_type.read_bits(bytes, bits),
+            None => bytes.to_vec(),
+        }
+    }
+
+    fn format_value(&self, bytes: &[u8]) -> String {
+        self.entity_type.format_element(&self.value(bytes))
+    }
+}
+
+/// Names a pointer to a type named `target`, putting the `*` where C would: `char **`,
+/// `int (*)[4]`, `void (*)(int)`.
+pub fn pointer_name(target: &str) -> String {
+    if let Some(i) = target.find(" (").or_else(|| target.find(" [")) {
+        format!("{} (*){}", &target[..i], &target[i + 1..])
+    } else if target.ends_with('*') {
+        format!("{}*", target)
+    } else {
+        format!("{} *", target)
+    }
+}
+
+/// Renders a char array the way gdb does: as a string, with long runs of a character split
+/// out as repeats. A final NUL is taken as the terminator and left out.
+fn format_string(bytes: &[u8]) -> String {
+    // gdb writes NUL as \000 in strings
+    let literal = |byte: u8| match byte {
+        0 => "'\\000'".to_string(),
+        _ => char_literal(byte),
+    };
+    let bytes = match bytes.split_last() {
+        Some((0, rest)) => rest,
+        _ => bytes,
+    };
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < bytes.len().min(PRINT_ELEMENTS) {
+        let run = bytes[i..].iter().take_while(|b| **b == bytes[i]).count();
+        if run > REPEAT_THRESHOLD {
+            if !text.is_empty() {
+                parts.push(format!("\"{}\"", std::mem::take(&mut text)));
+            }
+            parts.push(format!("{} <repeats {} times>", literal(bytes[i]), run));
+            i += run;
+            continue;
+        }
+        text.push_str(&match bytes[i] {
+            b'"' => "\\\"".to_string(),
+            b'\'' => "'".to_string(),
+            byte => literal(byte).trim_matches('\'').to_string(),
+        });
+        i += 1;
+    }
+    if !text.is_empty() || parts.is_empty() {
+        parts.push(format!("\"{}\"", text));
+    }
+    if bytes.len() > PRINT_ELEMENTS {
+        parts.push("...".to_string());
+    }
+    parts.join(", ")
+}
+
+/// Reads up to 8 little-endian bytes as an integer, both zero- and sign-extended.
+fn raw_value(bytes: &[u8]) -> (u64, i64) {
+    let mut raw = [0u8; 8];
+    let len = bytes.len().min(8);
+    raw[..len].copy_from_slice(&bytes[..len]);
+    let unsigned = u64::from_le_bytes(raw);
+    let shift = 64 - 8 * len.max(1) as u32;
+    (unsigned, ((unsigned << shift) as i64) >> shift)
+}
+
+/// Renders a byte the way C would write it as a character literal.
+fn char_literal(byte: u8) -> String {
+    match byte {
+        0 => "'\\0'".to_string(),
+        b'\n' => "'\\n'".to_string(),
+        b'\t' => "'\\t'".to_string(),
+        b'\'' => "'\\''".to_string(),
+        b'\\' => "'\\\\'".to_string(),
+        0x20..=0x7e => format!("'{}'", byte as char),
+        _ => format!("'\\{:03o}'", byte),
+    }
+}
+
 #[derive(Clone)]
 pub enum Location {
     Address(usize),
+    /// An offset from the frame base of the function
     FramePointerOffset(isize),
+    /// Any other location expression, like a register or a value pieced together from several
+    Expression(DwarfExpression),
+    /// Location expressions that each apply while the pc is in some range. Outside of them,
+    /// which is everywhere if there are none, the variable is optimized out.
+    List(Vec<(Range<usize>, DwarfExpression)>),
+}
+
+/// A DWARF expression, kept to be evaluated against a frame once it is needed.
+#[derive(Debug, Clone)]
+pub struct DwarfExpression {
+    pub bytes: Vec<u8>,
+    /// How far the binary was loaded from its link-time addresses, which the addresses in the
+    /// expression are
+    pub load_bias: usize,
 }
 
 impl fmt::Display for Location {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match *self {
+        match self {
             Location::Address(addr) => write!(f, "Address({:#x})", addr),
             Location::FramePointerOffset(offset) => write!(f, "FramePointerOffset({})", offset),
+            Location::Expression(expression) => write!(f, "Expression({:02x?})", expression.bytes),
+            Location::List(entries) => {
+                write!(f, "List(")?;
+                for (i, (range, expression)) in entries.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    write!(
+                        f,
+                        "{}{:#x}..{:#x}: {:02x?}",
+                        separator, range.start, range.end, expression.bytes
+                    )?;
+                }
+                write!(f, ")")
+            }
         }
     }
 }
@@ -191,6 +993,8 @@ pub struct Variable {
     pub entity_type: Type,
     pub location: Location,
     pub line_number: usize, // Line number in source file
+    /// Whether this is a formal parameter rather than a local variable
+    pub is_parameter: bool,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -199,12 +1003,51 @@ pub struct Function {
     pub address: usize,
     pub text_length: usize,
     pub line_number: usize, // Line number in source file
+    /// Its type, which says what it takes and returns
+    pub entity_type: Option<Type>,
     pub variables: Vec<Variable>,
+    /// What DW_OP_fbreg locations are relative to, if not the CFA like gcc does
+    pub frame_base: Option<DwarfExpression>,
+    /// The blocks and inlined calls in the function, which have variables of their own
+    pub scopes: Vec<Scope>,
+}
+
+/// A lexical block or an inlined call inside a function, and the variables declared in it.
+#[derive(Debug, Default, Clone)]
+pub struct Scope {
+    pub ranges: Vec<Range<usize>>,
+    /// Whether this is a call of a function inlined here. Its variables, and those of the
+    /// scopes inside it, belong to that function rather than the one it is in.
+    pub inlined: bool,
+    pub variables: Vec<Variable>,
+    pub scopes: Vec<Scope>,
+}
+
+impl Scope {
+    fn contains(&self, addr: usize) -> bool {
+        self.ranges.iter().any(|range| range.contains(&addr))
+    }
+
+    /// Relocates the ranges and variables of this scope and those inside it.
+    fn relocate(&mut self, delta: usize, relocate_variable: &dyn Fn(&mut Variable)) {
+        for range in &mut self.ranges {
+            *range = range.start.wrapping_add(delta)..range.end.wrapping_add(delta);
+        }
+        self.variables.iter_mut().for_each(relocate_variable);
+        for scope in &mut self.scopes {
+            scope.relocate(delta, relocate_variable);
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct File {
     pub name: String,
+    /// The directory the unit was compiled in, which a relative `name` is relative to
+    pub comp_dir: Option<String>,
+    /// The types the unit declares. Pointers look types up in here by way of a Weak, so this
+    /// is what keeps the table alive.
+    pub types: Rc<TypeTable>,
     pub global_variables: Vec<Variable>,
     pub functions: Vec<Function>,
     pub lines: Vec<Line>,
@@ -0,0 +1,280 @@
+use crate::disassembler::{decode, read_code};
+use crate::dwarf_data::{Encoding, Type};
+use crate::modules::Modules;
+use crate::target::Target;
+
+/// The longest string `x/s` prints before giving up on finding the terminating NUL.
+const MAX_STRING: usize = 200;
+
+/// The /FMT part of `x/FMT`: how many units to show, how to show them and how big they are.
+#[derive(Debug, Clone, Copy)]
+pub struct Format {
+    pub count: usize,
+    pub letter: char,
+    pub size: usize,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format {
+            count: 1,
+            letter: 'x',
+            size: 4,
+        }
+    }
+}
+
+impl Format {
+    /// Parses a format like `16xb` or `s`. As in gdb, the letter and size default to those used
+    /// last, and the count to 1.
+    pub fn parse(spec: &str, last: Format) -> Result<Format, String> {
+        let digits = spec.chars().take_while(char::is_ascii_digit).count();
+        let count = match &spec[..digits] {
+            "" => 1,
+            count => count
+                .parse()
+                .map_err(|_| format!("Invalid count {}", count))?,
+        };
+        let mut format = Format { count, ..last };
+        let mut letter = None;
+        let mut size = None;
+        for c in spec[digits..].chars() {
+            match c {
+                'b' => size = Some(1),
+                'h' => size = Some(2),
+                'w' => size = Some(4),
+                'g' => size = Some(8),
+                'x' | 'd' | 'u' | 'o' | 't' | 'c' | 'f' | 'a' | 's' | 'i' => letter = Some(c),
+                _ => return Err(format!("Invalid format letter '{}'", c)),
+            }
+        }
+        format.letter = match (letter, size) {
+            (Some(letter), _) => letter,
+            // a size after strings or instructions means numbers again
+            (None, Some(_)) if last.letter == 's' || last.letter == 'i' => 'x',
+            (None, _) => last.letter,
+        };
+        format.size = match (format.letter, size) {
+            (_, Some(size)) => size,
+            // these only come in one size, and leave the last one alone
+            ('c', None) => 1,
+            ('a', None) => 8,
+            (_, None) => last.size,
+        };
+        // floats are 4 or 8 bytes
+        if format.letter == 'f' && format.size < 4 {
+            format.size = 8;
+        }
+        Ok(format)
+    }
+
+    /// How many units fit on a line.
+    fn per_line(&self) -> usize {
+        match (self.letter, self.size) {
+            ('c', _) | (_, 1) | (_, 2) => 8,
+            (_, 4) => 4,
+            _ => 2,
+        }
+    }
+}
+
+/// Describes `addr` as `<func+offset>` when it is in a known function.
+pub fn symbolize(debug_data: &Modules, addr: usize) -> Option<String> {
+    let (name, range) = debug_data.get_function_range(addr)?;
+    Some(match addr - range.start {
+        0 => format!("<{}>", name),
+        offset => format!("<{}+{}>", name, offset),
+    })
+}
+
+/// Prefixes a line of `x` output with the address it starts at.
+fn line_start(debug_data: &Modules, addr: usize) -> String {
+    match symbolize(debug_data, addr) {
+        Some(symbol) => format!("{:#x} {}:", addr, symbol),
+        None => format!("{:#x}:", addr),
+    }
+}
+
+/// Writes a string the way C would, escaping what isn't printable.
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for byte in bytes {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            0x20..=0x7e => quoted.push(*byte as char),
+            _ => quoted.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads a NUL-terminated string at `addr`, up to MAX_STRING bytes. Also returns whether it
+/// was cut short.
+fn read_string(target: &dyn Target, addr: usize) -> Result<(Vec<u8>, bool), nix::Error> {
+    let mut bytes = Vec::new();
+    while bytes.len() < MAX_STRING {
+        // read up to the next 64-byte boundary, so as never to cross into an unmapped page
+        let start = addr + bytes.len();
+        let len = (64 - start % 64).min(MAX_STRING - bytes.len());
+        let chunk = match target.read_memory(start, len) {
+            Ok(chunk) => chunk,
+            Err(e) if bytes.is_empty() => return Err(e),
+            Err(_) => return Ok((bytes, false)),
+        };
+        match chunk.iter().position(|byte| *byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                return Ok((bytes, false));
+            }
+            None => bytes.extend_from_slice(&chunk),
+        }
+    }
+    Ok((bytes, true))
+}
+
+/// Renders one unit of memory in the format's letter.
+fn format_unit(debug_data: &Modules, bytes: &[u8], format: Format) -> String {
+    let size = format.size;
+    let signed = Type::new(String::new(), size, Encoding::Signed);
+    let unsigned = signed.integer_value(bytes).unwrap_or(0) as u64 & (u64::MAX >> (64 - 8 * size));
+    match format.letter {
+        'd' => signed.format_value(bytes),
+        'u' => unsigned.to_string(),
+        'o' if unsigned == 0 => "0".to_string(),
+        'o' => format!("0{:o}", unsigned),
+        't' => format!("{:0width$b}", unsigned, width = size * 8),
+        'c' => Type::new("char".to_string(), 1, Encoding::Char).format_value(bytes),
+        'f' => Type::new("double".to_string(), size, Encoding::Float).format_value(bytes),
+        'a' => match symbolize(debug_data, unsigned as usize) {
+            Some(symbol) => format!("{:#x} {}", unsigned, symbol),
+            None => format!("{:#x}", unsigned),
+        },
+        _ => format!("{:#0width$x}", unsigned, width = 2 + size * 2),
+    }
+}
+
+/// Implements `x`: prints `format.count` units of memory starting at `addr`. Returns the
+/// address after the last one, for the next `x` to carry on from.
+pub fn examine(
+    target: &dyn Target,
+    debug_data: &Modules,
+    addr: usize,
+    format: Format,
+) -> Result<usize, String> {
+    let cannot_access = |addr: usize| format!("Cannot access memory at address {:#x}", addr);
+    let mut addr = addr;
+    if format.letter == 'i' {
+        let pc = target.get_registers().ok().map(|regs| regs.rip as usize);
+        for _ in 0..format.count {
+            let code = read_code(target, addr, 0).map_err(|_| cannot_access(addr))?;
+            let instruction = decode(&code, addr);
+            let marker = if pc == Some(addr) { "=> " } else { "   " };
+            let line = line_start(debug_data, addr);
+            println!("{}{}\t{}", marker, line, instruction.render(debug_data));
+            addr += instruction.len;
+        }
+        return Ok(addr);
+    }
+    if format.letter == 's' {
+        for _ in 0..format.count {
+            let (bytes, truncated) = read_string(target, addr).map_err(|_| cannot_access(addr))?;
+            let ellipsis = if truncated { "..." } else { "" };
+            let line = line_start(debug_data, addr);
+            println!("{}\t{}{}", line, quote(&bytes), ellipsis);
+            addr += bytes.len() + if truncated { 0 } else { 1 };
+        }
+        return Ok(addr);
+    }
+    let mut remaining = format.count;
+    while remaining > 0 {
+        let units = remaining.min(format.per_line());
+        let bytes = target
+            .read_memory(addr, units * format.size)
+            .map_err(|_| cannot_access(addr))?;
+        let values: Vec<String> = bytes
+            .chunks(format.size)
+            .map(|unit| format_unit(debug_data, unit, format))
+            .collect();
+        println!("{}\t{}", line_start(debug_data, addr), values.join("\t"));
+        addr += bytes.len();
+        remaining -= units;
+    }
+    Ok(addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str, letter: char, size: usize) -> (usize, char, usize) {
+        let last = Format {
+            count: 3,
+            letter,
+            size,
+        };
+        let format = Format::parse(spec, last).unwrap();
+        (format.count, format.letter, format.size)
+    }
+
+    #[test]
+    fn count_defaults_to_one() {
+        assert_eq!(parse("", 'x', 4), (1, 'x', 4));
+        assert_eq!(parse("16xb", 'x', 4), (16, 'x', 1));
+        assert_eq!(parse("0x", 'x', 4), (0, 'x', 4));
+    }
+
+    #[test]
+    fn letter_and_size_carry_over_from_last_format() {
+        assert_eq!(parse("2", 'd', 2), (2, 'd', 2));
+        assert_eq!(parse("u", 'd', 2), (1, 'u', 2));
+        assert_eq!(parse("g", 'o', 1), (1, 'o', 8));
+        assert_eq!(parse("4tw", 'o', 1), (4, 't', 4));
+        // the last of several letters or sizes wins
+        assert_eq!(parse("xdbh", 'o', 8), (1, 'd', 2));
+    }
+
+    #[test]
+    fn chars_and_addresses_have_their_own_default_size() {
+        assert_eq!(parse("c", 'x', 4), (1, 'c', 1));
+        assert_eq!(parse("8c", 'x', 8), (8, 'c', 1));
+        assert_eq!(parse("a", 'x', 1), (1, 'a', 8));
+        assert_eq!(parse("2a", 'd', 4), (2, 'a', 8));
+        // unless a size is given
+        assert_eq!(parse("cw", 'x', 8), (1, 'c', 4));
+    }
+
+    #[test]
+    fn size_after_strings_or_instructions_means_hex() {
+        assert_eq!(parse("b", 's', 1), (1, 'x', 1));
+        assert_eq!(parse("2h", 'i', 1), (2, 'x', 2));
+        assert_eq!(parse("", 's', 1), (1, 's', 1));
+        assert_eq!(parse("3", 'i', 1), (3, 'i', 1));
+        assert_eq!(parse("g", 'x', 4), (1, 'x', 8));
+    }
+
+    #[test]
+    fn floats_are_at_least_four_bytes() {
+        assert_eq!(parse("f", 'x', 1), (1, 'f', 8));
+        assert_eq!(parse("fh", 'x', 4), (1, 'f', 8));
+        assert_eq!(parse("f", 'x', 4), (1, 'f', 4));
+        assert_eq!(parse("fw", 'x', 8), (1, 'f', 4));
+        assert_eq!(parse("2fg", 'x', 1), (2, 'f', 8));
+    }
+
+    #[test]
+    fn rejects_bad_formats() {
+        let last = Format::default();
+        assert_eq!(
+            Format::parse("4q", last).unwrap_err(),
+            "Invalid format letter 'q'"
+        );
+        assert_eq!(
+            Format::parse("99999999999999999999999x", last).unwrap_err(),
+            "Invalid count 99999999999999999999999"
+        );
+    }
+}
@@ -0,0 +1,1833 @@
+//! Parses and evaluates C expressions like `a[i] + b->len * 2`, `&x`, `(char) c` or
+//! `sizeof(struct foo)` against the state of the program, with the types its debugging
+//! information declares. This is what `print`, `display`, `x`, `watch`, `set var` and breakpoint
+//! conditions take.
+
+use crate::call::{self, Outcome};
+use crate::dwarf_data::{Encoding, Kind, Type};
+use crate::examine;
+use crate::inferior::{Inferior, Status};
+use crate::location::Storage;
+use crate::modules::Modules;
+use crate::registers;
+use crate::signals;
+use crate::target::Target;
+use crate::unwind::Frame;
+use std::convert::TryInto;
+
+/// The punctuators expressions are made of, longest first so that `->` isn't taken for `-`.
+const PUNCTUATORS: [&str; 30] = [
+    "->", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&",
+    "|", "^", "!", "~", "?", ":", "=", "(", ")", "[", "]", ".", ",",
+];
+
+/// The binary operators by precedence, loosest first.
+const BINARY_OPERATORS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", ">", "<=", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// The keywords a type name can be made of.
+const TYPE_WORDS: [&str; 15] = [
+    "struct", "union", "enum", "const", "volatile", "signed", "unsigned", "short", "long", "int",
+    "char", "float", "double", "void", "_Bool",
+];
+
+/// Strings longer than this are cut short when printed, as gdb does.
+const PRINT_ELEMENTS: usize = 200;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// An integer constant, and whether its suffix makes it unsigned and long
+    Integer(u64, bool, bool),
+    Float(f64),
+    Char(u8),
+    String(Vec<u8>),
+    Identifier(String),
+    /// `$name`
+    Register(String),
+    Punctuator(&'static str),
+}
+
+/// A type as written in a cast or `sizeof`: the name of a type and how many pointers to it.
+#[derive(Debug, Clone)]
+pub struct TypeName {
+    name: String,
+    pointers: usize,
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone)]
+pub enum Expression {
+    Integer(u64, Type),
+    Float(f64),
+    Char(u8),
+    String(Vec<u8>),
+    Identifier(String),
+    Register(String),
+    Unary(&'static str, Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+    Assign(Box<Expression>, Box<Expression>),
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    Cast(TypeName, Box<Expression>),
+    Sizeof(Box<Expression>),
+    SizeofType(TypeName),
+    Member(Box<Expression>, String),
+    Arrow(Box<Expression>, String),
+    Index(Box<Expression>, Box<Expression>),
+    /// A call of a function in the program, with its arguments
+    Call(Box<Expression>, Vec<Expression>),
+}
+
+/// Splits an expression into tokens, each with the offset it starts at.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+        let token = if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        } else if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            let hex = text[i..].starts_with("0x") || text[i..].starts_with("0X");
+            while i < bytes.len()
+                && (bytes[i].is_ascii_alphanumeric()
+                    || bytes[i] == b'.'
+                    || (matches!(bytes[i], b'+' | b'-')
+                        && matches!(bytes[i - 1], b'e' | b'E')
+                        && !hex))
+            {
+                i += 1;
+            }
+            parse_number(&text[start..i])?
+        } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+            i += 1;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            match text[start..i].strip_prefix('$') {
+                Some(name) => Token::Register(name.to_string()),
+                None => Token::Identifier(text[start..i].to_string()),
+            }
+        } else if c == b'\'' || c == b'"' {
+            let (value, end) = parse_quoted(bytes, i)?;
+            i = end;
+            match (c, value.as_slice()) {
+                (b'"', _) => Token::String(value),
+                (_, [byte]) => Token::Char(*byte),
+                _ => return Err("Invalid character constant.".to_string()),
+            }
+        } else {
+            let punctuator = PUNCTUATORS
+                .iter()
+                .find(|punctuator| text[i..].starts_with(**punctuator))
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid character '{}' in expression.",
+                        text[i..].chars().next().unwrap()
+                    )
+                })?;
+            i += punctuator.len();
+            Token::Punctuator(punctuator)
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// Reads an integer or floating point constant, with the suffixes C allows.
+fn parse_number(text: &str) -> Result<Token, String> {
+    let invalid = || format!("Invalid number \"{}\".", text);
+    let lower = text.to_ascii_lowercase();
+    let hex = lower.starts_with("0x");
+    if !hex && (lower.contains('.') || lower.contains('e')) {
+        return lower
+            .trim_end_matches(['f', 'l'])
+            .parse()
+            .map(Token::Float)
+            .map_err(|_| invalid());
+    }
+    let digits = lower.trim_end_matches(['u', 'l']);
+    let suffix = &lower[digits.len()..];
+    let value = if hex {
+        u64::from_str_radix(&digits[2..], 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    value
+        .map(|value| Token::Integer(value, suffix.contains('u'), suffix.contains('l')))
+        .map_err(|_| invalid())
+}
+
+/// Reads a character or string literal starting at the quote at `start`, returning the bytes
+/// it stands for and where it ends.
+fn parse_quoted(bytes: &[u8], start: usize) -> Result<(Vec<u8>, usize), String> {
+    let quote = bytes[start];
+    let mut value = Vec::new();
+    let mut i = start + 1;
+    loop {
+        let byte = match bytes.get(i) {
+            Some(byte) => *byte,
+            None if quote == b'"' => return Err("Unterminated string in expression.".to_string()),
+            None => return Err("Unmatched single quote.".to_string()),
+        };
+        i += 1;
+        if byte == quote {
+            return Ok((value, i));
+        }
+        if byte != b'\\' {
+            value.push(byte);
+            continue;
+        }
+        let escape = *bytes.get(i).ok_or("Unterminated string in expression.")?;
+        i += 1;
+        // numeric escapes run for as long as there are digits, up to three of them in octal
+        let digits = |i: usize, radix: u32, max: usize| {
+            let limit = bytes.len().min(i.saturating_add(max));
+            let end = (i..limit)
+                .find(|j| !(bytes[*j] as char).is_digit(radix))
+                .unwrap_or(limit);
+            let text = std::str::from_utf8(&bytes[i..end]).unwrap();
+            (u32::from_str_radix(text, radix).unwrap_or(0) as u8, end)
+        };
+        value.push(match escape {
+            b'n' => b'\n',
+            b't' => b'\t',
+            b'r' => b'\r',
+            b'a' => 7,
+            b'b' => 8,
+            b'f' => 12,
+            b'v' => 11,
+            b'e' => 27,
+            b'x' => {
+                let (byte, end) = digits(i, 16, usize::MAX);
+                i = end;
+                byte
+            }
+            b'0'..=b'7' => {
+                let (byte, end) = digits(i - 1, 8, 3);
+                i = end;
+                byte
+            }
+            other => other,
+        });
+    }
+}
+
+/// A recursive descent parser over the tokens of an expression. `is_type` tells typedef names
+/// apart from variables, which C needs to know to parse a cast.
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    is_type: &'a dyn Fn(&str) -> bool,
+}
+
+/// Parses a C expression. `is_type` says whether an identifier names a type.
+pub fn parse(text: &str, is_type: &dyn Fn(&str) -> bool) -> Result<Expression, String> {
+    let mut parser = Parser {
+        text,
+        tokens: tokenize(text)?,
+        position: 0,
+        is_type,
+    };
+    if parser.tokens.is_empty() {
+        return Err("Argument required (expression to compute).".to_string());
+    }
+    let expression = parser.assignment()?;
+    match parser.peek() {
+        None => Ok(expression),
+        Some(_) => Err(parser.syntax_error()),
+    }
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<&Token> {
+        self.tokens
+            .get(self.position + ahead)
+            .map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Consumes the punctuator `punctuator` if it comes next.
+    fn eat(&mut self, punctuator: &str) -> bool {
+        match self.peek() {
+            Some(Token::Punctuator(p)) if *p == punctuator => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, punctuator: &str) -> Result<(), String> {
+        match self.eat(punctuator) {
+            true => Ok(()),
+            false => Err(self.syntax_error()),
+        }
+    }
+
+    fn syntax_error(&self) -> String {
+        let rest = match self.tokens.get(self.position) {
+            Some((offset, _)) => &self.text[*offset..],
+            None => "",
+        };
+        format!("A syntax error in expression, near `{}'.", rest)
+    }
+
+    /// Returns whether the token `ahead` tokens on starts the name of a type.
+    fn starts_type(&self, ahead: usize) -> bool {
+        match self.peek_at(ahead) {
+            Some(Token::Identifier(word)) => {
+                TYPE_WORDS.contains(&word.as_str()) || (self.is_type)(word)
+            }
+            _ => false,
+        }
+    }
+
+    fn assignment(&mut self) -> Result<Expression, String> {
+        let lhs = self.conditional()?;
+        if self.eat("=") {
+            return Ok(Expression::Assign(
+                Box::new(lhs),
+                Box::new(self.assignment()?),
+            ));
+        }
+        Ok(lhs)
+    }
+
+    fn conditional(&mut self) -> Result<Expression, String> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.assignment()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expression::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Parses operators of precedence `level` and tighter.
+    fn binary(&mut self, level: usize) -> Result<Expression, String> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Punctuator(p)) if BINARY_OPERATORS[level].contains(p) => *p,
+                _ => return Ok(lhs),
+            };
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expression::Binary(operator, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.peek() {
+            Some(Token::Punctuator(p)) if ["-", "+", "!", "~", "*", "&"].contains(p) => {
+                let operator = *p;
+                self.position += 1;
+                Ok(Expression::Unary(operator, Box::new(self.unary()?)))
+            }
+            Some(Token::Identifier(word)) if word == "sizeof" => {
+                self.position += 1;
+                if matches!(self.peek(), Some(Token::Punctuator("("))) && self.starts_type(1) {
+                    self.position += 1;
+                    let type_name = self.type_name()?;
+                    self.expect(")")?;
+                    return Ok(Expression::SizeofType(type_name));
+                }
+                Ok(Expression::Sizeof(Box::new(self.unary()?)))
+            }
+            Some(Token::Punctuator("(")) if self.starts_type(1) => {
+                self.position += 1;
+                let type_name = self.type_name()?;
+                self.expect(")")?;
+                Ok(Expression::Cast(type_name, Box::new(self.unary()?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    /// Parses a type name like `unsigned long`, `struct node *` or `my_typedef`.
+    fn type_name(&mut self) -> Result<TypeName, String> {
+        let mut words = Vec::new();
+        while let Some(Token::Identifier(word)) = self.peek().cloned() {
+            match word.as_str() {
+                "const" | "volatile" => {}
+                "struct" | "union" | "enum" => match self.peek_at(1).cloned() {
+                    Some(Token::Identifier(tag)) => {
+                        words.push(format!("{} {}", word, tag));
+                        self.position += 1;
+                    }
+                    _ => return Err(self.syntax_error()),
+                },
+                _ if TYPE_WORDS.contains(&word.as_str()) => words.push(word),
+                // a typedef name is the whole type, unless it's a variable after one
+                _ if words.is_empty() && (self.is_type)(&word) => words.push(word),
+                _ => break,
+            }
+            self.position += 1;
+        }
+        if words.is_empty() {
+            return Err(self.syntax_error());
+        }
+        let mut pointers = 0;
+        loop {
+            if self.eat("*") {
+                pointers += 1;
+            } else if matches!(self.peek(), Some(Token::Identifier(word)) if word == "const" || word == "volatile")
+            {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        Ok(TypeName {
+            name: words.join(" "),
+            pointers,
+        })
+    }
+
+    fn postfix(&mut self) -> Result<Expression, String> {
+        let mut expression = self.primary()?;
+        loop {
+            expression = if self.eat("[") {
+                let index = self.assignment()?;
+                self.expect("]")?;
+                Expression::Index(Box::new(expression), Box::new(index))
+            } else if self.eat(".") {
+                Expression::Member(Box::new(expression), self.member_name()?)
+            } else if self.eat("->") {
+                Expression::Arrow(Box::new(expression), self.member_name()?)
+            } else if self.eat("(") {
+                let mut arguments = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        arguments.push(self.assignment()?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                Expression::Call(Box::new(expression), arguments)
+            } else {
+                return Ok(expression);
+            };
+        }
+    }
+
+    fn member_name(&mut self) -> Result<String, String> {
+        match self.peek().cloned() {
+            Some(Token::Identifier(name)) => {
+                self.position += 1;
+                Ok(name)
+            }
+            _ => Err(self.syntax_error()),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let error = self.syntax_error();
+        Ok(match self.next() {
+            Some(Token::Integer(value, unsigned, long)) => {
+                Expression::Integer(value, literal_type(value, unsigned, long))
+            }
+            Some(Token::Float(value)) => Expression::Float(value),
+            Some(Token::Char(value)) => Expression::Char(value),
+            Some(Token::String(mut value)) => {
+                // adjacent literals are one string
+                while let Some(Token::String(more)) = self.peek() {
+                    value.extend_from_slice(more);
+                    self.position += 1;
+                }
+                Expression::String(value)
+            }
+            Some(Token::Identifier(name)) if !TYPE_WORDS.contains(&name.as_str()) => {
+                Expression::Identifier(name)
+            }
+            Some(Token::Register(name)) => Expression::Register(name),
+            Some(Token::Punctuator("(")) => {
+                let expression = self.assignment()?;
+                self.expect(")")?;
+                expression
+            }
+            _ => return Err(error),
+        })
+    }
+}
+
+/// Returns the type C gives an integer constant: the first of int, unsigned int, long and
+/// unsigned long its value fits in, starting from the one its suffix asks for.
+fn literal_type(value: u64, unsigned: bool, long: bool) -> Type {
+    let name = if !long && !unsigned && value <= i32::MAX as u64 {
+        "int"
+    } else if !long && value <= u32::MAX as u64 && (unsigned || value > i32::MAX as u64) {
+        "unsigned int"
+    } else if !unsigned && value <= i64::MAX as u64 {
+        "long"
+    } else {
+        "unsigned long"
+    };
+    c_type(name)
+}
+
+impl Expression {
+    /// Returns the identifiers the expression names, which may be variables.
+    pub fn identifiers(&self) -> Vec<&str> {
+        match self {
+            Expression::Identifier(name) => vec![name.as_str()],
+            Expression::Unary(_, operand)
+            | Expression::Cast(_, operand)
+            | Expression::Sizeof(operand)
+            | Expression::Member(operand, _)
+            | Expression::Arrow(operand, _) => operand.identifiers(),
+            Expression::Binary(_, lhs, rhs)
+            | Expression::Assign(lhs, rhs)
+            | Expression::Index(lhs, rhs) => {
+                let mut names = lhs.identifiers();
+                names.extend(rhs.identifiers());
+                names
+            }
+            Expression::Conditional(condition, then, otherwise) => {
+                let mut names = condition.identifiers();
+                names.extend(then.identifiers());
+                names.extend(otherwise.identifiers());
+                names
+            }
+            Expression::Call(function, arguments) => {
+                let mut names = function.identifiers();
+                for argument in arguments {
+                    names.extend(argument.identifiers());
+                }
+                names
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Returns one of the C types the debugger knows without debugging information.
+fn c_type(name: &str) -> Type {
+    Type::from_c_name(name).unwrap()
+}
+
+/// The type of a function the debugger knows nothing about but its address.
+fn function_type() -> Type {
+    Type {
+        name: "<text variable, no debug info>".to_string(),
+        size: 1,
+        encoding: Encoding::Signed,
+        kind: Kind::Function(None),
+    }
+}
+
+/// The type of what a function returning void returns.
+fn void_type() -> Type {
+    Type {
+        name: "void".to_string(),
+        size: 0,
+        ..Type::default()
+    }
+}
+
+/// Writes a type name the way C does, with the sign first and `int` left out where it can be:
+/// `long unsigned int` becomes `unsigned long`.
+fn canonical_type_name(name: &str) -> String {
+    let mut words: Vec<&str> = name.split_whitespace().collect();
+    words.sort_by_key(|word| match *word {
+        "signed" | "unsigned" => 0,
+        "short" | "long" => 1,
+        _ => 2,
+    });
+    if words.len() > 1 && words.last() == Some(&"int") {
+        words.pop();
+    }
+    words.join(" ")
+}
+
+/// Whether values of a type are signed integers.
+fn is_signed(entity_type: &Type) -> bool {
+    matches!(entity_type.encoding, Encoding::Signed | Encoding::Char)
+        && !matches!(entity_type.kind, Kind::Pointer(_))
+}
+
+fn is_float(entity_type: &Type) -> bool {
+    entity_type.encoding == Encoding::Float && entity_type.is_scalar()
+}
+
+/// Whether a value of this type stands for an address in arithmetic.
+fn is_pointer(entity_type: &Type) -> bool {
+    matches!(entity_type.kind, Kind::Pointer(_) | Kind::Array(..))
+}
+
+/// Whether this is a pointer to `char`, which is printed with the string it points to.
+fn is_string_pointer(entity_type: &Type) -> bool {
+    entity_type.pointee().is_some_and(|pointee| {
+        matches!(pointee.encoding, Encoding::Char | Encoding::UnsignedChar)
+            && pointee.size == 1
+            && pointee.is_scalar()
+    })
+}
+
+/// Returns the type an integer operand is promoted to for arithmetic: int, unless the value
+/// doesn't fit.
+fn promote(entity_type: &Type) -> Type {
+    if is_float(entity_type) {
+        return entity_type.clone();
+    }
+    let unsigned = !is_signed(entity_type) && !matches!(entity_type.kind, Kind::Enum(_));
+    c_type(match (entity_type.size, unsigned) {
+        (0..=3, _) | (4, false) => "int",
+        (4, true) => "unsigned int",
+        (_, false) => "long",
+        (_, true) => "unsigned long",
+    })
+}
+
+/// Returns the type C carries out arithmetic between two operands in.
+fn arithmetic_type(lhs: &Type, rhs: &Type) -> Type {
+    if is_float(lhs) || is_float(rhs) {
+        let size = |t: &Type| if is_float(t) { t.size } else { 0 };
+        return c_type(if size(lhs).max(size(rhs)) > 4 {
+            "double"
+        } else {
+            "float"
+        });
+    }
+    let (lhs, rhs) = (promote(lhs), promote(rhs));
+    if lhs.size != rhs.size {
+        return if lhs.size > rhs.size { lhs } else { rhs };
+    }
+    if is_signed(&lhs) {
+        rhs
+    } else {
+        lhs
+    }
+}
+
+/// Converts an integer to a value of `entity_type`, wrapping it around like C does.
+fn convert(value: i128, entity_type: &Type) -> i128 {
+    let bits = 8 * entity_type.size.clamp(1, 8) as u32;
+    if bits == 64 && is_signed(entity_type) {
+        return value as i64 as i128;
+    }
+    let truncated = (value as u128 & (u128::MAX >> (128 - bits))) as i128;
+    if is_signed(entity_type) && truncated >> (bits - 1) & 1 == 1 {
+        truncated - (1 << bits)
+    } else {
+        truncated
+    }
+}
+
+/// A scalar value as arithmetic sees it.
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Integer(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn to_f64(self) -> f64 {
+        match self {
+            Number::Integer(value) => value as f64,
+            Number::Float(value) => value,
+        }
+    }
+
+    fn to_i128(self) -> i128 {
+        match self {
+            Number::Integer(value) => value,
+            Number::Float(value) => value as i128,
+        }
+    }
+}
+
+/// The result of evaluating an expression: an lvalue somewhere in the program or a value the
+/// debugger computed, and its type.
+#[derive(Debug, Clone)]
+pub struct Value {
+    pub entity_type: Type,
+    pub storage: Storage,
+    /// For a bit field, which bits of the bytes at `storage` it takes up
+    pub bits: Option<(usize, usize)>,
+}
+
+impl Value {
+    /// A value the debugger computed.
+    fn new(entity_type: Type, mut bytes: Vec<u8>) -> Value {
+        bytes.resize(entity_type.size, 0);
+        Value {
+            entity_type,
+            storage: Storage::Value(bytes),
+            bits: None,
+        }
+    }
+
+    /// An lvalue in memory.
+    fn at(entity_type: Type, addr: usize) -> Value {
+        Value {
+            entity_type,
+            storage: Storage::Memory(addr),
+            bits: None,
+        }
+    }
+
+    fn integer(entity_type: Type, value: i128) -> Value {
+        let bytes = value.to_le_bytes()[..entity_type.size.min(16)].to_vec();
+        Value::new(entity_type, bytes)
+    }
+
+    fn float(entity_type: Type, value: f64) -> Value {
+        let bytes = match entity_type.size {
+            4 => (value as f32).to_le_bytes().to_vec(),
+            _ => value.to_le_bytes().to_vec(),
+        };
+        Value::new(entity_type, bytes)
+    }
+
+    fn boolean(value: bool) -> Value {
+        Value::integer(c_type("int"), value as i128)
+    }
+
+    /// Returns the address of the lvalue, if it is in memory.
+    pub fn addr(&self) -> Result<usize, String> {
+        match self.storage {
+            Storage::Memory(addr) if self.bits.is_none() => Ok(addr),
+            Storage::OptimizedOut => Err("value has been optimized out".to_string()),
+            _ => Err("Attempt to take address of value not located in memory.".to_string()),
+        }
+    }
+
+    /// Returns where the part of the value `offset` bytes in and `size` bytes long is.
+    fn part(&self, offset: usize, size: usize) -> Storage {
+        match &self.storage {
+            Storage::Memory(addr) => Storage::Memory(addr + offset),
+            Storage::Value(bytes) => {
+                let end = (offset + size).min(bytes.len());
+                Storage::Value(bytes.get(offset..end).unwrap_or_default().to_vec())
+            }
+            Storage::OptimizedOut => Storage::OptimizedOut,
+        }
+    }
+}
+
+/// What expressions are evaluated against. Only a live process can be changed by an
+/// assignment; a core dump, or a process whose breakpoint condition is being tested, can only
+/// be looked at.
+pub enum Program<'a> {
+    ReadOnly(&'a dyn Target),
+    Live(&'a mut Inferior),
+}
+
+/// Evaluates expressions in one frame of the program.
+pub struct Evaluator<'a> {
+    program: Program<'a>,
+    debug_data: &'a Modules,
+    frame: Frame,
+    /// Whether `frame` is the innermost one, whose registers are those of the thread
+    innermost: bool,
+    /// How the program ended, if it did in a function the expression called
+    ended: Option<Status>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(
+        program: Program<'a>,
+        debug_data: &'a Modules,
+        frame: Frame,
+        innermost: bool,
+    ) -> Evaluator<'a> {
+        Evaluator {
+            program,
+            debug_data,
+            frame,
+            innermost,
+            ended: None,
+        }
+    }
+
+    /// Returns how the program ended if it did while the expression called a function in it,
+    /// after which there is nothing left to evaluate against.
+    pub fn take_ended(&mut self) -> Option<Status> {
+        self.ended.take()
+    }
+
+    fn target(&self) -> &dyn Target {
+        match &self.program {
+            Program::ReadOnly(target) => *target,
+            Program::Live(inferior) => &**inferior,
+        }
+    }
+
+    pub fn evaluate(&mut self, expression: &Expression) -> Result<Value, String> {
+        match expression {
+            Expression::Integer(value, entity_type) => {
+                Ok(Value::integer(entity_type.clone(), *value as i128))
+            }
+            Expression::Float(value) => Ok(Value::float(c_type("double"), *value)),
+            Expression::Char(value) => Ok(Value::integer(c_type("char"), *value as i128)),
+            Expression::String(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.push(0);
+                let entity_type = Type {
+                    name: format!("char [{}]", bytes.len()),
+                    size: bytes.len(),
+                    encoding: Encoding::Char,
+                    kind: Kind::Array(Box::new(c_type("char")), Some(bytes.len())),
+                };
+                Ok(Value::new(entity_type, bytes))
+            }
+            Expression::Identifier(name) => self.identifier(name),
+            Expression::Register(name) => self.register(name),
+            Expression::Unary(operator, operand) => self.unary(operator, operand),
+            Expression::Binary(operator, lhs, rhs) => self.binary(operator, lhs, rhs),
+            Expression::Assign(lhs, rhs) => self.assign(lhs, rhs),
+            Expression::Conditional(condition, then, otherwise) => {
+                let condition = self.evaluate(condition)?;
+                match self.truth(&condition)? {
+                    true => self.evaluate(then),
+                    false => self.evaluate(otherwise),
+                }
+            }
+            Expression::Cast(type_name, operand) => {
+                let entity_type = self.resolve_type(type_name)?;
+                let value = match &**operand {
+                    // the cast says what a function without debugging information returns
+                    Expression::Call(function, arguments) => {
+                        self.call(function, arguments, Some(&entity_type))?
+                    }
+                    _ => self.evaluate(operand)?,
+                };
+                self.cast(value, entity_type)
+            }
+            Expression::Sizeof(operand) => {
+                let size = self.evaluate(operand)?.entity_type.size;
+                Ok(Value::integer(c_type("unsigned long"), size as i128))
+            }
+            Expression::SizeofType(type_name) => {
+                let size = self.resolve_type(type_name)?.size;
+                Ok(Value::integer(c_type("unsigned long"), size as i128))
+            }
+            Expression::Member(operand, name) => {
+                let base = self.evaluate(operand)?;
+                self.member(base, name)
+            }
+            Expression::Arrow(operand, name) => {
+                let base = self.evaluate(operand)?;
+                let base = self.dereference(base, 0)?;
+                self.member(base, name)
+            }
+            Expression::Index(operand, index) => {
+                let base = self.evaluate(operand)?;
+                let index = self.evaluate(index)?;
+                // C allows `i[array]` too
+                let (base, index) = match is_pointer(&index.entity_type) {
+                    true => (index, base),
+                    false => (base, index),
+                };
+                if is_float(&index.entity_type) {
+                    return Err("Invalid array index.".to_string());
+                }
+                let index = self.number(&index)?.to_i128() as i64;
+                self.dereference(base, index)
+            }
+            Expression::Call(function, arguments) => self.call(function, arguments, None),
+        }
+    }
+
+    /// Works out the type a cast or `sizeof` names, from the debugging information or the
+    /// C types the debugger knows.
+    fn resolve_type(&self, type_name: &TypeName) -> Result<Type, String> {
+        let canonical = canonical_type_name(&type_name.name);
+        let mut entity_type = self
+            .debug_data
+            .get_type(&type_name.name)
+            .or_else(|| Type::from_c_name(&canonical))
+            .ok_or_else(|| format!("No symbol \"{}\" in current context.", type_name.name))?;
+        for i in 0..type_name.pointers {
+            entity_type = match (i, canonical.as_str()) {
+                (0, "void") => c_type("void *"),
+                _ => entity_type.pointer_to(),
+            };
+        }
+        Ok(entity_type)
+    }
+
+    fn identifier(&mut self, name: &str) -> Result<Value, String> {
+        let target = self.target();
+        match target.locate_variable(self.debug_data, &self.frame, name) {
+            Ok(Some((var, storage))) => {
+                return Ok(Value {
+                    storage,
+                    entity_type: var.entity_type.clone(),
+                    bits: None,
+                })
+            }
+            Ok(None) => {}
+            Err(e) => return Err(format!("Cannot find {}: {}", name, e)),
+        }
+        if let Some((entity_type, value)) = self.debug_data.get_enumerator(name) {
+            return Ok(Value::integer(entity_type, value as i128));
+        }
+        let debug_data = self.debug_data;
+        let entity_type = || {
+            debug_data
+                .get_function_type(name)
+                .unwrap_or_else(function_type)
+        };
+        if let Some(addr) = self.debug_data.get_addr_for_function(None, name) {
+            return Ok(Value::at(entity_type(), addr));
+        }
+        match self.debug_data.get_indirect_function(name) {
+            Some(resolver) => {
+                // the resolver returns the implementation to use on this machine
+                let pointer = c_type("void *");
+                let addr = self.invoke(name, resolver, Vec::new(), Some(&pointer))?;
+                let addr = self.number(&addr)?.to_i128() as usize;
+                Ok(Value::at(entity_type(), addr))
+            }
+            None => Err(format!("No symbol \"{}\" in current context.", name)),
+        }
+    }
+
+    /// Reads `$name` in the frame. Outer frames only have the registers the unwinder
+    /// recovered.
+    fn register(&self, name: &str) -> Result<Value, String> {
+        let register =
+            registers::find(name).ok_or_else(|| format!("Invalid register `{}'", name))?;
+        let regs = self
+            .target()
+            .get_registers()
+            .map_err(|e| format!("Cannot read registers: {}", e))?;
+        let value = register
+            .value(&regs, &self.frame, self.innermost)
+            .ok_or_else(|| format!("value of ${} is not available", name))?;
+        let entity_type = match register.name {
+            "rip" => Type {
+                name: "void ()".to_string(),
+                ..function_type()
+            }
+            .pointer_to(),
+            "rsp" | "rbp" => c_type("void *"),
+            "eflags" => c_type("int"),
+            _ => c_type("long"),
+        };
+        Ok(Value::integer(entity_type, value as i128))
+    }
+
+    /// Reads the bytes of a value.
+    fn read(&self, value: &Value) -> Result<Vec<u8>, String> {
+        let bytes = value
+            .storage
+            .read(self.target(), value.entity_type.size)?
+            .ok_or_else(|| "value has been optimized out".to_string())?;
+        Ok(match value.bits {
+            Some(bits) => value.entity_type.read_bits(&bytes, bits),
+            None => bytes,
+        })
+    }
+
+    /// Reads a scalar value as a number. Arrays and functions stand for their addresses.
+    fn number(&self, value: &Value) -> Result<Number, String> {
+        let entity_type = &value.entity_type;
+        match entity_type.kind {
+            Kind::Array(..) | Kind::Function(_) => {
+                return Ok(Number::Integer(value.addr()? as i128))
+            }
+            Kind::Struct(_) => {
+                return Err("Argument to arithmetic operation not a number or boolean.".to_string())
+            }
+            _ => {}
+        }
+        let bytes = self.read(value)?;
+        if is_float(entity_type) {
+            return match bytes.len() {
+                4 => Ok(Number::Float(
+                    f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+                )),
+                8 => Ok(Number::Float(f64::from_le_bytes(
+                    bytes[..8].try_into().unwrap(),
+                ))),
+                _ => Err(format!(
+                    "Cannot do arithmetic on {} values",
+                    entity_type.name
+                )),
+            };
+        }
+        entity_type
+            .integer_value(&bytes)
+            .map(Number::Integer)
+            .ok_or_else(|| "Argument to arithmetic operation not a number or boolean.".to_string())
+    }
+
+    /// Returns whether a value counts as true in a condition: whether it is non-zero.
+    pub fn truth(&self, value: &Value) -> Result<bool, String> {
+        Ok(match self.number(value)? {
+            Number::Integer(value) => value != 0,
+            Number::Float(value) => value != 0.0,
+        })
+    }
+
+    fn unary(&mut self, operator: &str, operand: &Expression) -> Result<Value, String> {
+        let value = self.evaluate(operand)?;
+        let not_a_number = || "Argument to arithmetic operation not a number or boolean.";
+        match operator {
+            "&" => self.address_of(value),
+            "*" => match value.entity_type.kind {
+                // like gdb, take what an integer points at for an int
+                Kind::Base if !is_float(&value.entity_type) => {
+                    let addr = self.number(&value)?.to_i128() as usize;
+                    Ok(Value::at(c_type("int"), addr))
+                }
+                Kind::Function(_) => Ok(value),
+                _ => self.dereference(value, 0),
+            },
+            "!" => Ok(Value::boolean(!self.truth(&value)?)),
+            _ if is_pointer(&value.entity_type) => Err(not_a_number().to_string()),
+            _ => {
+                let result_type = promote(&value.entity_type);
+                match (operator, self.number(&value)?) {
+                    ("-", Number::Float(number)) => Ok(Value::float(result_type, -number)),
+                    ("~", Number::Float(_)) => Err(not_a_number().to_string()),
+                    (_, Number::Float(number)) => Ok(Value::float(result_type, number)),
+                    (operator, Number::Integer(number)) => {
+                        let number = convert(number, &result_type);
+                        let result = match operator {
+                            "-" => number.wrapping_neg(),
+                            "~" => !number,
+                            _ => number,
+                        };
+                        Ok(Value::integer(result_type, result))
+                    }
+                }
+            }
+        }
+    }
+
+    fn address_of(&self, value: Value) -> Result<Value, String> {
+        let addr = value.addr()?;
+        let pointer_type = value.entity_type.pointer_to();
+        Ok(Value::integer(pointer_type, addr as i128))
+    }
+
+    /// Works out element `index` of what `value` points to, or of `value` itself if it is an
+    /// array, so that `*p` is element 0 and `p[i]` element i.
+    fn dereference(&self, value: Value, index: i64) -> Result<Value, String> {
+        match &value.entity_type.kind {
+            Kind::Array(element, _) => {
+                // an element of an array kept in registers is there too
+                let offset = index.wrapping_mul(element.size as i64);
+                let storage = match value.storage {
+                    Storage::Memory(addr) => Storage::Memory(addr.wrapping_add(offset as usize)),
+                    _ if offset < 0 => Storage::OptimizedOut,
+                    _ => value.part(offset as usize, element.size),
+                };
+                return Ok(Value {
+                    storage,
+                    entity_type: Type::clone(element),
+                    bits: None,
+                });
+            }
+            Kind::Pointer(_) => {}
+            _ => {
+                return Err(match index {
+                    0 => "Attempt to take contents of a non-pointer value.".to_string(),
+                    _ => "cannot subscript something that is not an array or pointer".to_string(),
+                })
+            }
+        }
+        let element = value
+            .entity_type
+            .pointee()
+            .ok_or_else(|| "Attempt to take contents of a non-pointer value.".to_string())?;
+        let start = self.number(&value)?.to_i128() as i64;
+        let addr = start.wrapping_add(index.wrapping_mul(element.size as i64));
+        Ok(Value::at(element, addr as usize))
+    }
+
+    fn member(&self, base: Value, name: &str) -> Result<Value, String> {
+        let member =
+            base.entity_type
+                .member(name)
+                .ok_or_else(|| match base.entity_type.kind {
+                    Kind::Struct(_) => format!("There is no member named {}.", name),
+                    _ => "Attempt to extract a component of a value that is not a structure."
+                        .to_string(),
+                })?;
+        Ok(Value {
+            storage: base.part(member.offset, member.entity_type.size),
+            entity_type: member.entity_type.clone(),
+            bits: member.bits,
+        })
+    }
+
+    fn binary(
+        &mut self,
+        operator: &str,
+        lhs: &Expression,
+        rhs: &Expression,
+    ) -> Result<Value, String> {
+        if operator == "&&" || operator == "||" {
+            let lhs = self.evaluate(lhs)?;
+            let lhs = self.truth(&lhs)?;
+            if lhs == (operator == "||") {
+                return Ok(Value::boolean(lhs));
+            }
+            let rhs = self.evaluate(rhs)?;
+            return Ok(Value::boolean(self.truth(&rhs)?));
+        }
+        let lhs = self.evaluate(lhs)?;
+        let rhs = self.evaluate(rhs)?;
+        let pointers = (is_pointer(&lhs.entity_type), is_pointer(&rhs.entity_type));
+        if let ("+" | "-", (true, _) | (_, true)) = (operator, pointers) {
+            return self.pointer_arithmetic(operator, lhs, rhs);
+        }
+        let comparison = ["==", "!=", "<", ">", "<=", ">="].contains(&operator);
+        if pointers != (false, false) && !comparison {
+            return Err("Argument to arithmetic operation not a number or boolean.".to_string());
+        }
+        let result_type = match operator {
+            "<<" | ">>" => promote(&lhs.entity_type),
+            _ if pointers != (false, false) => c_type("unsigned long"),
+            _ => arithmetic_type(&lhs.entity_type, &rhs.entity_type),
+        };
+        let (lhs, rhs) = (self.number(&lhs)?, self.number(&rhs)?);
+        if is_float(&result_type) {
+            let (lhs, rhs) = (lhs.to_f64(), rhs.to_f64());
+            let result = match operator {
+                "+" => lhs + rhs,
+                "-" => lhs - rhs,
+                "*" => lhs * rhs,
+                "/" => lhs / rhs,
+                "==" => return Ok(Value::boolean(lhs == rhs)),
+                "!=" => return Ok(Value::boolean(lhs != rhs)),
+                "<" => return Ok(Value::boolean(lhs < rhs)),
+                ">" => return Ok(Value::boolean(lhs > rhs)),
+                "<=" => return Ok(Value::boolean(lhs <= rhs)),
+                ">=" => return Ok(Value::boolean(lhs >= rhs)),
+                _ => return Err("Integer only operation.".to_string()),
+            };
+            return Ok(Value::float(result_type, result));
+        }
+        let lhs = convert(lhs.to_i128(), &result_type);
+        let rhs = match operator {
+            "<<" | ">>" => rhs.to_i128(),
+            _ => convert(rhs.to_i128(), &result_type),
+        };
+        let result = match operator {
+            "+" => lhs.wrapping_add(rhs),
+            "-" => lhs.wrapping_sub(rhs),
+            "*" => lhs.wrapping_mul(rhs),
+            "/" | "%" if rhs == 0 => return Err("Division by zero".to_string()),
+            "/" => lhs.wrapping_div(rhs),
+            "%" => lhs.wrapping_rem(rhs),
+            "<<" => lhs.checked_shl(rhs as u32).unwrap_or(0),
+            ">>" => lhs
+                .checked_shr(rhs as u32)
+                .unwrap_or(if lhs < 0 { -1 } else { 0 }),
+            "&" => lhs & rhs,
+            "|" => lhs | rhs,
+            "^" => lhs ^ rhs,
+            "==" => return Ok(Value::boolean(lhs == rhs)),
+            "!=" => return Ok(Value::boolean(lhs != rhs)),
+            "<" => return Ok(Value::boolean(lhs < rhs)),
+            ">" => return Ok(Value::boolean(lhs > rhs)),
+            "<=" => return Ok(Value::boolean(lhs <= rhs)),
+            _ => return Ok(Value::boolean(lhs >= rhs)),
+        };
+        Ok(Value::integer(result_type, result))
+    }
+
+    /// Adds an integer to a pointer or subtracts one from it, in elements of the type it points
+    /// to, or works out how many elements apart two pointers are.
+    fn pointer_arithmetic(&self, operator: &str, lhs: Value, rhs: Value) -> Result<Value, String> {
+        // an array stands for a pointer to its first element
+        let decay = |value: Value| match &value.entity_type.kind {
+            Kind::Array(element, _) => {
+                Ok(Value::integer(element.pointer_to(), value.addr()? as i128))
+            }
+            _ => Ok::<_, String>(value),
+        };
+        let (lhs, rhs) = (decay(lhs)?, decay(rhs)?);
+        // like gdb, take void and functions to be a byte long
+        let element_size = |value: &Value| {
+            value
+                .entity_type
+                .pointee()
+                .map_or(1, |pointee| pointee.size)
+                .max(1) as i128
+        };
+        let (pointer, offset) = match (
+            operator,
+            is_pointer(&lhs.entity_type),
+            is_pointer(&rhs.entity_type),
+        ) {
+            ("-", true, true) => {
+                if element_size(&lhs) != element_size(&rhs) {
+                    return Err(
+                        "First argument of `-' is a pointer and second argument is neither\n\
+                         an integer nor a pointer of the same type."
+                            .to_string(),
+                    );
+                }
+                let difference = self.number(&lhs)?.to_i128() - self.number(&rhs)?.to_i128();
+                return Ok(Value::integer(
+                    c_type("long"),
+                    difference / element_size(&lhs),
+                ));
+            }
+            (_, true, false) => (lhs, rhs),
+            ("+", false, true) => (rhs, lhs),
+            _ => {
+                return Err("Argument to arithmetic operation not a number or boolean.".to_string())
+            }
+        };
+        if is_float(&offset.entity_type) {
+            return Err("Argument to arithmetic operation not a number or boolean.".to_string());
+        }
+        let base = self.number(&pointer)?.to_i128();
+        let offset = self.number(&offset)?.to_i128() * element_size(&pointer);
+        let addr = match operator {
+            "+" => base.wrapping_add(offset),
+            _ => base.wrapping_sub(offset),
+        };
+        Ok(Value::integer(pointer.entity_type, addr))
+    }
+
+    /// Converts a value to another type, as a C cast does.
+    fn cast(&self, value: Value, entity_type: Type) -> Result<Value, String> {
+        if !entity_type.is_scalar() || (!value.entity_type.is_scalar() && !is_pointer(&entity_type))
+        {
+            if entity_type.name == value.entity_type.name {
+                return Ok(value);
+            }
+            return Err("Invalid cast.".to_string());
+        }
+        let number = self.number(&value)?;
+        if is_float(&entity_type) {
+            return Ok(Value::float(entity_type, number.to_f64()));
+        }
+        let number = match entity_type.encoding {
+            Encoding::Boolean => (number.to_f64() != 0.0) as i128,
+            _ => number.to_i128(),
+        };
+        Ok(Value::integer(entity_type, number))
+    }
+
+    fn assign(&mut self, lhs: &Expression, rhs: &Expression) -> Result<Value, String> {
+        if let Expression::Register(name) = lhs {
+            return self.assign_register(name, rhs);
+        }
+        let target = self.evaluate(lhs)?;
+        let value = self.evaluate(rhs)?;
+        let addr = match target.storage {
+            Storage::Memory(addr) => addr,
+            Storage::Value(_) => {
+                return Err("Left operand of assignment is not an lvalue.".to_string())
+            }
+            Storage::OptimizedOut => return Err("value has been optimized out".to_string()),
+        };
+        let value = self.cast(value, target.entity_type.clone())?;
+        let mut bytes = self.read(&value)?;
+        if let Some(bits) = target.bits {
+            // the bits around a bit field belong to its neighbours
+            let value = bytes;
+            bytes = self
+                .target()
+                .read_memory(addr, target.entity_type.size)
+                .map_err(|e| format!("Cannot access memory at address {:#x}: {}", addr, e))?;
+            target.entity_type.write_bits(&mut bytes, bits, &value);
+        }
+        match &mut self.program {
+            Program::Live(inferior) => inferior
+                .write_memory(addr, &bytes)
+                .map_err(|e| format!("Cannot access memory at address {:#x}: {}", addr, e))?,
+            Program::ReadOnly(_) => return Err("Cannot change the program here.".to_string()),
+        }
+        Ok(target)
+    }
+
+    /// Implements `$reg = value`. Only the innermost frame's registers can be written.
+    fn assign_register(&mut self, name: &str, rhs: &Expression) -> Result<Value, String> {
+        let register =
+            registers::find(name).ok_or_else(|| format!("Invalid register `{}'", name))?;
+        if !self.innermost {
+            return Err("Registers can only be set in the innermost frame.".to_string());
+        }
+        let value = self.evaluate(rhs)?;
+        let value = self.number(&value)?.to_i128() as u64;
+        let inferior = match &mut self.program {
+            Program::Live(inferior) => inferior,
+            Program::ReadOnly(_) => return Err("Cannot change the program here.".to_string()),
+        };
+        inferior
+            .get_registers()
+            .and_then(|mut regs| {
+                register.set(&mut regs, value);
+                inferior.set_registers(regs)
+            })
+            .map_err(|e| format!("Cannot write register {}: {}", register.name, e))?;
+        self.register(name)
+    }
+
+    /// Calls a function in the program. A cast of the call says what the function returns, if
+    /// there is no debugging information to say.
+    fn call(
+        &mut self,
+        function: &Expression,
+        arguments: &[Expression],
+        cast: Option<&Type>,
+    ) -> Result<Value, String> {
+        let name = match function {
+            Expression::Identifier(name) => Some(name.clone()),
+            _ => None,
+        };
+        let mut function = self.evaluate(function)?;
+        if let Some(pointee) = function.entity_type.pointee() {
+            if let Kind::Function(_) = pointee.kind {
+                function = self.dereference(function, 0)?;
+            }
+        }
+        let signature = match &function.entity_type.kind {
+            Kind::Function(signature) => signature.clone(),
+            _ => return Err("Cannot call something that is not a function.".to_string()),
+        };
+        let addr = function.addr()?;
+        let name = name
+            .or_else(|| Some(self.debug_data.get_function_range(addr)?.0))
+            .unwrap_or_else(|| format!("{:#x}", addr));
+        let mut values = Vec::new();
+        for argument in arguments {
+            values.push(self.evaluate(argument)?);
+        }
+        let (parameters, returns) = match signature {
+            Some(signature) if values.len() < signature.parameters.len() => {
+                return Err("Too few arguments in function call.".to_string())
+            }
+            Some(signature) if values.len() > signature.parameters.len() && !signature.variadic => {
+                return Err("Too many arguments in function call.".to_string())
+            }
+            Some(signature) => (signature.parameters, signature.returns),
+            None => match cast {
+                Some(cast) => (
+                    Vec::new(),
+                    Some(cast.clone()).filter(|cast| cast.name != "void"),
+                ),
+                None => {
+                    return Err(format!(
+                        "'{}' has unknown return type; cast the call to its declared return type",
+                        name
+                    ))
+                }
+            },
+        };
+        let mut passed = Vec::new();
+        for (i, value) in values.into_iter().enumerate() {
+            let value = match parameters.get(i) {
+                // an array the debugger made up, like a string literal, is copied into the
+                // program for the call
+                _ if matches!(
+                    (&value.entity_type.kind, &value.storage),
+                    (Kind::Array(..), Storage::Value(_))
+                ) =>
+                {
+                    value
+                }
+                Some(parameter) => self.cast(value, parameter.clone())?,
+                None => self.promote_argument(value)?,
+            };
+            passed.push((value.entity_type.clone(), self.read(&value)?));
+        }
+        self.invoke(&name, addr, passed, returns.as_ref())
+    }
+
+    /// Applies the promotions C gives arguments that no prototype gives a type: integers
+    /// narrower than int become int, float becomes double, and arrays and functions pointers.
+    fn promote_argument(&self, value: Value) -> Result<Value, String> {
+        let entity_type = value.entity_type.clone();
+        match &entity_type.kind {
+            Kind::Array(element, _) => {
+                Ok(Value::integer(element.pointer_to(), value.addr()? as i128))
+            }
+            Kind::Function(_) => Ok(Value::integer(
+                entity_type.pointer_to(),
+                value.addr()? as i128,
+            )),
+            Kind::Struct(_) | Kind::Pointer(_) => Ok(value),
+            _ if is_float(&entity_type) => self.cast(value, c_type("double")),
+            _ => self.cast(value, promote(&entity_type)),
+        }
+    }
+
+    /// Calls the function `name` at `addr` with arguments laid out as their types, and returns
+    /// what it returns as `returns`, or void.
+    fn invoke(
+        &mut self,
+        name: &str,
+        addr: usize,
+        arguments: Vec<(Type, Vec<u8>)>,
+        returns: Option<&Type>,
+    ) -> Result<Value, String> {
+        if returns.is_some_and(|returns| is_float(returns) && returns.size > 8) {
+            return Err("Cannot call functions that return long double.".to_string());
+        }
+        let entry = self.debug_data.executable().entry_point();
+        let inferior = match &mut self.program {
+            Program::Live(inferior) => inferior,
+            Program::ReadOnly(_) => {
+                return Err("Cannot call functions in the program here.".to_string())
+            }
+        };
+        let abandoned = format!(
+            "Evaluation of the expression containing the function\n({}) will be abandoned.",
+            name
+        );
+        match call::call(inferior, addr, entry, &arguments, returns)
+            .map_err(|e| format!("Cannot call {}: {}", name, e))?
+        {
+            Outcome::Returned(bytes) => Ok(match returns {
+                Some(returns) => Value::new(returns.clone(), bytes),
+                None => Value::new(void_type(), bytes),
+            }),
+            Outcome::Interrupted(signal) => Err(format!(
+                "Program received signal {}, {}.\n\
+                 The program being debugged was signaled while in a function called from deet.\n\
+                 deet has restored the context to what it was before the call.\n{}",
+                signal.as_str(),
+                signals::describe(signal),
+                abandoned
+            )),
+            Outcome::Ended(status) => {
+                let what = match status {
+                    Status::Exec => "started another program",
+                    _ => "exited",
+                };
+                self.ended = Some(status);
+                Err(format!(
+                    "The program being debugged {} while in a function called from deet.\n{}",
+                    what, abandoned
+                ))
+            }
+        }
+    }
+
+    /// Works out the address `x` is to look at for a value: that of the lvalue, unless it is a
+    /// pointer or a number, which stand for the address they hold.
+    pub fn address(&self, value: &Value) -> Result<usize, String> {
+        match value.entity_type.kind {
+            Kind::Pointer(_) => Ok(self.number(value)?.to_i128() as usize),
+            _ if matches!(value.storage, Storage::Memory(_)) => value.addr(),
+            Kind::Base | Kind::Enum(_) if !is_float(&value.entity_type) => {
+                Ok(self.number(value)?.to_i128() as usize)
+            }
+            _ => value.addr(),
+        }
+    }
+
+    /// Renders a value the way `print` shows it. Functions and pointers to code are followed by
+    /// the symbol they point to, and pointers to char by the string there.
+    pub fn format(&self, value: &Value) -> Result<String, String> {
+        if let Storage::OptimizedOut = value.storage {
+            return Ok("<optimized out>".to_string());
+        }
+        if value.entity_type.name == "void" {
+            return Ok("void".to_string());
+        }
+        let symbol = |addr: usize| match examine::symbolize(self.debug_data, addr) {
+            Some(symbol) => format!(" {}", symbol),
+            None => String::new(),
+        };
+        if let Kind::Function(_) = value.entity_type.kind {
+            let addr = value.addr()?;
+            return Ok(format!(
+                "{{{}}} {:#x}{}",
+                value.entity_type.name,
+                addr,
+                symbol(addr)
+            ));
+        }
+        let bytes = self.read(value)?;
+        let text = value.entity_type.format_value(&bytes);
+        if !matches!(value.entity_type.kind, Kind::Pointer(_)) {
+            return Ok(text);
+        }
+        let addr = self.number(value)?.to_i128() as usize;
+        let pointee = value.entity_type.pointee();
+        Ok(if is_string_pointer(&value.entity_type) {
+            format!("{:#x} {}", addr, self.string_at(addr))
+        } else if pointee.is_some_and(|pointee| matches!(pointee.kind, Kind::Function(_))) {
+            format!("{}{}", text, symbol(addr))
+        } else {
+            text
+        })
+    }
+
+    /// Reads the NUL-terminated string at `addr` and renders it like a C string literal.
+    fn string_at(&self, addr: usize) -> String {
+        let mut bytes = Vec::new();
+        // read up to the end of each word, so as not to stray into a page that isn't mapped
+        while bytes.len() <= PRINT_ELEMENTS && !bytes.contains(&0) {
+            let start = addr + bytes.len();
+            match self.target().read_memory(start, 8 - start % 8) {
+                Ok(more) => bytes.extend_from_slice(&more),
+                Err(_) if bytes.is_empty() => {
+                    return format!("<error: Cannot access memory at address {:#x}>", addr)
+                }
+                Err(_) => break,
+            }
+        }
+        if let Some(end) = bytes.iter().position(|byte| *byte == 0) {
+            bytes.truncate(end);
+        }
+        bytes.truncate(PRINT_ELEMENTS + 1);
+        let array = Type {
+            name: "char []".to_string(),
+            size: bytes.len(),
+            encoding: Encoding::Char,
+            kind: Kind::Array(Box::new(c_type("char")), Some(bytes.len())),
+        };
+        array.format_value(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf_data::DwarfData;
+    use crate::unwind;
+    use libc::{user_fpregs_struct, user_regs_struct};
+    use nix::unistd::Pid;
+
+    /// Parses `text` in a program whose only typedef is `size_t`.
+    fn parse_text(text: &str) -> Result<Expression, String> {
+        parse(text, &|name| name == "size_t")
+    }
+
+    /// Writes an expression back out with every operation in parentheses, to show how it was
+    /// grouped.
+    fn grouped(expression: &Expression) -> String {
+        match expression {
+            Expression::Integer(value, _) => value.to_string(),
+            Expression::Identifier(name) => name.clone(),
+            Expression::Unary(operator, operand) => format!("({}{})", operator, grouped(operand)),
+            Expression::Binary(operator, lhs, rhs) => {
+                format!("({} {} {})", grouped(lhs), operator, grouped(rhs))
+            }
+            Expression::Assign(lhs, rhs) => format!("({} = {})", grouped(lhs), grouped(rhs)),
+            Expression::Conditional(condition, then, otherwise) => format!(
+                "({} ? {} : {})",
+                grouped(condition),
+                grouped(then),
+                grouped(otherwise)
+            ),
+            Expression::Cast(type_name, operand) => format!(
+                "(({}{}) {})",
+                type_name.name,
+                " *".repeat(type_name.pointers),
+                grouped(operand)
+            ),
+            Expression::Sizeof(operand) => format!("(sizeof {})", grouped(operand)),
+            Expression::SizeofType(type_name) => format!("(sizeof({}))", type_name.name),
+            Expression::Member(operand, name) => format!("({}.{})", grouped(operand), name),
+            Expression::Arrow(operand, name) => format!("({}->{})", grouped(operand), name),
+            Expression::Index(operand, index) => {
+                format!("({}[{}])", grouped(operand), grouped(index))
+            }
+            other => format!("{:?}", other),
+        }
+    }
+
+    fn grouping(text: &str) -> String {
+        grouped(&parse_text(text).unwrap())
+    }
+
+    /// A program with nothing in memory and every register zero, which is all that
+    /// expressions without variables need.
+    struct Empty;
+
+    impl Target for Empty {
+        fn pid(&self) -> Pid {
+            Pid::from_raw(0)
+        }
+
+        fn threads(&self) -> Vec<(usize, Pid)> {
+            Vec::new()
+        }
+
+        fn current_thread(&self) -> usize {
+            1
+        }
+
+        fn select_thread(&mut self, _id: usize) -> bool {
+            false
+        }
+
+        fn executable_path(&self) -> Result<String, std::io::Error> {
+            Ok(String::new())
+        }
+
+        fn mapped_objects(&self) -> Result<Vec<(String, usize)>, std::io::Error> {
+            Ok(Vec::new())
+        }
+
+        fn get_registers(&self) -> Result<user_regs_struct, nix::Error> {
+            Ok(unsafe { std::mem::zeroed() })
+        }
+
+        fn get_fp_registers(&self) -> Result<user_fpregs_struct, nix::Error> {
+            Ok(unsafe { std::mem::zeroed() })
+        }
+
+        fn read_word(&self, _addr: usize) -> Result<usize, nix::Error> {
+            Err(nix::Error::EIO)
+        }
+    }
+
+    /// Evaluates `text` and shows the result the way `print` does.
+    fn print(text: &str) -> Result<String, String> {
+        let path = std::fs::canonicalize("/bin/sleep").unwrap();
+        let path = path.to_str().unwrap();
+        let debug_data = Modules::new(path, DwarfData::from_file(path).unwrap());
+        let frame = unwind::innermost_frame(&Empty, &debug_data).unwrap();
+        let mut evaluator = Evaluator::new(Program::ReadOnly(&Empty), &debug_data, frame, true);
+        let value = evaluator.evaluate(&parse_text(text)?)?;
+        evaluator.format(&value)
+    }
+
+    fn printed(text: &str) -> String {
+        print(text).unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(grouping("a + b * c"), "(a + (b * c))");
+        assert_eq!(grouping("a * b + c"), "((a * b) + c)");
+        assert_eq!(grouping("a << b + c"), "(a << (b + c))");
+        assert_eq!(grouping("a < b << c"), "(a < (b << c))");
+        assert_eq!(grouping("a == b < c"), "(a == (b < c))");
+        assert_eq!(grouping("a & b == c"), "(a & (b == c))");
+        assert_eq!(grouping("a | b ^ c & d"), "(a | (b ^ (c & d)))");
+        assert_eq!(grouping("a || b && c | d"), "(a || (b && (c | d)))");
+        assert_eq!(grouping("a = b ? c : d || e"), "(a = (b ? c : (d || e)))");
+        assert_eq!(grouping("-a * !b"), "((-a) * (!b))");
+        assert_eq!(grouping("*p[1]"), "(*(p[1]))");
+        assert_eq!(grouping("&s.a->b"), "(&((s.a)->b))");
+        assert_eq!(grouping("(a + b) * c"), "((a + b) * c)");
+        assert_eq!(printed("1 + 2 * 3"), "7");
+        assert_eq!(printed("1 << 2 + 1"), "8");
+        assert_eq!(printed("7 & 3 == 3"), "1");
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eq!(grouping("a - b - c"), "((a - b) - c)");
+        assert_eq!(grouping("a / b * c"), "((a / b) * c)");
+        assert_eq!(grouping("a = b = c"), "(a = (b = c))");
+        assert_eq!(grouping("a ? b : c ? d : e"), "(a ? b : (c ? d : e))");
+        assert_eq!(grouping("- - a"), "(-(-a))");
+        assert_eq!(printed("10 - 4 - 3"), "3");
+        assert_eq!(printed("64 / 4 / 2"), "8");
+        assert_eq!(printed("0 ? 1 : 0 ? 2 : 3"), "3");
+    }
+
+    #[test]
+    fn casts() {
+        assert_eq!(grouping("(char) a + b"), "(((char) a) + b)");
+        assert_eq!(grouping("(unsigned long) -a"), "((unsigned long) (-a))");
+        assert_eq!(grouping("(size_t *) p"), "((size_t *) p)");
+        assert_eq!(grouping("(struct node **) p"), "((struct node * *) p)");
+        // a parenthesized variable isn't a type
+        assert_eq!(grouping("(a) + b"), "(a + b)");
+        assert_eq!(printed("(char) 321"), "65 'A'");
+        assert_eq!(printed("(unsigned char) -1"), "255 '\\377'");
+        assert_eq!(printed("(short) 65537"), "1");
+        assert_eq!(printed("(unsigned int) -1"), "4294967295");
+        assert_eq!(printed("(int) 3.9"), "3");
+        assert_eq!(printed("(double) 1 / 4"), "0.25");
+        assert_eq!(printed("(long *) 16"), "(long *) 0x10");
+    }
+
+    #[test]
+    fn sizeof() {
+        assert_eq!(grouping("sizeof a + b"), "((sizeof a) + b)");
+        assert_eq!(grouping("sizeof (a) + b"), "((sizeof a) + b)");
+        assert_eq!(grouping("sizeof (unsigned int)"), "(sizeof(unsigned int))");
+        assert_eq!(printed("sizeof (char)"), "1");
+        assert_eq!(printed("sizeof (short)"), "2");
+        assert_eq!(printed("sizeof (unsigned int)"), "4");
+        assert_eq!(printed("sizeof (long long)"), "8");
+        assert_eq!(printed("sizeof (char *)"), "8");
+        assert_eq!(printed("sizeof (double)"), "8");
+        assert_eq!(printed("sizeof 1.5"), "8");
+        assert_eq!(printed("sizeof \"abc\""), "4");
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(printed("42"), "42");
+        assert_eq!(printed("0x2a"), "42");
+        assert_eq!(printed("0X2A"), "42");
+        assert_eq!(printed("052"), "42");
+        assert_eq!(printed("0"), "0");
+        // the type is the first of int, unsigned int, long and unsigned long the value fits
+        assert_eq!(printed("sizeof 2147483647"), "4");
+        assert_eq!(printed("sizeof 4294967295"), "4");
+        assert_eq!(printed("sizeof 4294967296"), "8");
+        assert_eq!(printed("sizeof 1L"), "8");
+        assert_eq!(printed("-1U"), "4294967295");
+        assert_eq!(printed("-1UL"), "18446744073709551615");
+        assert_eq!(printed("0x7fffffff + 1"), "-2147483648");
+        assert_eq!(printed("0xffffffff + 1"), "0");
+        assert_eq!(printed("1.5e1"), "15");
+    }
+
+    #[test]
+    fn character_and_string_literals() {
+        assert_eq!(printed("'a'"), "97 'a'");
+        assert_eq!(printed("'\\n'"), "10 '\\n'");
+        assert_eq!(printed("'\\t'"), "9 '\\t'");
+        assert_eq!(printed("'\\\\'"), "92 '\\\\'");
+        assert_eq!(printed("'\\''"), "39 '\\''");
+        assert_eq!(printed("'\\x41'"), "65 'A'");
+        assert_eq!(printed("'\\101'"), "65 'A'");
+        assert_eq!(printed("'a' + 1"), "98");
+        assert_eq!(printed("\"hi\""), "\"hi\"");
+        assert_eq!(printed("\"a\\tb\\\"c\""), "\"a\\tb\\\"c\"");
+        // an octal escape stops after three digits
+        assert_eq!(printed("\"\\1011\""), "\"A1\"");
+        // adjacent literals are one string
+        assert_eq!(printed("\"ab\" \"cd\""), "\"abcd\"");
+        assert_eq!(printed("sizeof \"ab\" \"cd\""), "5");
+    }
+
+    #[test]
+    fn pointer_arithmetic_scales_by_element_size() {
+        assert_eq!(printed("(int *) 0x1000 + 2"), "(int *) 0x1008");
+        assert_eq!(printed("2 + (long *) 0x1000"), "(long *) 0x1010");
+        assert_eq!(printed("(short *) 0x1000 - 1"), "(short *) 0xffe");
+        assert_eq!(printed("(char *) 0x1000 + 3 == (char *) 0x1003"), "1");
+        // void and functions count as a byte, as in gdb
+        assert_eq!(printed("(void *) 0x1000 + 1"), "(void *) 0x1001");
+        assert_eq!(printed("(int *) 0x1010 - (int *) 0x1000"), "4");
+        assert_eq!(printed("(double *) 0x1000 - (double *) 0x1010"), "-2");
+        assert_eq!(
+            print("(int *) 0x1010 - (char *) 0x1000").unwrap_err(),
+            "First argument of `-' is a pointer and second argument is neither\n\
+             an integer nor a pointer of the same type."
+        );
+        assert_eq!(
+            print("(int *) 0x1000 * 2").unwrap_err(),
+            "Argument to arithmetic operation not a number or boolean."
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(printed("1 < 2"), "1");
+        assert_eq!(printed("2 < 1"), "0");
+        assert_eq!(printed("2 <= 2"), "1");
+        assert_eq!(printed("2 >= 3"), "0");
+        assert_eq!(printed("3 > 2"), "1");
+        assert_eq!(printed("3 == 3"), "1");
+        assert_eq!(printed("3 != 3"), "0");
+        assert_eq!(printed("-1 < 0"), "1");
+        // the usual arithmetic conversions make -1 unsigned
+        assert_eq!(printed("-1 < 0U"), "0");
+        assert_eq!(printed("-1 < 0UL"), "0");
+        assert_eq!(printed("-1L < 0U"), "1");
+        assert_eq!(printed("1.5 > 1"), "1");
+        assert_eq!(printed("3 == 3.0"), "1");
+        assert_eq!(printed("(int *) 8 < (int *) 16"), "1");
+    }
+
+    #[test]
+    fn logical_operators() {
+        assert_eq!(printed("0 || 2"), "1");
+        assert_eq!(printed("0 || 0"), "0");
+        assert_eq!(printed("1 && 2"), "1");
+        assert_eq!(printed("1 && 0"), "0");
+        assert_eq!(printed("!0"), "1");
+        assert_eq!(printed("!7"), "0");
+        assert_eq!(printed("!0.0"), "1");
+        // the right operand isn't evaluated when the left decides
+        assert_eq!(printed("0 && 1 / 0"), "0");
+        assert_eq!(printed("1 || 1 / 0"), "1");
+        assert_eq!(print("1 && 1 / 0").unwrap_err(), "Division by zero");
+    }
+
+    #[test]
+    fn malformed_input() {
+        let error = |text| parse_text(text).unwrap_err();
+        assert_eq!(error(""), "Argument required (expression to compute).");
+        assert_eq!(error("   "), "Argument required (expression to compute).");
+        assert_eq!(error("1 +"), "A syntax error in expression, near `'.");
+        assert_eq!(error("1 2"), "A syntax error in expression, near `2'.");
+        assert_eq!(error("(1 + 2"), "A syntax error in expression, near `'.");
+        assert_eq!(error("1 + 2)"), "A syntax error in expression, near `)'.");
+        assert_eq!(error("a ? b"), "A syntax error in expression, near `'.");
+        assert_eq!(error("a[1"), "A syntax error in expression, near `'.");
+        assert_eq!(error("f(1,)"), "A syntax error in expression, near `)'.");
+        assert_eq!(error("s.1"), "A syntax error in expression, near `.1'.");
+        assert_eq!(error("* / 2"), "A syntax error in expression, near `/ 2'.");
+        assert_eq!(
+            error("sizeof (int"),
+            "A syntax error in expression, near `'."
+        );
+        assert_eq!(error("int"), "A syntax error in expression, near `int'.");
+        assert_eq!(error("1 @ 2"), "Invalid character '@' in expression.");
+        assert_eq!(error("09"), "Invalid number \"09\".");
+        assert_eq!(error("1.2.3"), "Invalid number \"1.2.3\".");
+        assert_eq!(error("0x"), "Invalid number \"0x\".");
+        assert_eq!(error("'ab'"), "Invalid character constant.");
+        assert_eq!(error("''"), "Invalid character constant.");
+        assert_eq!(error("'a"), "Unmatched single quote.");
+        assert_eq!(error("\"abc"), "Unterminated string in expression.");
+        assert_eq!(error("\"abc\\"), "Unterminated string in expression.");
+        assert_eq!(print("1 / 0").unwrap_err(), "Division by zero");
+        assert_eq!(print("1 % 0").unwrap_err(), "Division by zero");
+        assert_eq!(print("1.5 % 2").unwrap_err(), "Integer only operation.");
+        assert_eq!(
+            print("sizeof (struct nothing)").unwrap_err(),
+            "No symbol \"struct nothing\" in current context."
+        );
+        assert_eq!(
+            print("*1.5").unwrap_err(),
+            "Attempt to take contents of a non-pointer value."
+        );
+        assert_eq!(
+            print("3 = 4").unwrap_err(),
+            "Left operand of assignment is not an lvalue."
+        );
+    }
+}
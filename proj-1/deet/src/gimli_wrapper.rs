@@ -5,17 +5,19 @@
 //!
 //! This code is a huge mess. Please don't read it unless you're trying to do an extension :)
 
-use gimli;
 use gimli::{UnitOffset, UnitSectionOffset};
 use object::{Object, ObjectSection};
 use std::borrow;
-use std::num::NonZeroU64;
 //use std::io::{BufWriter, Write};
-use crate::dwarf_data::{File, Function, Line, Location, Type, Variable};
+use crate::dwarf_data::{
+    pointer_name, DwarfExpression, Encoding, File, Function, Kind, Line, Location, Member, Scope,
+    Signature, Type, TypeRef, TypeTable, Variable,
+};
 use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fmt::Write;
-use std::{io, path};
+use std::io;
+use std::rc::{Rc, Weak};
 
 pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<Vec<File>, Error> {
     // Load a section and return as `Cow<[u8]>`.
@@ -32,7 +34,7 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
             None => Ok(borrow::Cow::Borrowed(&[][..])),
         }
     };
-    
+
     // Load all of the sections.
     let dwarf_cow = gimli::Dwarf::load(&load_section)?;
 
@@ -40,14 +42,11 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     let borrow_section: &dyn for<'a> Fn(
         &'a borrow::Cow<[u8]>,
     ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, endian);
+        &|section| gimli::EndianSlice::new(section, endian);
 
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
 
-    // Define a mapping from type offsets to type structs
-    let mut offset_to_type: HashMap<usize, Type> = HashMap::new();
-
     let mut compilation_units: Vec<File> = Vec::new();
 
     // Iterate over the compilation units.
@@ -55,11 +54,30 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     while let Some(header) = iter.next()? {
         let unit = dwarf.unit(header)?;
 
+        // Define a mapping from type offsets to type structs. Types are collected before anything
+        // else, since variables may refer to types declared further down the unit.
+        let mut types_error = None;
+        let offset_to_type = Rc::new_cyclic(|table| {
+            load_types(&unit, &dwarf, table).unwrap_or_else(|err| {
+                types_error = Some(err);
+                TypeTable::new()
+            })
+        });
+        if let Some(err) = types_error {
+            return Err(err);
+        }
+
         // Iterate over the Debugging Information Entries (DIEs) in the unit.
+        let first_file = compilation_units.len();
         let mut depth = 0;
+        // The blocks and inlined calls the current entry is in, and their depths
+        let mut open_scopes: Vec<(isize, Scope)> = Vec::new();
         let mut entries = unit.entries();
         while let Some((delta_depth, entry)) = entries.next_dfs()? {
             depth += delta_depth;
+            while open_scopes.last().is_some_and(|(d, _)| *d >= depth) {
+                close_scope(&mut open_scopes, &mut compilation_units);
+            }
             // Update the offset_to_type mapping for types
             // Update the variable list for formal params/variables
             match entry.tag() {
@@ -75,42 +93,26 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                     };
                     compilation_units.push(File {
                         name,
+                        comp_dir: unit.comp_dir.map(|dir| dir.to_string_lossy().into_owned()),
                         global_variables: Vec::new(),
                         functions: Vec::new(),
                         lines: Vec::new(),
+                        types: offset_to_type.clone(),
                     });
                 }
-                gimli::DW_TAG_base_type => {
-                    let name = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_name) {
-                        if let Ok(DebugValue::Str(name)) = get_attr_value(&attr, &unit, &dwarf) {
-                            name
-                        } else {
-                            "<unknown>".to_string()
-                        }
-                    } else {
-                        "<unknown>".to_string()
-                    };
-                    let byte_size = if let Ok(Some(attr)) = entry.attr(gimli::DW_AT_byte_size) {
-                        if let Ok(DebugValue::Uint(byte_size)) =
-                            get_attr_value(&attr, &unit, &dwarf)
-                        {
-                            byte_size
-                        } else {
-                            // TODO: report error?
-                            0
-                        }
-                    } else {
-                        // TODO: report error?
-                        0
-                    };
-                    let type_offset = entry.offset().0;
-                    offset_to_type
-                        .insert(type_offset, Type::new(name, byte_size.try_into().unwrap()));
-                }
                 gimli::DW_TAG_subprogram => {
                     let mut func: Function = Default::default();
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
+                    // an out-of-line copy of an inlined function has the type of the original
+                    let origin = match entry.attr_value(gimli::DW_AT_abstract_origin)? {
+                        Some(gimli::AttributeValue::UnitRef(offset)) => offset,
+                        _ => entry.offset(),
+                    };
+                    if let UnitSectionOffset::DebugInfoOffset(goff) =
+                        origin.to_unit_section_offset(&unit)
+                    {
+                        func.entity_type = offset_to_type.get(&goff.0).cloned();
+                    }
+                    for attr in attrs_with_origin(entry, &unit)? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
@@ -135,18 +137,34 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                     func.line_number = line_number.try_into().unwrap();
                                 }
                             }
+                            gimli::DW_AT_frame_base => {
+                                func.frame_base = get_frame_base(&attr, &unit)?;
+                            }
                             _ => {}
                         }
                     }
                     compilation_units.last_mut().unwrap().functions.push(func);
                 }
+                gimli::DW_TAG_lexical_block | gimli::DW_TAG_inlined_subroutine if depth > 1 => {
+                    let mut ranges = Vec::new();
+                    let mut die_ranges = dwarf.die_ranges(&unit, entry)?;
+                    while let Some(range) = die_ranges.next()? {
+                        ranges.push(range.begin as usize..range.end as usize);
+                    }
+                    let scope = Scope {
+                        ranges,
+                        inlined: entry.tag() == gimli::DW_TAG_inlined_subroutine,
+                        ..Default::default()
+                    };
+                    open_scopes.push((depth, scope));
+                }
                 gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
                     let mut name = String::new();
                     let mut entity_type: Option<Type> = None;
                     let mut location: Option<Location> = None;
                     let mut line_number = 0;
-                    let mut attrs = entry.attrs();
-                    while let Some(attr) = attrs.next()? {
+                    let mut declaration = false;
+                    for attr in attrs_with_origin(entry, &unit)? {
                         let val = get_attr_value(&attr, &unit, &dwarf);
                         //println!("   {}: {:?}", attr.name(), val);
                         match attr.name() {
@@ -157,16 +175,20 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             }
                             gimli::DW_AT_type => {
                                 if let Ok(DebugValue::Size(offset)) = val {
-                                    if let Some(dtype) = offset_to_type.get(&offset).clone() {
+                                    if let Some(dtype) = offset_to_type.get(&offset) {
                                         entity_type = Some(dtype.clone());
                                     }
                                 }
                             }
                             gimli::DW_AT_location => {
-                                if let Some(loc) = get_location(&attr, &unit) {
+                                if let Some(loc) = get_location(&attr, &unit, &dwarf)? {
                                     location = Some(loc);
                                 }
                             }
+                            gimli::DW_AT_const_value => {
+                                location = get_const_value(&attr).or(location);
+                            }
+                            gimli::DW_AT_declaration => declaration = true,
                             gimli::DW_AT_decl_line => {
                                 if let Ok(DebugValue::Uint(num)) = val {
                                     line_number = num;
@@ -175,12 +197,18 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                             _ => {}
                         }
                     }
-                    if entity_type.is_some() && location.is_some() {
+                    // a local without a location has been optimized out entirely, where a
+                    // global without one is just declared here and defined elsewhere
+                    if location.is_none() && depth > 1 && !declaration && !name.is_empty() {
+                        location = Some(Location::List(Vec::new()));
+                    }
+                    if let (Some(entity_type), Some(location)) = (entity_type, location) {
                         let var = Variable {
                             name,
-                            entity_type: entity_type.unwrap(),
-                            location: location.unwrap(),
+                            entity_type,
+                            location,
                             line_number: line_number.try_into().unwrap(),
+                            is_parameter: entry.tag() == gimli::DW_TAG_formal_parameter,
                         };
                         if depth == 1 {
                             compilation_units
@@ -188,6 +216,8 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                                 .unwrap()
                                 .global_variables
                                 .push(var);
+                        } else if let Some((_, scope)) = open_scopes.last_mut() {
+                            scope.variables.push(var);
                         } else if depth > 1 {
                             compilation_units
                                 .last_mut()
@@ -205,32 +235,39 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                 _ => {}
             }
         }
+        while !open_scopes.is_empty() {
+            close_scope(&mut open_scopes, &mut compilation_units);
+        }
 
-        // Get line numbers
+        // Get line numbers. They go with the unit's File, whichever file each row is in, since
+        // rows from headers and inlined functions belong to the unit that has the code.
+        let file = match compilation_units.get_mut(first_file) {
+            Some(file) => file,
+            None => continue,
+        };
         if let Some(program) = unit.line_program.clone() {
             // Iterate over the line program rows.
             let mut rows = program.rows();
             while let Some((header, row)) = rows.next_row()? {
-                if !row.end_sequence() {
+                // only statements are places to stop at
+                if !row.end_sequence() && row.is_stmt() {
                     // Determine the path. Real applications should cache this for performance.
-                    let mut path = path::PathBuf::new();
-                    if let Some(file) = row.file(header) {
-                        if let Some(dir) = file.directory(header) {
-                            path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+                    let mut path = match unit.comp_dir {
+                        Some(ref comp_dir) => comp_dir.to_string_lossy().into_owned(),
+                        None => String::new(),
+                    };
+                    if let Some(entry) = row.file(header) {
+                        if let Some(dir) = entry.directory(header) {
+                            push_path(&mut path, &dwarf.attr_string(&unit, dir)?.to_string_lossy());
                         }
-                        path.push(
-                            dwarf
-                                .attr_string(&unit, file.path_name())?
-                                .to_string_lossy()
-                                .as_ref(),
+                        push_path(
+                            &mut path,
+                            &dwarf
+                                .attr_string(&unit, entry.path_name())?
+                                .to_string_lossy(),
                         );
                     }
 
-                    // Get the File
-                    let file = compilation_units
-                        .iter_mut()
-                        .find(|f| f.name == path.as_os_str().to_str().unwrap());
-
                     // Determine line/column. DWARF line/column is never 0, so we use that
                     // but other applications may want to display this differently.
                     let line = match row.line() {
@@ -238,13 +275,11 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
                         None => 0,
                     };
 
-                    if let Some(file) = file {
-                        file.lines.push(Line {
-                            file: file.name.clone(),
-                            number: line as usize,
-                            address: row.address().try_into().unwrap(),
-                        });
-                    }
+                    file.lines.push(Line {
+                        file: path,
+                        number: line as usize,
+                        address: row.address().try_into().unwrap(),
+                    });
                 }
             }
         }
@@ -252,6 +287,450 @@ pub fn load_file(object: &object::File, endian: gimli::RunTimeEndian) -> Result<
     Ok(compilation_units)
 }
 
+/// Puts the innermost open block or inlined call into the one around it, or into the function
+/// it is in.
+fn close_scope(open_scopes: &mut Vec<(isize, Scope)>, compilation_units: &mut [File]) {
+    let scope = match open_scopes.pop() {
+        Some((_, scope)) => scope,
+        None => return,
+    };
+    match open_scopes.last_mut() {
+        Some((_, outer)) => outer.scopes.push(scope),
+        None => {
+            let func = compilation_units
+                .last_mut()
+                .and_then(|file| file.functions.last_mut());
+            if let Some(func) = func {
+                func.scopes.push(scope);
+            }
+        }
+    }
+}
+
+/// Appends `name` to a path the way addr2line does, so lines have the same file names as the
+/// ones `DwarfData::get_line_from_addr` returns.
+fn push_path(path: &mut String, name: &str) {
+    if name.starts_with('/') {
+        *path = name.to_string();
+    } else {
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        path.push_str(name);
+    }
+}
+
+/// How deep types may nest, so that a malformed chain of typedefs can't recurse forever.
+const MAX_TYPE_DEPTH: usize = 64;
+
+/// A type as the debugging information declares it, referring to other types by offset.
+enum RawType {
+    Base(String, usize, Encoding),
+    Pointer(Option<usize>),
+    Typedef(String, Option<usize>),
+    /// A `const` or `volatile` qualified type
+    Qualified(&'static str, Option<usize>),
+    Struct {
+        keyword: &'static str,
+        name: Option<String>,
+        size: usize,
+        members: Vec<RawMember>,
+    },
+    /// The element type and the number of elements in each dimension
+    Array(Option<usize>, Vec<Option<usize>>),
+    Enum {
+        name: Option<String>,
+        size: usize,
+        underlying: Option<usize>,
+        values: Vec<(String, i64)>,
+    },
+    /// The return type, the parameter types, and whether other arguments may follow them
+    Function(Option<usize>, Vec<Option<usize>>, bool),
+}
+
+struct RawMember {
+    name: String,
+    offset: usize,
+    bits: Option<(usize, usize)>,
+    entity_type: Option<usize>,
+}
+
+fn attr_name<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<String> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_name).ok()??, unit, dwarf) {
+        Ok(DebugValue::Str(name)) => Some(name),
+        _ => None,
+    }
+}
+
+/// Returns the .debug_info offset of the type an entry refers to with DW_AT_type.
+fn attr_type<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Option<usize> {
+    match get_attr_value(&entry.attr(gimli::DW_AT_type).ok()??, unit, dwarf) {
+        Ok(DebugValue::Size(offset)) => Some(offset),
+        _ => None,
+    }
+}
+
+fn attr_udata<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    name: gimli::DwAt,
+) -> Option<usize> {
+    entry.attr(name).ok()??.udata_value()?.try_into().ok()
+}
+
+/// Reads a member of a struct or union, working out where a bit field sits from either the
+/// DWARF 4 or the older DWARF 2 attributes.
+fn load_member<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> RawMember {
+    let mut offset = attr_udata(entry, gimli::DW_AT_data_member_location).unwrap_or(0);
+    let bits = attr_udata(entry, gimli::DW_AT_bit_size).map(|width| {
+        let start = match attr_udata(entry, gimli::DW_AT_data_bit_offset) {
+            Some(start) => start,
+            None => {
+                // counted from the most significant bit of the storage unit
+                let storage = attr_udata(entry, gimli::DW_AT_byte_size).unwrap_or(4);
+                let from_top = attr_udata(entry, gimli::DW_AT_bit_offset).unwrap_or(0);
+                offset * 8 + (storage * 8).saturating_sub(from_top + width)
+            }
+        };
+        offset = start / 8;
+        (start % 8, width)
+    });
+    RawMember {
+        name: attr_name(entry, unit, dwarf).unwrap_or_default(),
+        offset,
+        bits,
+        entity_type: attr_type(entry, unit, dwarf),
+    }
+}
+
+/// Collects the types of a unit, keyed by their .debug_info offset. Pointers refer to their
+/// targets through `table`, which is where the result is to be kept.
+fn load_types<R: Reader>(
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+    table: &Weak<TypeTable>,
+) -> Result<TypeTable, Error> {
+    let mut raw_types: HashMap<usize, RawType> = HashMap::new();
+    // The types whose children are being read, like the members of a struct, and their depths
+    let mut parents: Vec<(isize, usize)> = Vec::new();
+
+    let mut depth = 0;
+    let mut entries = unit.entries();
+    while let Some((delta_depth, entry)) = entries.next_dfs()? {
+        depth += delta_depth;
+        let type_offset = match entry.offset().to_unit_section_offset(unit) {
+            UnitSectionOffset::DebugInfoOffset(goff) => goff.0,
+            UnitSectionOffset::DebugTypesOffset(goff) => goff.0,
+        };
+        while parents.last().is_some_and(|(d, _)| *d >= depth) {
+            parents.pop();
+        }
+        let parent = parents
+            .last()
+            .filter(|(d, _)| *d == depth - 1)
+            .and_then(|(_, offset)| raw_types.get_mut(offset));
+        match (entry.tag(), parent) {
+            (gimli::DW_TAG_member, Some(RawType::Struct { members, .. })) => {
+                members.push(load_member(entry, unit, dwarf));
+                continue;
+            }
+            (gimli::DW_TAG_enumerator, Some(RawType::Enum { values, .. })) => {
+                // zero-extended for now, until the size and signedness of the enum are known
+                let value = match entry.attr_value(gimli::DW_AT_const_value)? {
+                    Some(gimli::AttributeValue::Sdata(value)) => value,
+                    Some(value) => value.udata_value().unwrap_or(0) as i64,
+                    None => 0,
+                };
+                values.push((attr_name(entry, unit, dwarf).unwrap_or_default(), value));
+                continue;
+            }
+            (gimli::DW_TAG_subrange_type, Some(RawType::Array(_, dimensions))) => {
+                let count = attr_udata(entry, gimli::DW_AT_count)
+                    .or_else(|| attr_udata(entry, gimli::DW_AT_upper_bound).map(|bound| bound + 1));
+                dimensions.push(count);
+                continue;
+            }
+            (gimli::DW_TAG_formal_parameter, Some(RawType::Function(_, parameters, _))) => {
+                parameters.push(attr_type(entry, unit, dwarf));
+                continue;
+            }
+            (gimli::DW_TAG_unspecified_parameters, Some(RawType::Function(_, _, variadic))) => {
+                *variadic = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        let name = attr_name(entry, unit, dwarf);
+        let target = attr_type(entry, unit, dwarf);
+        let size = attr_udata(entry, gimli::DW_AT_byte_size).unwrap_or(0);
+        let raw_type = match entry.tag() {
+            gimli::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(gimli::DW_AT_encoding)? {
+                    Some(gimli::AttributeValue::Encoding(ate)) => match ate {
+                        gimli::DW_ATE_unsigned => Encoding::Unsigned,
+                        gimli::DW_ATE_signed_char => Encoding::Char,
+                        gimli::DW_ATE_unsigned_char => Encoding::UnsignedChar,
+                        gimli::DW_ATE_boolean => Encoding::Boolean,
+                        gimli::DW_ATE_float => Encoding::Float,
+                        _ => Encoding::Signed,
+                    },
+                    _ => Encoding::Signed,
+                };
+                let name = name.unwrap_or_else(|| "<unknown>".to_string());
+                RawType::Base(name, size, encoding)
+            }
+            gimli::DW_TAG_pointer_type => RawType::Pointer(target),
+            gimli::DW_TAG_typedef => RawType::Typedef(name.unwrap_or_default(), target),
+            gimli::DW_TAG_const_type => RawType::Qualified("const", target),
+            gimli::DW_TAG_volatile_type => RawType::Qualified("volatile", target),
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_union_type | gimli::DW_TAG_class_type => {
+                RawType::Struct {
+                    keyword: match entry.tag() {
+                        gimli::DW_TAG_union_type => "union",
+                        gimli::DW_TAG_class_type => "class",
+                        _ => "struct",
+                    },
+                    name,
+                    size,
+                    members: Vec::new(),
+                }
+            }
+            gimli::DW_TAG_array_type => RawType::Array(target, Vec::new()),
+            gimli::DW_TAG_enumeration_type => RawType::Enum {
+                name,
+                size,
+                underlying: target,
+                values: Vec::new(),
+            },
+            gimli::DW_TAG_subroutine_type | gimli::DW_TAG_subprogram => {
+                // `int f()` in C says nothing about the arguments
+                let prototyped = matches!(
+                    entry.attr_value(gimli::DW_AT_prototyped)?,
+                    Some(gimli::AttributeValue::Flag(true))
+                );
+                RawType::Function(target, Vec::new(), !prototyped)
+            }
+            _ => continue,
+        };
+        if entry.has_children() {
+            parents.push((depth, type_offset));
+        }
+        raw_types.insert(type_offset, raw_type);
+    }
+
+    let mut resolver = TypeResolver {
+        raw_types: &raw_types,
+        table,
+        types: HashMap::new(),
+    };
+    for offset in raw_types.keys() {
+        resolver.resolve(*offset, 0);
+    }
+    Ok(resolver.types)
+}
+
+/// Turns the raw types of a unit into `Type`s, following typedefs and qualifiers to the types
+/// they name.
+struct TypeResolver<'a> {
+    raw_types: &'a HashMap<usize, RawType>,
+    table: &'a Weak<TypeTable>,
+    types: TypeTable,
+}
+
+impl<'a> TypeResolver<'a> {
+    /// Names the type at `offset` the way C writes it. No type at all is void.
+    fn name(&self, offset: Option<usize>, depth: usize) -> String {
+        let offset = match offset {
+            Some(offset) => offset,
+            None => return "void".to_string(),
+        };
+        let raw_type = match self.raw_types.get(&offset) {
+            Some(raw_type) if depth < MAX_TYPE_DEPTH => raw_type,
+            _ => return "<unknown>".to_string(),
+        };
+        match raw_type {
+            RawType::Base(name, ..) | RawType::Typedef(name, _) => name.clone(),
+            RawType::Pointer(target) => pointer_name(&self.name(*target, depth + 1)),
+            RawType::Qualified(qualifier, target) => {
+                let target = self.name(*target, depth + 1);
+                // a qualified pointer is written with the qualifier after the *
+                if target.ends_with('*') {
+                    format!("{} {}", target, qualifier)
+                } else {
+                    format!("{} {}", qualifier, target)
+                }
+            }
+            RawType::Struct { keyword, name, .. } => {
+                format!("{} {}", keyword, name.as_deref().unwrap_or("{...}"))
+            }
+            RawType::Enum { name, .. } => format!("enum {}", name.as_deref().unwrap_or("{...}")),
+            RawType::Array(element, dimensions) => {
+                array_name(&self.name(*element, depth + 1), dimensions)
+            }
+            RawType::Function(returns, parameters, variadic) => {
+                let mut parameters: Vec<String> = parameters
+                    .iter()
+                    .map(|parameter| self.name(*parameter, depth + 1))
+                    .collect();
+                match (parameters.is_empty(), variadic) {
+                    (true, false) => parameters.push("void".to_string()),
+                    (false, true) => parameters.push("...".to_string()),
+                    _ => {}
+                }
+                format!(
+                    "{} ({})",
+                    self.name(*returns, depth + 1),
+                    parameters.join(", ")
+                )
+            }
+        }
+    }
+
+    /// Works out the type at `offset`, and those it is made of. Returns None if it is made of a
+    /// type we don't know how to describe.
+    fn resolve(&mut self, offset: usize, depth: usize) -> Option<Type> {
+        if let Some(resolved) = self.types.get(&offset) {
+            return Some(resolved.clone());
+        }
+        if depth >= MAX_TYPE_DEPTH {
+            return None;
+        }
+        let name = self.name(Some(offset), depth);
+        let resolved = match self.raw_types.get(&offset)? {
+            RawType::Base(name, size, encoding) => Type::new(name.clone(), *size, *encoding),
+            RawType::Pointer(target) => Type {
+                name,
+                size: 8,
+                encoding: Encoding::Pointer,
+                kind: Kind::Pointer(target.map(|offset| TypeRef::Declared {
+                    table: self.table.clone(),
+                    offset,
+                })),
+            },
+            RawType::Typedef(_, target) | RawType::Qualified(_, target) => Type {
+                name,
+                ..self.resolve((*target)?, depth + 1)?
+            },
+            RawType::Struct { size, members, .. } => {
+                let members = members
+                    .iter()
+                    .filter_map(|member| {
+                        Some(Member {
+                            name: member.name.clone(),
+                            offset: member.offset,
+                            bits: member.bits,
+                            entity_type: self.resolve(member.entity_type?, depth + 1)?,
+                        })
+                    })
+                    .collect();
+                Type {
+                    name,
+                    size: *size,
+                    encoding: Encoding::Unsigned,
+                    kind: Kind::Struct(members),
+                }
+            }
+            RawType::Array(element, dimensions) => {
+                let element = self.resolve((*element)?, depth + 1)?;
+                // int m[2][3] is an array of 2 arrays of 3 ints
+                let mut array = element.clone();
+                for (i, count) in dimensions.iter().enumerate().rev() {
+                    array = Type {
+                        name: array_name(&element.name, &dimensions[i..]),
+                        size: array.size * count.unwrap_or(0),
+                        encoding: Encoding::Unsigned,
+                        kind: Kind::Array(Box::new(array), *count),
+                    };
+                }
+                array
+            }
+            RawType::Enum {
+                size,
+                underlying,
+                values,
+                ..
+            } => {
+                let encoding = match underlying.and_then(|offset| self.resolve(offset, depth + 1)) {
+                    Some(underlying) => underlying.encoding,
+                    None if values.iter().any(|(_, value)| *value < 0) => Encoding::Signed,
+                    None => Encoding::Unsigned,
+                };
+                // now that the size is known, values given as unsigned data can be sign-extended
+                let shift = 64 - 8 * (*size).clamp(1, 8) as u32;
+                let values = values
+                    .iter()
+                    .map(|(name, value)| match encoding {
+                        Encoding::Signed => (name.clone(), (value << shift) >> shift),
+                        _ => (name.clone(), value & (u64::MAX >> shift) as i64),
+                    })
+                    .collect();
+                Type {
+                    name,
+                    size: *size,
+                    encoding,
+                    kind: Kind::Enum(values),
+                }
+            }
+            RawType::Function(returns, parameters, variadic) => {
+                // without all the types involved, calls can't be set up by the prototype
+                let returns = match returns {
+                    Some(offset) => self.resolve(*offset, depth + 1).map(Some),
+                    None => Some(None),
+                };
+                let parameters: Option<Vec<Type>> = parameters
+                    .iter()
+                    .map(|parameter| self.resolve((*parameter)?, depth + 1))
+                    .collect();
+                let signature = match (returns, parameters) {
+                    (Some(returns), Some(parameters)) => Some(Box::new(Signature {
+                        returns,
+                        parameters,
+                        variadic: *variadic,
+                    })),
+                    _ => None,
+                };
+                Type {
+                    name,
+                    size: 1,
+                    encoding: Encoding::Unsigned,
+                    kind: Kind::Function(signature),
+                }
+            }
+        };
+        self.types.insert(offset, resolved.clone());
+        Some(resolved)
+    }
+}
+
+/// Names an array of `element` with the given dimensions, like `int [2][3]` or `char *[4]`.
+fn array_name(element: &str, dimensions: &[Option<usize>]) -> String {
+    let dimensions: String = dimensions
+        .iter()
+        .map(|count| match count {
+            Some(count) => format!("[{}]", count),
+            None => "[]".to_string(),
+        })
+        .collect();
+    if element.ends_with('*') {
+        format!("{}{}", element, dimensions)
+    } else {
+        format!("{} {}", element, dimensions)
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum DebugValue {
     Str(String),
@@ -261,10 +740,10 @@ pub enum DebugValue {
     NoVal,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     GimliError(gimli::Error),
-    Addr2lineError(addr2line::gimli::Error),
     ObjectError(String),
     IoError,
 }
@@ -300,25 +779,118 @@ impl<'input, Endian> Reader for gimli::EndianSlice<'input, Endian> where
 
 trait Reader: gimli::Reader<Offset = usize> + Send + Sync {}
 
-fn get_location<R: Reader>(attr: &gimli::Attribute<R>, unit: &gimli::Unit<R>) -> Option<Location> {
+/// Reads a DW_AT_location. An address or an offset from the frame base on its own is taken
+/// as such, and anything else kept as an expression or location list to evaluate later.
+fn get_location<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+    dwarf: &gimli::Dwarf<R>,
+) -> Result<Option<Location>, Error> {
     if let gimli::AttributeValue::Exprloc(ref data) = attr.value() {
-        let encoding = unit.encoding();
         let mut pc = data.0.clone();
-        if pc.len() > 0 {
-            if let Ok(op) = gimli::Operation::parse(&mut pc, encoding) {
-                match op {
-                    gimli::Operation::FrameOffset { offset } => {
-                        return Some(Location::FramePointerOffset(offset.try_into().unwrap()));
-                    }
-                    gimli::Operation::Address { address } => {
-                        return Some(Location::Address(address.try_into().unwrap()));
-                    }
-                    _ => {}
+        if let Ok(op) = gimli::Operation::parse(&mut pc, unit.encoding()) {
+            match op {
+                gimli::Operation::FrameOffset { offset } if pc.is_empty() => {
+                    return Ok(Some(Location::FramePointerOffset(
+                        offset.try_into().unwrap(),
+                    )));
                 }
+                gimli::Operation::Address { address } if pc.is_empty() => {
+                    return Ok(Some(Location::Address(address.try_into().unwrap())));
+                }
+                _ => {}
+            }
+        }
+        return Ok(Some(Location::Expression(to_expression(data)?)));
+    }
+    let mut entries = match dwarf.attr_locations(unit, attr.value())? {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+    let mut list = Vec::new();
+    while let Some(entry) = entries.next()? {
+        let range = entry.range.begin as usize..entry.range.end as usize;
+        list.push((range, to_expression(&entry.data)?));
+    }
+    Ok(Some(Location::List(list)))
+}
+
+/// Reads a DW_AT_frame_base. Returns None for DW_OP_call_frame_cfa, which is what gcc uses and
+/// what frames are assumed to have.
+fn get_frame_base<R: Reader>(
+    attr: &gimli::Attribute<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Option<DwarfExpression>, Error> {
+    let data = match attr.value() {
+        gimli::AttributeValue::Exprloc(data) => data,
+        _ => return Ok(None),
+    };
+    let mut pc = data.0.clone();
+    match gimli::Operation::parse(&mut pc, unit.encoding()) {
+        Ok(gimli::Operation::CallFrameCFA) if pc.is_empty() => Ok(None),
+        _ => Ok(Some(to_expression(&data)?)),
+    }
+}
+
+/// Turns the DW_AT_const_value of a variable the compiler got rid of into an expression that
+/// results in that value.
+fn get_const_value<R: Reader>(attr: &gimli::Attribute<R>) -> Option<Location> {
+    let bytes = match attr.value() {
+        gimli::AttributeValue::Block(block) => block.to_slice().ok()?.into_owned(),
+        gimli::AttributeValue::Sdata(value) => value.to_le_bytes().to_vec(),
+        value => value.udata_value()?.to_le_bytes().to_vec(),
+    };
+    let mut expression = vec![gimli::DW_OP_implicit_value.0];
+    let mut len = bytes.len();
+    // the length is a ULEB128
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            expression.push(byte);
+            break;
+        }
+        expression.push(byte | 0x80);
+    }
+    expression.extend_from_slice(&bytes);
+    Some(Location::Expression(DwarfExpression {
+        bytes: expression,
+        load_bias: 0,
+    }))
+}
+
+fn to_expression<R: Reader>(expression: &gimli::Expression<R>) -> Result<DwarfExpression, Error> {
+    Ok(DwarfExpression {
+        bytes: expression.0.to_slice()?.into_owned(),
+        load_bias: 0,
+    })
+}
+
+/// Returns the attributes of an entry, after those of the entry its DW_AT_abstract_origin
+/// refers to if it has one: out-of-line and optimized copies of a function, and their
+/// variables, leave their names, types and lines to the abstract one.
+fn attrs_with_origin<R: Reader>(
+    entry: &gimli::DebuggingInformationEntry<R>,
+    unit: &gimli::Unit<R>,
+) -> Result<Vec<gimli::Attribute<R>>, Error> {
+    let mut attrs = Vec::new();
+    if let Some(gimli::AttributeValue::UnitRef(offset)) =
+        entry.attr_value(gimli::DW_AT_abstract_origin)?
+    {
+        let origin = unit.entry(offset)?;
+        let mut origin_attrs = origin.attrs();
+        while let Some(attr) = origin_attrs.next()? {
+            // the abstract entry only declares what the concrete one defines
+            if attr.name() != gimli::DW_AT_declaration && attr.name() != gimli::DW_AT_inline {
+                attrs.push(attr);
             }
         }
     }
-    None
+    let mut own_attrs = entry.attrs();
+    while let Some(attr) = own_attrs.next()? {
+        attrs.push(attr);
+    }
+    Ok(attrs)
 }
 
 // based on dwarf_dump.rs
@@ -347,6 +919,16 @@ fn get_attr_value<R: Reader>(
                 Ok(DebugValue::Str(format!("<.debug_str+0x{:08x}>", offset.0)))
             }
         }
+        gimli::AttributeValue::DebugLineStrRef(offset) => {
+            if let Ok(s) = dwarf.debug_line_str.get_str(offset) {
+                Ok(DebugValue::Str(format!("{}", s.to_string_lossy()?)))
+            } else {
+                Ok(DebugValue::Str(format!(
+                    "<.debug_line_str+0x{:08x}>",
+                    offset.0
+                )))
+            }
+        }
         gimli::AttributeValue::Sdata(data) => Ok(DebugValue::Int(data)),
         gimli::AttributeValue::Addr(data) => Ok(DebugValue::Uint(data)),
         gimli::AttributeValue::Udata(data) => Ok(DebugValue::Uint(data)),
@@ -610,9 +1192,11 @@ fn dump_op<R: Reader, W: Write>(
         | gimli::Operation::TLS
         | gimli::Operation::CallFrameCFA
         | gimli::Operation::StackValue => {}
-        gimli::Operation::WasmLocal { index } => todo!(),
-        gimli::Operation::WasmGlobal { index } => todo!(),
-        gimli::Operation::WasmStack { index } => todo!(),
+        gimli::Operation::WasmLocal { index }
+        | gimli::Operation::WasmGlobal { index }
+        | gimli::Operation::WasmStack { index } => {
+            write!(w, " {}", index)?;
+        }
     };
     Ok(())
 }
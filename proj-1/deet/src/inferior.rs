@@ -1,16 +1,46 @@
-use crate::dwarf_data::{DwarfData, Error as DwarfError};
-use nix::sys::ptrace;
+use crate::signals::{self, SignalTable};
+use crate::target::{align_addr_to_word, Target};
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::errno::Errno;
+use nix::sys::ptrace::{self, Options};
 use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use std::collections::HashMap;
-use std::mem::size_of;
+use std::fs::File;
+use std::mem::{offset_of, size_of};
+use std::os::unix::fs::FileExt;
 use std::os::unix::prelude::CommandExt;
-use std::process::Child;
 use std::process::Command;
 
-fn align_addr_to_word(addr: usize) -> usize {
-    addr & (-(size_of::<usize>() as isize) as usize)
+/// The number of hardware watchpoints x86-64 supports (DR0-DR3).
+pub const DEBUG_REGISTER_SLOTS: usize = 4;
+
+/// What kind of access a hardware watchpoint traps on. x86 has no read-only watchpoints, so
+/// `Read` traps on writes as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+/// Why the inferior trapped after we resumed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trap {
+    /// It executed the breakpoint at this address.
+    Breakpoint(usize),
+    /// The watchpoint in this debug register slot triggered.
+    Watchpoint(usize),
+}
+
+/// A snapshot of a stopped thread, as a core dump saves it.
+pub struct ThreadState {
+    pub tid: Pid,
+    pub regs: user_regs_struct,
+    pub fpregs: user_fpregs_struct,
+    /// The signal it stopped with that hasn't been delivered yet
+    pub signal: Option<signal::Signal>,
 }
 
 pub enum Status {
@@ -24,138 +54,1097 @@ pub enum Status {
     /// Indicates the inferior exited due to a signal. Contains the signal that killed the
     /// process.
     Signaled(signal::Signal),
+
+    /// Indicates the inferior called execve and is now at the start of a new program. Its
+    /// breakpoints went away with the old one.
+    Exec,
+}
+
+/// What to do when the inferior forks, as chosen with `set follow-fork-mode` and
+/// `set detach-on-fork`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForkPolicy {
+    /// Whether to go on debugging the child rather than the parent
+    pub follow_child: bool,
+    /// Whether to let the other process run freely, rather than keep it suspended until the
+    /// one we follow is gone
+    pub detach: bool,
+}
+
+impl Default for ForkPolicy {
+    fn default() -> ForkPolicy {
+        ForkPolicy {
+            follow_child: false,
+            detach: true,
+        }
+    }
+}
+
+/// The ptrace events we want to hear about: new threads, new processes and new programs.
+fn trace_options() -> Options {
+    Options::PTRACE_O_TRACECLONE
+        | Options::PTRACE_O_TRACEFORK
+        | Options::PTRACE_O_TRACEVFORK
+        | Options::PTRACE_O_TRACEVFORKDONE
+        | Options::PTRACE_O_TRACEEXEC
+}
+
+/// Returns the process that thread `tid` belongs to, from /proc/<tid>/status.
+fn thread_group(tid: Pid) -> Option<Pid> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", tid)).ok()?;
+    let tgid = status.lines().find_map(|line| line.strip_prefix("Tgid:"))?;
+    Some(Pid::from_raw(tgid.trim().parse().ok()?))
+}
+
+fn debug_register_offset(index: usize) -> usize {
+    offset_of!(libc::user, u_debugreg) + index * size_of::<u64>()
+}
+
+fn peek_debug_register(tid: Pid, index: usize) -> Result<usize, nix::Error> {
+    // PTRACE_PEEKUSER returns the data itself, so errors can only be told apart through errno
+    Errno::clear();
+    let value = unsafe {
+        libc::ptrace(
+            libc::PTRACE_PEEKUSER,
+            tid.as_raw(),
+            debug_register_offset(index),
+            0,
+        )
+    };
+    if value == -1 && Errno::last() != Errno::UnknownErrno {
+        return Err(Errno::last());
+    }
+    Ok(value as usize)
+}
+
+fn poke_debug_register(tid: Pid, index: usize, value: usize) -> Result<(), nix::Error> {
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_POKEUSER,
+            tid.as_raw(),
+            debug_register_offset(index),
+            value,
+        )
+    };
+    Errno::result(result).map(drop)
+}
+
+/// Reads the x87 and SSE registers of thread `tid`, which nix has no wrapper for.
+fn get_fp_registers(tid: Pid) -> Result<user_fpregs_struct, nix::Error> {
+    let mut fpregs: user_fpregs_struct = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETFPREGS,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            &mut fpregs as *mut user_fpregs_struct,
+        )
+    };
+    Errno::result(result).map(|_| fpregs)
+}
+
+/// Overwrites the x87 and SSE registers of thread `tid`.
+fn set_fp_registers(tid: Pid, fpregs: &user_fpregs_struct) -> Result<(), nix::Error> {
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_SETFPREGS,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            fpregs as *const user_fpregs_struct,
+        )
+    };
+    Errno::result(result).map(drop)
+}
+
+/// Lists the threads of process `pid`, from /proc/<pid>/task.
+fn list_threads(pid: Pid) -> Result<Vec<Pid>, nix::Error> {
+    let tasks = std::fs::read_dir(format!("/proc/{}/task", pid)).map_err(|_| Errno::ESRCH)?;
+    Ok(tasks
+        .filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok())
+        .map(Pid::from_raw)
+        .collect())
 }
 
 /// This function calls ptrace with PTRACE_TRACEME to enable debugging on a process. You should use
 /// pre_exec with Command to call this in the child process.
 fn child_traceme() -> Result<(), std::io::Error> {
-    ptrace::traceme().or(Err(std::io::Error::new(
-        std::io::ErrorKind::Other,
-        "ptrace TRACEME failed",
-    )))
+    ptrace::traceme().or(Err(std::io::Error::other("ptrace TRACEME failed")))
+}
+
+/// A thread of the inferior.
+struct Thread {
+    /// The number the user refers to the thread by, counting from 1 in order of creation
+    id: usize,
+    tid: Pid,
+    /// Whether it has been resumed and hasn't stopped since
+    running: bool,
+    /// Whether a SIGSTOP we sent it is still to arrive, to be swallowed when it does
+    sigstop_expected: bool,
+    /// A stop it reached while we were stopping the other threads, which is reported the next
+    /// time the inferior is resumed
+    pending: Option<(Status, Option<Trap>)>,
+    /// A signal it received, to deliver when it resumes
+    signal: Option<signal::Signal>,
 }
 
 pub struct Inferior {
-    child: Child,
+    pid: Pid,
+    /// Whether we attached to an existing process rather than spawning it.
+    attached: bool,
+    /// Addresses where a 0xcc is currently inserted, mapped to the byte it replaced.
+    breakpoints: HashMap<usize, u8>,
+    /// Every thread, in order of creation. The inferior runs in all-stop mode: either all of
+    /// them are running or all of them are stopped.
+    threads: Vec<Thread>,
+    /// The thread whose registers we read and that gets single-stepped.
+    current: Pid,
+    next_thread_id: usize,
+    /// The watchpoints in the debug registers. Those are per thread, so new threads need them
+    /// programmed too.
+    watches: Vec<(usize, usize, WatchKind)>,
+    fork_policy: ForkPolicy,
+    signals: SignalTable,
+    /// Processes that forked off the one we follow, or that it forked from, kept suspended
+    /// because detach-on-fork is off.
+    held: Vec<Inferior>,
+    /// New processes whose first stop came before their parent's fork event.
+    early_forks: Vec<Pid>,
+    /// The parent we followed a vfork child away from. They share memory, so the breakpoints
+    /// can only be taken out of the parent once the child has exec'ed or exited.
+    vfork_parent: Option<Box<Inferior>>,
+    /// Whether the breakpoints were taken out of memory to detach a vfork child that shares it,
+    /// to be put back once the child is done with it.
+    breakpoints_lifted: bool,
 }
 
 impl Inferior {
+    fn traced(pid: Pid, attached: bool) -> Inferior {
+        Inferior {
+            pid,
+            attached,
+            breakpoints: HashMap::new(),
+            threads: Vec::new(),
+            current: pid,
+            next_thread_id: 1,
+            watches: Vec::new(),
+            fork_policy: ForkPolicy::default(),
+            signals: SignalTable::default(),
+            held: Vec::new(),
+            early_forks: Vec::new(),
+            vfork_parent: None,
+            breakpoints_lifted: false,
+        }
+    }
+
     /// Attempts to start a new inferior process. Returns Some(Inferior) if successful, or None if
     /// an error is encountered.
-    pub fn new(
-        target: &str,
-        args: &Vec<String>,
-        breakpoints: &mut HashMap<usize, u8>,
-    ) -> Option<Inferior> {
+    pub fn new(target: &str, args: &[String]) -> Option<Inferior> {
         let mut cmd = Command::new(target);
         cmd.args(args);
         unsafe {
             cmd.pre_exec(child_traceme);
         }
         let child = cmd.spawn().ok()?;
-        let mut inferior = Inferior { child };
-        for bp in breakpoints.clone().keys() {
-            match inferior.write_byte(*bp, 0xcc) {
-                Ok(ori_instr) => {
-                    breakpoints.insert(*bp, ori_instr);
+        let mut inferior = Inferior::traced(Pid::from_raw(child.id() as i32), false);
+        // The child stops with SIGTRAP once it has exec'ed the target. Its memory can't be
+        // touched before that.
+        match waitpid(inferior.pid, None).ok()? {
+            WaitStatus::Stopped(_, signal::Signal::SIGTRAP) => {}
+            _ => return None,
+        }
+        ptrace::setoptions(inferior.pid, trace_options()).ok()?;
+        inferior.add_thread(inferior.pid);
+        Some(inferior)
+    }
+
+    /// Attaches to every thread of the running process `pid` with PTRACE_ATTACH, leaving them
+    /// stopped.
+    pub fn attach(pid: Pid) -> Result<Inferior, nix::Error> {
+        let mut inferior = Inferior::traced(pid, true);
+        // threads can be created while we attach to the others, so look until there are no new
+        // ones
+        loop {
+            let mut tids = list_threads(pid)?;
+            tids.retain(|tid| inferior.thread(*tid).is_none());
+            if tids.is_empty() {
+                return Ok(inferior);
+            }
+            // the main thread comes first
+            tids.sort_by_key(|tid| (*tid != pid, tid.as_raw()));
+            for tid in tids {
+                if let Err(e) = ptrace::attach(tid) {
+                    if tid == pid {
+                        return Err(e);
+                    }
+                    // the thread exited in the meantime
+                    continue;
+                }
+                // PTRACE_ATTACH sends the thread a SIGSTOP, which we swallow here
+                match waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                    WaitStatus::Stopped(_, _) => {}
+                    _ if tid == pid => return Err(Errno::ESRCH),
+                    _ => continue,
                 }
-                Err(_) => println!("Invalid breakpoint address {:#x}", bp),
+                ptrace::setoptions(tid, trace_options())?;
+                inferior.add_thread(tid);
+            }
+        }
+    }
+
+    /// Inserts a breakpoint at each of `breakpoints`, reporting the ones that can't be.
+    pub fn insert_breakpoints(&mut self, breakpoints: &[usize]) {
+        for bp in breakpoints {
+            if self.insert_breakpoint(*bp).is_err() {
+                println!("Invalid breakpoint address {:#x}", bp);
+            }
+        }
+    }
+
+    /// Returns whether this inferior was attached to rather than spawned by us.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Sets what to do when the inferior forks.
+    pub fn set_fork_policy(&mut self, policy: ForkPolicy) {
+        self.fork_policy = policy;
+    }
+
+    /// Sets how to react to each signal the inferior receives.
+    pub fn set_signal_table(&mut self, signals: SignalTable) {
+        self.signals = signals;
+    }
+
+    /// Returns the processes kept suspended after a fork.
+    pub fn held_processes(&self) -> Vec<Pid> {
+        self.held.iter().map(|process| process.pid).collect()
+    }
+
+    /// Lets the processes kept suspended after a fork go, once the one we follow has ended.
+    pub fn release_held(&mut self) {
+        let held = std::mem::take(&mut self.held);
+        for process in held.into_iter().chain(self.vfork_parent.take().map(|p| *p)) {
+            let pid = process.pid;
+            match process.detach() {
+                Ok(()) => println!("[Detaching held process {}]", pid),
+                Err(e) => println!("Error detaching from process {}: {}", pid, e),
             }
         }
-        Some(inferior)
     }
 
-    /// Returns the pid of this inferior.
-    pub fn pid(&self) -> Pid {
-        nix::unistd::Pid::from_raw(self.child.id() as i32)
+    /// Returns the LWP, registers and undelivered signal of every thread, the current one
+    /// first.
+    pub fn thread_states(&self) -> Result<Vec<ThreadState>, nix::Error> {
+        let mut threads: Vec<&Thread> = self.threads.iter().collect();
+        threads.sort_by_key(|thread| thread.tid != self.current);
+        threads
+            .into_iter()
+            .map(|thread| {
+                Ok(ThreadState {
+                    tid: thread.tid,
+                    regs: ptrace::getregs(thread.tid)?,
+                    fpregs: get_fp_registers(thread.tid)?,
+                    signal: thread.signal,
+                })
+            })
+            .collect()
     }
 
-    /// Try to kill the child and Returns the result of this [`Inferior`].
-    pub fn kill(&mut self) -> Result<(), std::io::Error> {
-        self.child.kill()
+    /// Overwrites the general purpose registers of the current thread.
+    pub fn set_registers(&self, regs: user_regs_struct) -> Result<(), nix::Error> {
+        ptrace::setregs(self.current, regs)
     }
 
-    pub fn print_backtrace(&self, debug_data: &DwarfData) -> Result<(), nix::Error> {
-        let regs = ptrace::getregs(self.pid()).unwrap();
-        let mut rip = regs.rip as usize;
-        let mut rbp = regs.rbp as usize;
+    /// Overwrites the x87 and SSE registers of the current thread.
+    pub fn set_fp_registers(&self, fpregs: &user_fpregs_struct) -> Result<(), nix::Error> {
+        set_fp_registers(self.current, fpregs)
+    }
+
+    /// Replaces the signal the current thread is to be delivered when it resumes, returning
+    /// the one it had.
+    pub fn replace_signal(&mut self, signal: Option<signal::Signal>) -> Option<signal::Signal> {
+        let current = self.current;
+        let thread = self.thread_mut(current)?;
+        std::mem::replace(&mut thread.signal, signal)
+    }
+
+    /// Puts the original instructions back in place of the 0xcc of inserted breakpoints in
+    /// `bytes`, which were read from `addr`.
+    pub fn hide_breakpoints(&self, addr: usize, bytes: &mut [u8]) {
+        for (site, orig_byte) in &self.breakpoints {
+            if let Some(byte) = site.checked_sub(addr).and_then(|i| bytes.get_mut(i)) {
+                *byte = *orig_byte;
+            }
+        }
+    }
+
+    fn thread(&self, tid: Pid) -> Option<&Thread> {
+        self.threads.iter().find(|thread| thread.tid == tid)
+    }
+
+    fn thread_mut(&mut self, tid: Pid) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|thread| thread.tid == tid)
+    }
+
+    /// Starts keeping track of a stopped thread and returns its id.
+    fn add_thread(&mut self, tid: Pid) -> usize {
+        let id = self.next_thread_id;
+        self.next_thread_id += 1;
+        self.threads.push(Thread {
+            id,
+            tid,
+            running: false,
+            sigstop_expected: false,
+            pending: None,
+            signal: None,
+        });
+        id
+    }
+
+    /// Forgets a thread that exited. If it was the current one, the main thread takes over.
+    fn remove_thread(&mut self, tid: Pid) {
+        if let Some(index) = self.threads.iter().position(|thread| thread.tid == tid) {
+            let thread = self.threads.remove(index);
+            println!("[Thread {} (LWP {}) exited]", thread.id, tid);
+        }
+        if self.current == tid {
+            self.current = self.threads.first().map_or(self.pid, |thread| thread.tid);
+        }
+    }
+
+    /// Kills the inferior and reaps it, along with the processes held after a fork.
+    pub fn kill(&mut self) -> Result<(), nix::Error> {
+        signal::kill(self.pid, signal::Signal::SIGKILL)?;
+        self.reap()?;
+        let held = std::mem::take(&mut self.held);
+        for mut process in held.into_iter().chain(self.vfork_parent.take().map(|p| *p)) {
+            process.kill()?;
+        }
+        Ok(())
+    }
+
+    /// Waits for the whole process to go away, reaping its threads, and returns how it ended.
+    fn reap(&mut self) -> Result<Status, nix::Error> {
         loop {
-            let _line = debug_data.get_line_from_addr(rip);
-            let _func = debug_data.get_function_from_addr(rip);
-            match (&_line, &_func) {
-                (None, None) => println!("unknown func (source file not found)"),
-                (Some(line), None) => println!("unknown func ({})", line),
-                (None, Some(func)) => println!("{} (source file not found)", func),
-                (Some(line), Some(func)) => println!("{} ({})", func, line),
-            }
-            if let Some(func) = _func {
-                if func == "main" {
-                    break;
+            match waitpid(None, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Exited(pid, code) if pid == self.pid => {
+                    return Ok(Status::Exited(code))
+                }
+                WaitStatus::Signaled(pid, signal, _) if pid == self.pid => {
+                    return Ok(Status::Signaled(signal))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Takes out all inserted breakpoints and watchpoints and lets the process carry on running
+    /// untraced, along with the processes held after a fork.
+    pub fn detach(mut self) -> Result<(), nix::Error> {
+        self.release_held();
+        let sites: Vec<usize> = self.breakpoints.keys().copied().collect();
+        for addr in sites {
+            self.remove_breakpoint(addr)?;
+        }
+        self.set_watchpoints(&[])?;
+        for thread in &self.threads {
+            if thread.sigstop_expected {
+                // it would stop as soon as it's let go otherwise
+                ptrace::cont(thread.tid, None)?;
+                waitpid(thread.tid, Some(WaitPidFlag::__WALL))?;
+            }
+            // hand over signals that haven't been delivered or reported yet
+            let signal = match thread.pending {
+                Some((Status::Stopped(signal, _), None)) => Some(signal),
+                _ => thread.signal,
+            };
+            ptrace::detach(thread.tid, signal)?;
+        }
+        Ok(())
+    }
+
+    /// Deals with the stops that only concern us: threads being created or exiting, and the
+    /// SIGSTOPs we sent. Returns any other stop for the caller to handle.
+    fn absorb(&mut self, status: WaitStatus) -> Result<Option<WaitStatus>, nix::Error> {
+        let tid = match status.pid() {
+            Some(tid) => tid,
+            None => return Ok(Some(status)),
+        };
+        let expected = match self.thread_mut(tid) {
+            Some(thread) => {
+                thread.running = false;
+                thread.sigstop_expected
+            }
+            None => false,
+        };
+        match status {
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_CLONE) => {
+                let new = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                if self.thread(new).is_none() {
+                    // new threads start out stopped by a SIGSTOP
+                    waitpid(new, Some(WaitPidFlag::__WALL))?;
+                    self.announce_thread(new)?;
                 }
+                Ok(None)
+            }
+            WaitStatus::PtraceEvent(
+                _,
+                _,
+                event @ (libc::PTRACE_EVENT_FORK | libc::PTRACE_EVENT_VFORK),
+            ) => {
+                let child = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                self.follow_fork(child, event == libc::PTRACE_EVENT_VFORK)?;
+                Ok(None)
+            }
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_VFORK_DONE) => {
+                if self.breakpoints_lifted {
+                    self.breakpoints_lifted = false;
+                    let sites: Vec<usize> = self.breakpoints.keys().copied().collect();
+                    for addr in sites {
+                        self.write_byte(addr, 0xcc)?;
+                    }
+                }
+                Ok(None)
+            }
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXEC) => {
+                self.start_new_program()?;
+                Ok(Some(status))
+            }
+            WaitStatus::Stopped(_, _) if self.thread(tid).is_none() => {
+                if thread_group(tid) == Some(self.pid) {
+                    // a new thread whose first stop beat its parent's clone event
+                    self.announce_thread(tid)?;
+                } else {
+                    self.early_forks.push(tid);
+                }
+                Ok(None)
+            }
+            WaitStatus::Stopped(_, signal::Signal::SIGSTOP) if expected => {
+                self.thread_mut(tid).unwrap().sigstop_expected = false;
+                Ok(None)
+            }
+            WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _) if tid != self.pid => {
+                self.remove_thread(tid);
+                Ok(None)
+            }
+            WaitStatus::Exited(_, _) | WaitStatus::Signaled(_, _, _) => {
+                // a vfork parent can run again once its child is gone
+                if let Some(parent) = self.vfork_parent.take() {
+                    let pid = parent.pid;
+                    println!("[Detaching vfork parent process {}]", pid);
+                    parent.detach()?;
+                }
+                Ok(Some(status))
+            }
+            _ => Ok(Some(status)),
+        }
+    }
+
+    /// Takes control of `child`, which the inferior just forked, and decides which of the two
+    /// processes to follow according to the fork policy.
+    fn follow_fork(&mut self, child: Pid, vfork: bool) -> Result<(), nix::Error> {
+        // the child starts out stopped by a SIGSTOP, which may have been reported already
+        match self.early_forks.iter().position(|pid| *pid == child) {
+            Some(index) => drop(self.early_forks.remove(index)),
+            None => drop(waitpid(child, Some(WaitPidFlag::__WALL))?),
+        }
+        let mut other = Inferior::traced(child, self.attached);
+        other.add_thread(child);
+        other.fork_policy = self.fork_policy;
+        other.signals = self.signals.clone();
+        // it got a copy of our memory, breakpoints included, but not of the debug registers
+        other.breakpoints = self.breakpoints.clone();
+        let kind = if vfork { "vfork" } else { "fork" };
+        if self.fork_policy.follow_child {
+            println!(
+                "[Attaching after process {} {} to child process {}]",
+                self.pid, kind, child
+            );
+            std::mem::swap(self, &mut other);
+            self.held = std::mem::take(&mut other.held);
+            self.set_watchpoints(&other.watches.clone())?;
+            // the parent's other threads are still running
+            other.stop_all()?;
+            if !self.fork_policy.detach {
+                println!("[Holding parent process {}]", other.pid);
+                self.held.push(other);
+            } else if vfork {
+                self.vfork_parent = Some(Box::new(other));
             } else {
-                break;
+                println!("[Detaching after fork from parent process {}]", other.pid);
+                other.detach()?;
             }
-            rip = ptrace::read(self.pid(), (rbp + 8) as ptrace::AddressType)? as usize;
-            rbp = ptrace::read(self.pid(), rbp as ptrace::AddressType)? as usize;
+        } else if !self.fork_policy.detach {
+            println!("[Holding child process {}]", child);
+            self.held.push(other);
+        } else {
+            println!("[Detaching after {} from child process {}]", kind, child);
+            // taking the breakpoints out of a vfork child takes them out of our memory too
+            self.breakpoints_lifted = vfork;
+            other.detach()?;
+        }
+        Ok(())
+    }
+
+    /// Catches up with an exec: the thread that called it is the only one left and has taken
+    /// over the pid, and the breakpoints went away with the old program.
+    fn start_new_program(&mut self) -> Result<(), nix::Error> {
+        let pid = self.pid;
+        self.threads.retain(|thread| thread.tid == pid);
+        if self.threads.is_empty() {
+            self.add_thread(self.pid);
+        }
+        self.current = self.pid;
+        self.breakpoints.clear();
+        self.breakpoints_lifted = false;
+        // the vfork parent has its own memory again
+        if let Some(parent) = self.vfork_parent.take() {
+            println!("[Detaching vfork parent process {}]", parent.pid);
+            parent.detach()?;
         }
         Ok(())
     }
 
-    /// Calls waitpid on this inferior and returns a Status to indicate the state of the process
-    /// after the waitpid call.
-    pub fn wait(&self, options: Option<WaitPidFlag>) -> Result<Status, nix::Error> {
-        Ok(match waitpid(self.pid(), options)? {
+    /// Starts tracking a thread the inferior just created.
+    fn announce_thread(&mut self, tid: Pid) -> Result<(), nix::Error> {
+        let id = self.add_thread(tid);
+        println!("[New thread {} (LWP {})]", id, tid);
+        self.program_debug_registers(tid)
+    }
+
+    /// Translates a wait status that absorb() let through into what the inferior did. Statuses
+    /// with no meaning here are an error rather than something to report.
+    fn to_status(&self, status: WaitStatus) -> Result<Status, nix::Error> {
+        Ok(match status {
             WaitStatus::Exited(_pid, exit_code) => Status::Exited(exit_code),
             WaitStatus::Signaled(_pid, signal, _core_dumped) => Status::Signaled(signal),
-            WaitStatus::Stopped(_pid, signal) => {
-                let regs = ptrace::getregs(self.pid())?;
+            WaitStatus::Stopped(tid, signal) => {
+                let regs = ptrace::getregs(tid)?;
                 Status::Stopped(signal, regs.rip as usize)
             }
-            other => panic!("waitpid returned unexpected status: {:?}", other),
+            WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_EXEC) => Status::Exec,
+            // events absorb() didn't handle, like those of a tracing option we never set
+            _ => return Err(nix::Error::EIO),
         })
     }
 
-    pub fn continue_run(&mut self, signal: Option<signal::Signal>, breakpoints: &mut HashMap<usize, u8>) -> Result<Status, nix::Error> {
-        let mut regs = ptrace::getregs(self.pid())?;        
-        let rip = regs.rip as usize;
+    /// Resumes every stopped thread, delivering the signals they are to be passed.
+    fn resume_all(&mut self) -> Result<(), nix::Error> {
+        for thread in self.threads.iter_mut().filter(|thread| !thread.running) {
+            ptrace::cont(thread.tid, thread.signal.take())?;
+            thread.running = true;
+        }
+        Ok(())
+    }
+
+    /// Applies the policy for `signal`, which the current thread received: keeps it for
+    /// delivery if it is to be passed, and announces it if it is to be printed but doesn't
+    /// stop the inferior. Returns whether it stops the inferior.
+    fn take_signal(&mut self, signal: signal::Signal) -> bool {
+        let policy = self.signals.get(signal);
+        if let Some(thread) = self.thread_mut(self.current) {
+            thread.signal = if policy.pass { Some(signal) } else { None };
+        }
+        if policy.print && !policy.stop {
+            println!(
+                "Child received signal {}, {}",
+                signal.as_str(),
+                signals::describe(signal)
+            );
+        }
+        policy.stop
+    }
 
-        if let Some(ori_instr) = breakpoints.get(&(rip - 1)) {
-            println!("stopped at a breakpoint");
-            self.write_byte(rip - 1, *ori_instr).unwrap();
+    /// Waits until some thread of the running inferior stops for a reason worth reporting,
+    /// keeping the others running.
+    fn wait_any(&mut self) -> Result<(Pid, WaitStatus), nix::Error> {
+        loop {
+            let status = waitpid(None, Some(WaitPidFlag::__WALL))?;
+            match self.absorb(status)? {
+                Some(status) => return Ok((status.pid().unwrap(), status)),
+                None => self.resume_all()?,
+            }
+        }
+    }
+
+    /// Stops every thread that is still running. Those that stop for another reason than our
+    /// SIGSTOP keep that as a pending stop, to report when the inferior is next resumed.
+    fn stop_all(&mut self) -> Result<(), nix::Error> {
+        for thread in self.threads.iter_mut().filter(|thread| thread.running) {
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_tgkill,
+                    self.pid.as_raw(),
+                    thread.tid.as_raw(),
+                    libc::SIGSTOP,
+                )
+            };
+            // a thread that is already gone has its exit to report instead
+            if Errno::result(result).is_ok() {
+                thread.sigstop_expected = true;
+            }
+        }
+        while self.threads.iter().any(|thread| thread.running) {
+            let status = waitpid(None, Some(WaitPidFlag::__WALL))?;
+            let tid = status.pid().unwrap();
+            match self.absorb(status)? {
+                Some(status) => {
+                    let stop = self.classify(tid, status)?;
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.pending = Some(stop);
+                    }
+                }
+                None => {
+                    // it stopped for some other reason before getting our SIGSTOP
+                    if self
+                        .thread(tid)
+                        .is_some_and(|thread| thread.sigstop_expected)
+                    {
+                        ptrace::cont(tid, None)?;
+                        self.thread_mut(tid).unwrap().running = true;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a stop that some thread reached while the others were being stopped, and makes
+    /// that thread current.
+    fn take_pending(&mut self) -> Option<(Status, Option<Trap>)> {
+        let thread = self
+            .threads
+            .iter_mut()
+            .find(|thread| thread.pending.is_some())?;
+        self.current = thread.tid;
+        thread.pending.take()
+    }
+
+    /// Executes one instruction in the current thread, leaving the others stopped. If rip is at
+    /// an inserted breakpoint, the original instruction is put back for the step and the 0xcc
+    /// restored afterwards. Also returns whether a watchpoint triggered by the instruction
+    /// should stop the inferior.
+    fn single_step(
+        &mut self,
+        should_stop: &mut dyn FnMut(&Inferior, Trap) -> bool,
+    ) -> Result<(Status, bool), nix::Error> {
+        let tid = self.current;
+        let rip = self.get_registers()?.rip as usize;
+        let ori_instr = self.breakpoints.get(&rip).copied();
+        if let Some(ori_instr) = ori_instr {
+            self.write_byte(rip, ori_instr)?;
+        }
+        let status = loop {
+            let signal = self.thread_mut(tid).and_then(|thread| thread.signal.take());
+            ptrace::step(tid, signal)?;
+            let status = waitpid(tid, Some(WaitPidFlag::__WALL))?;
+            // thread creation or one of our SIGSTOPs interrupted the step
+            if let Some(status) = self.absorb(status)? {
+                match self.to_status(status)? {
+                    // step again, delivering it if it's passed
+                    Status::Stopped(signal, _)
+                        if signal != signal::Signal::SIGTRAP && !self.take_signal(signal) => {}
+                    status => break status,
+                }
+            }
+            if self.thread(tid).is_none() {
+                // the thread exited or, after a fork, we now follow the child. Carry on with the
+                // new current thread if the process is still there.
+                let new_rip = match self.get_registers() {
+                    Ok(regs) => regs.rip as usize,
+                    Err(_) => return Ok((self.reap()?, false)),
+                };
+                if ori_instr.is_some() {
+                    self.write_byte(rip, 0xcc)?;
+                }
+                return Ok((Status::Stopped(signal::Signal::SIGTRAP, new_rip), false));
+            }
+        };
+        if let Status::Stopped(signal, _) = status {
+            if ori_instr.is_some() {
+                // restore 0xcc in the breakpoint location
+                self.write_byte(rip, 0xcc)?;
+            }
+            if signal == signal::Signal::SIGTRAP {
+                if let Some(slot) = self.take_watchpoint_hit(tid)? {
+                    return Ok((status, should_stop(self, Trap::Watchpoint(slot))));
+                }
+            }
+        }
+        Ok((status, false))
+    }
 
+    /// Works out whether a stop of thread `tid` is one of our breakpoints or watchpoints. For a
+    /// breakpoint, rip is rewound onto the breakpoint address so that it points at the
+    /// instruction that was replaced.
+    fn classify(
+        &mut self,
+        tid: Pid,
+        status: WaitStatus,
+    ) -> Result<(Status, Option<Trap>), nix::Error> {
+        let status = self.to_status(status)?;
+        let rip = match status {
+            Status::Stopped(signal::Signal::SIGTRAP, rip) => rip,
+            _ => return Ok((status, None)),
+        };
+        if let Some(slot) = self.take_watchpoint_hit(tid)? {
+            return Ok((status, Some(Trap::Watchpoint(slot))));
+        }
+        if self.breakpoints.contains_key(&rip.wrapping_sub(1)) {
+            let mut regs = ptrace::getregs(tid)?;
             regs.rip = (rip - 1) as u64;
-            ptrace::setregs(self.pid(), regs).unwrap();
-            // go to the next instruction
-            ptrace::step(self.pid(), None).unwrap();
-            // wait for inferior to stop due to SIGTRAP, just return if the inferior terminates here
-            match self.wait(None).unwrap() {
-                Status::Exited(exit_code) => return Ok(Status::Exited(exit_code)), 
-                Status::Signaled(signal) => return Ok(Status::Signaled(signal)),
-                Status::Stopped(_, _) => {
-                    // restore 0xcc in the breakpoint location
-                    self.write_byte(rip - 1, 0xcc).unwrap();
+            ptrace::setregs(tid, regs)?;
+            let status = Status::Stopped(signal::Signal::SIGTRAP, rip - 1);
+            return Ok((status, Some(Trap::Breakpoint(rip - 1))));
+        }
+        Ok((status, None))
+    }
+
+    /// Steps every thread sitting on an inserted breakpoint off it. The other threads stay
+    /// stopped meanwhile, so that none of them can run through the breakpoint while its original
+    /// byte is back in. Returns the status to report if the current thread stopped for some
+    /// other reason; other threads keep theirs pending.
+    fn step_off_breakpoints(
+        &mut self,
+        should_stop: &mut dyn FnMut(&Inferior, Trap) -> bool,
+    ) -> Result<Option<Status>, nix::Error> {
+        let current = self.current;
+        let mut tids: Vec<Pid> = self.threads.iter().map(|thread| thread.tid).collect();
+        // the current thread goes first, as it's the one whose stop we report
+        tids.sort_by_key(|tid| *tid != current);
+        for tid in tids {
+            // following a fork child may have replaced the process under our feet
+            if self.thread(tid).is_none() {
+                continue;
+            }
+            self.current = tid;
+            if !self
+                .breakpoints
+                .contains_key(&(self.get_registers()?.rip as usize))
+            {
+                continue;
+            }
+            match self.single_step(should_stop)? {
+                (Status::Stopped(signal::Signal::SIGTRAP, _), false) => {}
+                // the inferior terminated, got a signal or hit a watchpoint while executing the
+                // original instruction
+                (status @ (Status::Exited(_) | Status::Signaled(_) | Status::Exec), _) => {
+                    return Ok(Some(status))
+                }
+                (status, _) if tid == current => return Ok(Some(status)),
+                (status, _) => {
+                    if let Some(thread) = self.thread_mut(tid) {
+                        thread.pending = Some((status, None));
+                    }
                 }
             }
         }
+        if self.thread(current).is_some() {
+            self.current = current;
+        }
+        Ok(None)
+    }
 
-        ptrace::cont(self.pid(), None)?;
-        Ok(self.wait(None).unwrap())
+    /// Continues the inferior until it stops. When a thread traps on one of our breakpoints or
+    /// watchpoints, `should_stop` decides whether to report that or quietly resume; for signals,
+    /// the signal table does. Signals the threads received are delivered as they resume. The
+    /// thread that stopped becomes the current one.
+    pub fn continue_run(
+        &mut self,
+        should_stop: &mut dyn FnMut(&Inferior, Trap) -> bool,
+    ) -> Result<Status, nix::Error> {
+        let previous = self.current;
+        loop {
+            let (status, trap) = match self.take_pending() {
+                Some(stop) => stop,
+                None => {
+                    if let Some(status) = self.step_off_breakpoints(should_stop)? {
+                        return Ok(status);
+                    }
+                    if self.threads.iter().any(|thread| thread.pending.is_some()) {
+                        continue;
+                    }
+                    self.resume_all()?;
+                    let (tid, status) = self.wait_any()?;
+                    let stop = self.classify(tid, status)?;
+                    if let (Status::Stopped(_, _), _) = stop {
+                        self.current = tid;
+                        self.stop_all()?;
+                    }
+                    stop
+                }
+            };
+            let stop = match (trap, &status) {
+                // deleted since the thread hit it, and already rewound to run the instruction
+                (Some(Trap::Breakpoint(addr)), _) => {
+                    self.breakpoints.contains_key(&addr)
+                        && should_stop(self, Trap::Breakpoint(addr))
+                }
+                (Some(trap), _) => should_stop(self, trap),
+                (None, Status::Stopped(signal, _)) => self.take_signal(*signal),
+                (None, _) => true,
+            };
+            if stop {
+                if self.current != previous && self.thread(previous).is_some() {
+                    println!(
+                        "[Switching to thread {} (LWP {})]",
+                        self.current_thread(),
+                        self.current
+                    );
+                }
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Executes a single machine instruction in the current thread. Watchpoints it triggers go
+    /// through `should_stop` so that their hits are accounted for.
+    pub fn step(
+        &mut self,
+        should_stop: &mut dyn FnMut(&Inferior, Trap) -> bool,
+    ) -> Result<Status, nix::Error> {
+        Ok(self.single_step(should_stop)?.0)
+    }
+
+    /// Continues the inferior until it reaches `addr` with a stack pointer above `sp`, i.e. after
+    /// the frame that owned `sp` has returned. This skips over hits on the same address from
+    /// deeper recursive calls and from other threads. A temporary breakpoint is used unless one
+    /// is already set at `addr`; it is taken out again whether the run stops or fails. Stops
+    /// early if anything else (a breakpoint, a signal, exit) interrupts the run.
+    pub fn run_until(
+        &mut self,
+        addr: usize,
+        sp: usize,
+        should_stop: &mut dyn FnMut(&Inferior, Trap) -> bool,
+    ) -> Result<Status, nix::Error> {
+        let temporary = !self.breakpoints.contains_key(&addr);
+        if temporary {
+            self.insert_breakpoint(addr)?;
+        }
+        let thread = self.current;
+        let result = loop {
+            match self.continue_run(&mut |inferior, trap| {
+                (trap == Trap::Breakpoint(addr) && inferior.current == thread)
+                    || should_stop(inferior, trap)
+            }) {
+                Ok(Status::Stopped(signal::Signal::SIGTRAP, rip))
+                    if rip == addr && self.current == thread =>
+                {
+                    match self.get_registers() {
+                        Ok(regs) if regs.rsp as usize <= sp => continue,
+                        Ok(_) => break Ok(Status::Stopped(signal::Signal::SIGTRAP, rip)),
+                        Err(err) => break Err(err),
+                    }
+                }
+                other => break other,
+            }
+        };
+        if temporary {
+            match result {
+                Ok(Status::Stopped(_, _)) => self.remove_breakpoint(addr)?,
+                // put the instruction back if the process is still there, but report what
+                // went wrong with the run
+                Err(_) => {
+                    let _ = self.remove_breakpoint(addr);
+                }
+                Ok(_) => {}
+            }
+        }
+        result
     }
 
+    /// Replaces the instruction byte at `addr` with 0xcc, unless one is already there.
+    pub fn insert_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if !self.breakpoints.contains_key(&addr) {
+            let ori_instr = self.write_byte(addr, 0xcc)?;
+            self.breakpoints.insert(addr, ori_instr);
+        }
+        Ok(())
+    }
+
+    /// Puts back the instruction byte that a breakpoint at `addr` replaced.
+    pub fn remove_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+        if let Some(ori_instr) = self.breakpoints.remove(&addr) {
+            self.write_byte(addr, ori_instr)?;
+        }
+        Ok(())
+    }
+
+    /// Programs the debug registers so that the inferior traps on accesses to the given
+    /// `(addr, len, kind)` locations, one per slot DR0-DR3. Any other slots are cleared.
+    pub fn set_watchpoints(
+        &mut self,
+        watches: &[(usize, usize, WatchKind)],
+    ) -> Result<(), nix::Error> {
+        if watches.len() > DEBUG_REGISTER_SLOTS {
+            return Err(nix::Error::ENOSPC);
+        }
+        let previous = std::mem::replace(&mut self.watches, watches.to_vec());
+        let tids: Vec<Pid> = self.threads.iter().map(|thread| thread.tid).collect();
+        for tid in tids {
+            if let Err(e) = self.program_debug_registers(tid) {
+                self.watches = previous;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Programs the debug registers of thread `tid` with the watchpoints of the inferior.
+    fn program_debug_registers(&self, tid: Pid) -> Result<(), nix::Error> {
+        // Disable everything first so no slot is live with a half-written address
+        poke_debug_register(tid, 7, 0)?;
+        let mut dr7 = 0;
+        for (slot, (addr, len, kind)) in self.watches.iter().enumerate() {
+            let rw = match kind {
+                WatchKind::Write => 0b01,
+                WatchKind::Read | WatchKind::Access => 0b11,
+            };
+            let len_bits = match len {
+                1 => 0b00,
+                2 => 0b01,
+                8 => 0b10,
+                4 => 0b11,
+                _ => return Err(nix::Error::EINVAL),
+            };
+            poke_debug_register(tid, slot, *addr)?;
+            dr7 |= 1 << (2 * slot);
+            dr7 |= (rw | (len_bits << 2)) << (16 + 4 * slot);
+        }
+        poke_debug_register(tid, 7, dr7)
+    }
+
+    /// If the last stop of thread `tid` was caused by a watchpoint, returns its slot and clears
+    /// the status in DR6 (the CPU never clears it on its own).
+    fn take_watchpoint_hit(&mut self, tid: Pid) -> Result<Option<usize>, nix::Error> {
+        let dr6 = peek_debug_register(tid, 6)?;
+        let slot = (0..DEBUG_REGISTER_SLOTS).find(|slot| dr6 & (1 << slot) != 0);
+        if slot.is_some() {
+            poke_debug_register(tid, 6, 0)?;
+        }
+        Ok(slot)
+    }
+
+    /// Writes one byte and returns the one it replaced.
     pub fn write_byte(&mut self, addr: usize, val: u8) -> Result<u8, nix::Error> {
         let aligned_addr = align_addr_to_word(addr);
-        let byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid(), aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((val as u64) << 8 * byte_offset);
-        unsafe {
-            ptrace::write(
-                self.pid(),
-                aligned_addr as ptrace::AddressType,
-                updated_word as *mut std::ffi::c_void,
-            )?;
+        let word = self.read_word(aligned_addr)?;
+        self.poke(addr, &[val])?;
+        Ok((word >> (8 * (addr - aligned_addr))) as u8)
+    }
+
+    /// Writes `bytes` at `addr` for the user. Inserted breakpoints stay in, with the new bytes
+    /// recorded as the instructions they replaced.
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let mut bytes = bytes.to_vec();
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            if let Some(orig_byte) = self.breakpoints.get_mut(&(addr + i)) {
+                *orig_byte = *byte;
+                *byte = 0xcc;
+            }
+        }
+        self.poke(addr, &bytes)
+    }
+
+    /// Writes `bytes` at `addr` a word at a time, reading each word first to keep the bytes
+    /// around them.
+    fn poke(&self, addr: usize, bytes: &[u8]) -> Result<(), nix::Error> {
+        let mut word_addr = align_addr_to_word(addr);
+        while word_addr < addr + bytes.len() {
+            let mut word = self.read_word(word_addr)?.to_le_bytes();
+            for (i, slot) in word.iter_mut().enumerate() {
+                let index = (word_addr + i).checked_sub(addr);
+                if let Some(byte) = index.and_then(|index| bytes.get(index)) {
+                    *slot = *byte;
+                }
+            }
+            unsafe {
+                ptrace::write(
+                    self.current,
+                    word_addr as ptrace::AddressType,
+                    usize::from_le_bytes(word) as *mut std::ffi::c_void,
+                )?;
+            }
+            word_addr += size_of::<usize>();
         }
-        Ok(orig_byte as u8)
+        Ok(())
+    }
+}
+
+impl Target for Inferior {
+    fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    fn threads(&self) -> Vec<(usize, Pid)> {
+        self.threads
+            .iter()
+            .map(|thread| (thread.id, thread.tid))
+            .collect()
+    }
+
+    fn current_thread(&self) -> usize {
+        self.thread(self.current).map_or(0, |thread| thread.id)
+    }
+
+    fn select_thread(&mut self, id: usize) -> bool {
+        match self.threads.iter().find(|thread| thread.id == id) {
+            Some(thread) => {
+                self.current = thread.tid;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn executable_path(&self) -> Result<String, std::io::Error> {
+        let exe = std::fs::read_link(format!("/proc/{}/exe", self.pid))?;
+        Ok(exe.to_string_lossy().into_owned())
+    }
+
+    /// Reads the mappings from /proc/<pid>/maps.
+    fn mapped_objects(&self) -> Result<Vec<(String, usize)>, std::io::Error> {
+        let maps = std::fs::read_to_string(format!("/proc/{}/maps", self.pid))?;
+        let mut objects = Vec::new();
+        for line in maps.lines() {
+            // start-end perms offset dev inode pathname
+            let fields: Vec<&str> = line.splitn(6, ' ').collect();
+            if fields.len() < 6 || usize::from_str_radix(fields[2], 16) != Ok(0) {
+                continue;
+            }
+            let path = fields[5].trim_start();
+            // skip anonymous mappings and pseudo files like [vdso]
+            if !path.starts_with('/') {
+                continue;
+            }
+            let start = fields[0].split('-').next().unwrap();
+            if let Ok(start) = usize::from_str_radix(start, 16) {
+                objects.push((path.to_string(), start));
+            }
+        }
+        Ok(objects)
+    }
+
+    fn get_registers(&self) -> Result<user_regs_struct, nix::Error> {
+        ptrace::getregs(self.current)
+    }
+
+    fn get_fp_registers(&self) -> Result<user_fpregs_struct, nix::Error> {
+        get_fp_registers(self.current)
+    }
+
+    fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+        Ok(ptrace::read(self.current, addr as ptrace::AddressType)? as usize)
+    }
+
+    /// Reads through /proc/<pid>/mem, in one system call rather than one per word, and shows
+    /// the original instructions where breakpoints are inserted.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let errno = |e: std::io::Error| Errno::from_i32(e.raw_os_error().unwrap_or(libc::EIO));
+        let mem = File::open(format!("/proc/{}/mem", self.pid)).map_err(errno)?;
+        let mut bytes = vec![0; len];
+        mem.read_exact_at(&mut bytes, addr as u64).map_err(errno)?;
+        self.hide_breakpoints(addr, &mut bytes);
+        Ok(bytes)
     }
 }
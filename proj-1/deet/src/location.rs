@@ -0,0 +1,330 @@
+//! Works out where variables are from their DWARF location expressions. In optimized code a
+//! variable may live in a register, be put together from pieces in several places, only exist
+//! as a value computed from others, or not exist at all at some points.
+
+use crate::dwarf_data::DwarfExpression;
+use crate::target::Target;
+use crate::unwind::Frame;
+use gimli::{EvaluationResult, Value};
+use std::convert::TryInto;
+
+type Reader<'a> = gimli::EndianSlice<'a, gimli::LittleEndian>;
+
+const ENCODING: gimli::Encoding = gimli::Encoding {
+    address_size: 8,
+    format: gimli::Format::Dwarf32,
+    version: 5,
+};
+
+/// DWARF numbers of the SSE registers, which is where floating point values are kept.
+const XMM0: u16 = 17;
+const XMM15: u16 = 32;
+
+/// Where a variable is while some frame runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Storage {
+    /// In memory, at this address
+    Memory(usize),
+    /// Not in memory, like in a register or computed from other values; these are its bytes
+    Value(Vec<u8>),
+    /// Nowhere: the compiler has done away with it at this point of the program
+    OptimizedOut,
+}
+
+impl Storage {
+    /// Reads the `size` bytes of a value stored here. Returns None if it is optimized out.
+    pub fn read<T: Target + ?Sized>(
+        &self,
+        target: &T,
+        size: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            Storage::Memory(addr) => target
+                .read_memory(*addr, size)
+                .map(Some)
+                .map_err(|_| format!("Cannot access memory at address {:#x}", addr)),
+            Storage::Value(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.resize(size, 0);
+                Ok(Some(bytes))
+            }
+            Storage::OptimizedOut => Ok(None),
+        }
+    }
+}
+
+/// Returns the value of register `register` in `frame`, as many bytes as the register has.
+/// The SSE registers are only known in the innermost frame, since calls don't preserve them.
+fn register_bytes<T: Target + ?Sized>(register: u16, frame: &Frame, target: &T) -> Option<Vec<u8>> {
+    if (XMM0..=XMM15).contains(&register) {
+        let regs = target.get_registers().ok()?;
+        if frame.pc() as u64 != regs.rip || frame.rsp() != Some(regs.rsp as usize) {
+            return None;
+        }
+        let index = (register - XMM0) as usize * 4;
+        let fp_regs = target.get_fp_registers().ok()?;
+        let words = &fp_regs.xmm_space[index..index + 4];
+        return Some(words.iter().flat_map(|word| word.to_le_bytes()).collect());
+    }
+    Some(frame.register(register)?.to_le_bytes().to_vec())
+}
+
+/// Returns the bytes of a value the expression computed.
+fn value_bytes(value: Value) -> Vec<u8> {
+    match value {
+        Value::Generic(value) | Value::U64(value) => value.to_le_bytes().to_vec(),
+        Value::I8(value) => value.to_le_bytes().to_vec(),
+        Value::U8(value) => value.to_le_bytes().to_vec(),
+        Value::I16(value) => value.to_le_bytes().to_vec(),
+        Value::U16(value) => value.to_le_bytes().to_vec(),
+        Value::I32(value) => value.to_le_bytes().to_vec(),
+        Value::U32(value) => value.to_le_bytes().to_vec(),
+        Value::I64(value) => value.to_le_bytes().to_vec(),
+        Value::F32(value) => value.to_le_bytes().to_vec(),
+        Value::F64(value) => value.to_le_bytes().to_vec(),
+    }
+}
+
+/// Evaluates a location expression against the registers and memory of `frame`. `frame_base`
+/// works out the frame base of the function, for expressions that use DW_OP_fbreg.
+pub fn evaluate<T: Target + ?Sized>(
+    expression: &DwarfExpression,
+    frame: &Frame,
+    frame_base: &dyn Fn() -> Result<u64, String>,
+    target: &T,
+) -> Result<Storage, String> {
+    // an empty expression is how DWARF says a variable doesn't exist here
+    if expression.bytes.is_empty() {
+        return Ok(Storage::OptimizedOut);
+    }
+    let bytes = Reader::new(&expression.bytes, gimli::LittleEndian);
+    let mut evaluation = gimli::Evaluation::new(bytes, ENCODING);
+    let unsupported = |what: &str| Err(format!("Unhandled dwarf expression: {}", what));
+    let mut result = evaluation.evaluate();
+    loop {
+        result = match result.map_err(|e| format!("Invalid dwarf expression: {}", e))? {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresMemory {
+                address,
+                size,
+                base_type,
+                ..
+            } => {
+                if base_type.0 != 0 {
+                    return unsupported("typed memory read");
+                }
+                let bytes = target
+                    .read_memory(address as usize, size as usize)
+                    .map_err(|_| format!("Cannot access memory at address {:#x}", address))?;
+                let mut word = [0u8; 8];
+                word[..bytes.len().min(8)].copy_from_slice(&bytes[..bytes.len().min(8)]);
+                evaluation.resume_with_memory(Value::Generic(u64::from_le_bytes(word)))
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                // a register the unwinder couldn't recover makes the variable as good as gone
+                let value = match frame.register(register.0) {
+                    Some(value) => value,
+                    None => return Ok(Storage::OptimizedOut),
+                };
+                evaluation.resume_with_register(Value::Generic(value))
+            }
+            EvaluationResult::RequiresFrameBase => evaluation.resume_with_frame_base(frame_base()?),
+            EvaluationResult::RequiresCallFrameCfa => {
+                let cfa = frame.cfa.ok_or("Cannot work out the frame's CFA")?;
+                evaluation.resume_with_call_frame_cfa(cfa as u64)
+            }
+            EvaluationResult::RequiresRelocatedAddress(address) => evaluation
+                .resume_with_relocated_address(address.wrapping_add(expression.load_bias as u64)),
+            // what a parameter was when the function was entered is long gone, unless we were
+            // to work it out from the caller
+            EvaluationResult::RequiresEntryValue(_) => return Ok(Storage::OptimizedOut),
+            EvaluationResult::RequiresTls(_) => return unsupported("thread-local storage"),
+            EvaluationResult::RequiresIndexedAddress { .. } => return unsupported("DW_OP_addrx"),
+            EvaluationResult::RequiresBaseType(_) => return unsupported("typed values"),
+            EvaluationResult::RequiresAtLocation(_) | EvaluationResult::RequiresParameterRef(_) => {
+                return unsupported("references to other entries")
+            }
+        };
+    }
+
+    let pieces = evaluation.result();
+    let whole = pieces.len() == 1 && pieces[0].size_in_bits.is_none();
+    let mut value = Vec::new();
+    for piece in pieces {
+        let size = piece.size_in_bits.map(|bits| bits as usize / 8);
+        if piece.size_in_bits.is_some_and(|bits| bits % 8 != 0) || piece.bit_offset.is_some() {
+            return unsupported("pieces of bytes");
+        }
+        let bytes = match piece.location {
+            gimli::Location::Empty => return Ok(Storage::OptimizedOut),
+            gimli::Location::Address { address } if whole => {
+                return Ok(Storage::Memory(address as usize))
+            }
+            gimli::Location::Address { address } => target
+                .read_memory(address as usize, size.unwrap_or(0))
+                .map_err(|_| format!("Cannot access memory at address {:#x}", address))?,
+            gimli::Location::Register { register } => {
+                match register_bytes(register.0, frame, target) {
+                    Some(bytes) => bytes,
+                    None => return Ok(Storage::OptimizedOut),
+                }
+            }
+            gimli::Location::Value { value } => value_bytes(value),
+            gimli::Location::Bytes { value } => value.to_vec(),
+            gimli::Location::ImplicitPointer { .. } => return unsupported("implicit pointers"),
+        };
+        let mut bytes = bytes;
+        if let Some(size) = size {
+            bytes.resize(size, 0);
+        }
+        value.extend_from_slice(&bytes);
+    }
+    Ok(Storage::Value(value))
+}
+
+/// Works out the frame base a DW_AT_frame_base expression describes. A register location means
+/// the frame base is what the register holds.
+pub fn frame_base<T: Target + ?Sized>(
+    expression: &DwarfExpression,
+    frame: &Frame,
+    target: &T,
+) -> Result<u64, String> {
+    let no_frame_base = || Err("Cannot work out the frame base".to_string());
+    match evaluate(expression, frame, &no_frame_base, target)? {
+        Storage::Memory(addr) => Ok(addr as u64),
+        Storage::Value(bytes) if bytes.len() >= 8 => {
+            Ok(u64::from_le_bytes(bytes[..8].try_into().unwrap()))
+        }
+        _ => no_frame_base(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf_data::DwarfData;
+    use crate::modules::Modules;
+    use crate::unwind;
+    use libc::{user_fpregs_struct, user_regs_struct};
+    use nix::unistd::Pid;
+
+    const STACK: usize = 0x7ffc0000;
+    const RDI: u64 = 0x1122334455667788;
+
+    /// A program stopped in a 32 byte stack frame, with its frame pointer at the top of it and
+    /// 0xcafef00d in the 8 bytes below that.
+    struct Stopped {
+        stack: Vec<u8>,
+    }
+
+    impl Target for Stopped {
+        fn pid(&self) -> Pid {
+            Pid::from_raw(0)
+        }
+
+        fn threads(&self) -> Vec<(usize, Pid)> {
+            Vec::new()
+        }
+
+        fn current_thread(&self) -> usize {
+            1
+        }
+
+        fn select_thread(&mut self, _id: usize) -> bool {
+            false
+        }
+
+        fn executable_path(&self) -> Result<String, std::io::Error> {
+            Ok(String::new())
+        }
+
+        fn mapped_objects(&self) -> Result<Vec<(String, usize)>, std::io::Error> {
+            Ok(Vec::new())
+        }
+
+        fn get_registers(&self) -> Result<user_regs_struct, nix::Error> {
+            let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+            regs.rsp = STACK as u64;
+            regs.rbp = STACK as u64 + 0x20;
+            regs.rdi = RDI;
+            Ok(regs)
+        }
+
+        fn get_fp_registers(&self) -> Result<user_fpregs_struct, nix::Error> {
+            Ok(unsafe { std::mem::zeroed() })
+        }
+
+        fn read_word(&self, addr: usize) -> Result<usize, nix::Error> {
+            let offset = addr.checked_sub(STACK).ok_or(nix::Error::EIO)?;
+            let bytes = self.stack.get(offset..offset + 8).ok_or(nix::Error::EIO)?;
+            Ok(usize::from_le_bytes(bytes.try_into().unwrap()))
+        }
+    }
+
+    /// Evaluates the expression `bytes` in the innermost frame of `Stopped`, whose frame base is
+    /// its frame pointer.
+    fn evaluate_bytes(bytes: &[u8]) -> Result<Storage, String> {
+        let path = std::fs::canonicalize("/bin/sleep").unwrap();
+        let path = path.to_str().unwrap();
+        let debug_data = Modules::new(path, DwarfData::from_file(path).unwrap());
+        let mut stack = vec![0; 0x30];
+        stack[0x18..0x20].copy_from_slice(&0xcafef00du64.to_le_bytes());
+        let target = Stopped { stack };
+        let frame = unwind::innermost_frame(&target, &debug_data).unwrap();
+        let frame_base = || {
+            let rbp = DwarfExpression {
+                bytes: vec![gimli::constants::DW_OP_reg6.0],
+                load_bias: 0,
+            };
+            super::frame_base(&rbp, &frame, &target)
+        };
+        let expression = DwarfExpression {
+            bytes: bytes.to_vec(),
+            load_bias: 0,
+        };
+        evaluate(&expression, &frame, &frame_base, &target)
+    }
+
+    #[test]
+    fn frame_base_offsets_are_in_memory() {
+        // DW_OP_fbreg -8
+        assert_eq!(
+            evaluate_bytes(&[0x91, 0x78]),
+            Ok(Storage::Memory(STACK + 0x18))
+        );
+    }
+
+    #[test]
+    fn registers_are_values() {
+        // DW_OP_reg5 (rdi)
+        assert_eq!(
+            evaluate_bytes(&[0x55]),
+            Ok(Storage::Value(RDI.to_le_bytes().to_vec()))
+        );
+        // DW_OP_breg5 1; DW_OP_stack_value
+        assert_eq!(
+            evaluate_bytes(&[0x75, 0x01, 0x9f]),
+            Ok(Storage::Value((RDI + 1).to_le_bytes().to_vec()))
+        );
+    }
+
+    #[test]
+    fn pieces_are_put_together() {
+        // DW_OP_reg5 DW_OP_piece 4; DW_OP_fbreg -8 DW_OP_piece 2; DW_OP_lit7 DW_OP_stack_value
+        // DW_OP_piece 1
+        assert_eq!(
+            evaluate_bytes(&[0x55, 0x93, 0x04, 0x91, 0x78, 0x93, 0x02, 0x37, 0x9f, 0x93, 0x01]),
+            Ok(Storage::Value(vec![0x88, 0x77, 0x66, 0x55, 0x0d, 0xf0, 7]))
+        );
+    }
+
+    #[test]
+    fn empty_locations_are_optimized_out() {
+        assert_eq!(evaluate_bytes(&[]), Ok(Storage::OptimizedOut));
+        // DW_OP_reg5 DW_OP_piece 4; DW_OP_piece 4
+        assert_eq!(
+            evaluate_bytes(&[0x55, 0x93, 0x04, 0x93, 0x04]),
+            Ok(Storage::OptimizedOut)
+        );
+    }
+}
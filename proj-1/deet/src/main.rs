@@ -1,25 +1,56 @@
+mod breakpoint;
+mod call;
+mod core_file;
 mod debugger;
 mod debugger_command;
-mod inferior;
+mod disassembler;
 mod dwarf_data;
+mod examine;
+mod expression;
 mod gimli_wrapper;
+mod inferior;
+mod location;
+mod modules;
+mod registers;
+mod signals;
+mod source;
+mod target;
+mod unwind;
 
 use crate::debugger::Debugger;
 use nix::sys::signal::{signal, SigHandler, Signal};
 use std::env;
 
 fn main() {
-    let mut args: Vec<String> = env::args().collect();
-    args.push("samples/segfault".to_string());
-    if args.len() != 2 {
-        println!("Usage: {} <target program>", args[0]);
-        std::process::exit(1);
-    }
-    let target = &args[1];
+    let args: Vec<String> = env::args().collect();
+    let (pid, core) = match args.len() {
+        2 => (None, None),
+        3 if args[1] == "--pid" => (Some(&args[2]), None),
+        4 if args[2] == "--core" => (None, Some(&args[3])),
+        _ => {
+            println!(
+                "Usage: {} <target program> [--core <corefile>] | --pid <pid>",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+    };
+    // An attached process's symbols come from its executable
+    let target = match pid {
+        Some(pid) => format!("/proc/{}/exe", pid),
+        None => args[1].clone(),
+    };
 
     // Disable handling of ctrl+c in this process (so that ctrl+c only gets delivered to child
     // processes)
     unsafe { signal(Signal::SIGINT, SigHandler::SigIgn) }.expect("Error disabling SIGINT handling");
 
-    Debugger::new(target).run();
+    let mut debugger = Debugger::new(&target);
+    if let Some(pid) = pid {
+        debugger.attach(pid);
+    }
+    if let Some(core) = core {
+        debugger.load_core(core);
+    }
+    debugger.run();
 }
@@ -0,0 +1,282 @@
+use crate::dwarf_data::{DwarfData, Function, Line, Type, Variable};
+use crate::unwind::UnwindRow;
+use std::ops::Range;
+use std::path::Path;
+
+/// An object file mapped into the inferior: the executable or a shared library.
+pub struct Module {
+    pub path: String,
+    /// Where the object is mapped in the inferior
+    pub base: usize,
+    pub debug_data: DwarfData,
+}
+
+impl Module {
+    /// Returns whether `name` refers to this module, either by its full path or by its file
+    /// name, with or without a version suffix (`libfoo.so` matches `libfoo.so.1`).
+    pub fn is_named(&self, name: &str) -> bool {
+        let file_name = Path::new(&self.path)
+            .file_name()
+            .map_or(self.path.as_str(), |name| name.to_str().unwrap_or_default());
+        self.path == name
+            || file_name == name
+            || file_name
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with('.'))
+    }
+}
+
+/// The symbols of every object loaded into the inferior. The executable always comes first,
+/// followed by the shared libraries in the order they were found.
+pub struct Modules {
+    modules: Vec<Module>,
+}
+
+impl Modules {
+    pub fn new(path: &str, debug_data: DwarfData) -> Modules {
+        Modules {
+            modules: vec![Module {
+                path: path.to_string(),
+                base: 0,
+                debug_data,
+            }],
+        }
+    }
+
+    pub fn executable(&self) -> &DwarfData {
+        &self.modules[0].debug_data
+    }
+
+    pub fn executable_mut(&mut self) -> &mut DwarfData {
+        &mut self.modules[0].debug_data
+    }
+
+    /// Returns the shared libraries currently known.
+    pub fn libraries(&self) -> &[Module] {
+        &self.modules[1..]
+    }
+
+    /// Adds a shared library mapped at `base`, relocating its symbols to that address.
+    pub fn add_library(&mut self, path: String, base: usize, mut debug_data: DwarfData) {
+        debug_data.set_load_bias(base.wrapping_sub(debug_data.link_base()));
+        self.modules.push(Module {
+            path,
+            base,
+            debug_data,
+        });
+    }
+
+    /// Drops the shared libraries for which `keep` returns false, returning them.
+    pub fn remove_libraries(&mut self, mut keep: impl FnMut(&Module) -> bool) -> Vec<Module> {
+        let mut removed = Vec::new();
+        let mut i = 1;
+        while i < self.modules.len() {
+            if keep(&self.modules[i]) {
+                i += 1;
+            } else {
+                removed.push(self.modules.remove(i));
+            }
+        }
+        removed
+    }
+
+    /// Finds the module `name` refers to (see [`Module::is_named`]).
+    pub fn find(&self, name: &str) -> Option<&Module> {
+        self.modules.iter().find(|module| module.is_named(name))
+    }
+
+    /// Returns the module whose image contains `addr`.
+    fn module_at(&self, addr: usize) -> Option<&DwarfData> {
+        self.modules
+            .iter()
+            .map(|module| &module.debug_data)
+            .find(|debug_data| debug_data.image().contains(&addr))
+    }
+
+    /// Finds the source file `file` names in any module, the executable first.
+    pub fn get_source_path(&self, file: &str) -> Option<String> {
+        self.modules
+            .iter()
+            .find_map(|module| module.debug_data.get_source_path(file))
+    }
+
+    /// Finds the first line at or after `line_number` of `file` with code in any module, and
+    /// every address a breakpoint on it goes at.
+    pub fn get_addrs_for_line(
+        &self,
+        file: &str,
+        line_number: usize,
+    ) -> Option<(usize, Vec<usize>)> {
+        let found: Vec<(usize, Vec<usize>)> = self
+            .modules
+            .iter()
+            .filter_map(|module| module.debug_data.get_addrs_for_line(file, line_number))
+            .collect();
+        let number = found.iter().map(|(number, _)| *number).min()?;
+        let addrs = found
+            .into_iter()
+            .filter(|(found_number, _)| *found_number == number)
+            .flat_map(|(_, addrs)| addrs)
+            .collect();
+        Some((number, addrs))
+    }
+
+    /// Looks up a function in every module, the executable first.
+    pub fn get_addr_for_function(&self, file: Option<&str>, func_name: &str) -> Option<usize> {
+        self.modules
+            .iter()
+            .find_map(|module| module.debug_data.get_addr_for_function(file, func_name))
+    }
+
+    pub fn get_indirect_function(&self, func_name: &str) -> Option<usize> {
+        self.modules
+            .iter()
+            .find_map(|module| module.debug_data.get_indirect_function(func_name))
+    }
+
+    pub fn get_function_type(&self, func_name: &str) -> Option<Type> {
+        self.modules
+            .iter()
+            .find_map(|module| module.debug_data.get_function_type(func_name))
+    }
+
+    /// Looks up a type by name in every module, the executable first.
+    pub fn get_type(&self, name: &str) -> Option<Type> {
+        self.modules
+            .iter()
+            .find_map(|module| module.debug_data.get_type(name))
+    }
+
+    pub fn get_enumerator(&self, name: &str) -> Option<(Type, i64)> {
+        self.modules
+            .iter()
+            .find_map(|module| module.debug_data.get_enumerator(name))
+    }
+
+    pub fn get_function_containing(&self, addr: usize) -> Option<&Function> {
+        self.module_at(addr)?.get_function_containing(addr)
+    }
+
+    pub fn get_function_range(&self, addr: usize) -> Option<(String, Range<usize>)> {
+        self.module_at(addr)?.get_function_range(addr)
+    }
+
+    /// Looks up a variable visible at `addr` in the function `inlined` inlined calls deep: a
+    /// local or a global of the module containing `addr`, or else a global of the executable.
+    pub fn get_variable(&self, addr: usize, inlined: usize, name: &str) -> Option<&Variable> {
+        self.module_at(addr)
+            .and_then(|debug_data| debug_data.get_variable(addr, inlined, name))
+            .or_else(|| self.executable().get_variable(addr, inlined, name))
+    }
+
+    pub fn get_scope_variables(&self, addr: usize, inlined: usize) -> Vec<&Variable> {
+        match self.module_at(addr) {
+            Some(debug_data) => debug_data.get_scope_variables(addr, inlined),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_inlined_frames(&self, addr: usize) -> Vec<(Option<String>, Option<Line>)> {
+        match self.module_at(addr) {
+            Some(debug_data) => debug_data.get_inlined_frames(addr),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_prologue_end(&self, func: &Function) -> usize {
+        match self.module_at(func.address) {
+            Some(debug_data) => debug_data.get_prologue_end(func),
+            None => func.address,
+        }
+    }
+
+    pub fn get_line_from_addr(&self, curr_addr: usize) -> Option<Line> {
+        self.module_at(curr_addr)?.get_line_from_addr(curr_addr)
+    }
+
+    pub fn get_function_from_addr(&self, curr_addr: usize) -> Option<String> {
+        self.module_at(curr_addr)?.get_function_from_addr(curr_addr)
+    }
+
+    pub fn unwind_row(&self, addr: usize) -> Option<UnwindRow> {
+        self.module_at(addr)?.unwind_row(addr)
+    }
+
+    pub fn print(&self) {
+        self.executable().print();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(path: &str) -> DwarfData {
+        DwarfData::from_file(path).unwrap()
+    }
+
+    /// Returns where a breakpoint on the function `name` goes, past its prologue.
+    fn after_prologue(modules: &Modules, name: &str) -> usize {
+        let addr = modules.get_addr_for_function(None, name).unwrap();
+        modules.get_prologue_end(modules.get_function_containing(addr).unwrap())
+    }
+
+    #[test]
+    fn lines_without_code_move_to_the_next_one() {
+        let modules = Modules::new("samples/segfault", load("samples/segfault"));
+        let func2 = after_prologue(&modules, "func2");
+        // blank lines and the #include before func2 have no code
+        assert_eq!(
+            modules.get_addrs_for_line("segfault.c", 1),
+            Some((3, vec![func2]))
+        );
+        assert_eq!(
+            modules.get_addrs_for_line("segfault.c", 2),
+            Some((3, vec![func2]))
+        );
+        let (number, addrs) = modules.get_addrs_for_line("segfault.c", 4).unwrap();
+        assert_eq!(number, 4);
+        assert_eq!(modules.get_line_from_addr(addrs[0]).unwrap().number, 4);
+        assert_eq!(modules.get_addrs_for_line("segfault.c", 17), None);
+        assert_eq!(modules.get_addrs_for_line("count.c", 1), None);
+    }
+
+    #[test]
+    fn lines_are_looked_up_in_every_module() {
+        let mut modules = Modules::new("samples/segfault", load("samples/segfault"));
+        modules.add_library(
+            "samples/function_calls".to_string(),
+            0x7f0000000000,
+            load("samples/function_calls"),
+        );
+        let func1 = after_prologue(&modules, "func1");
+        assert_eq!(
+            modules.get_addrs_for_line("segfault.c", 8),
+            Some((9, vec![func1]))
+        );
+        let (number, addrs) = modules.get_addrs_for_line("function_calls.c", 8).unwrap();
+        assert_eq!(number, 9);
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(
+            modules.get_function_from_addr(addrs[0]).as_deref(),
+            Some("func2")
+        );
+        assert!(addrs[0] >= 0x7f0000000000);
+    }
+
+    #[test]
+    fn addresses_from_every_module_with_the_line_are_merged() {
+        let mut modules = Modules::new("samples/segfault", load("samples/segfault"));
+        modules.add_library(
+            "samples/segfault".to_string(),
+            0x7f0000000000,
+            load("samples/segfault"),
+        );
+        let (_, addrs) = modules.get_addrs_for_line("segfault.c", 11).unwrap();
+        let bias = modules.libraries()[0].debug_data.load_bias();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs[1], addrs[0].wrapping_add(bias));
+        let (number, addrs) = modules.get_addrs_for_line("segfault.c", 13).unwrap();
+        assert_eq!((number, addrs.len()), (14, 2));
+    }
+}
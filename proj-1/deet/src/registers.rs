@@ -0,0 +1,245 @@
+use crate::examine::symbolize;
+use crate::modules::Modules;
+use crate::unwind::Frame;
+use libc::{user_fpregs_struct, user_regs_struct};
+use std::convert::TryInto;
+use std::fmt;
+
+/// A general purpose register, as the user names it after a `$`.
+#[derive(Debug)]
+pub struct Register {
+    pub name: &'static str,
+    /// Its DWARF number, for the registers the unwinder recovers in outer frames
+    dwarf: Option<u16>,
+    get: fn(&user_regs_struct) -> u64,
+    set: fn(&mut user_regs_struct, u64),
+}
+
+macro_rules! register {
+    ($name:ident, $dwarf:expr) => {
+        Register {
+            name: stringify!($name),
+            dwarf: $dwarf,
+            get: |regs| regs.$name,
+            set: |regs, value| regs.$name = value,
+        }
+    };
+}
+
+/// The general purpose registers in the order `info registers` shows them.
+pub const REGISTERS: [Register; 26] = [
+    register!(rax, Some(0)),
+    register!(rbx, Some(3)),
+    register!(rcx, Some(2)),
+    register!(rdx, Some(1)),
+    register!(rsi, Some(4)),
+    register!(rdi, Some(5)),
+    register!(rbp, Some(6)),
+    register!(rsp, Some(7)),
+    register!(r8, Some(8)),
+    register!(r9, Some(9)),
+    register!(r10, Some(10)),
+    register!(r11, Some(11)),
+    register!(r12, Some(12)),
+    register!(r13, Some(13)),
+    register!(r14, Some(14)),
+    register!(r15, Some(15)),
+    register!(rip, Some(16)),
+    register!(eflags, None),
+    register!(cs, None),
+    register!(ss, None),
+    register!(ds, None),
+    register!(es, None),
+    register!(fs, None),
+    register!(gs, None),
+    register!(fs_base, None),
+    register!(gs_base, None),
+];
+
+/// Names of the flags in eflags and mxcsr, by bit.
+const EFLAGS: [(u32, &str); 13] = [
+    (0, "CF"),
+    (2, "PF"),
+    (4, "AF"),
+    (6, "ZF"),
+    (7, "SF"),
+    (8, "TF"),
+    (9, "IF"),
+    (10, "DF"),
+    (11, "OF"),
+    (14, "NT"),
+    (16, "RF"),
+    (18, "AC"),
+    (21, "ID"),
+];
+const MXCSR: [(u32, &str); 14] = [
+    (0, "IE"),
+    (1, "DE"),
+    (2, "ZE"),
+    (3, "OE"),
+    (4, "UE"),
+    (5, "PE"),
+    (6, "DAZ"),
+    (7, "IM"),
+    (8, "DM"),
+    (9, "ZM"),
+    (10, "OM"),
+    (11, "UM"),
+    (12, "PM"),
+    (15, "FZ"),
+];
+
+/// Looks up a general purpose register by name, taking gdb's $pc, $sp and $fp as well.
+pub fn find(name: &str) -> Option<&'static Register> {
+    let name = match name {
+        "pc" => "rip",
+        "sp" => "rsp",
+        "fp" => "rbp",
+        name => name,
+    };
+    REGISTERS.iter().find(|register| register.name == name)
+}
+
+impl Register {
+    /// Returns the value of the register in `frame`, given the registers of the innermost one.
+    /// Outer frames only know the registers the unwinder recovered; None means it wasn't saved.
+    pub fn value(&self, regs: &user_regs_struct, frame: &Frame, innermost: bool) -> Option<u64> {
+        match (innermost, self.dwarf) {
+            (false, Some(dwarf)) => frame.register(dwarf),
+            _ => Some((self.get)(regs)),
+        }
+    }
+
+    pub fn set(&self, regs: &mut user_regs_struct, value: u64) {
+        (self.set)(regs, value)
+    }
+
+    /// Renders a value of the register in its natural form, like gdb's second column.
+    pub fn natural(&self, value: u64, debug_data: &Modules) -> String {
+        match self.name {
+            "rip" => match symbolize(debug_data, value as usize) {
+                Some(symbol) => format!("{:#x} {}", value, symbol),
+                None => format!("{:#x}", value),
+            },
+            "rsp" | "rbp" => format!("{:#x}", value),
+            "eflags" => flags(value as u32, &EFLAGS),
+            _ => (value as i64).to_string(),
+        }
+    }
+
+    /// Renders a value of the register the way `print $reg` does.
+    pub fn print_value(&self, value: u64, debug_data: &Modules) -> String {
+        match self.name {
+            "rip" => format!("(void (*)()) {}", self.natural(value, debug_data)),
+            "rsp" | "rbp" => format!("(void *) {:#x}", value),
+            _ => self.natural(value, debug_data),
+        }
+    }
+}
+
+/// Lines up a value in hex and in its natural form the way `info registers` does.
+pub fn columns(value: u64, natural: String) -> String {
+    format!("{:<19}{}", format!("{:#x}", value), natural)
+}
+
+/// Lists the set flags of a flags register, like `[ PF ZF IF ]`.
+fn flags(value: u32, names: &[(u32, &str)]) -> String {
+    let set: Vec<&str> = names
+        .iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    format!("[ {} ]", set.join(" "))
+}
+
+/// Converts an x87 80-bit extended precision value to the nearest double.
+fn extended_to_f64(bytes: &[u8]) -> f64 {
+    let mantissa = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let sign_exponent = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let sign = if sign_exponent & 0x8000 != 0 {
+        -1.0
+    } else {
+        1.0
+    };
+    let exponent = (sign_exponent & 0x7fff) as i32;
+    let magnitude = match exponent {
+        0 if mantissa == 0 => 0.0,
+        0x7fff if mantissa << 1 == 0 => f64::INFINITY,
+        0x7fff => f64::NAN,
+        // the integer bit is explicit, so the mantissa is a 64-bit fixed point number
+        _ => mantissa as f64 * 2f64.powi(exponent - 16383 - 63),
+    };
+    sign * magnitude
+}
+
+/// Writes a float compactly, switching to scientific notation for the garbage that unused
+/// vector lanes usually hold, like printf's %g.
+fn float<T: Copy + Into<f64> + fmt::Display + fmt::LowerExp>(value: T) -> String {
+    let magnitude = value.into().abs();
+    if magnitude == 0.0 || !magnitude.is_finite() || (1e-4..1e16).contains(&magnitude) {
+        value.to_string()
+    } else {
+        format!("{:e}", value)
+    }
+}
+
+/// Renders an SSE register as the vectors gdb shows.
+fn xmm(bytes: &[u8]) -> String {
+    let floats: Vec<String> = bytes
+        .chunks(4)
+        .map(|c| float(f32::from_le_bytes(c.try_into().unwrap())))
+        .collect();
+    let doubles: Vec<String> = bytes
+        .chunks(8)
+        .map(|c| float(f64::from_le_bytes(c.try_into().unwrap())))
+        .collect();
+    let ints: Vec<String> = bytes
+        .chunks(4)
+        .map(|c| format!("{:#x}", u32::from_le_bytes(c.try_into().unwrap())))
+        .collect();
+    format!(
+        "{{v4_float = {{{}}}, v2_double = {{{}}}, v4_int32 = {{{}}}, uint128 = {:#x}}}",
+        floats.join(", "),
+        doubles.join(", "),
+        ints.join(", "),
+        u128::from_le_bytes(bytes.try_into().unwrap())
+    )
+}
+
+/// Returns the names and renderings of the floating point and SSE registers.
+pub fn fp_registers(fpregs: &user_fpregs_struct) -> Vec<(String, String)> {
+    let st_space: Vec<u8> = fpregs
+        .st_space
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    let xmm_space: Vec<u8> = fpregs
+        .xmm_space
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    let mut registers = Vec::new();
+    for (i, st) in st_space.chunks(16).enumerate() {
+        let raw: String = st[..10]
+            .iter()
+            .rev()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let value = format!("{} (raw 0x{})", float(extended_to_f64(st)), raw);
+        registers.push((format!("st{}", i), value));
+    }
+    for (name, value) in [
+        ("fctrl", fpregs.cwd as u32),
+        ("fstat", fpregs.swd as u32),
+        ("ftag", fpregs.ftw as u32),
+        ("fop", fpregs.fop as u32),
+    ] {
+        registers.push((name.to_string(), columns(value as u64, value.to_string())));
+    }
+    let mxcsr = columns(fpregs.mxcsr as u64, flags(fpregs.mxcsr, &MXCSR));
+    registers.push(("mxcsr".to_string(), mxcsr));
+    for (i, register) in xmm_space.chunks(16).enumerate() {
+        registers.push((format!("xmm{}", i), xmm(register)));
+    }
+    registers
+}
@@ -0,0 +1,118 @@
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::CStr;
+
+/// How the debugger reacts when the inferior receives a signal, as set with `handle`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SignalPolicy {
+    /// Whether to stop the inferior and hand control back to the user
+    pub stop: bool,
+    /// Whether to say that the signal arrived
+    pub print: bool,
+    /// Whether to deliver the signal to the inferior when it resumes
+    pub pass: bool,
+}
+
+impl SignalPolicy {
+    /// Applies one of the `handle` keywords. Like gdb, stopping implies printing and not
+    /// printing implies not stopping. Returns false for an unknown keyword.
+    pub fn apply(&mut self, keyword: &str) -> bool {
+        match keyword {
+            "stop" => {
+                self.stop = true;
+                self.print = true;
+            }
+            "nostop" => self.stop = false,
+            "print" => self.print = true,
+            "noprint" => {
+                self.print = false;
+                self.stop = false;
+            }
+            "pass" | "noignore" => self.pass = true,
+            "nopass" | "ignore" => self.pass = false,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The policy for every signal. The defaults are gdb's: signals that programs use routinely
+/// for timers and child processes go straight through, and the ones the debugger itself uses
+/// aren't passed on.
+#[derive(Debug, Clone)]
+pub struct SignalTable {
+    policies: HashMap<Signal, SignalPolicy>,
+}
+
+impl Default for SignalTable {
+    fn default() -> SignalTable {
+        let mut policies = HashMap::new();
+        for signal in Signal::iterator() {
+            let quiet = matches!(
+                signal,
+                Signal::SIGALRM
+                    | Signal::SIGURG
+                    | Signal::SIGCHLD
+                    | Signal::SIGWINCH
+                    | Signal::SIGIO
+                    | Signal::SIGVTALRM
+                    | Signal::SIGPROF
+            );
+            let policy = SignalPolicy {
+                stop: !quiet,
+                print: !quiet,
+                pass: !matches!(signal, Signal::SIGINT | Signal::SIGTRAP),
+            };
+            policies.insert(signal, policy);
+        }
+        SignalTable { policies }
+    }
+}
+
+impl SignalTable {
+    pub fn get(&self, signal: Signal) -> SignalPolicy {
+        self.policies[&signal]
+    }
+
+    pub fn set(&mut self, signal: Signal, policy: SignalPolicy) {
+        self.policies.insert(signal, policy);
+    }
+
+    /// Prints the policy of each of `signals` in the layout of `info signals`.
+    pub fn print(&self, signals: &[Signal]) {
+        let yes_no = |flag: bool| if flag { "Yes" } else { "No" };
+        println!("Signal        Stop\tPrint\tPass to program\tDescription");
+        for signal in signals {
+            let policy = self.get(*signal);
+            println!(
+                "{:<14}{}\t{}\t{}\t\t{}",
+                signal.as_str(),
+                yes_no(policy.stop),
+                yes_no(policy.print),
+                yes_no(policy.pass),
+                describe(*signal)
+            );
+        }
+    }
+}
+
+/// Parses a signal given by name, with or without the SIG prefix, or by number.
+pub fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::try_from(number).ok();
+    }
+    let name = name.to_uppercase();
+    match name.strip_prefix("SIG") {
+        Some(_) => name.parse().ok(),
+        None => format!("SIG{}", name).parse().ok(),
+    }
+}
+
+/// Returns the usual description of `signal`, e.g. "Segmentation fault".
+pub fn describe(signal: Signal) -> String {
+    // strsignal returns a pointer to a static string for the signals nix knows about
+    unsafe { CStr::from_ptr(libc::strsignal(signal as i32)) }
+        .to_string_lossy()
+        .into_owned()
+}
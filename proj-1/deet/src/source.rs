@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// How many lines `list` shows at a time.
+const LIST_SIZE: usize = 10;
+
+/// The lines `list` showed last, which the next `list` or `list -` carries on from.
+#[derive(Debug, Clone)]
+struct Listing {
+    file: String,
+    first: usize,
+    end: usize,
+}
+
+/// Finds and caches source files, and keeps track of where `list` is.
+pub struct SourceFiles {
+    /// The directory of the executable, where relative file names are looked up when they can't
+    /// be found relative to the working directory
+    directory: PathBuf,
+    /// The rules of `set substitute-path`, in the order they were given
+    substitutions: Vec<(String, String)>,
+    /// The lines of each file read so far, or None if it couldn't be read
+    loaded: HashMap<String, Option<Vec<String>>>,
+    listing: Option<Listing>,
+}
+
+/// Replaces the leading directories `from` of `path` with `to`. Like gdb, `from` only matches
+/// whole path components.
+fn rewrite(path: &str, from: &str, to: &str) -> Option<String> {
+    let from = from.trim_end_matches('/');
+    let rest = path.strip_prefix(from)?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    Some(format!("{}{}", to.trim_end_matches('/'), rest))
+}
+
+impl SourceFiles {
+    pub fn new(executable: &str) -> SourceFiles {
+        let directory = Path::new(executable)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        SourceFiles {
+            directory,
+            substitutions: Vec::new(),
+            loaded: HashMap::new(),
+            listing: None,
+        }
+    }
+
+    /// Implements `set substitute-path FROM TO`, replacing any earlier rule for `from`.
+    pub fn substitute(&mut self, from: &str, to: &str) {
+        self.substitutions.retain(|(old, _)| old != from);
+        self.substitutions.push((from.to_string(), to.to_string()));
+        // files that weren't found may be found now, and others may be found elsewhere
+        self.loaded.clear();
+    }
+
+    /// Implements `show substitute-path`.
+    pub fn print_substitutions(&self) {
+        println!("List of all source path substitution rules:");
+        for (from, to) in &self.substitutions {
+            println!("  `{}' -> `{}'.", from, to);
+        }
+    }
+
+    /// Returns where to look for `file`: rewritten by the first substitution rule that applies,
+    /// and if relative, relative to the working directory and then to the executable.
+    fn candidates(&self, file: &str) -> Vec<PathBuf> {
+        let file = self
+            .substitutions
+            .iter()
+            .find_map(|(from, to)| rewrite(file, from, to))
+            .unwrap_or_else(|| file.to_string());
+        let path = PathBuf::from(file);
+        if path.is_absolute() {
+            vec![path]
+        } else {
+            vec![path.clone(), self.directory.join(path)]
+        }
+    }
+
+    /// Returns the lines of `file`, reading it the first time it is asked for.
+    fn lines(&mut self, file: &str) -> Option<&Vec<String>> {
+        if !self.loaded.contains_key(file) {
+            let text = self
+                .candidates(file)
+                .iter()
+                .find_map(|path| std::fs::read_to_string(path).ok());
+            let lines = text.map(|text| text.lines().map(str::to_string).collect());
+            self.loaded.insert(file.to_string(), lines);
+        }
+        self.loaded.get(file)?.as_ref()
+    }
+
+    /// Prints line `line` of `file`, or just where it is if the file can't be read.
+    pub fn print_line(&mut self, file: &str, line: usize) {
+        match self
+            .lines(file)
+            .and_then(|lines| lines.get(line.wrapping_sub(1)))
+        {
+            Some(text) => println!("{}\t{}", line, text),
+            None => println!("{}\tin {}", line, file),
+        }
+    }
+
+    /// Shows `line` of `file` for a stop or a newly selected frame, and makes it what a plain
+    /// `list` centres on.
+    pub fn show_stop(&mut self, file: &str, line: usize) {
+        self.print_line(file, line);
+        let first = line.saturating_sub(LIST_SIZE / 2).max(1);
+        self.listing = Some(Listing {
+            file: file.to_string(),
+            first,
+            end: first,
+        });
+    }
+
+    /// Returns the file `list N` refers to: the one listed or stopped in last.
+    pub fn current_file(&self) -> Option<&str> {
+        self.listing.as_ref().map(|listing| listing.file.as_str())
+    }
+
+    /// Implements `list` with a location: shows the lines around `line` of `file`.
+    pub fn list_around(&mut self, file: &str, line: usize) {
+        let first = line.saturating_sub(LIST_SIZE / 2).max(1);
+        self.list(file, first);
+    }
+
+    /// Implements a plain `list`, which shows the lines after the last ones shown.
+    pub fn list_more(&mut self) -> Result<(), String> {
+        let listing = self
+            .listing
+            .clone()
+            .ok_or_else(|| "No default source file.".to_string())?;
+        self.list(&listing.file, listing.end);
+        Ok(())
+    }
+
+    /// Implements `list -`, which shows the lines before the last ones shown.
+    pub fn list_less(&mut self) -> Result<(), String> {
+        let listing = self
+            .listing
+            .clone()
+            .ok_or_else(|| "No default source file.".to_string())?;
+        if listing.first <= 1 {
+            return Err(format!("Already at the start of {}.", listing.file));
+        }
+        let first = listing.first.saturating_sub(LIST_SIZE).max(1);
+        self.list(&listing.file, first);
+        // keep what came after in place for another `list -`
+        if let Some(shown) = self.listing.as_mut() {
+            shown.end = shown.end.min(listing.first);
+        }
+        Ok(())
+    }
+
+    /// Prints up to LIST_SIZE lines of `file` starting at `first`, and remembers them.
+    fn list(&mut self, file: &str, first: usize) {
+        let lines = match self.lines(file) {
+            Some(lines) => lines,
+            None => {
+                println!("{}: No such file or directory.", file);
+                return;
+            }
+        };
+        if first > lines.len() {
+            println!(
+                "Line number {} out of range; \"{}\" has {} lines.",
+                first,
+                file,
+                lines.len()
+            );
+            return;
+        }
+        let end = (first + LIST_SIZE).min(lines.len() + 1);
+        for (number, text) in lines[first - 1..end - 1].iter().enumerate() {
+            println!("{}\t{}", first + number, text);
+        }
+        self.listing = Some(Listing {
+            file: file.to_string(),
+            first,
+            end,
+        });
+    }
+}
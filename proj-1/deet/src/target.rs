@@ -0,0 +1,119 @@
+use crate::dwarf_data::{Location, Variable};
+use crate::location::{self, Storage};
+use crate::modules::Modules;
+use crate::unwind::Frame;
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::unistd::Pid;
+use std::mem::size_of;
+
+pub fn align_addr_to_word(addr: usize) -> usize {
+    addr & (-(size_of::<usize>() as isize) as usize)
+}
+
+/// A program whose registers and memory can be inspected: a live inferior stopped under
+/// ptrace, or a core dump of one that crashed. Everything that only looks at the program, like
+/// `bt` and `print`, works on either.
+pub trait Target {
+    /// Returns the pid of the process.
+    fn pid(&self) -> Pid;
+
+    /// Returns the id and LWP of every thread, in order of creation.
+    fn threads(&self) -> Vec<(usize, Pid)>;
+
+    /// Returns the id of the thread that registers are read from.
+    fn current_thread(&self) -> usize;
+
+    /// Makes thread `id` the current one. Returns false if there is no such thread.
+    fn select_thread(&mut self, id: usize) -> bool;
+
+    /// Returns the path of the executable the process is running.
+    fn executable_path(&self) -> Result<String, std::io::Error>;
+
+    /// Lists the files mapped into the process with their load bases: the start of their
+    /// mapping at file offset 0.
+    fn mapped_objects(&self) -> Result<Vec<(String, usize)>, std::io::Error>;
+
+    /// Returns the general purpose registers of the current thread.
+    fn get_registers(&self) -> Result<user_regs_struct, nix::Error>;
+
+    /// Returns the x87 and SSE registers of the current thread.
+    fn get_fp_registers(&self) -> Result<user_fpregs_struct, nix::Error>;
+
+    /// Reads one word of memory at `addr`.
+    fn read_word(&self, addr: usize) -> Result<usize, nix::Error>;
+
+    /// Returns where the executable is mapped.
+    fn load_base(&self) -> Result<Option<usize>, std::io::Error> {
+        let exe = self.executable_path()?;
+        Ok(self
+            .mapped_objects()?
+            .into_iter()
+            .find(|(path, _)| *path == exe)
+            .map(|(_, base)| base))
+    }
+
+    /// Reads `len` bytes of memory starting at `addr`.
+    fn read_memory(&self, addr: usize, len: usize) -> Result<Vec<u8>, nix::Error> {
+        let aligned_addr = align_addr_to_word(addr);
+        let mut bytes = Vec::with_capacity(len + size_of::<usize>() * 2);
+        let mut word_addr = aligned_addr;
+        while word_addr < addr + len {
+            bytes.extend_from_slice(&self.read_word(word_addr)?.to_le_bytes());
+            word_addr += size_of::<usize>();
+        }
+        let start = addr - aligned_addr;
+        Ok(bytes[start..start + len].to_vec())
+    }
+
+    /// Works out where `var` is while `frame` runs.
+    fn variable_storage(
+        &self,
+        debug_data: &Modules,
+        var: &Variable,
+        frame: &Frame,
+    ) -> Result<Storage, String> {
+        let frame_base = || {
+            let function = debug_data.get_function_containing(frame.lookup_pc());
+            match function.and_then(|func| func.frame_base.as_ref()) {
+                Some(expression) => location::frame_base(expression, frame, self),
+                // gcc's DW_AT_frame_base is DW_OP_call_frame_cfa
+                None => frame
+                    .cfa
+                    .map(|cfa| cfa as u64)
+                    .ok_or_else(|| "Cannot work out the frame's CFA".to_string()),
+            }
+        };
+        match &var.location {
+            Location::Address(addr) => Ok(Storage::Memory(*addr)),
+            Location::FramePointerOffset(offset) => {
+                Ok(Storage::Memory((frame_base()? as isize + offset) as usize))
+            }
+            Location::Expression(expression) => {
+                location::evaluate(expression, frame, &frame_base, self)
+            }
+            Location::List(entries) => {
+                let pc = frame.lookup_pc();
+                match entries.iter().find(|(range, _)| range.contains(&pc)) {
+                    Some((_, expression)) => {
+                        location::evaluate(expression, frame, &frame_base, self)
+                    }
+                    None => Ok(Storage::OptimizedOut),
+                }
+            }
+        }
+    }
+
+    /// Looks up a variable visible in `frame` and works out where it is. Returns None if there
+    /// is no such variable.
+    fn locate_variable<'a>(
+        &self,
+        debug_data: &'a Modules,
+        frame: &Frame,
+        name: &str,
+    ) -> Result<Option<(&'a Variable, Storage)>, String> {
+        match debug_data.get_variable(frame.lookup_pc(), frame.inlined, name) {
+            Some(var) => Ok(Some((var, self.variable_storage(debug_data, var, frame)?))),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,330 @@
+use crate::dwarf_data::Line;
+use crate::modules::Modules;
+use crate::target::Target;
+use gimli::{
+    BaseAddresses, CfaRule, DebugFrame, EhFrame, EvaluationResult, Expression, RegisterRule,
+    UnwindContext, UnwindSection, UnwindTableRow, Value,
+};
+use libc::user_regs_struct;
+use object::{Object, ObjectSection};
+use std::rc::Rc;
+
+type Reader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+/// DWARF numbers of the x86-64 registers we need by name. Column 16 holds the return address,
+/// i.e. the rip of the caller.
+const RBX: usize = 3;
+const RBP: usize = 6;
+const RSP: usize = 7;
+const RIP: usize = 16;
+const REGISTER_COUNT: usize = 17;
+
+/// Registers a function must preserve for its caller. They keep their value across a call
+/// unless the CFI says where they were saved; the others are lost.
+const CALLEE_SAVED: [usize; 6] = [RBX, RBP, 12, 13, 14, 15];
+
+/// Deeper than any stack we expect, to stop runaway unwinding of a corrupt stack.
+const MAX_FRAMES: usize = 4096;
+
+const ENCODING: gimli::Encoding = gimli::Encoding {
+    address_size: 8,
+    format: gimli::Format::Dwarf32,
+    version: 4,
+};
+
+/// The call frame information of one object file, from .eh_frame and, failing that,
+/// .debug_frame.
+pub struct CallFrameInfo {
+    eh_frame: EhFrame<Reader>,
+    debug_frame: DebugFrame<Reader>,
+    bases: BaseAddresses,
+}
+
+/// The rules for recovering the caller's registers at some address.
+pub struct UnwindRow {
+    row: UnwindTableRow<Reader>,
+    /// Whether this is a signal trampoline, whose caller was interrupted rather than calling
+    signal_frame: bool,
+}
+
+impl CallFrameInfo {
+    pub fn load(object: &object::File, endian: gimli::RunTimeEndian) -> CallFrameInfo {
+        let data = |name: &str| {
+            let bytes = object
+                .section_by_name(name)
+                .and_then(|section| section.uncompressed_data().ok())
+                .unwrap_or_default();
+            Reader::new(Rc::from(&*bytes), endian)
+        };
+        let address = |name: &str| object.section_by_name(name).map_or(0, |s| s.address());
+        let mut eh_frame = EhFrame::from(data(".eh_frame"));
+        eh_frame.set_address_size(8);
+        let mut debug_frame = DebugFrame::from(data(".debug_frame"));
+        debug_frame.set_address_size(8);
+        CallFrameInfo {
+            eh_frame,
+            debug_frame,
+            bases: BaseAddresses::default()
+                .set_eh_frame(address(".eh_frame"))
+                .set_text(address(".text"))
+                .set_got(address(".got")),
+        }
+    }
+
+    /// Finds the unwind rules in effect at the link-time address `addr`.
+    pub fn find_row(&self, addr: u64) -> Option<UnwindRow> {
+        let mut ctx = UnwindContext::new();
+        if let Ok(fde) = self
+            .eh_frame
+            .fde_for_address(&self.bases, addr, EhFrame::cie_from_offset)
+        {
+            let row = fde.unwind_info_for_address(&self.eh_frame, &self.bases, &mut ctx, addr);
+            return Some(UnwindRow {
+                row: row.ok()?.clone(),
+                signal_frame: fde.is_signal_trampoline(),
+            });
+        }
+        let fde = self
+            .debug_frame
+            .fde_for_address(&self.bases, addr, DebugFrame::cie_from_offset)
+            .ok()?;
+        let row = fde.unwind_info_for_address(&self.debug_frame, &self.bases, &mut ctx, addr);
+        Some(UnwindRow {
+            row: row.ok()?.clone(),
+            signal_frame: fde.is_signal_trampoline(),
+        })
+    }
+}
+
+/// A stack frame, with the registers as they were while it ran. Registers the unwinder couldn't
+/// recover are None.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    regs: [Option<u64>; REGISTER_COUNT],
+    /// The canonical frame address: the stack pointer before the call that created this frame.
+    /// gcc uses it as the frame base that locals are located from.
+    pub cfa: Option<usize>,
+    /// Whether `pc` is exact, as in the innermost frame or one interrupted by a signal, rather
+    /// than a return address just past a call
+    exact_pc: bool,
+    /// How many inlined calls deep into the function at `pc` this frame is. The frames of
+    /// inlined calls are made up: they share the registers of the function they're inlined into.
+    pub inlined: usize,
+}
+
+impl Frame {
+    fn from_registers(regs: &user_regs_struct) -> Frame {
+        let values = [
+            regs.rax, regs.rdx, regs.rcx, regs.rbx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+            regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+        ];
+        Frame {
+            regs: values.map(Some),
+            cfa: None,
+            exact_pc: true,
+            inlined: 0,
+        }
+    }
+
+    /// Returns the instruction pointer of this frame.
+    pub fn pc(&self) -> usize {
+        self.regs[RIP].unwrap_or(0) as usize
+    }
+
+    /// Returns an address inside the instruction this frame is executing: for callers, that is
+    /// the call instruction rather than the return address after it, which may belong to the
+    /// next line or even the next function.
+    pub fn lookup_pc(&self) -> usize {
+        if self.exact_pc {
+            self.pc()
+        } else {
+            self.pc() - 1
+        }
+    }
+
+    /// Describes the frame for a backtrace, as `#index func (file:line)`.
+    pub fn describe(&self, index: usize, debug_data: &Modules) -> String {
+        format!("#{:<2} {}", index, self.location(debug_data))
+    }
+
+    /// Describes where the frame is running, as `func (file:line)`.
+    pub fn location(&self, debug_data: &Modules) -> String {
+        let (func, line) = self.function_and_line(debug_data);
+        match (&line, &func) {
+            (None, None) => format!("{:#x} in unknown func", self.pc()),
+            (Some(line), None) => format!("unknown func ({})", line),
+            (None, Some(func)) => format!("{:#x} in {}", self.pc(), func),
+            (Some(line), Some(func)) => format!("{} ({})", func, line),
+        }
+    }
+
+    /// Returns the function the frame is running and the line it is at. For a function that an
+    /// inlined call was inlined into, that is the line of the call.
+    pub fn function_and_line(&self, debug_data: &Modules) -> (Option<String>, Option<Line>) {
+        let pc = self.lookup_pc();
+        let mut frames = debug_data.get_inlined_frames(pc);
+        let (func, line) = match frames.len().checked_sub(self.inlined + 1) {
+            Some(index) => frames.swap_remove(index),
+            None => (None, None),
+        };
+        (
+            func.or_else(|| debug_data.get_function_from_addr(pc)),
+            line.or_else(|| debug_data.get_line_from_addr(pc)),
+        )
+    }
+
+    /// Returns the value of the register with DWARF number `register`.
+    pub fn register(&self, register: u16) -> Option<u64> {
+        *self.regs.get(register as usize)?
+    }
+
+    pub fn rbp(&self) -> Option<usize> {
+        self.regs[RBP].map(|rbp| rbp as usize)
+    }
+
+    pub fn rsp(&self) -> Option<usize> {
+        self.regs[RSP].map(|rsp| rsp as usize)
+    }
+
+    /// Works out the caller's registers by following `row`, and this frame's CFA.
+    fn unwind(&mut self, row: &UnwindRow, inferior: &dyn Target) -> Option<Frame> {
+        let cfa = match row.row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => {
+                self.register(register.0)?.wrapping_add(*offset as u64)
+            }
+            CfaRule::Expression(expression) => evaluate(expression, self, inferior)?,
+        };
+        self.cfa = Some(cfa as usize);
+        let read = |addr: u64| inferior.read_word(addr as usize).ok().map(|v| v as u64);
+        let mut caller = Frame {
+            regs: [None; REGISTER_COUNT],
+            cfa: None,
+            exact_pc: row.signal_frame,
+            inlined: 0,
+        };
+        for register in CALLEE_SAVED {
+            caller.regs[register] = self.regs[register];
+        }
+        // on x86-64 the CFA is by definition the caller's stack pointer
+        caller.regs[RSP] = Some(cfa);
+        for (register, rule) in row.row.registers() {
+            let value = match rule {
+                RegisterRule::Undefined | RegisterRule::Architectural => None,
+                RegisterRule::SameValue => self.register(register.0),
+                RegisterRule::Offset(offset) => read(cfa.wrapping_add(*offset as u64)),
+                RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add(*offset as u64)),
+                RegisterRule::Register(other) => self.register(other.0),
+                RegisterRule::Expression(expression) => {
+                    evaluate(expression, self, inferior).and_then(read)
+                }
+                RegisterRule::ValExpression(expression) => evaluate(expression, self, inferior),
+            };
+            if let Some(slot) = caller.regs.get_mut(register.0 as usize) {
+                *slot = value;
+            }
+        }
+        Some(caller)
+    }
+
+    /// Works out the caller's registers for code without call frame information, assuming it
+    /// keeps a frame pointer.
+    fn unwind_frame_pointer(&mut self, inferior: &dyn Target) -> Option<Frame> {
+        let rbp = self.rbp().filter(|rbp| *rbp != 0)?;
+        self.cfa = Some(rbp + 16);
+        let mut caller = Frame {
+            regs: [None; REGISTER_COUNT],
+            cfa: None,
+            exact_pc: false,
+            inlined: 0,
+        };
+        for register in CALLEE_SAVED {
+            caller.regs[register] = self.regs[register];
+        }
+        caller.regs[RSP] = Some(rbp as u64 + 16);
+        caller.regs[RBP] = Some(inferior.read_word(rbp).ok()? as u64);
+        caller.regs[RIP] = Some(inferior.read_word(rbp + 8).ok()? as u64);
+        Some(caller)
+    }
+}
+
+/// Evaluates a DWARF expression from the CFI against the registers of `frame`.
+fn evaluate(expression: &Expression<Reader>, frame: &Frame, inferior: &dyn Target) -> Option<u64> {
+    let mut evaluation = expression.clone().evaluation(ENCODING);
+    let mut result = evaluation.evaluate().ok()?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresRegister { register, .. } => {
+                let value = frame.register(register.0)?;
+                evaluation
+                    .resume_with_register(Value::Generic(value))
+                    .ok()?
+            }
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let word = inferior.read_word(address as usize).ok()? as u64;
+                let value = match size {
+                    1..=7 => word & ((1 << (size * 8)) - 1),
+                    _ => word,
+                };
+                evaluation.resume_with_memory(Value::Generic(value)).ok()?
+            }
+            _ => return None,
+        };
+    }
+    match evaluation.result().first()?.location {
+        gimli::Location::Address { address } => Some(address),
+        _ => None,
+    }
+}
+
+/// Works out the CFA of `frame` and returns its caller.
+fn unwind_step(frame: &mut Frame, inferior: &dyn Target, modules: &Modules) -> Option<Frame> {
+    match modules.unwind_row(frame.lookup_pc()) {
+        Some(row) => frame.unwind(&row, inferior),
+        None => frame.unwind_frame_pointer(inferior),
+    }
+}
+
+/// Returns how many calls were inlined into one another at the code `frame` runs.
+fn inline_depth(frame: &Frame, modules: &Modules) -> usize {
+    let frames = modules.get_inlined_frames(frame.lookup_pc());
+    frames.len().saturating_sub(1)
+}
+
+/// Returns the innermost frame of the stopped inferior, with its CFA worked out. If the code is
+/// inlined, it is the frame of the innermost inlined call.
+pub fn innermost_frame(inferior: &dyn Target, modules: &Modules) -> Result<Frame, nix::Error> {
+    let mut frame = Frame::from_registers(&inferior.get_registers()?);
+    unwind_step(&mut frame, inferior, modules);
+    frame.inlined = inline_depth(&frame, modules);
+    Ok(frame)
+}
+
+/// Unwinds the stack of the stopped inferior, innermost frame first, for as long as the
+/// caller's registers can be recovered.
+pub fn backtrace(inferior: &dyn Target, modules: &Modules) -> Result<Vec<Frame>, nix::Error> {
+    let mut frame = Frame::from_registers(&inferior.get_registers()?);
+    let mut frames = Vec::new();
+    loop {
+        let caller = unwind_step(&mut frame, inferior, modules);
+        let sp = frame.rsp();
+        // a frame for each inlined call, innermost first, then that of the real function
+        for inlined in (1..=inline_depth(&frame, modules)).rev() {
+            frames.push(Frame {
+                inlined,
+                ..frame.clone()
+            });
+        }
+        frames.push(frame);
+        frame = match caller {
+            // the outermost frame (_start) marks its return address undefined
+            Some(caller) if caller.pc() != 0 => caller,
+            _ => break,
+        };
+        // callers live higher up the stack, anything else means we've lost track
+        if frame.rsp() <= sp || frames.len() >= MAX_FRAMES {
+            break;
+        }
+    }
+    Ok(frames)
+}